@@ -0,0 +1,218 @@
+//! Tuning recommendations derived from the queries of the project. Models
+//! are the queries arnab knows of, so the columns they filter their upstream
+//! tables on tell which sort order would let DuckDB skip the most data.
+
+use std::{collections::BTreeMap, fmt::Display};
+
+use sqlparser::{
+    ast::{BinaryOperator, Expr, Query, Select, SetExpr, Statement, TableFactor},
+    dialect::DuckDbDialect,
+    parser::Parser,
+};
+
+use crate::{catalog::Catalog, graph::Graph};
+
+/// A table model repeatedly filtered on one of its columns
+#[derive(Debug, PartialEq)]
+pub struct Advice {
+    pub(crate) model: String,
+    pub(crate) column: String,
+    /// Models filtering the table on the column
+    pub(crate) filtering_models: Vec<String>,
+    /// Row count of the table, when analyzed
+    pub(crate) estimated_rows: Option<i64>,
+}
+
+impl Display for Advice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} is filtered on {} by {} model{} ({})",
+            self.model,
+            self.column,
+            self.filtering_models.len(),
+            if self.filtering_models.len() == 1 {
+                ""
+            } else {
+                "s"
+            },
+            self.filtering_models.join(", ")
+        )?;
+        if let Some(n_rows) = self.estimated_rows {
+            write!(f, " and holds ~{} rows", n_rows)?;
+        }
+        write!(
+            f,
+            "; consider sorting it by {} (`ORDER BY {}` in its query), so that scans skip row groups",
+            self.column, self.column
+        )
+    }
+}
+
+/// A table of a FROM clause, by name and the qualifier of its columns
+struct FromTable {
+    name: String,
+    qualifier: String,
+}
+
+/// (table, column) pairs of the filters of a query, i.e., its WHERE
+/// conditions comparing a column against a value. Nothing is reported for
+/// SQL that can't be parsed.
+pub fn filtered_columns(sql: &str) -> Vec<(String, String)> {
+    let mut filters = vec![];
+    if let Ok(statements) = Parser::parse_sql(&DuckDbDialect {}, sql) {
+        for statement in &statements {
+            if let Statement::Query(query) = statement {
+                collect_query(query, &mut filters);
+            }
+        }
+    }
+    filters.sort();
+    filters.dedup();
+    filters
+}
+
+fn collect_query(query: &Query, filters: &mut Vec<(String, String)>) {
+    for cte in query.with.iter().flat_map(|w| &w.cte_tables) {
+        collect_query(&cte.query, filters);
+    }
+    collect_set_expr(&query.body, filters);
+}
+
+fn collect_set_expr(body: &SetExpr, filters: &mut Vec<(String, String)>) {
+    match body {
+        SetExpr::Select(select) => collect_select(select, filters),
+        SetExpr::Query(query) => collect_query(query, filters),
+        SetExpr::SetOperation { left, right, .. } => {
+            collect_set_expr(left, filters);
+            collect_set_expr(right, filters);
+        }
+        _ => {}
+    }
+}
+
+fn collect_select(select: &Select, filters: &mut Vec<(String, String)>) {
+    let mut tables = vec![];
+    for table_with_joins in &select.from {
+        let joined = table_with_joins.joins.iter().map(|j| &j.relation);
+        for relation in std::iter::once(&table_with_joins.relation).chain(joined) {
+            match relation {
+                TableFactor::Table { name, alias, .. } => {
+                    let name = name.0.last().map(|i| i.value.clone()).unwrap_or_default();
+                    tables.push(FromTable {
+                        qualifier: alias
+                            .as_ref()
+                            .map_or(name.clone(), |a| a.name.value.clone()),
+                        name,
+                    });
+                }
+                TableFactor::Derived { subquery, .. } => collect_query(subquery, filters),
+                _ => {}
+            }
+        }
+    }
+    if let Some(selection) = &select.selection {
+        collect_conditions(selection, &tables, filters);
+    }
+}
+
+fn collect_conditions(expr: &Expr, tables: &[FromTable], filters: &mut Vec<(String, String)>) {
+    let is_column = |e: &Expr| matches!(e, Expr::Identifier(_) | Expr::CompoundIdentifier(_));
+    let filtered = match expr {
+        Expr::Nested(expr) => return collect_conditions(expr, tables, filters),
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::And | BinaryOperator::Or,
+            right,
+        } => {
+            collect_conditions(left, tables, filters);
+            return collect_conditions(right, tables, filters);
+        }
+        // Comparing two columns is a join condition, not a filter
+        Expr::BinaryOp {
+            left,
+            op:
+                BinaryOperator::Eq
+                | BinaryOperator::Lt
+                | BinaryOperator::LtEq
+                | BinaryOperator::Gt
+                | BinaryOperator::GtEq,
+            right,
+        } => match (is_column(left), is_column(right)) {
+            (true, false) => left,
+            (false, true) => right,
+            _ => return,
+        },
+        Expr::Between { expr, .. } | Expr::InList { expr, .. } => expr,
+        _ => return,
+    };
+    let column = match &**filtered {
+        // An unqualified column belongs to the only table of the FROM clause
+        Expr::Identifier(column) if tables.len() == 1 => Some((&tables[0], column)),
+        Expr::CompoundIdentifier(parts) if parts.len() == 2 => tables
+            .iter()
+            .find(|t| t.qualifier == parts[0].value)
+            .map(|t| (t, &parts[1])),
+        _ => None,
+    };
+    if let Some((table, column)) = column {
+        filters.push((table.name.clone(), column.value.clone()));
+    }
+}
+
+/// Recommend sort orders for the table models filtered on the same column by
+/// at least `min_models` models, most filtered first
+pub fn advise(graph: &Graph, catalog: &Catalog, min_models: usize) -> Vec<Advice> {
+    let mut filtering_models = BTreeMap::<(String, String), Vec<String>>::new();
+    for id in &graph.sorted_ids {
+        // Ephemeral models are part of the queries they are inlined into
+        let node = &graph.nodes[id];
+        if node.is_ephemeral() {
+            continue;
+        }
+        let Ok(sql) = node.select_sql() else {
+            continue;
+        };
+        for (table, column) in filtered_columns(&sql) {
+            if graph.nodes.get(&table).is_some_and(|n| n.is_table()) {
+                filtering_models
+                    .entry((table, column))
+                    .or_default()
+                    .push(id.clone());
+            }
+        }
+    }
+    let mut advices = filtering_models
+        .into_iter()
+        .filter(|(_, models)| models.len() >= min_models.max(1))
+        .map(|((model, column), filtering_models)| Advice {
+            estimated_rows: catalog.nodes.get(&model).and_then(|n| n.estimated_rows),
+            model,
+            column,
+            filtering_models,
+        })
+        .collect::<Vec<_>>();
+    advices.sort_by_key(|a| std::cmp::Reverse(a.filtering_models.len()));
+    advices
+}
+
+#[cfg(test)]
+mod test {
+    use super::filtered_columns;
+
+    #[test]
+    fn collect_filtered_columns() {
+        let sql = "WITH recent AS (SELECT * FROM events WHERE event_date >= '2024-01-01') \
+                   SELECT * FROM recent AS r JOIN users AS u ON r.user_id = u.id \
+                   WHERE u.country IN ('FR', 'DE') AND (r.kind = 'click' OR r.user_id = u.id)";
+        assert_eq!(
+            filtered_columns(sql),
+            vec![
+                ("events".to_string(), "event_date".to_string()),
+                ("recent".to_string(), "kind".to_string()),
+                ("users".to_string(), "country".to_string()),
+            ]
+        );
+        assert!(filtered_columns("not SQL").is_empty());
+    }
+}
@@ -0,0 +1,99 @@
+//! Bootstrap of the database objects a run relies on: the schemas of models
+//! and arnab's metadata tables. They are created up front, idempotently,
+//! rather than by the first statement needing them, so that a fresh database
+//! doesn't fail mid-run and every creation is logged.
+
+use std::collections::HashMap;
+
+use duckdb::Connection;
+
+use crate::{
+    errors::ArnabError,
+    node::Node,
+    source::{cache_entries_sql, CACHE_SCHEMA},
+};
+
+/// Schemas the given models are created in, as `[database.]schema`, sorted
+pub fn required_schemas(ids: &[String], node_map: &HashMap<String, Node>) -> Vec<String> {
+    let mut schemas = ids
+        .iter()
+        .map(|id| &node_map[id])
+        .filter(|node| node.relation_schema.is_some() && !node.is_ephemeral())
+        .filter_map(|node| {
+            let relation = node.relation(node.is_table());
+            relation
+                .rsplit_once('.')
+                .map(|(schema, _)| schema.to_string())
+        })
+        .collect::<Vec<_>>();
+    schemas.sort();
+    schemas.dedup();
+    schemas
+}
+
+/// Create the missing schemas among `schemas`, with the metadata table of
+/// source caches if the cache schema is one of them. Returns the created
+/// objects, e.g., `schema marts`.
+pub fn bootstrap(conn: &Connection, schemas: &[String]) -> Result<Vec<String>, ArnabError> {
+    let mut created = vec![];
+    for schema in schemas {
+        let (database, name) = match schema.split_once('.') {
+            Some((database, name)) => (Some(database), name),
+            None => (None, schema.as_str()),
+        };
+        let to_error = |sql: String| {
+            move |e: duckdb::Error| ArnabError::StatementExecutionError {
+                msg: e.to_string(),
+                sql,
+                path: schema.clone(),
+            }
+        };
+        let exists_sql = "SELECT count(*) > 0 FROM duckdb_schemas() \
+                          WHERE schema_name = ? AND database_name = coalesce(?, current_database())";
+        let exists: bool = conn
+            .query_row(exists_sql, duckdb::params![name, database], |r| r.get(0))
+            .map_err(to_error(exists_sql.to_string()))?;
+        if !exists {
+            let sql = format!("CREATE SCHEMA IF NOT EXISTS {}", schema);
+            conn.execute_batch(&sql).map_err(to_error(sql.clone()))?;
+            created.push(format!("schema {}", schema));
+        }
+        if schema == CACHE_SCHEMA {
+            let exists_sql = "SELECT count(*) > 0 FROM duckdb_tables() \
+                              WHERE schema_name = ? AND table_name = 'cache_entries' \
+                              AND database_name = current_database()";
+            let exists: bool = conn
+                .query_row(exists_sql, [CACHE_SCHEMA], |r| r.get(0))
+                .map_err(to_error(exists_sql.to_string()))?;
+            if !exists {
+                let sql = cache_entries_sql();
+                conn.execute_batch(&sql).map_err(to_error(sql.clone()))?;
+                created.push(format!("table {}.cache_entries", CACHE_SCHEMA));
+            }
+        }
+    }
+    Ok(created)
+}
+
+#[cfg(test)]
+mod test {
+    use duckdb::Connection;
+
+    use super::bootstrap;
+    use crate::source::CACHE_SCHEMA;
+
+    #[test]
+    fn bootstrap_is_idempotent() {
+        let conn = Connection::open_in_memory().unwrap();
+        let schemas = vec![CACHE_SCHEMA.to_string(), "marts".into(), "main".into()];
+        assert_eq!(
+            bootstrap(&conn, &schemas).unwrap(),
+            vec![
+                "schema arnab_cache",
+                "table arnab_cache.cache_entries",
+                "schema marts"
+            ]
+        );
+        assert!(bootstrap(&conn, &schemas).unwrap().is_empty());
+    }
+}
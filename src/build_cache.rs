@@ -0,0 +1,206 @@
+//! Result cache of table models: a table whose SQL and upstream nodes are
+//! unchanged since its last successful build is kept as is instead of being
+//! rebuilt. Changes to the data of sources aren't detected, hence
+//! `--full-refresh`.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    errors::ArnabError,
+    graph::Graph,
+    manifest::checksum,
+    node::{Node, NodeKind},
+    source::cache_relation,
+};
+
+pub const BUILD_CACHE_FILE: &str = "build_cache.json";
+
+/// Cache keys of the table models as of their last successful build
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BuildCache {
+    pub(crate) keys: BTreeMap<String, String>,
+}
+
+impl BuildCache {
+    /// Load the cache of previous runs, if any
+    pub fn load(dir: &Path) -> Result<Self, ArnabError> {
+        let file_path = dir.join(BUILD_CACHE_FILE);
+        if !file_path.exists() {
+            return Ok(Self::default());
+        }
+        let src = std::fs::read_to_string(&file_path).map_err(|e| {
+            ArnabError::Error(format!("Cannot read {}: {}", file_path.display(), e))
+        })?;
+        serde_json::from_str(&src).map_err(|e| {
+            ArnabError::Error(format!(
+                "Invalid build cache {}: {}",
+                file_path.display(),
+                e
+            ))
+        })
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<(), ArnabError> {
+        std::fs::create_dir_all(dir).map_err(|e| ArnabError::Error(e.to_string()))?;
+        let json = serde_json::to_string_pretty(self).unwrap();
+        std::fs::write(dir.join(BUILD_CACHE_FILE), json)
+            .map_err(|e| ArnabError::Error(e.to_string()))
+    }
+}
+
+/// Whether a node's table may be kept from a previous build
+pub fn is_cacheable(node: &Node) -> bool {
+    node.is_table() && matches!(node.node_kind, NodeKind::Sql | NodeKind::Python)
+}
+
+/// Cache key of each node: a hash of `context`, e.g., the target and database
+/// built into, its compiled SQL (the file content for seeds), its assertion,
+/// settings and environment variables, its sources, with the time cached
+/// ones were refreshed as given by `source_versions`, and the keys of its
+/// dependencies. Nodes downstream of a shell model have none, as the script
+/// may fetch new data at every run.
+pub fn cache_keys(
+    graph: &Graph,
+    source_versions: &HashMap<String, i64>,
+    context: &str,
+) -> HashMap<String, Option<String>> {
+    let mut keys: HashMap<String, Option<String>> = HashMap::new();
+    for id in &graph.sorted_ids {
+        let node = &graph.nodes[id];
+        let content = match node.node_kind {
+            NodeKind::Shell => None,
+            NodeKind::Seed => std::fs::read_to_string(&node.path).ok(),
+            _ if node.is_ephemeral() => node.ephemeral_sql().ok(),
+            _ => node.compiled_sql().ok(),
+        };
+        let mut prevs = node.prevs.iter().collect::<Vec<_>>();
+        prevs.sort();
+        let key = content.and_then(|content| {
+            let mut input = format!("{}\n{}", context, content);
+            input.push_str(&format!(
+                "\nassertion {:?}\nsettings {:?}\nenv {:?}",
+                node.assertion, node.duckdb_settings, node.env_vars
+            ));
+            for prev in prevs {
+                input.push_str(&format!("\n{}={}", prev, keys.get(prev)?.as_ref()?));
+            }
+            let mut sources = node.sources.iter().collect::<Vec<_>>();
+            sources.sort();
+            for (source, table) in sources {
+                let version = source_versions.get(&cache_relation(source, table));
+                input.push_str(&format!("\nsource {}.{} {:?}", source, table, version));
+            }
+            Some(checksum(&input))
+        });
+        keys.insert(id.clone(), key);
+    }
+    keys
+}
+
+/// Cacheable nodes among `ids` whose previous build is kept, as `is_hit`
+/// holds for them and no node upstream of them within `ids` is rebuilt: the
+/// keys of nodes don't change with the data of their dependencies, e.g., a
+/// table rebuilt after a failure.
+pub fn kept_builds(
+    graph: &Graph,
+    ids: &[String],
+    is_hit: impl Fn(&str) -> bool,
+) -> BTreeSet<String> {
+    let ids = ids.iter().collect::<HashSet<_>>();
+    let mut rebuilt = HashSet::new();
+    let mut kept = BTreeSet::new();
+    for id in graph.sorted_ids.iter().filter(|id| ids.contains(id)) {
+        let node = &graph.nodes[id];
+        if node.prevs.iter().any(|prev| rebuilt.contains(prev)) {
+            rebuilt.insert(id.clone());
+        } else if is_cacheable(node) {
+            match is_hit(id) {
+                true => kept.insert(id.clone()),
+                false => rebuilt.insert(id.clone()),
+            };
+        }
+    }
+    kept
+}
+
+#[cfg(test)]
+mod test {
+    use super::{cache_keys, kept_builds};
+    use crate::{
+        graph::GraphBuilder,
+        node::{Node, NodeKind},
+    };
+
+    #[test]
+    fn propagate_cache_keys() {
+        let keys_of = |sql_b: &str, kind_a: NodeKind| {
+            let mut builder = GraphBuilder::new();
+            let mut a = Node::new(kind_a, "a", "a", "SELECT 1");
+            a.rendered_src = "SELECT 1".into();
+            let mut b = Node::new(NodeKind::Sql, "b", "b", sql_b);
+            b.rendered_src = sql_b.into();
+            let mut c = Node::new(NodeKind::Sql, "c", "c", "SELECT * FROM b");
+            c.rendered_src = "SELECT * FROM b".into();
+            for node in [a, b, c] {
+                builder.add_node(node).unwrap();
+            }
+            builder.add_dependency("b", "a").unwrap();
+            builder.add_dependency("c", "b").unwrap();
+            cache_keys(&builder.build().unwrap(), &Default::default(), "")
+        };
+        let keys = keys_of("SELECT * FROM a", NodeKind::Sql);
+        assert_eq!(keys, keys_of("SELECT * FROM a", NodeKind::Sql));
+
+        let changed = keys_of("SELECT * FROM a WHERE true", NodeKind::Sql);
+        assert_eq!(keys["a"], changed["a"]);
+        assert_ne!(keys["b"], changed["b"]);
+        assert_ne!(keys["c"], changed["c"]);
+
+        let after_shell = keys_of("SELECT * FROM a", NodeKind::Shell);
+        assert!(after_shell.values().all(|key| key.is_none()));
+    }
+
+    #[test]
+    fn rebuild_downstream_of_rebuilt_tables() {
+        let mut builder = GraphBuilder::new();
+        for id in ["a", "b", "c", "d"] {
+            let mut node = Node::new(NodeKind::Sql, id, id, "");
+            node.materialize = Some(if id == "b" { "view" } else { "table" }.into());
+            builder.add_node(node).unwrap();
+        }
+        builder.add_dependency("b", "a").unwrap();
+        builder.add_dependency("c", "b").unwrap();
+        let graph = builder.build().unwrap();
+        let ids = ["a", "b", "c", "d"].map(String::from).to_vec();
+
+        let all_hits = kept_builds(&graph, &ids, |_| true);
+        assert_eq!(all_hits, ["a", "c", "d"].map(String::from).into());
+        // `c` reads `a` through the view `b`
+        let a_rebuilt = kept_builds(&graph, &ids, |id| id != "a");
+        assert_eq!(a_rebuilt, ["d"].map(String::from).into());
+    }
+
+    #[test]
+    fn cache_keys_depend_on_context() {
+        let mut builder = GraphBuilder::new();
+        let mut a = Node::new(NodeKind::Sql, "a", "a", "SELECT 1");
+        a.rendered_src = "SELECT 1".into();
+        builder.add_node(a).unwrap();
+        let graph = builder.build().unwrap();
+        let dev = cache_keys(&graph, &Default::default(), "target dev");
+        let prod = cache_keys(&graph, &Default::default(), "target prod");
+        assert_ne!(dev["a"], prod["a"]);
+
+        let mut graph = graph;
+        graph.nodes.get_mut("a").unwrap().assertion = Some("SELECT true".into());
+        assert_ne!(
+            cache_keys(&graph, &Default::default(), "target dev")["a"],
+            dev["a"]
+        );
+    }
+}
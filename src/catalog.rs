@@ -0,0 +1,166 @@
+use std::{collections::BTreeMap, path::Path};
+
+use duckdb::Connection;
+use serde::{Deserialize, Serialize};
+
+use crate::{errors::ArnabError, node::Node, time_format::iso_now};
+
+pub const CATALOG_FILE: &str = "catalog.json";
+
+/// Statistics of built table models, for docs consumers and cardinality hints
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Catalog {
+    pub(crate) generated_at: String,
+    pub(crate) nodes: BTreeMap<String, CatalogNode>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CatalogNode {
+    pub(crate) relation: String,
+    pub(crate) analyzed_at: String,
+    /// Row count estimated by DuckDB
+    pub(crate) estimated_rows: Option<i64>,
+    pub(crate) columns: Vec<CatalogColumn>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CatalogColumn {
+    pub(crate) name: String,
+    pub(crate) data_type: String,
+    /// Approximate number of distinct values, collected by `ANALYZE`
+    pub(crate) approx_unique: Option<i64>,
+    /// Raw column statistics as reported by DuckDB, e.g., min/max values
+    pub(crate) stats: String,
+}
+
+impl Catalog {
+    /// Load the catalog of a previous run, if any
+    pub fn load(dir: &Path) -> Result<Self, ArnabError> {
+        let file_path = dir.join(CATALOG_FILE);
+        if !file_path.exists() {
+            return Ok(Self::default());
+        }
+        let src = std::fs::read_to_string(&file_path).map_err(|e| {
+            ArnabError::Error(format!("Cannot read {}: {}", file_path.display(), e))
+        })?;
+        serde_json::from_str(&src).map_err(|e| {
+            ArnabError::Error(format!("Invalid catalog {}: {}", file_path.display(), e))
+        })
+    }
+
+    pub fn save(&mut self, dir: &Path) -> Result<(), ArnabError> {
+        self.generated_at = iso_now();
+        std::fs::create_dir_all(dir).map_err(|e| ArnabError::Error(e.to_string()))?;
+        let json = serde_json::to_string_pretty(self).unwrap();
+        std::fs::write(dir.join(CATALOG_FILE), json).map_err(|e| ArnabError::Error(e.to_string()))
+    }
+}
+
+/// Run `ANALYZE` on the table of a model and collect its statistics
+pub fn analyze_table(conn: &Connection, node: &Node) -> Result<CatalogNode, ArnabError> {
+    let current = |function: &str| -> String {
+        conn.query_row(&format!("SELECT {}()", function), [], |r| r.get(0))
+            .unwrap_or_default()
+    };
+    let database = node
+        .database
+        .clone()
+        .unwrap_or_else(|| current("current_database"));
+    let schema = node
+        .relation_schema
+        .clone()
+        .unwrap_or_else(|| current("current_schema"));
+    let table = node.alias.as_deref().unwrap_or(&node.id);
+    let relation = format!("{}.{}.{}", database, schema, table);
+    let to_error = |e: duckdb::Error| ArnabError::StatementExecutionError {
+        msg: e.to_string(),
+        path: node.path.clone(),
+        sql: format!("ANALYZE {}", relation),
+    };
+    conn.execute_batch(&format!("ANALYZE {};", relation))
+        .map_err(to_error)?;
+
+    let estimated_rows = conn
+        .query_row(
+            "SELECT estimated_size FROM duckdb_tables() \
+             WHERE database_name = ? AND schema_name = ? AND table_name = ?",
+            [&database, &schema, table],
+            |r| r.get(0),
+        )
+        .ok();
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT column_name, data_type FROM duckdb_columns() \
+             WHERE database_name = ? AND schema_name = ? AND table_name = ? \
+             ORDER BY column_index",
+        )
+        .map_err(to_error)?;
+    let column_types = stmt
+        .query_map([&database, &schema, table], |r| {
+            Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?))
+        })
+        .map_err(to_error)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(to_error)?;
+
+    // `stats()` reports the statistics of a column for each row, so one row
+    // is enough. Empty tables have no statistics to report.
+    let stats_sql = format!(
+        "SELECT {} FROM {} LIMIT 1",
+        column_types
+            .iter()
+            .map(|(name, _)| format!("stats(\"{}\")", name))
+            .collect::<Vec<_>>()
+            .join(", "),
+        relation
+    );
+    let mut stmt = conn.prepare(&stats_sql).map_err(to_error)?;
+    let mut rows = stmt.query([]).map_err(to_error)?;
+    let stats = match rows.next().map_err(to_error)? {
+        Some(row) => (0..column_types.len())
+            .map(|i| row.get::<_, String>(i))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(to_error)?,
+        None => vec![String::new(); column_types.len()],
+    };
+
+    let columns = column_types
+        .into_iter()
+        .zip(stats)
+        .map(|((name, data_type), stats)| CatalogColumn {
+            name,
+            data_type,
+            approx_unique: approx_unique(&stats),
+            stats,
+        })
+        .collect();
+    Ok(CatalogNode {
+        relation,
+        analyzed_at: iso_now(),
+        estimated_rows,
+        columns,
+    })
+}
+
+/// Extract the approximate distinct count from DuckDB's column statistics,
+/// e.g., `[Min: 1, Max: 3][Has Null: false, Has No Null: true][Approx Unique: 3]`
+fn approx_unique(stats: &str) -> Option<i64> {
+    let (_, rest) = stats.split_once("Approx Unique: ")?;
+    rest.split(|c: char| !c.is_ascii_digit())
+        .next()?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::approx_unique;
+
+    #[test]
+    fn parse_approx_unique() {
+        let stats = "[Min: 1, Max: 2][Has Null: false, Has No Null: true][Approx Unique: 2]";
+        assert_eq!(approx_unique(stats), Some(2));
+        assert_eq!(approx_unique("[Has Null: true]"), None);
+    }
+}
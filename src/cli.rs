@@ -0,0 +1,1270 @@
+use crate::dashboard::Dashboard;
+use crate::ephemeral::EphemeralBuild;
+use crate::errors::{
+    ArnabError, EXIT_CONFIG_ERROR, EXIT_ERROR, EXIT_MODEL_FAILURE, EXIT_TEST_FAILURE,
+};
+use crate::events::LogFormat;
+use crate::graphml::GraphFormat;
+use crate::graphviz::{VizFormat, VizRenderer};
+use crate::listing::{ListFormat, ListResource};
+use crate::maintenance::{ExportFormat, MaintenanceInfo};
+use crate::run_results::RunResults;
+use crate::script::ResumePoint;
+use crate::seed::DEFAULT_SEED_PATH;
+use crate::selection::Selection;
+use crate::session::{
+    CompileOptions, Config, ModelsDirs, RunOptions, Session, ARTIFACTS_DB_NAME, CONFIG_FILE_NAMES,
+};
+use crate::test_results::TestResults;
+use crate::verbosity::{info, set_verbosity, verbose, Verbosity};
+use crate::{
+    codegen, docs, listing, maintenance, manifest, provenance, remote, schedule, script, seed,
+    server, settings, threads, time_format, watch,
+};
+#[allow(unused_imports)]
+use clap::{Command, Parser, Subcommand};
+use duckdb::Connection;
+use std::{
+    error::Error,
+    io::{IsTerminal, Write},
+};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
+    /// Directory of models, repeat for several
+    #[arg(short, long)]
+    models_dir: Vec<String>,
+    #[arg(short, long)]
+    db_path: Option<String>,
+    /// Target environment, e.g., dev or prod
+    #[arg(short, long)]
+    target: Option<String>,
+    /// Variables available in templates through `var()`, as a YAML mapping,
+    /// e.g., 'start_date: 2024-01-01'
+    #[arg(long, global = true)]
+    vars: Option<String>,
+    /// Print only final summaries and errors
+    #[arg(short, long, global = true, conflicts_with_all = ["verbose", "debug"])]
+    quiet: bool,
+    /// Also print execution plans and settings
+    #[arg(short, long, global = true, conflicts_with = "debug")]
+    verbose: bool,
+    /// Also print SQL statements as they are executed
+    #[arg(long, global = true)]
+    debug: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Run sql script file
+    RunFile(RunScriptArgs),
+    /// Run pipelines
+    Run(RunArgs),
+    /// Run a job declared in `jobs` of the config, with its selection and
+    /// settings
+    Job(JobArgs),
+    /// Create the schemas of the models and arnab's metadata tables, so that
+    /// runs on a fresh database don't fail midway
+    Bootstrap,
+    /// Build again the nodes that failed in the last run, and those
+    /// downstream of them
+    Retry(RetryArgs),
+    /// Visualize pipelines, or export their graph
+    Viz(VizArgs),
+    /// Export the graph of pipelines for network analysis tools
+    Graph(GraphArgs),
+    /// List models with their metadata
+    Ls(LsArgs),
+    /// Print the first rows returned by a model, without materializing it
+    Show(ShowArgs),
+    /// Write the SQL executed for each model into <target_path>/compiled, without executing it
+    Compile(CompileArgs),
+    /// Pin models, for runs to keep them as they are instead of rebuilding
+    /// them, or list pinned models
+    Pin(PinArgs),
+    /// Unpin models pinned with `pin`
+    Unpin(UnpinArgs),
+    /// Fail if model contracts break relative to another run's manifest
+    CheckBreaking(CheckBreakingArgs),
+    /// Recommend sort orders for tables repeatedly filtered on a column
+    Advise(AdviseArgs),
+    /// Check that no run provenance record was modified or removed
+    VerifyProvenance(VerifyProvenanceArgs),
+    /// Run data tests against built models
+    Test(TestArgs),
+    /// Load seed tables
+    Seed(SeedArgs),
+    /// Generate boilerplate files
+    Codegen(CodegenArgs),
+    /// Generate project documentation
+    Docs(DocsArgs),
+    /// Vacuum and checkpoint the databases, and report their sizes
+    Maintain,
+    /// Run the `schedules` of the config at their times, until interrupted
+    Schedule,
+    /// Serve node builds dispatched by `arnab run` (experimental)
+    Worker(WorkerArgs),
+    /// Serve an HTTP API to trigger runs and fetch their status and artifacts
+    Serve(ServeArgs),
+    /// Export the database to a directory of table files and schema SQL
+    ExportDb(ExportDbArgs),
+    /// Import a database exported with `export-db`
+    ImportDb(ImportDbArgs),
+}
+
+impl Commands {
+    /// Name of the command, as used for command-specific configuration
+    fn name(&self) -> &'static str {
+        match self {
+            Commands::RunFile(_) => "run-file",
+            Commands::Run(_) => "run",
+            Commands::Job(_) => "job",
+            Commands::Bootstrap => "bootstrap",
+            Commands::Retry(_) => "retry",
+            Commands::Viz(_) => "viz",
+            Commands::Graph(_) => "graph",
+            Commands::Compile(_) => "compile",
+            Commands::Ls(_) => "ls",
+            Commands::Pin(_) => "pin",
+            Commands::Unpin(_) => "unpin",
+            Commands::CheckBreaking(_) => "check-breaking",
+            Commands::Advise(_) => "advise",
+            Commands::VerifyProvenance(_) => "verify-provenance",
+            Commands::Show(_) => "show",
+            Commands::Test(_) => "test",
+            Commands::Seed(_) => "seed",
+            Commands::Codegen(_) => "codegen",
+            Commands::Docs(_) => "docs",
+            Commands::Maintain => "maintain",
+            Commands::Schedule => "schedule",
+            Commands::Worker(_) => "worker",
+            Commands::Serve(_) => "serve",
+            Commands::ExportDb(_) => "export-db",
+            Commands::ImportDb(_) => "import-db",
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct RunScriptArgs {
+    /// Paths to script or pattern
+    script_paths: Vec<String>,
+    /// Skip the statements of a script before this one (counting from 1), to
+    /// resume it after a failure, e.g., `migrate.sql:12`. The script can be
+    /// left out when it's the only one run.
+    #[arg(long)]
+    resume_from: Option<ResumePoint>,
+}
+
+#[derive(clap::Args, Debug)]
+struct SelectionArgs {
+    /// Only include the selected models, by id or dotted path (e.g., `marts.*`).
+    /// Prefix with `+` to add ancestors, suffix with `+` to add descendants.
+    /// `state:modified` selects models changed since the last run.
+    #[arg(short, long)]
+    select: Vec<String>,
+    /// Leave out the matching models
+    #[arg(short, long)]
+    exclude: Vec<String>,
+    /// Also leave out descendants that only depend on excluded models
+    #[arg(long)]
+    exclude_exclusive_descendants: bool,
+    /// Manifest that `state:` selectors compare against, or the target
+    /// directory holding it. Defaults to the manifest of the last run.
+    #[arg(long)]
+    state: Option<String>,
+}
+
+impl From<SelectionArgs> for Selection {
+    fn from(args: SelectionArgs) -> Self {
+        Selection {
+            select: args.select,
+            exclude: args.exclude,
+            exclude_exclusive_descendants: args.exclude_exclusive_descendants,
+            state: args.state.map(Into::into),
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct RunArgs {
+    #[command(flatten)]
+    selection: SelectionArgs,
+    /// Number of nodes to run concurrently, or `auto`
+    #[arg(long)]
+    threads: Option<threads::ThreadCount>,
+    /// Also stream NDJSON run events to this open file descriptor
+    #[arg(long)]
+    log_fd: Option<i32>,
+    /// Console output: colored text, or one JSON event per line
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+    /// Allow running against an in-memory database, discarded at exit
+    #[arg(long)]
+    ephemeral: bool,
+    /// Build into a temporary in-memory database, reading sources and
+    /// unselected models from the real one, which is left untouched
+    #[arg(long, conflicts_with = "worker")]
+    ephemeral_build: bool,
+    /// Print the execution plan without executing it
+    #[arg(long)]
+    dry_run: bool,
+    /// Rebuild table models even when their SQL and upstream models are
+    /// unchanged since their last build
+    #[arg(long)]
+    full_refresh: bool,
+    /// Stop at the first failure of a model without `on_error: continue`
+    #[arg(long)]
+    fail_fast: bool,
+    /// Show a progress bar with an ETA when building nodes one at a time on
+    /// a terminal
+    #[arg(long)]
+    progress: bool,
+    /// Show a live dashboard of the run on a terminal
+    #[arg(long, conflicts_with_all = ["log_format", "progress"])]
+    tui: bool,
+    /// Keep running, re-running the models affected by each change of the
+    /// models and macros directories
+    #[arg(long, conflicts_with_all = ["dry_run", "tui"])]
+    watch: bool,
+    /// Address of an `arnab worker` to dispatch node builds to (experimental).
+    /// Repeat to use several workers.
+    #[arg(long)]
+    worker: Vec<String>,
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct JobArgs {
+    /// Name of the job
+    name: String,
+    /// Print the execution plan without executing it
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct RetryArgs {
+    /// Number of nodes to run concurrently, or `auto`
+    #[arg(long)]
+    threads: Option<threads::ThreadCount>,
+    /// Stop at the first failure of a model without `on_error: continue`
+    #[arg(long)]
+    fail_fast: bool,
+    /// Show a progress bar with an ETA when building nodes one at a time on
+    /// a terminal
+    #[arg(long)]
+    progress: bool,
+    /// Also stream NDJSON run events to this open file descriptor
+    #[arg(long)]
+    log_fd: Option<i32>,
+    /// Console output: colored text, or one JSON event per line
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+    /// Print the execution plan without executing it
+    #[arg(long)]
+    dry_run: bool,
+    /// Address of an `arnab worker` to dispatch node builds to (experimental).
+    /// Repeat to use several workers.
+    #[arg(long)]
+    worker: Vec<String>,
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct TestArgs {
+    #[command(flatten)]
+    selection: SelectionArgs,
+    /// Build the selected models into a temporary in-memory database first
+    /// and test them there, leaving the real database untouched
+    #[arg(long)]
+    ephemeral_build: bool,
+    /// Also write the test results as a JUnit XML report to this path
+    #[arg(long)]
+    junit_xml: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct ServeArgs {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1")]
+    host: String,
+    #[arg(long, default_value_t = 8080)]
+    port: u16,
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct WorkerArgs {
+    /// Address to listen on. Anyone reaching it with the worker token can
+    /// run SQL on the worker, so only listen on trusted networks.
+    #[arg(long, default_value = "127.0.0.1:7878")]
+    listen: String,
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct LsArgs {
+    #[command(flatten)]
+    selection: SelectionArgs,
+    /// Resources to list. Macros are listed with their signature and doc
+    /// comment, regardless of the selection.
+    #[arg(long, value_enum, default_value_t = ListResource::Model)]
+    resource: ListResource,
+    #[arg(long, value_enum, default_value_t = ListFormat::Text)]
+    output: ListFormat,
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct ShowArgs {
+    /// Id of the model
+    model: String,
+    /// Maximum number of rows to print
+    #[arg(long, default_value_t = 10)]
+    limit: usize,
+    /// Run the query even if its result is cached
+    #[arg(long)]
+    no_cache: bool,
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct PinArgs {
+    /// Ids of the models to pin, all pinned models are listed if none
+    models: Vec<String>,
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct UnpinArgs {
+    /// Ids of the models to unpin
+    #[arg(required_unless_present = "all")]
+    models: Vec<String>,
+    /// Unpin all pinned models
+    #[arg(long, conflicts_with = "models")]
+    all: bool,
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct CheckBreakingArgs {
+    /// Manifest to compare against, or the target directory holding it,
+    /// e.g., one from production
+    #[arg(long)]
+    state: String,
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct VerifyProvenanceArgs {
+    /// Hash of the last record of a previous verification, kept outside of
+    /// the target directory, for records removed since then to be detected
+    #[arg(long)]
+    head: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct AdviseArgs {
+    /// Number of models that must filter a table on a column to recommend
+    /// sorting it by that column
+    #[arg(long, default_value_t = 2)]
+    min_models: usize,
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct CompileArgs {
+    #[command(flatten)]
+    selection: SelectionArgs,
+    /// Warn about models selecting `*` from upstream models whose columns
+    /// aren't declared in a schema file
+    #[arg(long)]
+    check_select_star: bool,
+    /// Replace `*` with the columns of the built upstream relations
+    #[arg(long)]
+    expand_select_star: bool,
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct VizArgs {
+    output_path: String,
+    /// Output file format, also used as the extension of the output path if
+    /// it has none
+    #[arg(long, value_enum, default_value = "svg")]
+    format: VizFormat,
+    #[command(flatten)]
+    selection: SelectionArgs,
+    /// Highlight changes relative to a manifest (or the directory containing it)
+    #[arg(long)]
+    diff: Option<String>,
+    /// Renderer of SVG output: `dot-cli` runs an installed Graphviz, much
+    /// faster on large graphs
+    #[arg(long, value_enum, default_value = "builtin")]
+    renderer: VizRenderer,
+    /// Refuse to draw SVG graphs of more nodes than this, sources included.
+    /// 0 disables the limit.
+    #[arg(long, default_value_t = 500)]
+    max_nodes: usize,
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct GraphArgs {
+    output_path: String,
+    /// Output file format, also used as the extension of the output path if
+    /// it has none
+    #[arg(long, value_enum, default_value = "graphml")]
+    format: GraphFormat,
+    #[command(flatten)]
+    selection: SelectionArgs,
+    /// Add the status of nodes relative to a manifest (or the directory
+    /// containing it) as an attribute
+    #[arg(long)]
+    diff: Option<String>,
+}
+
+impl From<GraphArgs> for VizArgs {
+    fn from(args: GraphArgs) -> Self {
+        VizArgs {
+            output_path: args.output_path,
+            format: args.format.into(),
+            selection: args.selection,
+            diff: args.diff,
+            renderer: VizRenderer::Builtin,
+            max_nodes: 0,
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct SeedArgs {
+    /// Also generate synthetic seeds declared in seed YAML files
+    #[arg(long)]
+    generate: bool,
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct ExportDbArgs {
+    /// Directory to create, which must not exist or be empty
+    output_dir: String,
+    /// File format of the exported tables
+    #[arg(long, value_enum, default_value = "csv")]
+    format: ExportFormat,
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct ImportDbArgs {
+    /// Directory written by `export-db`
+    input_dir: String,
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct DocsArgs {
+    #[command(subcommand)]
+    command: DocsCommands,
+}
+
+#[derive(Subcommand, Debug)]
+enum DocsCommands {
+    /// Write a static HTML site documenting the models into <target_path>/docs
+    Generate {
+        #[command(flatten)]
+        selection: SelectionArgs,
+    },
+    /// Serve the site written by `docs generate` over HTTP
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8000")]
+        listen: String,
+    },
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct CodegenArgs {
+    #[command(subcommand)]
+    command: CodegenCommands,
+}
+
+#[derive(Subcommand, Debug)]
+enum CodegenCommands {
+    /// Generate a staging model and its schema stub from a source table
+    Staging {
+        /// Source table as `source_name.table_name`
+        source_table: String,
+        /// Directory to write the files to, defaults to `<models_dir>/staging`
+        #[arg(long)]
+        output_dir: Option<String>,
+        /// Overwrite existing files
+        #[arg(long)]
+        force: bool,
+    },
+    /// Write or update schema YAML files of built models
+    Schema {
+        /// Models to generate schema files for, e.g., `marts.*`. All models if unset.
+        #[arg(short, long)]
+        select: Vec<String>,
+    },
+}
+
+impl std::fmt::Display for ArnabError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArnabError::Error(msg) | ArnabError::Config(msg) => write!(f, "{}", msg),
+            ArnabError::StatementExecutionError { .. } => write!(f, "{:#?}", self),
+            ArnabError::UnknownModelType(model_type) => {
+                write!(f, "Unknown model type: {}", model_type)
+            }
+        }
+    }
+}
+
+fn save_visualization_with_args(
+    args: VizArgs,
+    conn: Connection,
+    config: Config,
+) -> Result<(), ArnabError> {
+    let mut session = Session::new(config, conn);
+
+    let extension = format!(".{}", args.format.extension());
+    let adjusted_path = if args.output_path.ends_with(&extension) {
+        args.output_path.clone()
+    } else {
+        let mut s = args.output_path.to_string();
+        s.push_str(&extension);
+        s
+    };
+
+    let diff_manifest = args.diff.as_ref().map(std::path::Path::new);
+    let selection = args.selection.into();
+    let max_nodes = (args.max_nodes > 0).then_some(args.max_nodes);
+    match session.save_visualization(
+        &adjusted_path,
+        &selection,
+        diff_manifest,
+        args.format,
+        args.renderer,
+        max_nodes,
+    ) {
+        Ok(_) => println!("Saved as {}", adjusted_path),
+        Err(e) => {
+            return Err(ArnabError::Error(format!(
+                "Failed to save {}: {}",
+                args.format.extension().to_uppercase(),
+                e
+            )))
+        }
+    }
+
+    Ok(())
+}
+
+fn run_session_with_args(
+    args: RunArgs,
+    retry: Option<RunResults>,
+    conn: Connection,
+    mut config: Config,
+) {
+    config.threads = args.threads.or(config.threads);
+    if !args.worker.is_empty() {
+        config.workers = Some(args.worker.clone());
+    }
+    if config.workers.as_ref().is_some_and(|w| !w.is_empty()) && config.worker_token.is_none() {
+        println!(
+            "Error: workers require a non-empty shared token: set `worker_token` in the config or {}.",
+            remote::WORKER_TOKEN_ENV
+        );
+        std::process::exit(EXIT_CONFIG_ERROR);
+    }
+    let ephemeral = args.ephemeral || args.ephemeral_build || config.ephemeral.unwrap_or(false);
+    if config.db_path.is_none() && !ephemeral && !args.dry_run {
+        println!("Error: db_path is not set, so the pipeline would be built in an in-memory database and discarded at exit.");
+        println!("Set `db_path` in the config file or pass `--db-path` to keep the results, or pass `--ephemeral` (or set `ephemeral: true`) to run anyway.");
+        std::process::exit(EXIT_CONFIG_ERROR);
+    }
+    let mut session = Session::new(config, conn);
+    let json_logs = args.log_format == LogFormat::Json;
+    if json_logs {
+        session.events.add_sink(Box::new(std::io::stdout()));
+    }
+    if let Some(fd) = args.log_fd {
+        if let Err(e) = session.events.add_fd_sink(fd) {
+            println!("Error: {}", e);
+            std::process::exit(e.exit_status());
+        }
+    }
+    let options = RunOptions {
+        selection: args.selection.into(),
+        dry_run: args.dry_run,
+        full_refresh: args.full_refresh,
+        retry,
+        fail_fast: args.fail_fast,
+        progress_bar: args.progress,
+        ..Default::default()
+    };
+    if args.watch {
+        watch::watch(&mut session, &options);
+    }
+    // The dashboard replaces the console output, on terminals only
+    let dashboard = (args.tui && std::io::stdout().is_terminal()).then(|| {
+        set_verbosity(Verbosity::Silent);
+        let dashboard = Dashboard::start();
+        session.events.add_listener(dashboard.listener());
+        dashboard
+    });
+    let result = session.run_nodes(&options);
+    if let Some(dashboard) = &dashboard {
+        dashboard.finish();
+    }
+    match result {
+        Ok(summary) if summary.failed() => std::process::exit(EXIT_MODEL_FAILURE),
+        Ok(_) => {
+            // TODO: do something on session completed
+        }
+        // The failure is already logged as a `run_failed` event
+        Err(e) if json_logs => std::process::exit(e.exit_status()),
+        Err(e) => {
+            let exit_status = e.exit_status();
+            match e {
+                ArnabError::Error(msg) | ArnabError::Config(msg) => println!("Error: {}", msg),
+                ArnabError::StatementExecutionError { msg, sql, path } => {
+                    println!("Failed to execute SQL statement.");
+                    println!("Error      : {}", msg);
+                    println!("Source path: {}", path);
+                    println!("SQL:\n{}", sql);
+                }
+                _ => {
+                    println!("{:#?}", e)
+                }
+            }
+            std::process::exit(exit_status)
+        }
+    }
+}
+
+fn codegen_with_args(
+    args: CodegenArgs,
+    conn: Connection,
+    config: Config,
+) -> Result<(), ArnabError> {
+    match args.command {
+        CodegenCommands::Staging {
+            source_table,
+            output_dir,
+            force,
+        } => {
+            let (source_name, table_name) = source_table.split_once('.').ok_or_else(|| {
+                ArnabError::Error(format!(
+                    "Expected `source_name.table_name`, got `{}`",
+                    source_table
+                ))
+            })?;
+            let output_dir = match output_dir {
+                Some(output_dir) => std::path::PathBuf::from(output_dir),
+                None => std::path::Path::new(
+                    config.models_dir.as_ref().map(|d| d.first()).unwrap_or("."),
+                )
+                .join("staging"),
+            };
+            codegen::generate_staging_model(
+                &conn,
+                &config.sources.unwrap_or_default(),
+                source_name,
+                table_name,
+                &output_dir,
+                force,
+            )
+        }
+        CodegenCommands::Schema { select } => {
+            let mut session = Session::new(config, conn);
+            let graph = session.build_graph()?;
+            let selected_ids = session.select(
+                &graph,
+                &Selection {
+                    select,
+                    ..Default::default()
+                },
+            )?;
+            let nodes = selected_ids
+                .iter()
+                .map(|id| &graph.nodes[id])
+                .collect::<Vec<_>>();
+            codegen::generate_model_schemas(&session.db_conn, &nodes)
+        }
+    }
+}
+
+pub fn main() -> Result<(), Box<dyn Error>> {
+    // No colors when output is piped or redirected, e.g., in CI logs
+    if !std::io::stdout().is_terminal() {
+        colored::control::set_override(false);
+    }
+
+    let cli = Cli::parse();
+    // Only the listing or the run events are printed as JSON, so that they
+    // can be parsed
+    let json_output = match &cli.command {
+        Commands::Ls(args) => args.output == ListFormat::Json,
+        Commands::Run(args) => args.log_format == LogFormat::Json,
+        Commands::Retry(args) => args.log_format == LogFormat::Json,
+        _ => false,
+    };
+    set_verbosity(if json_output {
+        Verbosity::Silent
+    } else if cli.quiet {
+        Verbosity::Quiet
+    } else if cli.debug {
+        Verbosity::Debug
+    } else if cli.verbose {
+        Verbosity::Verbose
+    } else {
+        Verbosity::Normal
+    });
+
+    let project_dir = std::path::Path::new(".");
+    let mut config: Config = match Config::discover(project_dir) {
+        Ok(Some((path, mut config))) => {
+            config.config_hash = std::fs::read_to_string(&path)
+                .ok()
+                .map(|src| manifest::checksum(&src));
+            let name = path.file_name().unwrap().to_string_lossy();
+            info!("Using config file {}", name);
+            for ignored in Config::find_files(project_dir).iter().skip(1) {
+                info!(
+                    "Ignoring {}, {} takes precedence",
+                    ignored.file_name().unwrap().to_string_lossy(),
+                    name
+                );
+            }
+            config
+        }
+        Ok(None) => {
+            info!(
+                "Config file ({}) not found on project root, using defaults",
+                CONFIG_FILE_NAMES.join(", ")
+            );
+            Default::default()
+        }
+        Err(e) => {
+            println!("FATAL ERROR: {}\nExiting", e);
+            std::process::exit(EXIT_CONFIG_ERROR);
+        }
+    };
+
+    if let Some(Err(e)) = config.time_format.as_ref().map(|f| f.validate()) {
+        println!("FATAL ERROR: {}\nExiting", e);
+        std::process::exit(EXIT_CONFIG_ERROR);
+    }
+
+    // Override config with the job's and the target's settings, then with
+    // root cli args
+    let job = match &cli.command {
+        Commands::Job(args) => match config.job(&args.name) {
+            Ok(job) => Some(job),
+            Err(e) => {
+                println!("FATAL ERROR: {}\nExiting", e);
+                std::process::exit(EXIT_CONFIG_ERROR);
+            }
+        },
+        _ => None,
+    };
+    let job_target = job.as_ref().and_then(|job| job.target.clone());
+    config.target = cli.target.or(job_target).or(config.target);
+    if let Err(e) = config.apply_target() {
+        println!("FATAL ERROR: {}\nExiting", e);
+        std::process::exit(EXIT_CONFIG_ERROR);
+    }
+    if let Some(job) = &job {
+        config.apply_job(job);
+    }
+    config.db_path = cli.db_path.or(config.db_path);
+    if !cli.models_dir.is_empty() {
+        config.models_dir = Some(ModelsDirs(cli.models_dir));
+    }
+    if let Ok(target_path) = std::env::var(manifest::TARGET_PATH_ENV) {
+        config.target_path = Some(target_path);
+    }
+    if let Ok(worker_token) = std::env::var(remote::WORKER_TOKEN_ENV) {
+        config.worker_token = Some(worker_token);
+    }
+    // A blank token would let anyone sending an empty one in
+    config.worker_token = config.worker_token.filter(|t| !t.trim().is_empty());
+    if let Some(vars) = &cli.vars {
+        if let Err(e) = config.override_vars(vars) {
+            println!("FATAL ERROR: {}\nExiting", e);
+            std::process::exit(EXIT_CONFIG_ERROR);
+        }
+    }
+
+    let ephemeral_build = match &cli.command {
+        Commands::Run(args) => args.ephemeral_build,
+        Commands::Test(args) => args.ephemeral_build,
+        _ => false,
+    };
+    let conn = match &config.db_path {
+        _ if ephemeral_build => {
+            match EphemeralBuild::connect(
+                config.db_path.as_deref(),
+                config.artifacts_db_path.is_some(),
+            ) {
+                Ok((build, conn)) => {
+                    config.ephemeral_build = Some(build);
+                    conn
+                }
+                Err(e) => {
+                    println!("FATAL ERROR: {}\nExiting", e);
+                    std::process::exit(EXIT_CONFIG_ERROR);
+                }
+            }
+        }
+        // Listing models only needs the graph, not the database
+        _ if matches!(
+            cli.command,
+            Commands::Ls(_)
+                | Commands::CheckBreaking(_)
+                | Commands::Advise(_)
+                | Commands::VerifyProvenance(_)
+        ) =>
+        {
+            Connection::open_in_memory()?
+        }
+        Some(db_path) => Connection::open(db_path)?,
+        None => {
+            info!("db_path unspecified, using in-memory DuckDB connection");
+            Connection::open_in_memory()?
+        }
+    };
+
+    // Attach the database holding table models when hot/cold splitting is enabled
+    let artifacts_db_path = config.artifacts_db_path.as_ref();
+    if let Some(artifacts_db_path) = artifacts_db_path.filter(|_| {
+        !ephemeral_build
+            && !matches!(
+                cli.command,
+                Commands::Ls(_)
+                    | Commands::CheckBreaking(_)
+                    | Commands::Advise(_)
+                    | Commands::VerifyProvenance(_)
+            )
+    }) {
+        let attach_sql = format!(
+            "ATTACH IF NOT EXISTS '{}' AS {};",
+            artifacts_db_path.replace('\'', "''"),
+            ARTIFACTS_DB_NAME
+        );
+        if let Err(e) = conn.execute_batch(&attach_sql) {
+            println!("FATAL ERROR: {}\nExiting", e);
+            std::process::exit(EXIT_CONFIG_ERROR);
+        }
+    }
+
+    // Override DuckDb's settings if specified in the configuration, for the
+    // current target and command
+    let duckdb_settings = settings::resolve_settings(&config, cli.command.name());
+    if !duckdb_settings.is_empty() {
+        if let Err(problems) = settings::apply_settings(&conn, &duckdb_settings) {
+            println!("FATAL ERROR: invalid DuckDB settings");
+            for problem in problems {
+                println!("  - {}", problem);
+            }
+            println!("Exiting");
+            std::process::exit(EXIT_CONFIG_ERROR);
+        }
+        verbose!("Overridden duckdb settings:\n{:?}", duckdb_settings);
+    }
+
+    match cli.command {
+        Commands::RunFile(arg) => {
+            let paths = arg
+                .script_paths
+                .iter()
+                .map(std::path::Path::new)
+                .collect::<Vec<_>>();
+            let first_statements = match script::first_statements(&paths, arg.resume_from.as_ref())
+            {
+                Ok(first_statements) => first_statements,
+                Err(e) => {
+                    println!("Error: {}", e);
+                    std::process::exit(e.exit_status());
+                }
+            };
+            for (path, first_statement) in paths.into_iter().zip(first_statements) {
+                print!("Running {}... ", path.display());
+                std::io::stdout().flush().unwrap();
+
+                match script::run_script(&conn, path, first_statement) {
+                    Ok(_) => println!("OK"),
+                    Err(e) => println!("ERROR: {}\nSkipping {}", e, path.display()),
+                }
+            }
+        }
+        Commands::Run(args) => {
+            run_session_with_args(args, None, conn, config);
+        }
+        Commands::Job(args) => {
+            let job = job.unwrap_or_default();
+            let run_args = RunArgs {
+                selection: SelectionArgs {
+                    select: job.select,
+                    exclude: job.exclude,
+                    exclude_exclusive_descendants: false,
+                    state: None,
+                },
+                threads: None,
+                log_fd: None,
+                log_format: LogFormat::Text,
+                ephemeral: false,
+                ephemeral_build: false,
+                dry_run: args.dry_run,
+                full_refresh: job.full_refresh,
+                fail_fast: job.fail_fast,
+                progress: false,
+                tui: false,
+                watch: false,
+                worker: vec![],
+            };
+            run_session_with_args(run_args, None, conn, config);
+        }
+        Commands::Bootstrap => {
+            let mut session = Session::new(config, conn);
+            if let Err(e) = session.bootstrap() {
+                println!("Error: {}", e);
+                std::process::exit(e.exit_status());
+            }
+        }
+        Commands::Retry(args) => {
+            let results = match RunResults::load(&config.target_dir()) {
+                Ok(Some(results)) => results,
+                Ok(None) => {
+                    println!("Error: no previous run to retry");
+                    std::process::exit(EXIT_ERROR);
+                }
+                Err(e) => {
+                    println!("Error: {}", e);
+                    std::process::exit(e.exit_status());
+                }
+            };
+            if results.failed.is_empty() && results.skipped.is_empty() {
+                println!(
+                    "The last run (started at {}) had no errors, nothing to retry",
+                    results.run_started_at
+                );
+                return Ok(());
+            }
+            let run_args = RunArgs {
+                selection: SelectionArgs {
+                    select: vec![],
+                    exclude: vec![],
+                    exclude_exclusive_descendants: false,
+                    state: None,
+                },
+                threads: args.threads,
+                log_fd: args.log_fd,
+                log_format: args.log_format,
+                ephemeral: false,
+                ephemeral_build: false,
+                dry_run: args.dry_run,
+                full_refresh: false,
+                fail_fast: args.fail_fast,
+                progress: args.progress,
+                tui: false,
+                watch: false,
+                worker: args.worker,
+            };
+            run_session_with_args(run_args, Some(results), conn, config);
+        }
+        Commands::Test(args) => {
+            let mut session = Session::new(config, conn);
+            let selection: Selection = args.selection.into();
+            if args.ephemeral_build {
+                let options = RunOptions {
+                    selection: selection.clone(),
+                    ..Default::default()
+                };
+                match session.run_nodes(&options) {
+                    Ok(summary) if summary.failed() => std::process::exit(EXIT_MODEL_FAILURE),
+                    Ok(_) => {}
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        std::process::exit(e.exit_status());
+                    }
+                }
+            }
+            let result = session.run_tests(&selection).and_then(|summary| {
+                if let Some(path) = &args.junit_xml {
+                    let results = TestResults {
+                        generated_at: time_format::iso_now(),
+                        results: &summary.results,
+                    };
+                    std::fs::write(path, results.to_junit())
+                        .map_err(|e| ArnabError::Error(format!("Cannot write {}: {}", path, e)))?;
+                }
+                Ok(summary)
+            });
+            match result {
+                Ok(summary) if summary.n_failed > 0 => std::process::exit(EXIT_TEST_FAILURE),
+                Ok(_) => {}
+                Err(e) => {
+                    println!("Error: {}", e);
+                    std::process::exit(e.exit_status());
+                }
+            }
+        }
+        Commands::Viz(args) => {
+            if let Err(e) = save_visualization_with_args(args, conn, config) {
+                println!("Error: {}", e);
+                std::process::exit(e.exit_status());
+            }
+        }
+        Commands::Graph(args) => {
+            if let Err(e) = save_visualization_with_args(args.into(), conn, config) {
+                println!("Error: {}", e);
+                std::process::exit(e.exit_status());
+            }
+        }
+        Commands::Pin(args) => {
+            let mut session = Session::new(config, conn);
+            if let Err(e) = session.pin(&args.models) {
+                println!("Error: {}", e);
+                std::process::exit(e.exit_status());
+            }
+        }
+        Commands::Unpin(args) => {
+            let mut session = Session::new(config, conn);
+            if let Err(e) = session.unpin(&args.models, args.all) {
+                println!("Error: {}", e);
+                std::process::exit(e.exit_status());
+            }
+        }
+        Commands::CheckBreaking(args) => {
+            let mut session = Session::new(config, conn);
+            if let Err(e) = session.check_breaking(std::path::Path::new(&args.state)) {
+                println!("Error: {}", e);
+                std::process::exit(e.exit_status());
+            }
+        }
+        Commands::VerifyProvenance(args) => {
+            let path = config.target_dir().join(provenance::PROVENANCE_FILE);
+            match provenance::verify_chain(&path, args.head.as_deref()) {
+                Ok((n_records, head)) => {
+                    println!("{} provenance records verified", n_records);
+                    if let Some(head) = head {
+                        println!("Head: {}", head);
+                    }
+                }
+                Err(e) => {
+                    println!("Error: {}", e);
+                    std::process::exit(e.exit_status());
+                }
+            }
+        }
+        Commands::Advise(args) => {
+            let mut session = Session::new(config, conn);
+            if let Err(e) = session.advise(args.min_models) {
+                println!("Error: {}", e);
+                std::process::exit(e.exit_status());
+            }
+        }
+        Commands::Ls(args) if args.resource == ListResource::Macro => {
+            let session = Session::new(config, conn);
+            match session.macro_docs() {
+                Ok(docs) if args.output == ListFormat::Json => {
+                    println!("{}", serde_json::to_string_pretty(&docs).unwrap())
+                }
+                Ok(docs) => println!("{}", listing::format_macro_table(&docs)),
+                Err(e) => {
+                    println!("Error: {}", e);
+                    std::process::exit(e.exit_status());
+                }
+            }
+        }
+        Commands::Ls(args) => {
+            let mut session = Session::new(config, conn);
+            let result = session.build_graph().and_then(|graph| {
+                let ids = session.select(&graph, &args.selection.into())?;
+                Ok(listing::model_entries(&graph, &ids))
+            });
+            match result {
+                Ok(entries) if args.output == ListFormat::Json => {
+                    println!("{}", serde_json::to_string_pretty(&entries).unwrap())
+                }
+                Ok(entries) => println!("{}", listing::format_table(&entries)),
+                Err(e) => {
+                    println!("Error: {}", e);
+                    std::process::exit(e.exit_status());
+                }
+            }
+        }
+        Commands::Show(args) => {
+            let mut session = Session::new(config, conn);
+            if let Err(e) = session.show(&args.model, args.limit, args.no_cache) {
+                println!("Error: {}", e);
+                std::process::exit(e.exit_status());
+            }
+        }
+        Commands::Compile(args) => {
+            let mut session = Session::new(config, conn);
+            let options = CompileOptions {
+                selection: args.selection.into(),
+                check_select_star: args.check_select_star,
+                expand_select_star: args.expand_select_star,
+            };
+            if let Err(e) = session.compile(&options) {
+                println!("Error: {}", e);
+                std::process::exit(e.exit_status());
+            }
+        }
+        Commands::Codegen(args) => {
+            if let Err(e) = codegen_with_args(args, conn, config) {
+                println!("Error: {}", e);
+                std::process::exit(e.exit_status());
+            }
+        }
+        Commands::Docs(args) => match args.command {
+            DocsCommands::Generate { selection } => {
+                let mut session = Session::new(config, conn);
+                if let Err(e) = session.generate_docs(&selection.into()) {
+                    println!("Error: {}", e);
+                    std::process::exit(e.exit_status());
+                }
+            }
+            DocsCommands::Serve { listen } => {
+                let docs_dir = config.target_dir().join(docs::DOCS_DIR);
+                if !docs_dir.join("index.html").exists() {
+                    println!("Error: No docs found, run `arnab docs generate` first");
+                    std::process::exit(EXIT_ERROR);
+                }
+                let listener = match std::net::TcpListener::bind(&listen) {
+                    Ok(listener) => listener,
+                    Err(e) => {
+                        println!("Error: Cannot listen on {}: {}", listen, e);
+                        std::process::exit(EXIT_ERROR);
+                    }
+                };
+                println!("Serving docs at http://{}", listen);
+                if let Err(e) = docs::serve(listener, &docs_dir) {
+                    println!("Error: {}", e);
+                    std::process::exit(e.exit_status());
+                }
+            }
+        },
+        Commands::Seed(args) => {
+            if args.generate {
+                let seed_path = config.seed_path.as_deref().unwrap_or(DEFAULT_SEED_PATH);
+                if let Err(e) = seed::generate_seeds(&conn, seed_path) {
+                    println!("Error: {}", e);
+                    std::process::exit(e.exit_status());
+                }
+            }
+            let mut session = Session::new(config, conn);
+            let options = RunOptions {
+                seeds_only: true,
+                ..Default::default()
+            };
+            match session.run_nodes(&options) {
+                Ok(summary) if summary.failed() => std::process::exit(EXIT_MODEL_FAILURE),
+                Ok(_) => {}
+                Err(e) => {
+                    println!("Error: {}", e);
+                    std::process::exit(e.exit_status());
+                }
+            }
+        }
+        Commands::ExportDb(args) => {
+            if config.artifacts_db_path.is_some() {
+                println!("WARNING: only the main database is exported, not the artifacts database");
+            }
+            let output_dir = std::path::Path::new(&args.output_dir);
+            match maintenance::export_database(&conn, output_dir, args.format) {
+                Ok(_) => println!("Exported database to {}", args.output_dir),
+                Err(e) => {
+                    println!("Error: {}", e);
+                    std::process::exit(e.exit_status());
+                }
+            }
+        }
+        Commands::ImportDb(args) => {
+            let input_dir = std::path::Path::new(&args.input_dir);
+            match maintenance::import_database(&conn, input_dir) {
+                Ok(_) => println!("Imported database from {}", args.input_dir),
+                Err(e) => {
+                    println!("Error: {}", e);
+                    std::process::exit(e.exit_status());
+                }
+            }
+        }
+        Commands::Maintain => {
+            if let Err(e) = maintenance::run_maintenance(&conn, &MaintenanceInfo::all()) {
+                println!("Error: {}", e);
+                std::process::exit(e.exit_status());
+            }
+        }
+        Commands::Schedule => {
+            let schedules = config.schedules.clone().unwrap_or_default();
+            if schedules.is_empty() {
+                println!("Error: no `schedules` in the config");
+                std::process::exit(EXIT_CONFIG_ERROR);
+            }
+            if config.db_path.is_none() && !config.ephemeral.unwrap_or(false) {
+                println!("Error: db_path is not set, so scheduled runs would be built in an in-memory database and discarded at exit.");
+                std::process::exit(EXIT_CONFIG_ERROR);
+            }
+            let mut session = Session::new(config, conn);
+            if let Err(e) = schedule::run_schedules(&mut session, &schedules) {
+                println!("Error: {}", e);
+                std::process::exit(e.exit_status());
+            }
+        }
+        Commands::Worker(args) => {
+            let Some(token) = config.worker_token.clone() else {
+                println!(
+                    "Error: a worker requires a non-empty shared token: set `worker_token` in the config or {}.",
+                    remote::WORKER_TOKEN_ENV
+                );
+                std::process::exit(EXIT_CONFIG_ERROR);
+            };
+            let listener = match std::net::TcpListener::bind(&args.listen) {
+                Ok(listener) => listener,
+                Err(e) => {
+                    println!("Error: Cannot listen on {}: {}", args.listen, e);
+                    std::process::exit(EXIT_ERROR);
+                }
+            };
+            println!("Worker listening on {}", args.listen);
+            if let Err(e) = remote::serve(listener, &conn, &token) {
+                println!("Error: {}", e);
+                std::process::exit(e.exit_status());
+            }
+        }
+        Commands::Serve(args) => {
+            let listener = match std::net::TcpListener::bind((args.host.as_str(), args.port)) {
+                Ok(listener) => listener,
+                Err(e) => {
+                    println!("Error: Cannot listen on {}:{}: {}", args.host, args.port, e);
+                    std::process::exit(EXIT_ERROR);
+                }
+            };
+            println!("Serving on http://{}:{}", args.host, args.port);
+            if let Err(e) = server::serve(listener, config, conn) {
+                println!("Error: {}", e);
+                std::process::exit(e.exit_status());
+            }
+        }
+    }
+
+    Ok(())
+}
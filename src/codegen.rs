@@ -0,0 +1,187 @@
+use std::{collections::HashMap, path::Path};
+
+use duckdb::Connection;
+
+use crate::{
+    errors::ArnabError,
+    node::Node,
+    schema::{ColumnSchema, ModelSchema},
+    seed::quote_identifier,
+    source::SourceInfo,
+};
+
+/// Get column names and types of a relation
+pub fn describe_relation(
+    conn: &Connection,
+    relation: &str,
+) -> Result<Vec<(String, String)>, ArnabError> {
+    let sql = format!("DESCRIBE SELECT * FROM {}", relation);
+    let to_error =
+        |e: duckdb::Error| ArnabError::Error(format!("Cannot describe {}: {}", relation, e));
+    let mut stmt = conn.prepare(&sql).map_err(to_error)?;
+    let columns = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .map_err(to_error)?
+        .collect::<Result<Vec<(String, String)>, _>>()
+        .map_err(to_error)?;
+    Ok(columns)
+}
+
+/// Convert an identifier such as `OrderDate`, `order-date` or `Order Date` to
+/// `order_date`
+pub fn to_snake_case(name: &str) -> String {
+    let mut out = String::new();
+    let chars = name.chars().collect::<Vec<_>>();
+    for (i, c) in chars.iter().enumerate() {
+        if c.is_alphanumeric() {
+            let prev_lower = i > 0 && (chars[i - 1].is_lowercase() || chars[i - 1].is_numeric());
+            if c.is_uppercase() && prev_lower && !out.ends_with('_') {
+                out.push('_');
+            }
+            out.extend(c.to_lowercase());
+        } else if !out.is_empty() && !out.ends_with('_') {
+            out.push('_');
+        }
+    }
+    out.trim_end_matches('_').to_string()
+}
+
+/// Select list of a staging model, renaming columns to snake case and casting
+/// them to their types. Names are quoted, as renamed ones may be keywords,
+/// e.g., `order`.
+fn staging_select_list(columns: &[(String, String)]) -> String {
+    columns
+        .iter()
+        .map(|(name, data_type)| {
+            format!(
+                "    CAST({} AS {}) AS {}",
+                quote_identifier(name),
+                data_type,
+                quote_identifier(&to_snake_case(name))
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",\n")
+}
+
+/// Write `content` to `path`, refusing to overwrite an existing file unless forced
+fn write_new_file(path: &Path, content: &str, force: bool) -> Result<(), ArnabError> {
+    if path.exists() && !force {
+        return Err(ArnabError::Error(format!(
+            "{} already exists, use --force to overwrite",
+            path.display()
+        )));
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| ArnabError::Error(e.to_string()))?;
+    }
+    std::fs::write(path, content).map_err(|e| ArnabError::Error(e.to_string()))?;
+    println!("Written {}", path.display());
+    Ok(())
+}
+
+/// Generate a staging model for source table `source_name.table_name` along
+/// with its schema YAML stub. Columns are renamed to snake case and explicitly
+/// cast to their introspected types.
+pub fn generate_staging_model(
+    conn: &Connection,
+    sources: &HashMap<String, SourceInfo>,
+    source_name: &str,
+    table_name: &str,
+    output_dir: &Path,
+    force: bool,
+) -> Result<(), ArnabError> {
+    let source = sources
+        .get(source_name)
+        .ok_or_else(|| ArnabError::Error(format!("Source `{}` is not declared", source_name)))?;
+    let table = source.table(table_name).ok_or_else(|| {
+        ArnabError::Error(format!(
+            "Table `{}` is not declared in source `{}`",
+            table_name, source_name
+        ))
+    })?;
+    let relation = source.relation(table);
+    let columns = describe_relation(conn, &relation)?;
+
+    let model_sql = format!(
+        "SELECT\n{}\nFROM {}\n",
+        staging_select_list(&columns),
+        relation
+    );
+
+    let schema = ModelSchema {
+        description: Some("".into()),
+        columns: columns
+            .iter()
+            .map(|(name, _)| ColumnSchema {
+                name: to_snake_case(name),
+                pii: None,
+                description: Some("".into()),
+                data_type: None,
+                tests: vec![],
+                sample: None,
+            })
+            .collect(),
+    };
+    let schema_yaml =
+        serde_yaml::to_string(&schema).map_err(|e| ArnabError::Error(e.to_string()))?;
+
+    let model_id = format!("stg_{}__{}", source_name, to_snake_case(table_name));
+    write_new_file(
+        &output_dir.join(format!("{}.sql", model_id)),
+        &model_sql,
+        force,
+    )?;
+    write_new_file(
+        &output_dir.join(format!("{}.yml", model_id)),
+        &schema_yaml,
+        force,
+    )?;
+    Ok(())
+}
+
+/// Write or update the schema YAML files of built models with their current
+/// column lists. Existing column declarations are preserved.
+pub fn generate_model_schemas(conn: &Connection, nodes: &[&Node]) -> Result<(), ArnabError> {
+    for node in nodes {
+        let column_names = describe_relation(conn, &node.id)?
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+        let model_path = Path::new(&node.path);
+        let mut schema = ModelSchema::load_for(model_path)?.unwrap_or_default();
+        let dropped = schema.sync_columns(&column_names);
+        let schema_path = schema.save_for(model_path)?;
+        println!("Written {}", schema_path.display());
+        for column in dropped {
+            println!("  removed column `{}` no longer in {}", column, node.id);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{staging_select_list, to_snake_case};
+
+    #[test]
+    fn snake_case_identifiers() {
+        assert_eq!(to_snake_case("OrderDate"), "order_date");
+        assert_eq!(to_snake_case("order-date"), "order_date");
+        assert_eq!(to_snake_case("Order Date"), "order_date");
+        assert_eq!(to_snake_case("customerID2"), "customer_id2");
+        assert_eq!(to_snake_case("already_snake"), "already_snake");
+    }
+
+    #[test]
+    fn quote_staging_columns() {
+        let columns = vec![
+            ("Order".to_string(), "INTEGER".to_string()),
+            ("売上 Total".to_string(), "DOUBLE".to_string()),
+        ];
+        assert_eq!(
+            staging_select_list(&columns),
+            "    CAST(\"Order\" AS INTEGER) AS \"order\",\n    CAST(\"売上 Total\" AS DOUBLE) AS \"売上_total\""
+        );
+    }
+}
@@ -0,0 +1,213 @@
+//! Detection of `SELECT *` over upstream models without a schema contract,
+//! i.e., without columns declared in their schema file. Columns added to or
+//! removed from such a model silently change the models selecting `*` from it.
+
+use std::collections::HashMap;
+
+use sqlparser::{
+    ast::{
+        Expr, Ident, Select, SelectItem, SetExpr, Statement, TableFactor, WildcardAdditionalOptions,
+    },
+    dialect::DuckDbDialect,
+    parser::Parser,
+};
+
+/// A table of a FROM clause, by (possibly qualified) name and alias
+struct FromTable {
+    name: String,
+    alias: Option<String>,
+}
+
+impl FromTable {
+    /// Name to qualify its columns with
+    fn qualifier(&self) -> &str {
+        self.alias
+            .as_deref()
+            .unwrap_or_else(|| self.name.rsplit('.').next().unwrap())
+    }
+
+    /// Whether `<qualifier>.*` picks the table's columns, e.g., `orders.*` or
+    /// `analytics.orders.*` for `analytics.orders`
+    fn is_qualified_by(&self, qualifier: &str) -> bool {
+        match &self.alias {
+            Some(alias) => same_relation(alias, qualifier) && !qualifier.contains('.'),
+            None => same_relation(&self.name, qualifier),
+        }
+    }
+}
+
+/// Parts of a possibly qualified and quoted name, lowercased as DuckDB
+/// identifiers are case-insensitive
+fn name_parts(name: &str) -> Vec<String> {
+    name.split('.')
+        .map(|part| part.trim_matches('"').to_lowercase())
+        .collect()
+}
+
+/// Whether two possibly qualified names can refer to the same relation, the
+/// parts of the shorter one ending the longer one, e.g., `orders` and
+/// `analytics.orders`
+pub fn same_relation(a: &str, b: &str) -> bool {
+    let (a, b) = (name_parts(a), name_parts(b));
+    let n = a.len().min(b.len());
+    a[a.len() - n..] == b[b.len() - n..]
+}
+
+/// Parse the final SELECT of a query, with the tables of its FROM clause.
+/// None when the SQL can't be parsed or selects from anything but tables.
+fn parse_select(sql: &str) -> Option<(Statement, Vec<FromTable>)> {
+    let statement = Parser::parse_sql(&DuckDbDialect {}, sql).ok()?.pop()?;
+    let select = final_select(&statement)?;
+    let mut tables = vec![];
+    for table_with_joins in &select.from {
+        let joined = table_with_joins.joins.iter().map(|j| &j.relation);
+        for relation in std::iter::once(&table_with_joins.relation).chain(joined) {
+            let TableFactor::Table { name, alias, .. } = relation else {
+                return None;
+            };
+            tables.push(FromTable {
+                name: name.to_string(),
+                alias: alias.as_ref().map(|a| a.name.value.clone()),
+            });
+        }
+    }
+    Some((statement, tables))
+}
+
+fn final_select(statement: &Statement) -> Option<&Select> {
+    match statement {
+        Statement::Query(query) => match &*query.body {
+            SetExpr::Select(select) => Some(select),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Tables whose columns the final SELECT of `sql` picks with `*`: all tables
+/// of its FROM clause for `*`, the matching one for `t.*`. Nothing is
+/// reported for SQL that can't be parsed.
+pub fn star_tables(sql: &str) -> Vec<String> {
+    let Some((statement, tables)) = parse_select(sql) else {
+        return vec![];
+    };
+    let mut starred = vec![];
+    for item in &final_select(&statement).unwrap().projection {
+        let matching = match item {
+            SelectItem::Wildcard(_) => tables.iter().collect(),
+            SelectItem::QualifiedWildcard(qualifier, _) => tables
+                .iter()
+                .filter(|t| t.is_qualified_by(&qualifier.to_string()))
+                .collect(),
+            _ => vec![],
+        };
+        for table in matching {
+            if !starred.contains(&table.name) {
+                starred.push(table.name.clone());
+            }
+        }
+    }
+    starred
+}
+
+/// Replace the `*` of the final SELECT of `sql` with the explicit columns of
+/// the tables, as given by `columns`. None when the SQL can't be parsed, a
+/// table's columns are unknown, or a `*` has modifiers such as `EXCLUDE`.
+pub fn expand_select_star(sql: &str, columns: &HashMap<String, Vec<String>>) -> Option<String> {
+    let (mut statement, tables) = parse_select(sql)?;
+    let Statement::Query(query) = &mut statement else {
+        return None;
+    };
+    let SetExpr::Select(select) = &mut *query.body else {
+        return None;
+    };
+
+    let column_items = |table: &FromTable, qualify: bool| -> Option<Vec<SelectItem>> {
+        let items = columns.get(&table.name)?.iter().map(|column| {
+            let column = Ident::with_quote('"', column);
+            SelectItem::UnnamedExpr(match qualify {
+                true => Expr::CompoundIdentifier(vec![Ident::new(table.qualifier()), column]),
+                false => Expr::Identifier(column),
+            })
+        });
+        Some(items.collect())
+    };
+    let mut projection = vec![];
+    for item in select.projection.drain(..) {
+        match &item {
+            SelectItem::Wildcard(options) | SelectItem::QualifiedWildcard(_, options)
+                if *options != WildcardAdditionalOptions::default() =>
+            {
+                return None
+            }
+            SelectItem::Wildcard(_) => {
+                for table in &tables {
+                    projection.extend(column_items(table, tables.len() > 1)?);
+                }
+            }
+            SelectItem::QualifiedWildcard(qualifier, _) => {
+                let qualifier = qualifier.to_string();
+                let table = tables.iter().find(|t| t.is_qualified_by(&qualifier))?;
+                projection.extend(column_items(table, true)?);
+            }
+            _ => projection.push(item),
+        }
+    }
+    select.projection = projection;
+    Some(statement.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{expand_select_star, same_relation, star_tables};
+
+    #[test]
+    fn detect_and_expand_select_star() {
+        let sql = "SELECT o.*, u.name FROM orders AS o JOIN users AS u ON o.user_id = u.id";
+        assert_eq!(star_tables(sql), vec!["orders"]);
+        assert_eq!(
+            star_tables("SELECT * FROM orders, users"),
+            vec!["orders", "users"]
+        );
+        assert!(star_tables("SELECT id FROM orders").is_empty());
+
+        let columns = HashMap::from([("orders".to_string(), vec!["id".into(), "amount".into()])]);
+        assert_eq!(
+            expand_select_star(sql, &columns).unwrap(),
+            "SELECT o.\"id\", o.\"amount\", u.name FROM orders AS o JOIN users AS u ON o.user_id = u.id"
+        );
+        assert_eq!(
+            expand_select_star("SELECT * FROM orders", &columns).unwrap(),
+            "SELECT \"id\", \"amount\" FROM orders"
+        );
+        assert!(expand_select_star("SELECT * FROM users", &columns).is_none());
+        assert!(expand_select_star("SELECT * EXCLUDE (id) FROM orders", &columns).is_none());
+    }
+
+    #[test]
+    fn match_qualified_wildcards() {
+        let sql = "SELECT analytics.orders.* FROM analytics.orders JOIN staging.orders ON true";
+        assert_eq!(star_tables(sql), vec!["analytics.orders"]);
+        assert_eq!(
+            star_tables("SELECT orders.* FROM analytics.orders"),
+            vec!["analytics.orders"]
+        );
+        assert_eq!(
+            star_tables("SELECT main.orders.* FROM orders"),
+            vec!["orders"]
+        );
+        assert!(star_tables("SELECT orders.* FROM orders AS o").is_empty());
+
+        let columns = HashMap::from([("analytics.orders".to_string(), vec!["id".into()])]);
+        assert_eq!(
+            expand_select_star("SELECT analytics.orders.* FROM analytics.orders", &columns)
+                .unwrap(),
+            "SELECT orders.\"id\" FROM analytics.orders"
+        );
+
+        assert!(same_relation("orders", "artifacts.analytics.Orders"));
+        assert!(!same_relation("staging.orders", "analytics.orders"));
+    }
+}
@@ -0,0 +1,354 @@
+//! Live dashboard of `arnab run --tui`: overall progress, the nodes being
+//! built, the durations of finished ones and a scrollable pane of errors and
+//! warnings. It is fed with run events and redrawn in place on the terminal,
+//! so its last frame stays on screen once the run is done.
+
+use std::{
+    io::{Read, Write},
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    errors::EXIT_INTERRUPTED,
+    events::{Event, EventListener, NodeStatus},
+    text_width::truncate_to_width,
+    time_format::format_elapsed,
+};
+
+/// Lines of the finished nodes and problems panes
+const N_FINISHED_LINES: usize = 8;
+const N_PROBLEM_LINES: usize = 6;
+
+/// Byte of Ctrl-C once the terminal doesn't turn it into a signal
+const CTRL_C: u8 = 0x03;
+
+struct FinishedNode {
+    id: String,
+    status: NodeStatus,
+    duration: Duration,
+    n_rows: Option<usize>,
+}
+
+#[derive(Default)]
+struct DashboardState {
+    n_nodes: usize,
+    /// Nodes being built, with their materialization and start
+    running: Vec<(String, String, Instant)>,
+    /// Finished nodes, the latest last
+    finished: Vec<FinishedNode>,
+    /// Errors and warnings, as one line each
+    problems: Vec<String>,
+    /// First problem shown in the pane
+    scroll: usize,
+    /// Final line once the run is over
+    outcome: Option<String>,
+    width: usize,
+    /// Number of lines of the last frame, to draw the next one in its place
+    n_drawn: usize,
+    closed: bool,
+}
+
+impl DashboardState {
+    fn apply(&mut self, event: &Event) {
+        match event {
+            Event::RunStarted { n_nodes } => self.n_nodes += n_nodes,
+            Event::NodeStarted {
+                node_id,
+                materialize,
+            } => self
+                .running
+                .push((node_id.clone(), materialize.clone(), Instant::now())),
+            Event::NodeFinished {
+                node_id,
+                status,
+                duration_ms,
+                n_rows,
+                error,
+                ..
+            } => {
+                self.running.retain(|(id, _, _)| id != node_id);
+                self.finished.push(FinishedNode {
+                    id: node_id.clone(),
+                    status: *status,
+                    duration: Duration::from_millis(*duration_ms as u64),
+                    n_rows: *n_rows,
+                });
+                if let Some(error) = error {
+                    self.problems.push(format!("ERROR {}: {}", node_id, error));
+                }
+            }
+            Event::Warning { message } => self.problems.push(format!("WARNING {}", message)),
+            Event::RunFinished {
+                n_success,
+                n_errors,
+                duration_ms,
+                ..
+            } => {
+                self.outcome = Some(format!(
+                    "Completed in {} with {} success and {} errors",
+                    format_elapsed(Duration::from_millis(*duration_ms as u64)),
+                    n_success,
+                    n_errors
+                ))
+            }
+            Event::RunFailed { error, .. } => {
+                self.outcome = Some(format!("Failed: {}", error));
+            }
+        }
+    }
+
+    fn scroll_by(&mut self, delta: isize) {
+        let max_scroll = self.problems.len().saturating_sub(N_PROBLEM_LINES);
+        self.scroll = self.scroll.saturating_add_signed(delta).min(max_scroll);
+    }
+
+    /// Lines of the frame, each cut to the terminal width
+    fn render(&self, now: Instant) -> Vec<String> {
+        let n_done = self.finished.len();
+        let n_errors = self
+            .finished
+            .iter()
+            .filter(|n| n.status == NodeStatus::Error)
+            .count();
+        let bar_width = 30;
+        let n_filled = bar_width * n_done / self.n_nodes.max(1);
+        let mut lines = vec![format!(
+            "[{:<bar_width$}] {}/{} nodes, {} running, {} errors",
+            "=".repeat(n_filled),
+            n_done,
+            self.n_nodes,
+            self.running.len(),
+            n_errors,
+        )];
+
+        lines.push("Running:".into());
+        for (id, materialize, started) in &self.running {
+            lines.push(format!(
+                "  {} ({}) {}",
+                id,
+                materialize,
+                format_elapsed(now - *started)
+            ));
+        }
+
+        lines.push("Finished:".into());
+        let skip = n_done.saturating_sub(N_FINISHED_LINES);
+        for node in self.finished.iter().skip(skip) {
+            lines.push(format!(
+                "  {} {} in {}{}",
+                node.id,
+                match node.status {
+                    NodeStatus::Success => "SUCCESS",
+                    NodeStatus::Error => "ERROR",
+                },
+                format_elapsed(node.duration),
+                match node.n_rows {
+                    Some(n_rows) => format!(", {} rows", n_rows),
+                    None => String::new(),
+                }
+            ));
+        }
+
+        lines.push(format!(
+            "Problems ({}, j/k to scroll):",
+            self.problems.len()
+        ));
+        for problem in self.problems.iter().skip(self.scroll).take(N_PROBLEM_LINES) {
+            // A multi-line error shows its first line only
+            lines.push(format!("  {}", problem.lines().next().unwrap_or("")));
+        }
+        lines.extend(self.outcome.clone());
+
+        lines
+            .into_iter()
+            .map(|line| truncate_to_width(&line, self.width))
+            .collect()
+    }
+
+    /// Draw the frame in place of the previous one
+    fn draw(&mut self) {
+        let lines = self.render(Instant::now());
+        let mut out = String::new();
+        if self.n_drawn > 0 {
+            // Back to the first line of the previous frame, which is cleared
+            out.push_str(&format!("\x1b[{}F\x1b[J", self.n_drawn));
+        }
+        for line in &lines {
+            out.push_str(line);
+            out.push('\n');
+        }
+        self.n_drawn = lines.len();
+        print!("{}", out);
+        std::io::stdout().flush().unwrap();
+    }
+}
+
+/// Redraws the dashboard on each event it listens to
+struct DashboardListener(Arc<Mutex<DashboardState>>);
+
+impl EventListener for DashboardListener {
+    fn on_event(&mut self, event: &Event) {
+        let mut state = self.0.lock().unwrap();
+        if !state.closed {
+            state.apply(event);
+            state.draw();
+        }
+    }
+}
+
+pub struct Dashboard {
+    state: Arc<Mutex<DashboardState>>,
+    /// Terminal settings to restore, when keys are read one at a time
+    saved_tty: Option<String>,
+}
+
+impl Dashboard {
+    /// Start drawing the dashboard, refreshed a few times per second for
+    /// the durations of running nodes, and reading scroll keys from stdin
+    /// when it's a terminal
+    pub fn start() -> Self {
+        let (_, width) = terminal_size().unwrap_or((24, 80));
+        let state = Arc::new(Mutex::new(DashboardState {
+            width,
+            ..Default::default()
+        }));
+
+        let ticked = state.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(Duration::from_millis(250));
+            let mut state = ticked.lock().unwrap();
+            if state.closed {
+                break;
+            }
+            state.draw();
+        });
+
+        // Keys are read as typed, without echo, only if the terminal
+        // settings can be restored afterwards. Ctrl-C is read as a key too,
+        // for the settings to be restored before exiting.
+        let saved_tty = stty(&["-g"]).filter(|_| stty(&["-icanon", "-echo", "-isig"]).is_some());
+        if let Some(saved) = saved_tty.clone() {
+            let scrolled = state.clone();
+            std::thread::spawn(move || {
+                for byte in std::io::stdin().lock().bytes() {
+                    let delta = match byte {
+                        Ok(b'j') | Ok(b'B') => 1,
+                        Ok(b'k') | Ok(b'A') => -1,
+                        Ok(CTRL_C) => {
+                            stty(&[saved.as_str()]);
+                            println!("Interrupted");
+                            std::process::exit(EXIT_INTERRUPTED);
+                        }
+                        Ok(_) => continue,
+                        Err(_) => break,
+                    };
+                    let mut state = scrolled.lock().unwrap();
+                    if state.closed {
+                        break;
+                    }
+                    state.scroll_by(delta);
+                    state.draw();
+                }
+            });
+        }
+        Self { state, saved_tty }
+    }
+
+    pub fn listener(&self) -> Box<dyn EventListener> {
+        Box::new(DashboardListener(self.state.clone()))
+    }
+
+    /// Draw the last frame and restore the terminal
+    pub fn finish(&self) {
+        let mut state = self.state.lock().unwrap();
+        if !state.closed {
+            state.draw();
+            state.closed = true;
+        }
+        self.restore_tty();
+    }
+
+    fn restore_tty(&self) {
+        if let Some(saved_tty) = &self.saved_tty {
+            stty(&[saved_tty.as_str()]);
+        }
+    }
+}
+
+/// Restores the terminal when a run ends without `finish`, e.g., on a panic
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        self.restore_tty();
+    }
+}
+
+/// Run `stty` on the terminal of stdin, returning its output on success
+fn stty(args: &[&str]) -> Option<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    match output.status.success() {
+        true => Some(String::from_utf8_lossy(&output.stdout).trim().to_string()),
+        false => None,
+    }
+}
+
+/// Rows and columns of the terminal
+fn terminal_size() -> Option<(usize, usize)> {
+    let size = stty(&["size"])?;
+    let (rows, cols) = size.split_once(' ')?;
+    // Pseudo-terminals may not report any size
+    Some((rows.parse().ok()?, cols.parse().ok()?)).filter(|(_, cols)| *cols > 0)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::DashboardState;
+    use crate::events::{Event, NodeStatus};
+
+    #[test]
+    fn render_dashboard() {
+        let mut state = DashboardState {
+            width: 40,
+            ..Default::default()
+        };
+        state.apply(&Event::RunStarted { n_nodes: 3 });
+        for id in ["orders", "users"] {
+            state.apply(&Event::NodeStarted {
+                node_id: id.into(),
+                materialize: "table".into(),
+            });
+        }
+        state.apply(&Event::NodeFinished {
+            node_id: "orders".into(),
+            status: NodeStatus::Error,
+            duration_ms: 1500,
+            n_rows: None,
+            error: Some("Binder Error: column \"nope\" not found\nLINE 1".into()),
+            upstream_source_age_s: None,
+            stats: None,
+        });
+        let now = state.running[0].2 + Duration::from_secs(2);
+        assert_eq!(
+            state.render(now),
+            vec![
+                format!("[{:<30}] 1/3 nod", "=".repeat(10)),
+                "Running:".into(),
+                "  users (table) 2s 0ms".into(),
+                "Finished:".into(),
+                "  orders ERROR in 1s 500ms".into(),
+                "Problems (1, j/k to scroll):".into(),
+                "  ERROR orders: Binder Error: column \"no".into(),
+            ]
+        );
+        state.scroll_by(1);
+        assert_eq!(state.scroll, 0);
+    }
+}
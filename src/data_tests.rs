@@ -0,0 +1,247 @@
+use std::path::Path;
+
+use colored::Colorize;
+use duckdb::Connection;
+
+use crate::{
+    errors::ArnabError,
+    node::{Node, NodeKind, RenderContext},
+    schema::{ColumnTest, ModelSchema},
+    test_results::{TestResult, TestStatus},
+    verbosity::{debug, info},
+};
+
+/// A data test: a query returning the rows violating an assertion
+#[derive(Clone, Debug, PartialEq)]
+pub struct DataTest {
+    pub(crate) name: String,
+    pub(crate) sql: String,
+    /// Model and column tested, for tests declared in schema files
+    pub(crate) model: Option<String>,
+    pub(crate) column: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct TestSummary {
+    pub(crate) n_passed: usize,
+    pub(crate) n_failed: usize,
+    pub(crate) results: Vec<TestResult>,
+}
+
+/// Compile the column tests declared in a model schema
+pub fn schema_tests(model_id: &str, schema: &ModelSchema) -> Vec<DataTest> {
+    let mut tests = vec![];
+    for column in &schema.columns {
+        let col = format!("\"{}\"", column.name);
+        // Sampling only applies to tests comparing rows with each other
+        let sampled = |col: &str| match &column.sample {
+            Some(sample) => format!(" AND {}", sample.condition(col)),
+            None => String::new(),
+        };
+        for test in &column.tests {
+            let (kind, sql) = match test {
+                ColumnTest::NotNull => (
+                    "not_null".to_string(),
+                    format!("SELECT * FROM {} WHERE {} IS NULL", model_id, col),
+                ),
+                ColumnTest::Unique => (
+                    "unique".to_string(),
+                    format!(
+                        "SELECT {col}, COUNT(*) AS n_records FROM {model} WHERE {col} IS NOT NULL{sampled} \
+                         GROUP BY {col} HAVING COUNT(*) > 1",
+                        col = col,
+                        model = model_id,
+                        sampled = sampled(&col)
+                    ),
+                ),
+                ColumnTest::AcceptedValues { values } => {
+                    let values = values
+                        .iter()
+                        .map(|v| format!("'{}'", yaml_to_string(v).replace('\'', "''")))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    (
+                        "accepted_values".to_string(),
+                        format!(
+                            "SELECT * FROM {model} WHERE {col} IS NOT NULL \
+                             AND CAST({col} AS VARCHAR) NOT IN ({values})",
+                            model = model_id,
+                            col = col,
+                            values = values
+                        ),
+                    )
+                }
+                ColumnTest::Relationships { to, field } => (
+                    format!("relationships_{}_{}", to, field),
+                    format!(
+                        "SELECT * FROM {model} AS child WHERE child.{col} IS NOT NULL{sampled} \
+                         AND NOT EXISTS (SELECT 1 FROM {to} AS parent WHERE parent.\"{field}\" = child.{col})",
+                        model = model_id,
+                        col = col,
+                        to = to,
+                        field = field,
+                        sampled = sampled(&format!("child.{}", col))
+                    ),
+                ),
+            };
+            let kind = match (&column.sample, test) {
+                (Some(sample), ColumnTest::Unique | ColumnTest::Relationships { .. }) => {
+                    format!("{}[sample {}%]", kind, sample.percent)
+                }
+                _ => kind,
+            };
+            tests.push(DataTest {
+                name: format!("{}.{}.{}", model_id, column.name, kind),
+                sql,
+                model: Some(model_id.to_string()),
+                column: Some(column.name.clone()),
+            });
+        }
+    }
+    tests
+}
+
+/// Load singular tests: SQL files in `tests_path` whose query returns the
+/// violating rows. They are rendered like models, so they may use macros,
+/// `source()` and refer to models, which are recorded as their `prevs`.
+pub fn singular_tests(
+    tests_path: &str,
+    context: &RenderContext,
+    model_names: &[String],
+) -> Result<Vec<Node>, ArnabError> {
+    let pattern = Path::new(tests_path).join("**/*.sql");
+    let mut paths = glob::glob(pattern.to_str().unwrap())
+        .unwrap()
+        .map(|p| p.unwrap())
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    let mut tests = vec![];
+    for path in paths {
+        let path_string = path.to_string_lossy().to_string();
+        let raw_src =
+            std::fs::read_to_string(&path).map_err(|e| ArnabError::Error(e.to_string()))?;
+        let id = path.file_stem().unwrap().to_string_lossy().to_string();
+        let mut node = Node::new(NodeKind::Sql, &path_string, &id, &raw_src);
+        node.fqn = format!("singular.{}", id);
+        node.render_and_populate_refs(context, model_names)?;
+        tests.push(node);
+    }
+    Ok(tests)
+}
+
+fn yaml_to_string(value: &serde_yaml::Value) -> String {
+    match value {
+        serde_yaml::Value::String(s) => s.clone(),
+        other => serde_yaml::to_string(other)
+            .unwrap_or_default()
+            .trim()
+            .to_string(),
+    }
+}
+
+/// Run data tests, printing the result of each. A test fails when its query
+/// returns rows, or when the query itself fails.
+pub fn run_tests(conn: &Connection, tests: &[DataTest]) -> TestSummary {
+    let mut summary = TestSummary::default();
+    for (nth, test) in (1..).zip(tests) {
+        let count_sql = format!("SELECT COUNT(*) FROM ({})", test.sql);
+        debug!("-- {}\n{}", test.name, count_sql);
+        let started = std::time::Instant::now();
+        let result = conn.query_row(&count_sql, [], |r| r.get::<_, usize>(0));
+        summary.results.push(TestResult {
+            name: test.name.clone(),
+            model: test.model.clone(),
+            column: test.column.clone(),
+            status: match &result {
+                Ok(0) => TestStatus::Pass,
+                Ok(_) => TestStatus::Fail,
+                Err(_) => TestStatus::Error,
+            },
+            n_failures: *result.as_ref().unwrap_or(&0),
+            duration_ms: started.elapsed().as_millis(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        });
+        let status = match result {
+            Ok(0) => {
+                summary.n_passed += 1;
+                info!(
+                    "{} of {}: {} [{}]",
+                    nth,
+                    tests.len(),
+                    test.name,
+                    "PASS".green()
+                );
+                continue;
+            }
+            Ok(n_failures) => {
+                summary.n_failed += 1;
+                format!(
+                    "{} {} failing row{}",
+                    "FAIL".red(),
+                    n_failures,
+                    if n_failures > 1 { "s" } else { "" }
+                )
+            }
+            Err(e) => {
+                summary.n_failed += 1;
+                format!("{} {}", "ERROR".red(), e)
+            }
+        };
+        // Failures are printed even with `--quiet`
+        println!("{} of {}: {} [{}]", nth, tests.len(), test.name, status);
+    }
+    summary
+}
+
+#[cfg(test)]
+mod test {
+    use crate::schema::ModelSchema;
+
+    use super::schema_tests;
+
+    #[test]
+    fn compile_schema_tests() {
+        let schema: ModelSchema = serde_yaml::from_str(
+            "columns:
+  - name: id
+    tests: [unique]
+  - name: status
+    tests:
+      - accepted_values: { values: [open, 1] }",
+        )
+        .unwrap();
+        let tests = schema_tests("orders", &schema);
+        assert_eq!(tests.len(), 2);
+        assert_eq!(tests[0].name, "orders.id.unique");
+        assert_eq!(tests[1].name, "orders.status.accepted_values");
+        assert_eq!(
+            tests[1].sql,
+            "SELECT * FROM orders WHERE \"status\" IS NOT NULL \
+             AND CAST(\"status\" AS VARCHAR) NOT IN ('open', '1')"
+        );
+    }
+
+    #[test]
+    fn compile_sampled_tests() {
+        let schema: ModelSchema = serde_yaml::from_str(
+            "columns:
+  - name: id
+    tests: [not_null, unique]
+    sample: 0.5%",
+        )
+        .unwrap();
+        let tests = schema_tests("orders", &schema);
+        assert_eq!(tests[0].sql, "SELECT * FROM orders WHERE \"id\" IS NULL");
+        assert_eq!(tests[1].name, "orders.id.unique[sample 0.5%]");
+        assert_eq!(
+            tests[1].sql,
+            "SELECT \"id\", COUNT(*) AS n_records FROM orders WHERE \"id\" IS NOT NULL \
+             AND hash(\"id\") % 1000000 < 5000 GROUP BY \"id\" HAVING COUNT(*) > 1"
+        );
+        assert!(
+            serde_yaml::from_str::<ModelSchema>("columns:\n  - { name: id, sample: 150% }")
+                .is_err()
+        );
+    }
+}
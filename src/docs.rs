@@ -0,0 +1,372 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    catalog::Catalog,
+    errors::ArnabError,
+    graph::Graph,
+    graphviz::render_dot,
+    macros::MacroDoc,
+    node::{Node, NodeKind},
+    schema::ColumnTest,
+};
+
+/// Directory of the target directory where `arnab docs generate` writes the site
+pub const DOCS_DIR: &str = "docs";
+
+const STYLE: &str =
+    "body { font-family: sans-serif; margin: 2em auto; max-width: 960px; color: #222; }
+table { border-collapse: collapse; width: 100%; margin-bottom: 1.5em; }
+th, td { border-bottom: 1px solid #ddd; padding: 4px 8px; text-align: left; vertical-align: top; }
+pre { background: #f6f8fa; padding: 1em; overflow-x: auto; }
+.graph { overflow-x: auto; }
+.graph text { cursor: pointer; }
+#search { width: 100%; padding: 6px; margin-bottom: 1em; box-sizing: border-box; }";
+
+/// Filters the model table and dims non-matching graph nodes as the search
+/// box is typed in, and opens a model's page when its graph node is clicked
+const INDEX_SCRIPT: &str = "const search = document.getElementById('search');
+const ids = [...document.querySelectorAll('#models tr[data-id]')].map(r => r.dataset.id);
+const labels = [...document.querySelectorAll('.graph text')];
+search.addEventListener('input', () => {
+  const q = search.value.toLowerCase();
+  document.querySelectorAll('#models tr[data-id]').forEach(r => {
+    r.style.display = r.textContent.toLowerCase().includes(q) ? '' : 'none';
+  });
+  labels.forEach(t => {
+    t.style.opacity = !q || t.textContent.toLowerCase().includes(q) ? 1 : 0.25;
+  });
+});
+labels.forEach(t => {
+  const id = t.textContent.trim();
+  if (ids.includes(id)) {
+    t.addEventListener('click', () => { location.href = 'models/' + id + '.html'; });
+  }
+});";
+
+/// Write a static HTML site documenting the given nodes: an index with the
+/// dependency graph and the list of models, and a page per model with its
+/// columns, compiled SQL and statistics of the last analysis
+pub fn generate_docs(
+    graph: &Graph,
+    ids: &[String],
+    catalog: &Catalog,
+    macros: &[MacroDoc],
+    out_dir: &Path,
+) -> Result<(), ArnabError> {
+    let to_error = |e: std::io::Error| ArnabError::Error(e.to_string());
+    if out_dir.exists() {
+        std::fs::remove_dir_all(out_dir).map_err(to_error)?;
+    }
+    std::fs::create_dir_all(out_dir.join("models")).map_err(to_error)?;
+
+    let svg = render_dot(ids, graph);
+    std::fs::write(out_dir.join("graph.svg"), &svg).map_err(to_error)?;
+    std::fs::write(
+        out_dir.join("index.html"),
+        index_page(graph, ids, &svg, !macros.is_empty()),
+    )
+    .map_err(to_error)?;
+    if !macros.is_empty() {
+        std::fs::write(out_dir.join("macros.html"), macros_page(macros)).map_err(to_error)?;
+    }
+    for id in ids {
+        let page = model_page(&graph.nodes[id], catalog)?;
+        std::fs::write(out_dir.join("models").join(format!("{}.html", id)), page)
+            .map_err(to_error)?;
+    }
+    Ok(())
+}
+
+fn index_page(graph: &Graph, ids: &[String], svg: &str, has_macros: bool) -> String {
+    let rows = ids
+        .iter()
+        .map(|id| {
+            let node = &graph.nodes[id];
+            format!(
+                "<tr data-id=\"{id}\"><td><a href=\"models/{id}.html\">{id}</a></td><td>{kind}</td><td>{materialize}</td><td>{path}</td><td>{description}</td></tr>",
+                id = escape(id),
+                kind = node_kind(node),
+                materialize = escape(node.materialize.as_deref().unwrap_or("view")),
+                path = escape(&node.path),
+                description = escape(node.description().unwrap_or("")),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    // The graph is inlined, rather than linked, so that the script can reach its nodes
+    let svg = svg.find("<svg").map(|start| &svg[start..]).unwrap_or(svg);
+    let macros_link = match has_macros {
+        true => "<p><a href=\"macros.html\">Macros</a></p>\n",
+        false => "",
+    };
+    page(
+        "Models",
+        &format!(
+            "<h1>Models</h1>\n{}<input id=\"search\" type=\"search\" placeholder=\"Search models\">\n\
+             <div class=\"graph\">{}</div>\n\
+             <table id=\"models\">\n<tr><th>Model</th><th>Kind</th><th>Materialization</th><th>Path</th><th>Description</th></tr>\n{}\n</table>\n<script>\n{}\n</script>",
+            macros_link, svg, rows, INDEX_SCRIPT
+        ),
+    )
+}
+
+fn macros_page(macros: &[MacroDoc]) -> String {
+    let rows = macros
+        .iter()
+        .map(|doc| {
+            format!(
+                "<tr id=\"{}\"><td><code>{}</code></td><td>{}</td><td>{}</td></tr>",
+                escape(&doc.name),
+                escape(&doc.signature()),
+                escape(&doc.file),
+                escape(doc.description.as_deref().unwrap_or("")).replace('\n', "<br>"),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    page(
+        "Macros",
+        &format!(
+            "<p><a href=\"index.html\">All models</a></p>\n<h1>Macros</h1>\n\
+             <table>\n<tr><th>Macro</th><th>File</th><th>Description</th></tr>\n{}\n</table>",
+            rows
+        ),
+    )
+}
+
+fn model_page(node: &Node, catalog: &Catalog) -> Result<String, ArnabError> {
+    let mut body = format!(
+        "<p><a href=\"../index.html\">All models</a></p>\n<h1>{}</h1>\n<table>\n\
+         <tr><th>Kind</th><td>{}</td></tr>\n<tr><th>Materialization</th><td>{}</td></tr>\n\
+         <tr><th>Path</th><td>{}</td></tr>\n<tr><th>Depends on</th><td>{}</td></tr>\n\
+         <tr><th>Used by</th><td>{}</td></tr>\n</table>\n",
+        escape(&node.id),
+        node_kind(node),
+        escape(node.materialize.as_deref().unwrap_or("view")),
+        escape(&node.path),
+        model_links(node.prevs.iter()),
+        model_links(node.nexts.iter()),
+    );
+    if let Some(description) = node.description() {
+        body.push_str(&format!("<p>{}</p>\n", escape(description)));
+    }
+
+    let schema = node.schema.clone().unwrap_or_default();
+    let catalog_node = catalog.nodes.get(&node.id);
+    let mut column_names = schema
+        .columns
+        .iter()
+        .map(|c| c.name.clone())
+        .collect::<Vec<_>>();
+    for column in catalog_node.iter().flat_map(|n| &n.columns) {
+        if !column_names.contains(&column.name) {
+            column_names.push(column.name.clone());
+        }
+    }
+    if !column_names.is_empty() {
+        let rows = column_names
+            .iter()
+            .map(|name| {
+                let declared = schema.columns.iter().find(|c| &c.name == name);
+                let analyzed =
+                    catalog_node.and_then(|n| n.columns.iter().find(|c| &c.name == name));
+                format!(
+                    "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                    escape(name),
+                    escape(analyzed.map(|c| c.data_type.as_str()).unwrap_or("")),
+                    escape(
+                        declared
+                            .and_then(|c| c.description.as_deref())
+                            .unwrap_or("")
+                    ),
+                    escape(
+                        &declared
+                            .map(|c| c.tests.iter().map(test_name).collect::<Vec<_>>().join(", "))
+                            .unwrap_or_default()
+                    ),
+                    analyzed
+                        .and_then(|c| c.approx_unique)
+                        .map(|n| n.to_string())
+                        .unwrap_or_default(),
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        body.push_str(&format!(
+            "<h2>Columns</h2>\n<table>\n<tr><th>Name</th><th>Type</th><th>Description</th><th>Tests</th><th>Approx. unique</th></tr>\n{}\n</table>\n",
+            rows
+        ));
+    }
+
+    if let Some(catalog_node) = catalog_node {
+        body.push_str(&format!(
+            "<h2>Statistics</h2>\n<p>{} rows (estimated), analyzed at {}</p>\n",
+            catalog_node
+                .estimated_rows
+                .map(|n| n.to_string())
+                .unwrap_or("unknown".into()),
+            escape(&catalog_node.analyzed_at)
+        ));
+    }
+
+    // Ephemeral models are inlined into others, so they have no SQL of their own
+    let sql = match node.is_ephemeral() {
+        true => node.ephemeral_sql()?,
+        false => node.compiled_sql()?,
+    };
+    let heading = match node.node_kind {
+        NodeKind::Python => "Python",
+        NodeKind::Shell => "Shell",
+        _ => "SQL",
+    };
+    body.push_str(&format!(
+        "<h2>{}</h2>\n<pre>{}</pre>\n",
+        heading,
+        escape(&sql)
+    ));
+    Ok(page(&node.id, &body))
+}
+
+fn page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n{}\n</style>\n</head>\n<body>\n{}\n</body>\n</html>\n",
+        escape(title),
+        STYLE,
+        body
+    )
+}
+
+pub(crate) fn node_kind(node: &Node) -> &'static str {
+    match node.node_kind {
+        NodeKind::Sql | NodeKind::Python | NodeKind::Shell => "model",
+        NodeKind::Seed => "seed",
+    }
+}
+
+/// Links to the pages of the given models, sorted
+fn model_links<'a>(ids: impl Iterator<Item = &'a String>) -> String {
+    let mut ids = ids.collect::<Vec<_>>();
+    ids.sort();
+    ids.iter()
+        .map(|id| format!("<a href=\"{id}.html\">{id}</a>", id = escape(id)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn test_name(test: &ColumnTest) -> String {
+    match test {
+        ColumnTest::NotNull => "not_null".into(),
+        ColumnTest::Unique => "unique".into(),
+        ColumnTest::AcceptedValues { .. } => "accepted_values".into(),
+        ColumnTest::Relationships { to, field } => format!("relationships({}.{})", to, field),
+    }
+}
+
+/// Serve the files of a generated docs site forever, each connection in its
+/// own thread
+pub fn serve(listener: TcpListener, dir: &Path) -> Result<(), ArnabError> {
+    for stream in listener.incoming() {
+        let stream = stream.map_err(|e| ArnabError::Error(e.to_string()))?;
+        let dir = dir.to_path_buf();
+        std::thread::spawn(move || {
+            if let Err(e) = handle_request(stream, &dir) {
+                println!("WARNING: Failed to handle docs request: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+fn handle_request(mut stream: TcpStream, dir: &Path) -> Result<(), std::io::Error> {
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or("/"));
+
+    let file = resolve_path(dir, target).filter(|p| p.is_file());
+    let (status, content_type, body) = match (method, file) {
+        ("GET", Some(path)) => ("200 OK", content_type(&path), std::fs::read(&path)?),
+        ("GET", None) => ("404 Not Found", "text/plain", b"Not found".to_vec()),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            b"Method not allowed".to_vec(),
+        ),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(&body)
+}
+
+/// File of `dir` requested by an HTTP request target, `index.html` for
+/// directories. Targets escaping `dir` resolve to nothing.
+fn resolve_path(dir: &Path, target: &str) -> Option<PathBuf> {
+    let path = target.split(['?', '#']).next().unwrap_or("");
+    let mut resolved = dir.to_path_buf();
+    for component in Path::new(path.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(part) => resolved.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    if path.ends_with('/') || resolved == dir {
+        resolved.push("index.html");
+    }
+    Some(resolved)
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        Some("css") => "text/css",
+        Some("js") => "text/javascript",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Escape text for HTML
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{escape, resolve_path};
+
+    #[test]
+    fn escape_html() {
+        assert_eq!(
+            escape("SELECT '<b>' AS \"x\" WHERE a && b"),
+            "SELECT &#39;&lt;b&gt;&#39; AS &quot;x&quot; WHERE a &amp;&amp; b"
+        );
+    }
+
+    #[test]
+    fn resolve_request_paths() {
+        let dir = Path::new("target/docs");
+        assert_eq!(resolve_path(dir, "/"), Some(dir.join("index.html")));
+        assert_eq!(
+            resolve_path(dir, "/models/orders.html?q=1"),
+            Some(dir.join("models/orders.html"))
+        );
+        assert_eq!(resolve_path(dir, "/../config.yaml"), None);
+        assert_eq!(resolve_path(dir, "/models/../../secrets"), None);
+    }
+}
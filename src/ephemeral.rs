@@ -0,0 +1,134 @@
+//! Ephemeral builds (`--ephemeral-build`): models are built into an
+//! in-memory database, with the real database attached read-only behind it
+//! on the search path, so that sources and unselected models are still read
+//! while nothing is written to it. Meant for CI pipelines checking a whole
+//! pipeline without provisioning storage.
+
+use std::path::Path;
+
+use duckdb::Connection;
+
+use crate::{errors::ArnabError, session::ARTIFACTS_DB_NAME};
+
+#[derive(Clone, Debug)]
+pub struct EphemeralBuild {
+    /// `SET search_path` statement, to run on each connection since DuckDB
+    /// doesn't share it between connections
+    pub(crate) search_path_sql: String,
+}
+
+impl EphemeralBuild {
+    /// Open the in-memory database of the build, attaching the real
+    /// database read-only if it exists and an in-memory artifacts database
+    /// in place of the real one if any
+    pub fn connect(
+        db_path: Option<&str>,
+        artifacts_db: bool,
+    ) -> Result<(Self, Connection), ArnabError> {
+        let error = |e: duckdb::Error| ArnabError::Error(e.to_string());
+        let conn = Connection::open_in_memory().map_err(error)?;
+        if artifacts_db {
+            conn.execute_batch(&format!("ATTACH ':memory:' AS {};", ARTIFACTS_DB_NAME))
+                .map_err(error)?;
+        }
+        let mut path = vec!["memory.main".to_string()];
+        if let Some(db_path) = db_path.filter(|db_path| Path::new(db_path).exists()) {
+            // Named after the file as when attached by default, so that
+            // qualified names of its relations still resolve
+            let name = Path::new(db_path)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or("db".into());
+            conn.execute_batch(&format!(
+                "ATTACH '{}' AS \"{}\" (READ_ONLY);",
+                db_path.replace('\'', "''"),
+                name.replace('"', "\"\"")
+            ))
+            .map_err(error)?;
+            let mut stmt = conn
+                .prepare(
+                    "SELECT schema_name FROM duckdb_schemas() \
+                     WHERE database_name = ? AND (NOT internal OR schema_name = 'main') \
+                     ORDER BY schema_name",
+                )
+                .map_err(error)?;
+            let schemas = stmt
+                .query_map([&name], |row| row.get::<_, String>(0))
+                .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+                .map_err(error)?;
+            // Schemas of the real database are created in memory too, ahead
+            // of it on the search path, for statements on them to write there
+            let (main, others): (Vec<_>, Vec<_>) =
+                schemas.into_iter().partition(|schema| schema == "main");
+            for schema in &others {
+                conn.execute_batch(&format!(
+                    "CREATE SCHEMA IF NOT EXISTS memory.\"{}\";",
+                    schema.replace('"', "\"\"")
+                ))
+                .map_err(error)?;
+            }
+            path.extend(others.iter().map(|schema| format!("memory.\"{}\"", schema)));
+            path.extend(
+                main.iter()
+                    .chain(&others)
+                    .map(|schema| format!("\"{}\".\"{}\"", name, schema)),
+            );
+        }
+        let build = Self {
+            search_path_sql: format!("SET search_path = '{}';", path.join(",")),
+        };
+        build.apply(&conn)?;
+        Ok((build, conn))
+    }
+
+    /// Set the search path of the build on a connection
+    pub fn apply(&self, conn: &Connection) -> Result<(), ArnabError> {
+        conn.execute_batch(&self.search_path_sql)
+            .map_err(|e| ArnabError::Error(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::EphemeralBuild;
+
+    #[test]
+    fn build_over_read_only_database() {
+        let dir = std::env::temp_dir().join(format!("arnab_ephemeral_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db_path = dir.join("warehouse.db");
+        let db_path = db_path.to_str().unwrap();
+        {
+            let conn = duckdb::Connection::open(db_path).unwrap();
+            conn.execute_batch(
+                "CREATE SCHEMA raw; CREATE TABLE raw.orders AS SELECT 1 AS id; \
+                 CREATE TABLE users AS SELECT 2 AS id;",
+            )
+            .unwrap();
+        }
+
+        let (build, conn) = EphemeralBuild::connect(Some(db_path), false).unwrap();
+        assert_eq!(
+            build.search_path_sql,
+            "SET search_path = 'memory.main,memory.\"raw\",\"warehouse\".\"main\",\"warehouse\".\"raw\"';"
+        );
+        // Reads resolve in the real database, writes go to memory
+        conn.execute_batch(
+            "CREATE TABLE orders AS SELECT id FROM raw.orders JOIN users USING (id)",
+        )
+        .unwrap();
+        conn.execute_batch(
+            "CREATE OR REPLACE TABLE users AS SELECT 3 AS id; \
+             CREATE SCHEMA IF NOT EXISTS raw; CREATE TABLE raw.customers AS SELECT 1 AS id",
+        )
+        .unwrap();
+        let user_id = |conn: &duckdb::Connection| -> i64 {
+            conn.query_row("SELECT id FROM users", [], |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(user_id(&conn), 3);
+        drop(conn);
+        assert_eq!(user_id(&duckdb::Connection::open(db_path).unwrap()), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,3 +1,14 @@
+/// Exit status of any other error, e.g., a failing statement of a script
+pub const EXIT_ERROR: i32 = 1;
+/// Exit status of an invalid configuration, as for invalid arguments
+pub const EXIT_CONFIG_ERROR: i32 = 2;
+/// Exit status of a run in which a model failed, its failure not tolerated
+pub const EXIT_MODEL_FAILURE: i32 = 3;
+/// Exit status of `arnab test` when a data test fails
+pub const EXIT_TEST_FAILURE: i32 = 4;
+/// Exit status of a run interrupted with Ctrl-C, as shells report for SIGINT
+pub const EXIT_INTERRUPTED: i32 = 130;
+
 #[derive(Debug)]
 pub enum ArnabError {
     Error(String),
@@ -7,4 +18,27 @@ pub enum ArnabError {
         path: String,
     },
     UnknownModelType(String),
+    /// Invalid configuration, e.g., contradicting settings of a model
+    Config(String),
+}
+
+impl ArnabError {
+    /// Error message without the SQL and path context
+    pub fn message(&self) -> String {
+        match self {
+            ArnabError::Error(msg) | ArnabError::Config(msg) => msg.clone(),
+            ArnabError::StatementExecutionError { msg, .. } => msg.clone(),
+            ArnabError::UnknownModelType(model_type) => {
+                format!("Unknown model type: {}", model_type)
+            }
+        }
+    }
+
+    /// Exit status of a command failing with the error
+    pub fn exit_status(&self) -> i32 {
+        match self {
+            ArnabError::Config(_) => EXIT_CONFIG_ERROR,
+            _ => EXIT_ERROR,
+        }
+    }
 }
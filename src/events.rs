@@ -0,0 +1,212 @@
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use serde::Serialize;
+
+use crate::{errors::ArnabError, time_format::iso_now};
+
+/// Lifecycle event of a pipeline run, serialized as one JSON object per line
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    RunStarted {
+        n_nodes: usize,
+    },
+    NodeStarted {
+        node_id: String,
+        materialize: String,
+    },
+    NodeFinished {
+        node_id: String,
+        status: NodeStatus,
+        duration_ms: u128,
+        n_rows: Option<usize>,
+        error: Option<String>,
+        /// Age in seconds of the oldest cached source upstream of the node
+        upstream_source_age_s: Option<u64>,
+        /// Column name and value pairs returned by the node's `stats_sql`
+        stats: Option<BTreeMap<String, String>>,
+    },
+    RunFinished {
+        n_success: usize,
+        n_errors: usize,
+        n_tolerated: usize,
+        n_skipped: usize,
+        duration_ms: u128,
+    },
+    /// The run stopped before completion, e.g., on a failing hook
+    RunFailed {
+        error: String,
+        source_path: Option<String>,
+    },
+    Warning {
+        message: String,
+    },
+}
+
+/// Format of the console output of runs
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Colored lines for humans
+    #[default]
+    Text,
+    /// One JSON event per line, for log collectors such as Datadog or Loki
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeStatus {
+    Success,
+    Error,
+}
+
+#[derive(Serialize)]
+struct TimestampedEvent<'a> {
+    timestamp: String,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+impl Event {
+    /// Whether the event is passed to `on_event_command`: failed nodes and
+    /// finished runs
+    fn is_notable(&self) -> bool {
+        matches!(
+            self,
+            Event::NodeFinished {
+                status: NodeStatus::Error,
+                ..
+            } | Event::RunFinished { .. }
+                | Event::RunFailed { .. }
+        )
+    }
+}
+
+/// Receiver of events within arnab itself, e.g., the dashboard of `--tui`
+pub trait EventListener: Send {
+    fn on_event(&mut self, event: &Event);
+}
+
+/// Writes events as NDJSON to any number of sinks, next to the human console output
+#[derive(Default)]
+pub struct EventLog {
+    sinks: Vec<Box<dyn Write + Send>>,
+    listeners: Vec<Box<dyn EventListener>>,
+    /// Shell commands given notable events as JSON on stdin
+    commands: Vec<String>,
+}
+
+impl EventLog {
+    pub fn add_sink(&mut self, sink: Box<dyn Write + Send>) {
+        self.sinks.push(sink);
+    }
+
+    pub fn add_listener(&mut self, listener: Box<dyn EventListener>) {
+        self.listeners.push(listener);
+    }
+
+    /// Stream events to an already-open file descriptor, e.g., one passed by a
+    /// wrapper process with `--log-fd 3`
+    #[cfg(unix)]
+    pub fn add_fd_sink(&mut self, fd: i32) -> Result<(), ArnabError> {
+        use std::os::fd::FromRawFd;
+
+        if fd <= 2 {
+            return Err(ArnabError::Error(format!(
+                "Cannot stream events to standard descriptor {}",
+                fd
+            )));
+        }
+        // SAFETY: the descriptor is handed over by the parent process for
+        // arnab's exclusive use and stays open for the whole run
+        let file = unsafe { std::fs::File::from_raw_fd(fd) };
+        self.add_sink(Box::new(file));
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn add_fd_sink(&mut self, _fd: i32) -> Result<(), ArnabError> {
+        Err(ArnabError::Error(
+            "--log-fd is only supported on unix platforms".into(),
+        ))
+    }
+
+    /// Run a shell command on notable events, e.g., to page someone when a
+    /// node fails
+    pub fn add_command(&mut self, command: &str) {
+        self.commands.push(command.to_string());
+    }
+
+    pub fn emit(&mut self, event: Event) {
+        for listener in self.listeners.iter_mut() {
+            listener.on_event(&event);
+        }
+        let notable = event.is_notable() && !self.commands.is_empty();
+        if self.sinks.is_empty() && !notable {
+            return;
+        }
+        let line = serde_json::to_string(&TimestampedEvent {
+            timestamp: iso_now(),
+            event: &event,
+        })
+        .unwrap();
+        // Failing sinks (e.g., a closed pipe) must not break the run itself
+        for sink in self.sinks.iter_mut() {
+            let _ = writeln!(sink, "{}", line).and_then(|_| sink.flush());
+        }
+        if notable {
+            for command in &self.commands {
+                if let Err(e) = run_command(command, &line) {
+                    println!("WARNING: on_event_command `{}` failed: {}", command, e);
+                }
+            }
+        }
+    }
+}
+
+/// Run a shell command with `input` on its stdin, waiting for it to exit
+fn run_command(command: &str, input: &str) -> Result<(), String> {
+    let mut child = if cfg!(windows) {
+        Command::new("cmd")
+            .args(["/C", command])
+            .stdin(Stdio::piped())
+            .spawn()
+    } else {
+        Command::new("sh")
+            .args(["-c", command])
+            .stdin(Stdio::piped())
+            .spawn()
+    }
+    .map_err(|e| e.to_string())?;
+    // The command may not read its input at all, which is fine
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = writeln!(stdin, "{}", input);
+    }
+    let status = child.wait().map_err(|e| e.to_string())?;
+    match status.success() {
+        true => Ok(()),
+        false => Err(status.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Event;
+
+    #[test]
+    fn serialize_run_failure() {
+        let event = Event::RunFailed {
+            error: "Cannot render hook".into(),
+            source_path: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"run_failed","error":"Cannot render hook","source_path":null}"#
+        );
+        assert!(event.is_notable());
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::{errors::ArnabError, node::Node, selection::Selection};
+
+/// Dependency graph of the project's nodes. Edges are stored in the nodes:
+/// `prevs` are the nodes a node depends on and `nexts` the nodes depending on it.
+pub struct Graph {
+    /// Node ids in topological order, dependencies first
+    pub(crate) sorted_ids: Vec<String>,
+    pub(crate) nodes: HashMap<String, Node>,
+}
+
+impl Graph {
+    /// Ids of the nodes picked by `selection`, in topological order
+    pub fn select(&self, selection: &Selection) -> Result<Vec<String>, ArnabError> {
+        selection.apply(&self.sorted_ids, &self.nodes)
+    }
+
+    /// (from, to) edges between the given nodes, `from` being the dependency
+    pub fn edges(&self, ids: &[String]) -> Vec<(String, String)> {
+        let mut edges = vec![];
+        for id in ids {
+            let nexts = self.nodes[id]
+                .nexts
+                .iter()
+                .filter(|next| ids.contains(next))
+                .collect::<BTreeSet<_>>();
+            edges.extend(nexts.into_iter().map(|next| (id.clone(), next.clone())));
+        }
+        edges
+    }
+
+    /// Ids of all nodes `id` depends on, directly or not
+    pub fn ancestors(&self, id: &str) -> HashSet<String> {
+        let mut ancestors = HashSet::new();
+        let mut to_visit = vec![id.to_string()];
+        while let Some(id) = to_visit.pop() {
+            for prev in &self.nodes[&id].prevs {
+                if ancestors.insert(prev.clone()) {
+                    to_visit.push(prev.clone());
+                }
+            }
+        }
+        ancestors
+    }
+
+    /// Group failed nodes by root cause: failed nodes without failed
+    /// ancestors, each with the failed nodes downstream of it, in the order
+    /// of `failed_ids`
+    pub fn failure_groups(&self, failed_ids: &[String]) -> Vec<(String, Vec<String>)> {
+        let failed = failed_ids.iter().collect::<HashSet<_>>();
+        let failed_ancestors = failed_ids
+            .iter()
+            .map(|id| {
+                let ancestors = self.ancestors(id);
+                (
+                    id,
+                    ancestors
+                        .into_iter()
+                        .filter(|a| failed.contains(a))
+                        .collect::<HashSet<_>>(),
+                )
+            })
+            .collect::<HashMap<_, _>>();
+        failed_ids
+            .iter()
+            .filter(|id| failed_ancestors[id].is_empty())
+            .map(|root| {
+                let downstream = failed_ids
+                    .iter()
+                    .filter(|id| failed_ancestors[id].contains(root))
+                    .cloned()
+                    .collect();
+                (root.clone(), downstream)
+            })
+            .collect()
+    }
+
+    /// Position of each node in the topological order
+    pub fn sorted_positions(&self) -> HashMap<String, usize> {
+        self.sorted_ids
+            .iter()
+            .enumerate()
+            .map(|(i, id)| (id.clone(), i))
+            .collect()
+    }
+}
+
+/// Collects nodes and their dependencies, then builds a `Graph`
+#[derive(Default)]
+pub struct GraphBuilder {
+    nodes: HashMap<String, Node>,
+}
+
+impl GraphBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.nodes.contains_key(id)
+    }
+
+    /// Ids of the added nodes, sorted
+    pub fn node_ids(&self) -> Vec<String> {
+        let mut ids = self.nodes.keys().cloned().collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    pub fn add_node(&mut self, node: Node) -> Result<(), ArnabError> {
+        if let Some(existing) = self.nodes.get(&node.id) {
+            return Err(ArnabError::Error(format!(
+                "{} and {} are both named `{}`",
+                existing.path, node.path, node.id
+            )));
+        }
+        self.nodes.insert(node.id.clone(), node);
+        Ok(())
+    }
+
+    pub fn nodes_mut(&mut self) -> impl Iterator<Item = &mut Node> {
+        self.nodes.values_mut()
+    }
+
+    /// Make `id` depend on `dependency`, both being added nodes
+    pub fn add_dependency(&mut self, id: &str, dependency: &str) -> Result<(), ArnabError> {
+        if !self.nodes.contains_key(dependency) {
+            return Err(ArnabError::Error(format!(
+                "Model `{}` depends on `{}`, which is not a model",
+                id, dependency
+            )));
+        }
+        let Some(node) = self.nodes.get_mut(id) else {
+            return Err(ArnabError::Error(format!("Model `{}` not found", id)));
+        };
+        node.prevs.insert(dependency.into());
+        Ok(())
+    }
+
+    /// Populate outgoing edges and sort the nodes topologically. Dependencies
+    /// on unknown nodes are dropped with a warning, and cycles are errors.
+    pub fn build(mut self) -> Result<Graph, ArnabError> {
+        let ids = self.node_ids();
+        for id in &ids {
+            let mut prevs = self.nodes[id].prevs.iter().cloned().collect::<Vec<_>>();
+            prevs.sort();
+            for prev_id in prevs {
+                match self.nodes.get_mut(&prev_id) {
+                    Some(prev_node) => {
+                        prev_node.nexts.insert(id.clone());
+                    }
+                    None => {
+                        println!(
+                            "WARNING: Model `{}` required by `{}` not found",
+                            prev_id, id
+                        );
+                        self.nodes.get_mut(id).unwrap().prevs.remove(&prev_id);
+                    }
+                }
+            }
+        }
+
+        let mut sorted_ids = vec![];
+        let mut path = vec![];
+        for id in &ids {
+            topo(id, &self.nodes, &mut path, &mut sorted_ids)?;
+        }
+        Ok(Graph {
+            sorted_ids,
+            nodes: self.nodes,
+        })
+    }
+}
+
+/// Depth-first topological sort. `path` holds the nodes being visited, so
+/// meeting one of them again means a cycle.
+fn topo(
+    id: &String,
+    nodes: &HashMap<String, Node>,
+    path: &mut Vec<String>,
+    out: &mut Vec<String>,
+) -> Result<(), ArnabError> {
+    if out.contains(id) {
+        return Ok(());
+    }
+    if let Some(start) = path.iter().position(|p| p == id) {
+        let mut cycle = path[start..].to_vec();
+        cycle.push(id.clone());
+        return Err(ArnabError::Error(format!(
+            "Circular dependency: {}",
+            cycle.join(" -> ")
+        )));
+    }
+
+    path.push(id.clone());
+    let mut prevs = nodes[id].prevs.iter().collect::<Vec<_>>();
+    prevs.sort();
+    for prev_id in prevs {
+        topo(prev_id, nodes, path, out)?;
+    }
+    path.pop();
+    out.push(id.clone());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::GraphBuilder;
+    use crate::node::{Node, NodeKind};
+
+    fn builder(ids: &[&str], deps: &[(&str, &str)]) -> GraphBuilder {
+        let mut builder = GraphBuilder::new();
+        for id in ids {
+            builder
+                .add_node(Node::new(NodeKind::Sql, &format!("{}.sql", id), id, ""))
+                .unwrap();
+        }
+        for (id, dep) in deps {
+            builder.add_dependency(id, dep).unwrap();
+        }
+        builder
+    }
+
+    #[test]
+    fn build_sorted_graph() {
+        // a -> b -> d, c -> d
+        let graph = builder(&["d", "c", "b", "a"], &[("b", "a"), ("d", "b"), ("d", "c")])
+            .build()
+            .unwrap();
+        assert_eq!(graph.sorted_ids, vec!["a", "b", "c", "d"]);
+        assert!(graph.nodes["a"].nexts.contains("b"));
+        assert_eq!(
+            graph.edges(&graph.sorted_ids[1..]),
+            vec![("b".into(), "d".into()), ("c".into(), "d".into())]
+        );
+
+        let mut builder = builder(&["a"], &[]);
+        assert!(builder.add_dependency("a", "missing").is_err());
+        assert!(builder
+            .add_node(Node::new(NodeKind::Seed, "a.csv", "a", ""))
+            .is_err());
+    }
+
+    #[test]
+    fn group_failures_by_root_cause() {
+        // a -> b -> c, a -> d, e
+        let graph = builder(
+            &["a", "b", "c", "d", "e"],
+            &[("b", "a"), ("c", "b"), ("d", "a")],
+        )
+        .build()
+        .unwrap();
+        let failed = ["b", "c", "d", "e"].map(String::from);
+        assert_eq!(
+            graph.failure_groups(&failed),
+            vec![
+                ("b".to_string(), vec!["c".to_string()]),
+                ("d".to_string(), vec![]),
+                ("e".to_string(), vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn detect_cycles() {
+        let result = builder(&["a", "b", "c"], &[("b", "a"), ("c", "b"), ("a", "c")]).build();
+        assert_eq!(
+            result.err().unwrap().message(),
+            "Circular dependency: a -> c -> b -> a"
+        );
+    }
+}
@@ -0,0 +1,214 @@
+//! GraphML and Graphology exports of the DAG, to analyze large projects with
+//! network tools such as Gephi, NetworkX or Sigma.js, which load node
+//! attributes as typed columns.
+
+use std::collections::BTreeMap;
+
+use serde_json::{json, Map, Value};
+
+use crate::{
+    docs::{escape, node_kind},
+    graph::Graph,
+    graphviz::{DotNode, VizFormat},
+    manifest::DiffStatus,
+};
+
+/// Output format of `arnab graph`
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub enum GraphFormat {
+    #[default]
+    Graphml,
+    /// Graphology JSON, for JavaScript network tools such as Sigma.js
+    Graphology,
+}
+
+impl From<GraphFormat> for VizFormat {
+    fn from(format: GraphFormat) -> Self {
+        match format {
+            GraphFormat::Graphml => VizFormat::Graphml,
+            GraphFormat::Graphology => VizFormat::Graphology,
+        }
+    }
+}
+
+/// Node attributes, declared as GraphML keys
+const KEYS: [&str; 5] = ["kind", "materialization", "path", "description", "status"];
+
+/// Attributes of a node, by key. Nodes of the graph get their kind,
+/// materialization and path, the others being source tables, and their diff
+/// status when `statuses` is given.
+fn node_attributes(
+    dot_node: &DotNode,
+    graph: &Graph,
+    statuses: Option<&BTreeMap<String, DiffStatus>>,
+) -> Vec<(&'static str, String)> {
+    let node = graph.nodes.get(&dot_node.name);
+    let status = statuses.and_then(|s| s.get(&dot_node.name));
+    let kind = match node {
+        Some(node) => node_kind(node),
+        // Removed models aren't part of the graph anymore
+        None if status.is_some() => "model",
+        None => "source",
+    };
+    let attributes = [
+        Some(kind.to_string()),
+        node.and_then(|n| n.materialize.clone()),
+        node.map(|n| n.path.clone()),
+        dot_node.tooltip.clone(),
+        status.map(|s| format!("{:?}", s).to_lowercase()),
+    ];
+    KEYS.into_iter()
+        .zip(attributes)
+        .filter_map(|(key, value)| Some((key, value?)))
+        .collect()
+}
+
+/// Build a GraphML document from nodes and (from, to) edges, with the
+/// attributes of nodes as data
+pub fn to_graphml(
+    nodes: &[DotNode],
+    edges: &[(String, String)],
+    graph: &Graph,
+    statuses: Option<&BTreeMap<String, DiffStatus>>,
+) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+    );
+    for key in KEYS {
+        xml.push_str(&format!(
+            "  <key id=\"{}\" for=\"node\" attr.name=\"{}\" attr.type=\"string\"/>\n",
+            key, key
+        ));
+    }
+    xml.push_str("  <graph id=\"arnab\" edgedefault=\"directed\">\n");
+    for dot_node in nodes {
+        xml.push_str(&format!("    <node id=\"{}\">\n", escape(&dot_node.name)));
+        for (key, value) in node_attributes(dot_node, graph, statuses) {
+            xml.push_str(&format!(
+                "      <data key=\"{}\">{}</data>\n",
+                key,
+                escape(&value)
+            ));
+        }
+        xml.push_str("    </node>\n");
+    }
+    for (nth, (from, to)) in edges.iter().enumerate() {
+        xml.push_str(&format!(
+            "    <edge id=\"e{}\" source=\"{}\" target=\"{}\"/>\n",
+            nth,
+            escape(from),
+            escape(to)
+        ));
+    }
+    xml.push_str("  </graph>\n</graphml>\n");
+    xml
+}
+
+/// Build a serialized Graphology graph from nodes and (from, to) edges, with
+/// the attributes of nodes
+pub fn to_graphology(
+    nodes: &[DotNode],
+    edges: &[(String, String)],
+    graph: &Graph,
+    statuses: Option<&BTreeMap<String, DiffStatus>>,
+) -> String {
+    let nodes = nodes
+        .iter()
+        .map(|dot_node| {
+            let attributes = node_attributes(dot_node, graph, statuses)
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), Value::String(value)))
+                .collect::<Map<_, _>>();
+            json!({"key": dot_node.name, "attributes": attributes})
+        })
+        .collect::<Vec<_>>();
+    let edges = edges
+        .iter()
+        .map(|(from, to)| json!({"source": from, "target": to}))
+        .collect::<Vec<_>>();
+    let graphology = json!({
+        "attributes": {"name": "arnab"},
+        "options": {"type": "directed", "multi": false, "allowSelfLoops": false},
+        "nodes": nodes,
+        "edges": edges,
+    });
+    serde_json::to_string_pretty(&graphology).unwrap() + "\n"
+}
+
+#[cfg(test)]
+mod test {
+    use super::{to_graphml, to_graphology};
+    use crate::{
+        graph::GraphBuilder,
+        graphviz::source_nodes,
+        graphviz::DotNode,
+        node::{Node, NodeKind},
+    };
+
+    #[test]
+    fn export_graphml() {
+        let mut builder = GraphBuilder::new();
+        let mut orders = Node::new(NodeKind::Sql, "models/orders.sql", "orders", "");
+        orders.materialize = Some("table".into());
+        orders.sources.insert(("raw".into(), "orders".into()));
+        builder.add_node(orders).unwrap();
+        builder
+            .add_node(Node::new(NodeKind::Seed, "seeds/a&b.csv", "a&b", ""))
+            .unwrap();
+        builder.add_dependency("orders", "a&b").unwrap();
+        let graph = builder.build().unwrap();
+
+        let ids = vec!["a&b".to_string(), "orders".to_string()];
+        let (mut nodes, mut edges) = source_nodes(&ids, &graph);
+        nodes.extend(ids.iter().map(|id| DotNode {
+            name: id.clone(),
+            fill_color: None,
+            tooltip: None,
+        }));
+        edges.extend(graph.edges(&ids));
+        let xml = to_graphml(&nodes, &edges, &graph, None);
+
+        assert!(xml.contains(
+            "<node id=\"raw.orders\">\n      <data key=\"kind\">source</data>\n    </node>"
+        ));
+        assert!(xml.contains("<node id=\"a&amp;b\">\n      <data key=\"kind\">seed</data>"));
+        assert!(xml.contains("<data key=\"materialization\">table</data>"));
+        assert!(xml.contains("<edge id=\"e0\" source=\"raw.orders\" target=\"orders\"/>"));
+        assert!(xml.contains("<edge id=\"e1\" source=\"a&amp;b\" target=\"orders\"/>"));
+    }
+
+    #[test]
+    fn export_graphology() {
+        let mut builder = GraphBuilder::new();
+        let mut orders = Node::new(NodeKind::Sql, "models/orders.sql", "orders", "");
+        orders.sources.insert(("raw".into(), "orders".into()));
+        builder.add_node(orders).unwrap();
+        let graph = builder.build().unwrap();
+
+        let ids = vec!["orders".to_string()];
+        let (mut nodes, mut edges) = source_nodes(&ids, &graph);
+        nodes.extend(ids.iter().map(|id| DotNode {
+            name: id.clone(),
+            fill_color: None,
+            tooltip: Some("Orders".into()),
+        }));
+        edges.extend(graph.edges(&ids));
+        let json: serde_json::Value =
+            serde_json::from_str(&to_graphology(&nodes, &edges, &graph, None)).unwrap();
+
+        assert_eq!(json["options"]["type"], "directed");
+        assert_eq!(
+            json["nodes"][0],
+            serde_json::json!({"key": "raw.orders", "attributes": {"kind": "source"}})
+        );
+        assert_eq!(
+            json["nodes"][1]["attributes"],
+            serde_json::json!({"kind": "model", "path": "models/orders.sql", "description": "Orders"})
+        );
+        assert_eq!(
+            json["edges"],
+            serde_json::json!([{"source": "raw.orders", "target": "orders"}])
+        );
+    }
+}
@@ -1,35 +1,103 @@
-use std::collections::HashMap;
+use std::{
+    collections::BTreeSet,
+    io::Write,
+    process::{Command, Stdio},
+};
 
-use crate::node::Node;
+use crate::{docs::escape, graph::Graph};
 use layout::{
     backends::svg::SVGWriter,
     gv::{self, GraphBuilder},
 };
 
-/// Given a list of node name and a map from node name to node object, render 
-/// rendered graph in SVG format.
-pub fn render_dot(node_names: &Vec<String>, node_map: &HashMap<String, Node>) -> String {
-    let gv_nodes = node_names
+/// Output format of `arnab viz`
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub enum VizFormat {
+    #[default]
+    Svg,
+    Graphml,
+    /// Graphology JSON, for JavaScript network tools such as Sigma.js
+    Graphology,
+    /// DOT source, for Graphviz or other tools to render
+    Dot,
+    /// Mermaid flowchart, for GitHub and Markdown documents
+    Mermaid,
+    /// Self-contained HTML page of the graph, which can be zoomed, panned and
+    /// searched, for large projects
+    Html,
+}
+
+impl VizFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            VizFormat::Svg => "svg",
+            VizFormat::Graphml => "graphml",
+            VizFormat::Graphology => "json",
+            VizFormat::Dot => "dot",
+            VizFormat::Mermaid => "mmd",
+            VizFormat::Html => "html",
+        }
+    }
+}
+
+/// Renderer of SVG visualizations
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub enum VizRenderer {
+    /// Layout computed by arnab itself, slow beyond a few hundred nodes
+    #[default]
+    Builtin,
+    /// The `dot` command of an installed Graphviz, falling back to the
+    /// builtin renderer when missing
+    DotCli,
+}
+
+/// A node to draw, optionally filled with a color and with a tooltip
+pub struct DotNode {
+    pub(crate) name: String,
+    pub(crate) fill_color: Option<String>,
+    pub(crate) tooltip: Option<String>,
+}
+
+/// Build DOT source from nodes and (from, to) edges
+pub fn to_dot(nodes: &[DotNode], edges: &[(String, String)]) -> String {
+    dot_source(nodes, edges, false)
+}
+
+/// Build DOT source, with the nodes' tooltips if `tooltips`: the layout
+/// crate doesn't support them, but Graphviz does
+pub fn dot_source(nodes: &[DotNode], edges: &[(String, String)], tooltips: bool) -> String {
+    let gv_nodes = nodes
         .iter()
-        .map(|s| format!("\t{};", s))
+        .map(|n| {
+            let mut attributes = vec![];
+            if let Some(color) = &n.fill_color {
+                attributes.push("style=filled".to_string());
+                attributes.push(format!("fillcolor={}", color));
+            }
+            if let Some(tooltip) = n.tooltip.as_ref().filter(|_| tooltips) {
+                let tooltip = tooltip.replace('\\', "\\\\").replace('"', "\\\"");
+                attributes.push(format!("tooltip=\"{}\"", tooltip));
+            }
+            match attributes.is_empty() {
+                true => format!("\t\"{}\";", n.name),
+                false => format!("\t\"{}\" [{}];", n.name, attributes.join(", ")),
+            }
+        })
         .collect::<Vec<String>>()
         .join("\n");
 
-    let mut gv_edges = "".to_string();
-    for name in node_names {
-        let node = &node_map[name];
-        let edges_str = node
-            .nexts
-            .iter()
-            .map(|s| format!("\t{} -> {};", name, s))
-            .collect::<Vec<String>>()
-            .join("\n");
+    let gv_edges = edges
+        .iter()
+        .map(|(from, to)| format!("\t\"{}\" -> \"{}\";", from, to))
+        .collect::<Vec<String>>()
+        .join("\n");
 
-        gv_edges.push_str(&edges_str);
-    }
+    format!("digraph LR {{\n {} \n {} \n}}", gv_nodes, gv_edges)
+}
 
-    let dot_src = format!("digraph LR {{\n {} \n {} \n}}", gv_nodes, gv_edges);
-    let mut parser = gv::DotParser::new(&dot_src);
+/// Render DOT source in SVG format
+pub fn render_svg(dot_src: &str) -> String {
+    let mut parser = gv::DotParser::new(dot_src);
     let graph = parser.process().unwrap();
 
     let mut gb = GraphBuilder::new();
@@ -39,3 +107,130 @@ pub fn render_dot(node_names: &Vec<String>, node_map: &HashMap<String, Node>) ->
     visual_graph.do_it(false, false, false, &mut svg_writer);
     svg_writer.finalize()
 }
+
+/// Render nodes and (from, to) edges in SVG format, with the nodes' tooltips
+pub fn render_nodes(nodes: &[DotNode], edges: &[(String, String)]) -> String {
+    let mut svg = render_svg(&to_dot(nodes, edges));
+    // The layout crate ignores the tooltip attribute, so tooltips are added
+    // as titles of the nodes' labels
+    for node in nodes {
+        if let Some(tooltip) = &node.tooltip {
+            let label = format!("dy=\"1.0em\">{}</tspan></text>", escape(&node.name));
+            let with_title = format!(
+                "dy=\"1.0em\">{}</tspan><title>{}</title></text>",
+                escape(&node.name),
+                escape(tooltip)
+            );
+            svg = svg.replacen(&label, &with_title, 1);
+        }
+    }
+    svg
+}
+
+/// Render nodes and (from, to) edges in SVG format with `renderer`
+pub fn render_nodes_with(
+    nodes: &[DotNode],
+    edges: &[(String, String)],
+    renderer: VizRenderer,
+) -> String {
+    if renderer == VizRenderer::DotCli {
+        match render_with_dot_cli(&dot_source(nodes, edges, true)) {
+            Ok(svg) => return svg,
+            Err(e) => println!(
+                "WARNING: cannot render with Graphviz, using the builtin renderer: {}",
+                e
+            ),
+        }
+    }
+    render_nodes(nodes, edges)
+}
+
+/// Render DOT source in SVG format with the `dot` command of Graphviz
+fn render_with_dot_cli(dot_src: &str) -> Result<String, String> {
+    let mut child = Command::new("dot")
+        .arg("-Tsvg")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("cannot run `dot`: {}", e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(dot_src.as_bytes())
+            .map_err(|e| e.to_string())?;
+    }
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    match output.status.success() {
+        true => Ok(String::from_utf8_lossy(&output.stdout).to_string()),
+        false => Err(String::from_utf8_lossy(&output.stderr).trim().to_string()),
+    }
+}
+
+/// Source tables used by the given nodes, named `source_name.table_name`,
+/// and their edges to the nodes using them
+pub fn source_nodes(node_names: &[String], graph: &Graph) -> (Vec<DotNode>, Vec<(String, String)>) {
+    let mut sources = BTreeSet::new();
+    let mut edges = vec![];
+    for name in node_names {
+        let mut node_sources = graph.nodes[name]
+            .sources
+            .iter()
+            .map(|(source, table)| format!("{}.{}", source, table))
+            .collect::<Vec<_>>();
+        node_sources.sort();
+        for source in node_sources {
+            edges.push((source.clone(), name.clone()));
+            sources.insert(source);
+        }
+    }
+    let nodes = sources
+        .into_iter()
+        .map(|name| DotNode {
+            name,
+            fill_color: Some("lightblue".into()),
+            tooltip: None,
+        })
+        .collect();
+    (nodes, edges)
+}
+
+/// Render the given nodes of a graph in SVG format, with their descriptions
+/// as tooltips. Source tables are drawn as inputs.
+pub fn render_dot(node_names: &[String], graph: &Graph) -> String {
+    let (mut nodes, mut edges) = source_nodes(node_names, graph);
+    nodes.extend(node_names.iter().map(|name| DotNode {
+        name: name.clone(),
+        fill_color: None,
+        tooltip: graph.nodes[name].description().map(String::from),
+    }));
+    edges.extend(graph.edges(node_names));
+    render_nodes(&nodes, &edges)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{dot_source, DotNode};
+
+    #[test]
+    fn dot_source_with_tooltips() {
+        let nodes = [
+            DotNode {
+                name: "orders".into(),
+                fill_color: Some("khaki".into()),
+                tooltip: Some("Orders, \"paid\" only".into()),
+            },
+            DotNode {
+                name: "users".into(),
+                fill_color: None,
+                tooltip: None,
+            },
+        ];
+        let edges = [("users".to_string(), "orders".to_string())];
+        assert_eq!(
+            dot_source(&nodes, &edges, true),
+            "digraph LR {\n \t\"orders\" [style=filled, fillcolor=khaki, tooltip=\"Orders, \\\"paid\\\" only\"];\n\t\"users\"; \n \t\"users\" -> \"orders\"; \n}"
+        );
+        // The layout crate doesn't support tooltips
+        assert!(!dot_source(&nodes, &edges, false).contains("tooltip"));
+    }
+}
@@ -0,0 +1,68 @@
+use duckdb::Connection;
+use serde::Serialize;
+
+use crate::{
+    errors::ArnabError,
+    verbosity::{debug, info},
+};
+
+/// Variables available in `on_run_start` and `on_run_end` hooks
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct HookContext {
+    /// Start of the run, as an RFC 3339 timestamp
+    pub(crate) run_started_at: String,
+    pub(crate) target: Option<String>,
+    /// Number of successfully built nodes, in `on_run_end` hooks only
+    pub(crate) n_success: Option<usize>,
+    /// Number of failed nodes, in `on_run_end` hooks only
+    pub(crate) n_errors: Option<usize>,
+}
+
+/// Render the SQL of a hook with the context of the run
+pub fn render_hook(sql: &str, context: &HookContext) -> Result<String, ArnabError> {
+    minijinja::Environment::new()
+        .render_str(sql, context)
+        .map_err(|e| ArnabError::Error(format!("Cannot render hook `{}`: {}", sql, e)))
+}
+
+/// Execute hooks in order, stopping at the first failing one
+pub fn run_hooks(
+    conn: &Connection,
+    name: &str,
+    hooks: &[String],
+    context: &HookContext,
+) -> Result<(), ArnabError> {
+    for (nth, sql) in (1..).zip(hooks) {
+        let sql = render_hook(sql, context)?;
+        info!("Running {} hook {} of {}", name, nth, hooks.len());
+        debug!("{}", sql);
+        conn.execute_batch(&sql)
+            .map_err(|e| ArnabError::StatementExecutionError {
+                msg: e.to_string(),
+                sql,
+                path: name.to_string(),
+            })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{render_hook, HookContext};
+
+    #[test]
+    fn render_hook_context() {
+        let context = HookContext {
+            run_started_at: "2024-01-01T00:00:00+00:00".into(),
+            target: Some("prod".into()),
+            n_success: Some(3),
+            n_errors: Some(1),
+        };
+        let sql = "INSERT INTO audit VALUES ('{{ run_started_at }}', '{{ target }}', \
+                   {{ n_success }}, {{ n_errors }})";
+        assert_eq!(
+            render_hook(sql, &context).unwrap(),
+            "INSERT INTO audit VALUES ('2024-01-01T00:00:00+00:00', 'prod', 3, 1)"
+        );
+    }
+}
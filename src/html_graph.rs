@@ -0,0 +1,396 @@
+//! Interactive HTML export of the DAG: a self-contained page drawing the
+//! graph in layers from left to right, which can be zoomed, panned and
+//! searched, with tooltips on nodes. Meant for projects too large for a
+//! static SVG to be read.
+
+use std::collections::BTreeMap;
+
+use crate::{
+    docs::{escape, node_kind},
+    graph::Graph,
+    graphviz::DotNode,
+    manifest::DiffStatus,
+    mermaid::{node_class, CLASS_STYLES},
+    run_results::RunResults,
+};
+
+const NODE_WIDTH: usize = 180;
+const NODE_HEIGHT: usize = 28;
+/// Distance between the left sides of consecutive layers
+const LAYER_GAP: usize = 240;
+/// Distance between the tops of consecutive nodes of a layer
+const ROW_GAP: usize = 44;
+/// Longest label drawn in a node, in characters. Tooltips show full names.
+const LABEL_LENGTH: usize = 24;
+
+const STYLE: &str = "body { font-family: sans-serif; margin: 0; color: #222; }
+#toolbar { padding: 8px; border-bottom: 1px solid #ddd; }
+#search { width: 320px; padding: 4px; }
+#graph { width: 100vw; height: calc(100vh - 44px); cursor: grab; display: block; }
+.node rect { fill: #fff; stroke: #808080; stroke-width: 1.5; }
+.node text { font-size: 12px; dominant-baseline: middle; pointer-events: none; }
+.edge { fill: none; stroke: #aaa; stroke-width: 1.2; }
+.run-error rect { stroke: #d00; stroke-width: 3; }
+.run-skipped rect { stroke: #e90; stroke-width: 3; }
+.dim { opacity: 0.15; }
+.match rect { stroke: #000; stroke-width: 3; }
+#tooltip { position: fixed; display: none; background: #fff; border: 1px solid #888;
+  padding: 6px 8px; font-size: 12px; white-space: pre; pointer-events: none; }";
+
+/// Zooms on wheel, pans on drag, dims nodes not matching the search (Enter
+/// centers the first match) and shows tooltips
+const SCRIPT: &str = "const svg = document.getElementById('graph');
+const viewport = document.getElementById('viewport');
+const tooltip = document.getElementById('tooltip');
+const search = document.getElementById('search');
+const nodes = [...document.querySelectorAll('.node')];
+let view = { x: 0, y: 0, k: 1 };
+const apply = () => viewport.setAttribute('transform', `translate(${view.x},${view.y}) scale(${view.k})`);
+const toSvg = e => {
+  const p = svg.createSVGPoint();
+  p.x = e.clientX; p.y = e.clientY;
+  return p.matrixTransform(svg.getScreenCTM().inverse());
+};
+svg.addEventListener('wheel', e => {
+  e.preventDefault();
+  const p = toSvg(e);
+  const k = Math.min(8, Math.max(0.05, view.k * (e.deltaY < 0 ? 1.2 : 1 / 1.2)));
+  view.x = p.x - (p.x - view.x) * k / view.k;
+  view.y = p.y - (p.y - view.y) * k / view.k;
+  view.k = k;
+  apply();
+}, { passive: false });
+let drag = null;
+svg.addEventListener('mousedown', e => { drag = { p: toSvg(e), x: view.x, y: view.y }; });
+window.addEventListener('mouseup', () => { drag = null; });
+window.addEventListener('mousemove', e => {
+  if (!drag) return;
+  const p = toSvg(e);
+  view.x = drag.x + p.x - drag.p.x;
+  view.y = drag.y + p.y - drag.p.y;
+  apply();
+});
+nodes.forEach(n => {
+  n.addEventListener('mousemove', e => {
+    tooltip.textContent = n.dataset.tooltip;
+    tooltip.style.display = 'block';
+    tooltip.style.left = (e.clientX + 12) + 'px';
+    tooltip.style.top = (e.clientY + 12) + 'px';
+  });
+  n.addEventListener('mouseleave', () => { tooltip.style.display = 'none'; });
+});
+const matches = () => {
+  const q = search.value.toLowerCase();
+  return nodes.filter(n => q && n.dataset.name.toLowerCase().includes(q));
+};
+search.addEventListener('input', () => {
+  const found = matches();
+  nodes.forEach(n => {
+    n.classList.toggle('match', found.includes(n));
+    n.classList.toggle('dim', search.value !== '' && !found.includes(n));
+  });
+  document.querySelectorAll('.edge').forEach(e => e.classList.toggle('dim', search.value !== ''));
+  document.getElementById('count').textContent = search.value ? found.length + ' found' : '';
+});
+search.addEventListener('keydown', e => {
+  const found = matches();
+  if (e.key !== 'Enter' || !found.length) return;
+  const box = svg.viewBox.baseVal;
+  const rect = found[0].querySelector('rect');
+  view.k = Math.max(view.k, 1);
+  view.x = box.width / 2 - (+rect.getAttribute('x') + NODE_WIDTH / 2) * view.k;
+  view.y = box.height / 2 - +rect.getAttribute('y') * view.k;
+  apply();
+});";
+
+/// Layer and row of each of `names`: layers are the longest paths from the
+/// roots, and nodes within a layer are sorted by the mean row of their
+/// upstream nodes, to limit crossing edges
+fn layout(names: &[String], edges: &[(String, String)]) -> Vec<(usize, usize)> {
+    let index = names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| (name.as_str(), i))
+        .collect::<BTreeMap<_, _>>();
+    let mut prevs = vec![vec![]; names.len()];
+    let mut nexts = vec![vec![]; names.len()];
+    for (from, to) in edges {
+        if let (Some(&from), Some(&to)) = (index.get(from.as_str()), index.get(to.as_str())) {
+            prevs[to].push(from);
+            nexts[from].push(to);
+        }
+    }
+
+    let mut layers = vec![0; names.len()];
+    let mut n_prevs = prevs.iter().map(Vec::len).collect::<Vec<_>>();
+    let mut ready = (0..names.len())
+        .filter(|i| n_prevs[*i] == 0)
+        .collect::<Vec<_>>();
+    while let Some(i) = ready.pop() {
+        for &next in &nexts[i] {
+            layers[next] = layers[next].max(layers[i] + 1);
+            n_prevs[next] -= 1;
+            if n_prevs[next] == 0 {
+                ready.push(next);
+            }
+        }
+    }
+
+    let n_layers = layers.iter().max().map_or(0, |max| max + 1);
+    let mut columns = vec![vec![]; n_layers];
+    for (i, layer) in layers.iter().enumerate() {
+        columns[*layer].push(i);
+    }
+    let mut rows = vec![0; names.len()];
+    for column in &columns {
+        for (row, i) in column.iter().enumerate() {
+            rows[*i] = row;
+        }
+    }
+    for column in columns.iter_mut().skip(1) {
+        let barycenter = |i: &usize| match prevs[*i].is_empty() {
+            true => rows[*i] as f64,
+            false => {
+                prevs[*i].iter().map(|p| rows[*p] as f64).sum::<f64>() / prevs[*i].len() as f64
+            }
+        };
+        column.sort_by(|a, b| barycenter(a).total_cmp(&barycenter(b)));
+        for (row, i) in column.iter().enumerate() {
+            rows[*i] = row;
+        }
+    }
+    layers.into_iter().zip(rows).collect()
+}
+
+/// Tooltip of a node: its name, kind, path, materialization, description,
+/// diff status and status in the last run
+fn tooltip(
+    node: &DotNode,
+    graph: &Graph,
+    status: Option<&DiffStatus>,
+    last_run: Option<&RunResults>,
+) -> String {
+    let mut lines = vec![node.name.clone()];
+    match graph.nodes.get(&node.name) {
+        Some(graph_node) => {
+            lines.push(format!("Kind: {}", node_kind(graph_node)));
+            lines.push(format!("Path: {}", graph_node.path));
+            lines.push(format!(
+                "Materialization: {}",
+                graph_node.materialize.as_deref().unwrap_or("view")
+            ));
+        }
+        None if status.is_some() => {}
+        None => lines.push("Kind: source".into()),
+    }
+    if let Some(description) = &node.tooltip {
+        lines.push(description.clone());
+    }
+    if let Some(status) = status {
+        lines.push(format!(
+            "Change: {}",
+            format!("{:?}", status).to_lowercase()
+        ));
+    }
+    if let Some(last_run) = last_run.filter(|_| graph.nodes.contains_key(&node.name)) {
+        lines.push(format!(
+            "Last run: {}",
+            last_run_status(&node.name, last_run).unwrap_or("not selected")
+        ));
+        if let Some(built_at) = last_run.last_successful_runs.get(&node.name) {
+            lines.push(format!("Last built: {}", built_at.to_rfc3339()));
+        }
+    }
+    lines.join("\n")
+}
+
+fn last_run_status(name: &String, last_run: &RunResults) -> Option<&'static str> {
+    if last_run.failed.contains(name) {
+        Some("error")
+    } else if last_run.skipped.contains(name) {
+        Some("skipped")
+    } else if last_run.selected.contains(name) {
+        Some("success")
+    } else {
+        None
+    }
+}
+
+/// Build a self-contained HTML page drawing nodes and (from, to) edges,
+/// styled by materialization like Mermaid exports, and by diff status when
+/// `statuses` is given. Nodes that failed or were skipped in `last_run` are
+/// outlined.
+pub fn to_html_graph(
+    nodes: &[DotNode],
+    edges: &[(String, String)],
+    graph: &Graph,
+    statuses: Option<&BTreeMap<String, DiffStatus>>,
+    last_run: Option<&RunResults>,
+) -> String {
+    let names = nodes.iter().map(|n| n.name.clone()).collect::<Vec<_>>();
+    let positions = layout(&names, edges);
+    let n_layers = positions
+        .iter()
+        .map(|(layer, _)| layer + 1)
+        .max()
+        .unwrap_or(1);
+    let mut layer_sizes = vec![0; n_layers];
+    for (layer, _) in &positions {
+        layer_sizes[*layer] += 1;
+    }
+    let n_rows = layer_sizes.iter().max().copied().unwrap_or(1);
+    // Layers are centered vertically
+    let coordinates = positions
+        .iter()
+        .map(|(layer, row)| {
+            let offset = (n_rows - layer_sizes[*layer]) * ROW_GAP / 2;
+            (layer * LAYER_GAP + 20, row * ROW_GAP + offset + 20)
+        })
+        .collect::<Vec<_>>();
+    let index = names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| (name.as_str(), i))
+        .collect::<BTreeMap<_, _>>();
+
+    let mut svg = String::new();
+    for (from, to) in edges {
+        let (Some(&from), Some(&to)) = (index.get(from.as_str()), index.get(to.as_str())) else {
+            continue;
+        };
+        let (x1, y1) = (
+            coordinates[from].0 + NODE_WIDTH,
+            coordinates[from].1 + NODE_HEIGHT / 2,
+        );
+        let (x2, y2) = (coordinates[to].0, coordinates[to].1 + NODE_HEIGHT / 2);
+        let middle = (x1 + x2) / 2;
+        svg.push_str(&format!(
+            "<path class=\"edge\" marker-end=\"url(#arrow)\" d=\"M{x1},{y1} C{middle},{y1} {middle},{y2} {x2},{y2}\"/>\n"
+        ));
+    }
+    for (node, (x, y)) in nodes.iter().zip(&coordinates) {
+        let status = statuses.and_then(|s| s.get(&node.name));
+        let mut classes = vec![
+            "node".to_string(),
+            node_class(&node.name, graph, status == Some(&DiffStatus::Removed)),
+        ];
+        match status {
+            Some(DiffStatus::Added) => classes.push("added".into()),
+            Some(DiffStatus::Removed) => classes.push("removed".into()),
+            Some(DiffStatus::Modified) => classes.push("modified".into()),
+            _ => {}
+        }
+        match last_run.and_then(|r| last_run_status(&node.name, r)) {
+            Some("error") => classes.push("run-error".into()),
+            Some("skipped") => classes.push("run-skipped".into()),
+            _ => {}
+        }
+        let mut label = node.name.chars().take(LABEL_LENGTH).collect::<String>();
+        if label.len() < node.name.len() {
+            label.push('…');
+        }
+        svg.push_str(&format!(
+            "<g class=\"{}\" data-name=\"{}\" data-tooltip=\"{}\"><rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" rx=\"4\"/><text x=\"{}\" y=\"{}\">{}</text></g>\n",
+            classes.join(" "),
+            escape(&node.name),
+            escape(&tooltip(node, graph, status, last_run)),
+            x,
+            y,
+            NODE_WIDTH,
+            NODE_HEIGHT,
+            x + 8,
+            y + NODE_HEIGHT / 2,
+            escape(&label)
+        ));
+    }
+
+    // Node colors follow the classes of Mermaid exports
+    let class_styles = CLASS_STYLES
+        .iter()
+        .map(|(class, style)| format!(".{} rect {{ {}; }}", class, style.replace(',', "; ")))
+        .collect::<Vec<_>>()
+        .join("\n");
+    // An empty selection still gives a page, with an empty graph
+    let width = n_layers.saturating_sub(1) * LAYER_GAP + NODE_WIDTH + 40;
+    let height = n_rows.saturating_sub(1) * ROW_GAP + NODE_HEIGHT + 40;
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>arnab DAG</title>\n<style>\n{}\n{}\n</style>\n</head>\n<body>\n\
+         <div id=\"toolbar\"><input id=\"search\" placeholder=\"Search nodes, Enter to center the first match\"> <span id=\"count\"></span></div>\n\
+         <svg id=\"graph\" viewBox=\"0 0 {} {}\" preserveAspectRatio=\"xMinYMin meet\">\n\
+         <defs><marker id=\"arrow\" viewBox=\"0 0 10 10\" refX=\"10\" refY=\"5\" markerWidth=\"6\" markerHeight=\"6\" orient=\"auto\"><path d=\"M0,0 L10,5 L0,10 z\" fill=\"#aaa\"/></marker></defs>\n\
+         <g id=\"viewport\">\n{}</g>\n</svg>\n<div id=\"tooltip\"></div>\n<script>\nconst NODE_WIDTH = {};\n{}\n</script>\n</body>\n</html>\n",
+        STYLE, class_styles, width, height, svg, NODE_WIDTH, SCRIPT
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{layout, to_html_graph};
+    use crate::{
+        graph::GraphBuilder,
+        graphviz::{source_nodes, DotNode},
+        node::{Node, NodeKind},
+        run_results::RunResults,
+    };
+
+    #[test]
+    fn layered_layout() {
+        let names = ["a", "b", "c", "d", "e"].map(String::from);
+        let edges = [("a", "c"), ("b", "d"), ("c", "e"), ("a", "e")]
+            .map(|(from, to)| (from.to_string(), to.to_string()));
+        // `d` is drawn below `c`, following their upstream nodes
+        assert_eq!(
+            layout(&names, &edges),
+            vec![(0, 0), (0, 1), (1, 0), (1, 1), (2, 0)]
+        );
+    }
+
+    #[test]
+    fn export_html_graph() {
+        let mut builder = GraphBuilder::new();
+        let mut orders = Node::new(NodeKind::Sql, "models/orders.sql", "orders", "");
+        orders.materialize = Some("table".into());
+        orders.sources.insert(("raw".into(), "orders".into()));
+        builder.add_node(orders).unwrap();
+        builder
+            .add_node(Node::new(NodeKind::Sql, "models/users.sql", "users", ""))
+            .unwrap();
+        builder.add_dependency("users", "orders").unwrap();
+        let graph = builder.build().unwrap();
+
+        let ids = vec!["orders".to_string(), "users".to_string()];
+        let (mut nodes, mut edges) = source_nodes(&ids, &graph);
+        nodes.extend(ids.iter().map(|id| DotNode {
+            name: id.clone(),
+            fill_color: None,
+            tooltip: None,
+        }));
+        edges.extend(graph.edges(&ids));
+        let last_run = RunResults {
+            selected: ids.clone(),
+            failed: vec!["users".into()],
+            ..Default::default()
+        };
+        let html = to_html_graph(&nodes, &edges, &graph, None, Some(&last_run));
+
+        assert!(html.contains(
+            "<g class=\"node source\" data-name=\"raw.orders\" data-tooltip=\"raw.orders\nKind: source\">"
+        ));
+        assert!(html.contains(
+            "data-tooltip=\"orders\nKind: model\nPath: models/orders.sql\nMaterialization: table\nLast run: success\""
+        ));
+        assert!(html.contains("<g class=\"node view run-error\" data-name=\"users\""));
+        assert!(html.contains(
+            "<path class=\"edge\" marker-end=\"url(#arrow)\" d=\"M200,34 C230,34 230,34 260,34\"/>"
+        ));
+        assert!(html.contains(".table rect { fill:#f5deb3; stroke:#cd853f; }"));
+        assert!(!html.contains("src=\"http"));
+    }
+
+    #[test]
+    fn export_empty_html_graph() {
+        let graph = GraphBuilder::new().build().unwrap();
+        let html = to_html_graph(&[], &[], &graph, None, None);
+        assert!(html.contains("viewBox=\"0 0 220 68\""));
+    }
+}
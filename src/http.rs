@@ -0,0 +1,191 @@
+//! Minimal HTTP client for outgoing notifications, e.g., catalog
+//! registrations. `http://` URLs are served over a plain TCP connection, and
+//! `https://` ones through `curl`, which handles TLS.
+
+use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
+    net::TcpStream,
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+/// How long to wait for a server to accept and answer a request
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Host, port and path of an `http://` URL
+#[derive(Debug, PartialEq)]
+struct HttpUrl {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+fn parse_http_url(url: &str) -> Result<HttpUrl, String> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| format!("Unsupported URL `{}`", url))?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], rest[i..].to_string()),
+        None => (rest, "/".to_string()),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse()
+                .map_err(|_| format!("Invalid port in URL `{}`", url))?,
+        ),
+        None => (authority, 80),
+    };
+    if host.is_empty() {
+        return Err(format!("Missing host in URL `{}`", url));
+    }
+    Ok(HttpUrl {
+        host: host.to_string(),
+        port,
+        path,
+    })
+}
+
+/// POST a JSON body, failing unless the server answers with a 2xx status
+pub fn post_json(url: &str, headers: &BTreeMap<String, String>, body: &str) -> Result<(), String> {
+    if url.starts_with("https://") {
+        return post_json_with_curl(url, headers, body);
+    }
+    let url_parts = parse_http_url(url)?;
+    let mut stream = TcpStream::connect((url_parts.host.as_str(), url_parts.port))
+        .map_err(|e| format!("Cannot connect to {}: {}", url, e))?;
+    let _ = stream.set_read_timeout(Some(TIMEOUT));
+    let _ = stream.set_write_timeout(Some(TIMEOUT));
+
+    let mut request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        url_parts.path,
+        url_parts.host,
+        body.len()
+    );
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("\r\n");
+    request.push_str(body);
+    stream
+        .write_all(request.as_bytes())
+        .map_err(|e| format!("Cannot send request to {}: {}", url, e))?;
+
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .map_err(|e| format!("Cannot read response of {}: {}", url, e))?;
+    let status_line = response.lines().next().unwrap_or_default();
+    match status_line.split_whitespace().nth(1) {
+        Some(status) if status.starts_with('2') => Ok(()),
+        _ => Err(format!("{} answered `{}`", url, status_line)),
+    }
+}
+
+/// Quote a value of a curl config file
+fn curl_quote(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+        .replace('\t', "\\t");
+    format!("\"{}\"", escaped)
+}
+
+/// Config of a curl request, read on stdin so that the URL, headers and body
+/// don't show up in the arguments of the process, e.g., in `ps`, as URLs of
+/// webhooks and authorization headers are secrets
+fn curl_config(url: &str, headers: &BTreeMap<String, String>, body: &str) -> String {
+    let mut lines = vec![
+        format!("url = {}", curl_quote(url)),
+        format!("header = {}", curl_quote("Content-Type: application/json")),
+    ];
+    for (name, value) in headers {
+        lines.push(format!(
+            "header = {}",
+            curl_quote(&format!("{}: {}", name, value))
+        ));
+    }
+    lines.push(format!("data-binary = {}", curl_quote(body)));
+    lines.join("\n") + "\n"
+}
+
+fn post_json_with_curl(
+    url: &str,
+    headers: &BTreeMap<String, String>,
+    body: &str,
+) -> Result<(), String> {
+    let mut child = Command::new("curl")
+        .args([
+            "--silent",
+            "--show-error",
+            "--fail",
+            "--max-time",
+            &TIMEOUT.as_secs().to_string(),
+            "--config",
+            "-",
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Cannot run curl for {}: {}", url, e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(curl_config(url, headers, body).as_bytes())
+            .map_err(|e| e.to_string())?;
+    }
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    match output.status.success() {
+        true => Ok(()),
+        false => Err(String::from_utf8_lossy(&output.stderr).trim().to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::{curl_config, parse_http_url, HttpUrl};
+
+    #[test]
+    fn parse_urls() {
+        assert_eq!(
+            parse_http_url("http://catalog:8080/api/tables").unwrap(),
+            HttpUrl {
+                host: "catalog".into(),
+                port: 8080,
+                path: "/api/tables".into()
+            }
+        );
+        assert_eq!(
+            parse_http_url("http://localhost").unwrap(),
+            HttpUrl {
+                host: "localhost".into(),
+                port: 80,
+                path: "/".into()
+            }
+        );
+        assert!(parse_http_url("ftp://catalog/tables").is_err());
+        assert!(parse_http_url("http://catalog:port/").is_err());
+    }
+
+    #[test]
+    fn write_curl_config() {
+        let headers = BTreeMap::from([("Authorization".to_string(), "Bearer s3cr\"t".to_string())]);
+        assert_eq!(
+            curl_config(
+                "https://hooks.slack.com/services/T0/B0/x",
+                &headers,
+                "{\"text\": \"a\\nb\"}"
+            ),
+            "url = \"https://hooks.slack.com/services/T0/B0/x\"\n\
+             header = \"Content-Type: application/json\"\n\
+             header = \"Authorization: Bearer s3cr\\\"t\"\n\
+             data-binary = \"{\\\"text\\\": \\\"a\\\\nb\\\"}\"\n"
+        );
+    }
+}
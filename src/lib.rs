@@ -0,0 +1,79 @@
+//! Arnab as a library, for programs embedding pipeline runs. The session
+//! of a run is planned and executed in two steps, so that the database can be
+//! checked in between:
+//!
+//! ```no_run
+//! use arnab::{duckdb::Connection, Config, RunOptions, Session};
+//!
+//! let (_, config) = Config::discover(".".as_ref()).unwrap().unwrap();
+//! let mut session = Session::new(config, Connection::open("pipeline.duckdb").unwrap());
+//! let plan = session.plan(&RunOptions::default()).unwrap();
+//! let n_orders: i64 = session
+//!     .connection()
+//!     .query_row("SELECT count(*) FROM raw.orders", [], |row| row.get(0))
+//!     .unwrap();
+//! if n_orders > 0 {
+//!     session.execute_plan(&plan).unwrap();
+//! }
+//! ```
+
+mod advise;
+mod bootstrap;
+mod build_cache;
+mod catalog;
+mod cli;
+mod codegen;
+mod contracts;
+mod dashboard;
+mod data_tests;
+mod docs;
+mod ephemeral;
+pub mod errors;
+mod events;
+mod graph;
+mod graphml;
+mod graphviz;
+mod hooks;
+mod html_graph;
+mod http;
+mod listing;
+mod macros;
+mod maintenance;
+mod manifest;
+mod mermaid;
+pub mod node;
+mod notifications;
+mod pins;
+mod plan;
+mod preview_cache;
+mod progress;
+mod project_checks;
+mod provenance;
+mod publish;
+mod python;
+mod remote;
+mod run_results;
+mod schedule;
+mod schema;
+mod script;
+mod seed;
+mod selection;
+mod server;
+mod session;
+mod settings;
+mod shell;
+mod source;
+mod test_results;
+mod text_width;
+mod threads;
+mod time_format;
+mod verbosity;
+mod watch;
+
+#[doc(hidden)]
+pub use cli::main;
+pub use duckdb;
+pub use errors::ArnabError;
+pub use plan::ExecutionPlan;
+pub use run_results::RunSummary;
+pub use session::{Config, RunOptions, Session};
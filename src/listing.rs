@@ -0,0 +1,157 @@
+use serde::Serialize;
+
+use crate::{
+    graph::Graph,
+    macros::MacroDoc,
+    node::NodeKind,
+    text_width::{display_width, pad_to_width},
+};
+
+/// Output format of `arnab ls`
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub enum ListFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Resources listed by `arnab ls`
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub enum ListResource {
+    #[default]
+    Model,
+    Macro,
+}
+
+/// A model as listed by `arnab ls`
+#[derive(Debug, Serialize)]
+pub struct ModelEntry {
+    pub(crate) id: String,
+    pub(crate) kind: &'static str,
+    pub(crate) path: String,
+    pub(crate) materialize: String,
+    pub(crate) tags: Vec<String>,
+    pub(crate) description: Option<String>,
+    /// Direct dependencies, sorted
+    pub(crate) depends_on: Vec<String>,
+}
+
+/// Entries of the given nodes of a graph, in the given order
+pub fn model_entries(graph: &Graph, ids: &[String]) -> Vec<ModelEntry> {
+    ids.iter()
+        .map(|id| {
+            let node = &graph.nodes[id];
+            let mut depends_on = node.prevs.iter().cloned().collect::<Vec<_>>();
+            depends_on.sort();
+            ModelEntry {
+                id: id.clone(),
+                kind: match node.node_kind {
+                    NodeKind::Sql | NodeKind::Python | NodeKind::Shell => "model",
+                    NodeKind::Seed => "seed",
+                },
+                path: node.path.clone(),
+                materialize: node.materialize.clone().unwrap_or("view".into()),
+                tags: node.tags.clone(),
+                description: node.description().map(String::from),
+                depends_on,
+            }
+        })
+        .collect()
+}
+
+/// Format entries as a table with aligned columns. Only the first line of
+/// descriptions is shown.
+pub fn format_table(entries: &[ModelEntry]) -> String {
+    let header = [
+        "ID",
+        "KIND",
+        "MATERIALIZE",
+        "PATH",
+        "TAGS",
+        "DEPENDS ON",
+        "DESCRIPTION",
+    ]
+    .map(String::from)
+    .to_vec();
+    let rows = entries.iter().map(|e| {
+        vec![
+            e.id.clone(),
+            e.kind.to_string(),
+            e.materialize.clone(),
+            e.path.clone(),
+            e.tags.join(","),
+            e.depends_on.join(","),
+            e.description
+                .as_deref()
+                .and_then(|d| d.lines().next())
+                .unwrap_or("")
+                .to_string(),
+        ]
+    });
+    align_columns(&std::iter::once(header).chain(rows).collect::<Vec<_>>())
+}
+
+pub fn format_macro_table(docs: &[MacroDoc]) -> String {
+    let header = ["MACRO", "FILE", "DESCRIPTION"].map(String::from).to_vec();
+    let rows = docs.iter().map(|doc| {
+        vec![
+            doc.signature(),
+            doc.file.clone(),
+            doc.description
+                .as_deref()
+                .and_then(|d| d.lines().next())
+                .unwrap_or("")
+                .to_string(),
+        ]
+    });
+    align_columns(&std::iter::once(header).chain(rows).collect::<Vec<_>>())
+}
+
+/// Join rows of cells into lines, padding cells to align columns
+pub fn align_columns(rows: &[Vec<String>]) -> String {
+    let n_columns = rows.iter().map(|r| r.len()).max().unwrap_or(0);
+    let widths = (0..n_columns)
+        .map(|i| {
+            rows.iter()
+                .filter_map(|r| r.get(i))
+                .map(|cell| display_width(cell))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect::<Vec<_>>();
+    rows.iter()
+        .map(|row| {
+            row.iter()
+                .zip(&widths)
+                .map(|(cell, width)| pad_to_width(cell, *width))
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{format_table, ModelEntry};
+
+    #[test]
+    fn align_table_columns() {
+        let entries = vec![ModelEntry {
+            id: "orders".into(),
+            kind: "model",
+            path: "models/orders.sql".into(),
+            materialize: "table".into(),
+            tags: vec!["daily".into(), "finance".into()],
+            description: Some("Orders of the day\nOne row per order".into()),
+            depends_on: vec!["stg_orders".into()],
+        }];
+        assert_eq!(
+            format_table(&entries),
+            "ID      KIND   MATERIALIZE  PATH               TAGS           DEPENDS ON  DESCRIPTION\n\
+             orders  model  table        models/orders.sql  daily,finance  stg_orders  Orders of the day"
+        );
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::{BTreeMap, HashMap};
+
+use regex::Regex;
+use serde::Serialize;
+
+use crate::errors::ArnabError;
+
+/// A macro as documented by `arnab docs` and listed by `arnab ls --resource macro`
+#[derive(Debug, PartialEq, Serialize)]
+pub struct MacroDoc {
+    pub(crate) name: String,
+    /// File defining the macro, relative to `macro_path`
+    pub(crate) file: String,
+    /// Arguments as declared, with their default values, e.g., `sep=', '`
+    pub(crate) args: Vec<String>,
+    /// Jinja comment right before the definition, e.g., `{# Amount in cents #}`
+    pub(crate) description: Option<String>,
+}
+
+impl MacroDoc {
+    /// Signature of the macro, e.g., `cents(x, precision=2)`
+    pub fn signature(&self) -> String {
+        format!("{}({})", self.name, self.args.join(", "))
+    }
+}
+
+/// Load user-defined macro files from `macro_path`, keyed by their file name.
+/// Macro files are registered as templates under that name, so they can
+/// import or include each other, e.g., `{% import "dates.sql" as dates %}`.
+pub fn load_macros(macro_path: &str) -> Result<HashMap<String, String>, ArnabError> {
+    let pattern = std::path::Path::new(macro_path).join("*.*");
+    let macro_paths = glob::glob(pattern.to_str().unwrap()).unwrap();
+    let mut macros = HashMap::new();
+    for path_opt in macro_paths {
+        let path = path_opt.unwrap();
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        let macro_src =
+            std::fs::read_to_string(&path).map_err(|e| ArnabError::Error(e.to_string()))?;
+        macros.insert(name, macro_src);
+    }
+    validate_macros(&macros)?;
+    Ok(macros)
+}
+
+/// Documentation of the macros defined in macro files, sorted by file and
+/// in order of definition
+pub fn macro_docs(macros: &HashMap<String, String>) -> Vec<MacroDoc> {
+    // A doc comment can't contain `#}`, so it is the one closest to the macro
+    let definition_re =
+        Regex::new(r"(?:\{#-?((?:[^#]|#[^}])*?)-?#\}\s*)?\{%-?\s*macro\s+(\w+)\s*\(([^)]*)\)")
+            .unwrap();
+    let sorted = macros.iter().collect::<BTreeMap<_, _>>();
+    let mut docs = vec![];
+    for (file, src) in sorted {
+        for cap in definition_re.captures_iter(src) {
+            let description = cap
+                .get(1)
+                .map(|doc| {
+                    doc.as_str()
+                        .lines()
+                        .map(str::trim)
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                        .trim()
+                        .to_string()
+                })
+                .filter(|doc| !doc.is_empty());
+            docs.push(MacroDoc {
+                name: cap[2].to_string(),
+                file: file.clone(),
+                args: split_args(&cap[3]),
+                description,
+            });
+        }
+    }
+    docs
+}
+
+/// Split macro arguments on the commas outside of quoted default values
+fn split_args(args: &str) -> Vec<String> {
+    let mut split = vec![];
+    let mut current = String::new();
+    let mut quote = None;
+    for c in args.chars() {
+        match (c, quote) {
+            (',', None) => split.push(std::mem::take(&mut current)),
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            _ => {}
+        }
+        if c != ',' || quote.is_some() {
+            current.push(c);
+        }
+    }
+    split.push(current);
+    split
+        .into_iter()
+        .map(|arg| arg.trim().to_string())
+        .filter(|arg| !arg.is_empty())
+        .collect()
+}
+
+/// Check macro files for duplicate macro definitions and circular
+/// imports/includes, reporting the files involved
+pub fn validate_macros(macros: &HashMap<String, String>) -> Result<(), ArnabError> {
+    let sorted = macros.iter().collect::<BTreeMap<_, _>>();
+
+    let definition_re = Regex::new(r"\{%-?\s*macro\s+(\w+)").unwrap();
+    let mut defined_in: BTreeMap<&str, &str> = BTreeMap::new();
+    for (file, src) in &sorted {
+        for cap in definition_re.captures_iter(src) {
+            let macro_name = cap.get(1).unwrap().as_str();
+            if let Some(other_file) = defined_in.insert(macro_name, file) {
+                return Err(ArnabError::Error(format!(
+                    "Macro `{}` is defined more than once, in {} and {}",
+                    macro_name, other_file, file
+                )));
+            }
+        }
+    }
+
+    let include_re =
+        Regex::new(r#"\{%-?\s*(?:import|include|from|extends)\s+["']([^"']+)["']"#).unwrap();
+    let includes = sorted
+        .iter()
+        .map(|(file, src)| {
+            let targets = include_re
+                .captures_iter(src)
+                .map(|cap| cap.get(1).unwrap().as_str())
+                .collect::<Vec<_>>();
+            (file.as_str(), targets)
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    for (file, targets) in &includes {
+        for target in targets {
+            if !macros.contains_key(*target) {
+                return Err(ArnabError::Error(format!(
+                    "Macro file {} includes unknown macro file {}",
+                    file, target
+                )));
+            }
+        }
+    }
+
+    let mut finished = vec![];
+    for file in includes.keys() {
+        let mut chain = vec![];
+        find_cycle(file, &includes, &mut chain, &mut finished)?;
+    }
+    Ok(())
+}
+
+fn find_cycle<'a>(
+    file: &'a str,
+    includes: &BTreeMap<&'a str, Vec<&'a str>>,
+    chain: &mut Vec<&'a str>,
+    finished: &mut Vec<&'a str>,
+) -> Result<(), ArnabError> {
+    if finished.contains(&file) {
+        return Ok(());
+    }
+    if let Some(pos) = chain.iter().position(|f| *f == file) {
+        let mut cycle = chain[pos..].to_vec();
+        cycle.push(file);
+        return Err(ArnabError::Error(format!(
+            "Circular macro include: {}",
+            cycle.join(" -> ")
+        )));
+    }
+    chain.push(file);
+    for target in &includes[file] {
+        find_cycle(target, includes, chain, finished)?;
+    }
+    chain.pop();
+    finished.push(file);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{macro_docs, validate_macros, MacroDoc};
+
+    fn macros(files: &[(&str, &str)]) -> HashMap<String, String> {
+        files
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn detect_include_cycle() {
+        let files = macros(&[
+            ("a.sql", "{% import 'b.sql' as b %}"),
+            ("b.sql", "{% include 'c.sql' %}"),
+            ("c.sql", "{% from 'a.sql' import x %}"),
+        ]);
+        let err = validate_macros(&files).unwrap_err();
+        assert_eq!(
+            err.message(),
+            "Circular macro include: a.sql -> b.sql -> c.sql -> a.sql"
+        );
+
+        let files = macros(&[("a.sql", "{% import 'b.sql' as b %}"), ("b.sql", "")]);
+        assert!(validate_macros(&files).is_ok());
+    }
+
+    #[test]
+    fn detect_duplicate_macro() {
+        let files = macros(&[
+            ("a.sql", "{% macro cents(x) %}{{ x }}{% endmacro %}"),
+            ("b.sql", "{%- macro cents(y) %}{{ y }}{% endmacro %}"),
+        ]);
+        let err = validate_macros(&files).unwrap_err();
+        assert_eq!(
+            err.message(),
+            "Macro `cents` is defined more than once, in a.sql and b.sql"
+        );
+    }
+
+    #[test]
+    fn document_macros() {
+        let files = macros(&[
+            (
+                "money.sql",
+                "{# Unrelated #}\n{# Amount in cents,\n   rounded #}\n\
+                 {% macro cents(x, precision=2) %}{{ x }}{% endmacro %}\n\
+                 {%- macro join_all(cols, sep=', ') %}{% endmacro %}",
+            ),
+            ("dates.sql", "{% macro today() %}current_date{% endmacro %}"),
+        ]);
+        let doc = |name: &str, file: &str, args: &[&str], description: Option<&str>| MacroDoc {
+            name: name.into(),
+            file: file.into(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            description: description.map(Into::into),
+        };
+        let docs = macro_docs(&files);
+        assert_eq!(
+            docs,
+            vec![
+                doc("today", "dates.sql", &[], None),
+                doc(
+                    "cents",
+                    "money.sql",
+                    &["x", "precision=2"],
+                    Some("Amount in cents,\nrounded")
+                ),
+                doc("join_all", "money.sql", &["cols", "sep=', '"], None),
+            ]
+        );
+        assert_eq!(docs[2].signature(), "join_all(cols, sep=', ')");
+    }
+}
@@ -0,0 +1,144 @@
+use std::path::Path;
+
+use duckdb::Connection;
+use serde::Deserialize;
+
+use crate::{
+    errors::ArnabError,
+    verbosity::{enabled, info, Verbosity},
+};
+
+/// Database maintenance to run at the end of a pipeline run. Repeatedly
+/// rebuilding tables leaves free blocks behind, growing the database file.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+pub struct MaintenanceInfo {
+    /// Run `VACUUM`
+    #[serde(default)]
+    pub(crate) vacuum: bool,
+    /// Run `CHECKPOINT` on each database, writing the WAL to the database
+    /// file and reclaiming free blocks
+    #[serde(default)]
+    pub(crate) checkpoint: bool,
+    /// Print the size of each database
+    #[serde(default)]
+    pub(crate) report_size: bool,
+}
+
+impl MaintenanceInfo {
+    /// All maintenance tasks, as run by `arnab maintain`
+    pub fn all() -> Self {
+        Self {
+            vacuum: true,
+            checkpoint: true,
+            report_size: true,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !(self.vacuum || self.checkpoint || self.report_size)
+    }
+}
+
+pub fn run_maintenance(conn: &Connection, info: &MaintenanceInfo) -> Result<(), ArnabError> {
+    let to_error = |e: duckdb::Error| ArnabError::Error(e.to_string());
+    if info.vacuum {
+        info!("Running VACUUM");
+        conn.execute_batch("VACUUM;").map_err(to_error)?;
+    }
+    if info.checkpoint {
+        for database in user_databases(conn).map_err(to_error)? {
+            info!("Running CHECKPOINT on {}", database);
+            conn.execute_batch(&format!("CHECKPOINT \"{}\";", database))
+                .map_err(to_error)?;
+        }
+    }
+    if info.report_size && enabled(Verbosity::Normal) {
+        report_size(conn).map_err(to_error)?;
+    }
+    Ok(())
+}
+
+/// Main and attached databases, excluding DuckDB's internal ones
+fn user_databases(conn: &Connection) -> Result<Vec<String>, duckdb::Error> {
+    let mut stmt =
+        conn.prepare("SELECT database_name FROM duckdb_databases() WHERE NOT internal")?;
+    let names = stmt
+        .query_map([], |r| r.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(names)
+}
+
+fn report_size(conn: &Connection) -> Result<(), duckdb::Error> {
+    let mut stmt = conn.prepare(
+        "SELECT database_name, database_size, used_blocks, free_blocks, wal_size \
+         FROM pragma_database_size() ORDER BY database_name",
+    )?;
+    let mut rows = stmt.query([])?;
+    println!("Database sizes:");
+    while let Some(row) = rows.next()? {
+        println!(
+            "  {}: {} ({} used blocks, {} free blocks, WAL {})",
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, i64>(2)?,
+            row.get::<_, i64>(3)?,
+            row.get::<_, String>(4)?,
+        );
+    }
+    Ok(())
+}
+
+/// File format of exported tables
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum ExportFormat {
+    Csv,
+    Parquet,
+}
+
+/// Export the main database with `EXPORT DATABASE`: a directory with the
+/// schema (`schema.sql`), the load script (`load.sql`), and a file per table
+pub fn export_database(
+    conn: &Connection,
+    dir: &Path,
+    format: ExportFormat,
+) -> Result<(), ArnabError> {
+    if dir.exists() && dir.read_dir().map_or(true, |mut d| d.next().is_some()) {
+        return Err(ArnabError::Error(format!(
+            "Cannot export to {}, it is not an empty directory",
+            dir.display()
+        )));
+    }
+    if let Some(parent) = dir.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent).map_err(|e| ArnabError::Error(e.to_string()))?;
+    }
+    let format = match format {
+        ExportFormat::Csv => "CSV",
+        ExportFormat::Parquet => "PARQUET",
+    };
+    let sql = format!(
+        "EXPORT DATABASE '{}' (FORMAT {});",
+        dir.to_string_lossy().replace('\'', "''"),
+        format
+    );
+    conn.execute_batch(&sql)
+        .map_err(|e| ArnabError::Error(e.to_string()))
+}
+
+/// Import a directory written by `export_database` into the main database
+pub fn import_database(conn: &Connection, dir: &Path) -> Result<(), ArnabError> {
+    for file in ["schema.sql", "load.sql"] {
+        if !dir.join(file).is_file() {
+            return Err(ArnabError::Error(format!(
+                "{} is not an exported database, {} is missing",
+                dir.display(),
+                file
+            )));
+        }
+    }
+    let sql = format!(
+        "IMPORT DATABASE '{}';",
+        dir.to_string_lossy().replace('\'', "''")
+    );
+    conn.execute_batch(&sql)
+        .map_err(|e| ArnabError::Error(e.to_string()))
+}
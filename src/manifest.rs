@@ -0,0 +1,286 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{errors::ArnabError, graph::Graph, time_format::iso_now};
+
+/// Directory where arnab writes its artifacts, unless `target_path` is set
+pub const DEFAULT_TARGET_PATH: &str = "target";
+/// Environment variable overriding `target_path`, e.g., to keep CI outputs in
+/// its workspace
+pub const TARGET_PATH_ENV: &str = "ARNAB_TARGET_PATH";
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Snapshot of the project graph, written after each run so that later runs
+/// (or other environments) can compare against it
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub(crate) generated_at: String,
+    pub(crate) nodes: BTreeMap<String, ManifestNode>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ManifestNode {
+    pub(crate) path: String,
+    pub(crate) materialize: String,
+    /// SHA-256 of the rendered SQL
+    pub(crate) checksum: String,
+    pub(crate) depends_on: Vec<String>,
+    /// Source tables used by the node, as `source_name.table_name`
+    #[serde(default)]
+    pub(crate) sources: Vec<String>,
+    pub(crate) compiled_sql: String,
+    /// Columns declared in the model's schema, its contract
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) columns: Vec<ManifestColumn>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ManifestColumn {
+    pub(crate) name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) data_type: Option<String>,
+}
+
+/// How a node changed between two manifests
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiffStatus {
+    Added,
+    Removed,
+    Modified,
+    Unchanged,
+}
+
+pub fn checksum(src: &str) -> String {
+    Sha256::digest(src.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+impl Manifest {
+    /// Manifest of the given nodes of a graph
+    pub fn from_graph(graph: &Graph, ids: &[String]) -> Self {
+        let nodes = ids
+            .iter()
+            .map(|id| {
+                let node = &graph.nodes[id];
+                let mut depends_on = node.prevs.iter().cloned().collect::<Vec<_>>();
+                depends_on.sort();
+                let mut sources = node
+                    .sources
+                    .iter()
+                    .map(|(source, table)| format!("{}.{}", source, table))
+                    .collect::<Vec<_>>();
+                sources.sort();
+                let manifest_node = ManifestNode {
+                    path: node.path.clone(),
+                    materialize: node.materialize.clone().unwrap_or("view".into()),
+                    checksum: checksum(&node.rendered_src),
+                    depends_on,
+                    sources,
+                    compiled_sql: node.rendered_src.clone(),
+                    columns: node
+                        .schema
+                        .iter()
+                        .flat_map(|schema| &schema.columns)
+                        .map(|column| ManifestColumn {
+                            name: column.name.clone(),
+                            data_type: column.data_type.clone(),
+                        })
+                        .collect(),
+                };
+                (id.clone(), manifest_node)
+            })
+            .collect();
+        Self {
+            generated_at: iso_now(),
+            nodes,
+        }
+    }
+
+    /// Load a manifest from its file, or from the directory containing it
+    pub fn load(path: &Path) -> Result<Self, ArnabError> {
+        let file_path = if path.is_dir() {
+            path.join(MANIFEST_FILE)
+        } else {
+            path.to_path_buf()
+        };
+        let src = std::fs::read_to_string(&file_path).map_err(|e| {
+            ArnabError::Error(format!("Cannot read {}: {}", file_path.display(), e))
+        })?;
+        serde_json::from_str(&src).map_err(|e| {
+            ArnabError::Error(format!("Invalid manifest {}: {}", file_path.display(), e))
+        })
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<PathBuf, ArnabError> {
+        std::fs::create_dir_all(dir).map_err(|e| ArnabError::Error(e.to_string()))?;
+        let file_path = dir.join(MANIFEST_FILE);
+        let json = serde_json::to_string_pretty(self).unwrap();
+        std::fs::write(&file_path, json).map_err(|e| ArnabError::Error(e.to_string()))?;
+        Ok(file_path)
+    }
+
+    /// Update the entries of the given nodes from the graph. Other nodes keep
+    /// the entries of earlier runs, so that models changed but not rebuilt
+    /// are still reported as modified, and nodes no longer in the graph are
+    /// dropped.
+    pub fn update(&mut self, graph: &Graph, ids: &[String]) {
+        let updated = Self::from_graph(graph, ids);
+        self.nodes.retain(|id, _| graph.nodes.contains_key(id));
+        self.nodes.extend(updated.nodes);
+        self.generated_at = updated.generated_at;
+    }
+
+    /// Compare this manifest against an older one. The result covers nodes of
+    /// both manifests.
+    pub fn diff(&self, old: &Manifest) -> BTreeMap<String, DiffStatus> {
+        let mut statuses = BTreeMap::new();
+        for (id, node) in &self.nodes {
+            let status = match old.nodes.get(id) {
+                None => DiffStatus::Added,
+                Some(old_node) if old_node.checksum != node.checksum => DiffStatus::Modified,
+                Some(_) => DiffStatus::Unchanged,
+            };
+            statuses.insert(id.clone(), status);
+        }
+        for id in old.nodes.keys() {
+            if !self.nodes.contains_key(id) {
+                statuses.insert(id.clone(), DiffStatus::Removed);
+            }
+        }
+        statuses
+    }
+
+    /// Changes breaking the contracts of the models of an older manifest, i.e.,
+    /// the models declaring columns: removed models, removed columns (a
+    /// renamed column being removed too) and changed column types
+    pub fn breaking_changes(&self, old: &Manifest) -> Vec<String> {
+        let mut changes = vec![];
+        for (id, old_node) in &old.nodes {
+            if old_node.columns.is_empty() {
+                continue;
+            }
+            let Some(node) = self.nodes.get(id) else {
+                changes.push(format!("{}: model was removed", id));
+                continue;
+            };
+            if node.columns.is_empty() {
+                changes.push(format!("{}: columns are no longer declared", id));
+                continue;
+            }
+            for old_column in &old_node.columns {
+                let Some(column) = node.columns.iter().find(|c| c.name == old_column.name) else {
+                    changes.push(format!("{}: column `{}` was removed", id, old_column.name));
+                    continue;
+                };
+                if let (Some(old_type), Some(data_type)) =
+                    (&old_column.data_type, &column.data_type)
+                {
+                    if !old_type.eq_ignore_ascii_case(data_type) {
+                        changes.push(format!(
+                            "{}: column `{}` changed type from {} to {}",
+                            id, column.name, old_type, data_type
+                        ));
+                    }
+                }
+            }
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::{checksum, DiffStatus, Manifest, ManifestColumn, ManifestNode};
+    use crate::{
+        graph::GraphBuilder,
+        node::{Node, NodeKind},
+    };
+
+    fn manifest(nodes: &[(&str, &str)]) -> Manifest {
+        Manifest {
+            generated_at: "".into(),
+            nodes: nodes
+                .iter()
+                .map(|(id, sql)| {
+                    let node = ManifestNode {
+                        path: format!("{}.sql", id),
+                        materialize: "view".into(),
+                        checksum: checksum(sql),
+                        depends_on: vec![],
+                        sources: vec![],
+                        compiled_sql: sql.to_string(),
+                        columns: vec![],
+                    };
+                    (id.to_string(), node)
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn update_built_nodes_only() {
+        let mut builder = GraphBuilder::new();
+        for (id, sql) in [("a", "SELECT 10"), ("b", "SELECT 20")] {
+            let mut node = Node::new(NodeKind::Sql, &format!("{}.sql", id), id, sql);
+            node.rendered_src = sql.into();
+            builder.add_node(node).unwrap();
+        }
+        let graph = builder.build().unwrap();
+
+        let mut updated = manifest(&[("a", "SELECT 1"), ("b", "SELECT 2"), ("c", "SELECT 3")]);
+        updated.update(&graph, &["b".to_string()]);
+        assert_eq!(updated.nodes["a"].checksum, checksum("SELECT 1"));
+        assert_eq!(updated.nodes["b"].checksum, checksum("SELECT 20"));
+        assert!(!updated.nodes.contains_key("c"));
+    }
+
+    #[test]
+    fn diff_manifests() {
+        let old = manifest(&[("a", "SELECT 1"), ("b", "SELECT 2"), ("c", "SELECT 3")]);
+        let new = manifest(&[("a", "SELECT 1"), ("b", "SELECT 20"), ("d", "SELECT 4")]);
+        assert_eq!(
+            new.diff(&old),
+            BTreeMap::from([
+                ("a".to_string(), DiffStatus::Unchanged),
+                ("b".to_string(), DiffStatus::Modified),
+                ("c".to_string(), DiffStatus::Removed),
+                ("d".to_string(), DiffStatus::Added),
+            ])
+        );
+    }
+
+    #[test]
+    fn detect_breaking_changes() {
+        let column = |name: &str, data_type: &str| ManifestColumn {
+            name: name.into(),
+            data_type: Some(data_type.into()),
+        };
+        let mut old = manifest(&[("a", ""), ("b", ""), ("c", ""), ("d", "")]);
+        let mut new = manifest(&[("a", ""), ("b", ""), ("d", "")]);
+        for id in ["a", "b", "c"] {
+            let node = old.nodes.get_mut(id).unwrap();
+            node.columns = vec![column("id", "INTEGER"), column("name", "VARCHAR")];
+        }
+        new.nodes.get_mut("a").unwrap().columns =
+            vec![column("id", "BIGINT"), column("full_name", "VARCHAR")];
+        new.nodes.get_mut("d").unwrap().columns = vec![column("id", "INTEGER")];
+        assert_eq!(
+            new.breaking_changes(&old),
+            vec![
+                "a: column `id` changed type from INTEGER to BIGINT",
+                "a: column `name` was removed",
+                "b: columns are no longer declared",
+                "c: model was removed",
+            ]
+        );
+    }
+}
@@ -0,0 +1,160 @@
+//! Mermaid export of the DAG, as a `flowchart LR` definition that GitHub and
+//! most Markdown renderers draw, e.g., in pull request descriptions. Nodes
+//! are styled by materialization through classes.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{graph::Graph, graphviz::DotNode, manifest::DiffStatus, node::NodeKind};
+
+/// Styles of the node classes, as `property:value` pairs
+pub const CLASS_STYLES: [(&str, &str); 9] = [
+    ("source", "fill:#add8e6,stroke:#4682b4"),
+    ("seed", "fill:#e6e6fa,stroke:#6a5acd"),
+    ("view", "fill:#ffffff,stroke:#808080"),
+    ("table", "fill:#f5deb3,stroke:#cd853f"),
+    (
+        "ephemeral",
+        "fill:#ffffff,stroke:#808080,stroke-dasharray:4 4",
+    ),
+    ("external", "fill:#d3d3d3,stroke:#696969"),
+    ("added", "fill:#98fb98"),
+    ("removed", "fill:#f08080"),
+    ("modified", "fill:#f0e68c"),
+];
+
+/// Class of a node: `source` for source tables, `seed`, or its
+/// materialization
+pub fn node_class(name: &str, graph: &Graph, removed: bool) -> String {
+    match graph.nodes.get(name) {
+        Some(node) if matches!(node.node_kind, NodeKind::Seed) => "seed".into(),
+        Some(node) => node
+            .materialize
+            .as_deref()
+            .unwrap_or("view")
+            .to_lowercase()
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || *c == '_')
+            .collect(),
+        // Removed models aren't part of the graph anymore
+        None if removed => "view".into(),
+        None => "source".into(),
+    }
+}
+
+/// Build a Mermaid flowchart from nodes and (from, to) edges. Nodes get
+/// generated ids, as names such as `raw.orders` aren't valid Mermaid ids,
+/// and the class of their diff status when `statuses` is given.
+pub fn to_mermaid(
+    nodes: &[DotNode],
+    edges: &[(String, String)],
+    graph: &Graph,
+    statuses: Option<&BTreeMap<String, DiffStatus>>,
+) -> String {
+    let mut ids = BTreeMap::new();
+    let mut classes = BTreeSet::new();
+    let mut lines = vec!["flowchart LR".to_string()];
+    let mut status_lines = vec![];
+    for (nth, node) in nodes.iter().enumerate() {
+        let id = format!("n{}", nth);
+        let status = statuses.and_then(|s| s.get(&node.name));
+        let class = node_class(&node.name, graph, status == Some(&DiffStatus::Removed));
+        lines.push(format!(
+            "    {}[\"{}\"]:::{}",
+            id,
+            node.name.replace('"', "#quot;"),
+            class
+        ));
+        classes.insert(class);
+        let status_class = match status {
+            Some(DiffStatus::Added) => Some("added"),
+            Some(DiffStatus::Removed) => Some("removed"),
+            Some(DiffStatus::Modified) => Some("modified"),
+            _ => None,
+        };
+        if let Some(status_class) = status_class {
+            status_lines.push(format!("    class {} {}", id, status_class));
+            classes.insert(status_class.to_string());
+        }
+        ids.insert(node.name.as_str(), id);
+    }
+    for (from, to) in edges {
+        if let (Some(from), Some(to)) = (ids.get(from.as_str()), ids.get(to.as_str())) {
+            lines.push(format!("    {} --> {}", from, to));
+        }
+    }
+    lines.extend(status_lines);
+    for (class, style) in CLASS_STYLES {
+        if classes.contains(class) {
+            lines.push(format!("    classDef {} {}", class, style));
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::to_mermaid;
+    use crate::{
+        graph::GraphBuilder,
+        graphviz::{source_nodes, DotNode},
+        manifest::DiffStatus,
+        node::{Node, NodeKind},
+    };
+
+    #[test]
+    fn export_mermaid() {
+        let mut builder = GraphBuilder::new();
+        let mut orders = Node::new(NodeKind::Sql, "models/orders.sql", "orders", "");
+        orders.materialize = Some("table".into());
+        orders.sources.insert(("raw".into(), "orders".into()));
+        builder.add_node(orders).unwrap();
+        builder
+            .add_node(Node::new(NodeKind::Seed, "seeds/tiers.csv", "tiers", ""))
+            .unwrap();
+        builder
+            .add_node(Node::new(NodeKind::Sql, "models/users.sql", "users", ""))
+            .unwrap();
+        builder.add_dependency("orders", "tiers").unwrap();
+        builder.add_dependency("users", "orders").unwrap();
+        let graph = builder.build().unwrap();
+
+        let ids = vec![
+            "tiers".to_string(),
+            "orders".to_string(),
+            "users".to_string(),
+        ];
+        let (mut nodes, mut edges) = source_nodes(&ids, &graph);
+        nodes.extend(ids.iter().map(|id| DotNode {
+            name: id.clone(),
+            fill_color: None,
+            tooltip: None,
+        }));
+        edges.extend(graph.edges(&ids));
+        let statuses = BTreeMap::from([
+            ("tiers".to_string(), DiffStatus::Unchanged),
+            ("orders".to_string(), DiffStatus::Modified),
+            ("users".to_string(), DiffStatus::Unchanged),
+        ]);
+
+        assert_eq!(
+            to_mermaid(&nodes, &edges, &graph, Some(&statuses)),
+            r#"flowchart LR
+    n0["raw.orders"]:::source
+    n1["tiers"]:::seed
+    n2["orders"]:::table
+    n3["users"]:::view
+    n0 --> n2
+    n1 --> n2
+    n2 --> n3
+    class n2 modified
+    classDef source fill:#add8e6,stroke:#4682b4
+    classDef seed fill:#e6e6fa,stroke:#6a5acd
+    classDef view fill:#ffffff,stroke:#808080
+    classDef table fill:#f5deb3,stroke:#cd853f
+    classDef modified fill:#f0e68c
+"#
+        );
+    }
+}
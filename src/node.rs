@@ -1,7 +1,16 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
 
-use duckdb::Connection;
+use chrono::{DateTime, Utc};
+use duckdb::{
+    types::{TimeUnit, Value},
+    Connection,
+};
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use sqlparser::{
     ast::{Cte, Query, Statement},
     dialect::DuckDbDialect,
@@ -11,30 +20,219 @@ use sqlparser::{
     parser::Parser,
 };
 
-use crate::errors::ArnabError;
+use crate::{
+    errors::ArnabError,
+    python::{python_references, run_python_model},
+    schema::ModelSchema,
+    settings::with_scoped_settings,
+    shell::{run_shell_model, shell_references},
+    source::SourceInfo,
+    time_format::template_timestamp,
+    verbosity::debug,
+};
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub enum NodeKind {
     Sql,
-    // Python, --> need to figure out how to pass data to-from python
-    // Shell,
+    /// CSV file loaded into a table, its source being the loading query
+    Seed,
+    /// Python file defining `model(ref)`, run in a subprocess
+    Python,
+    /// Shell script printing the SQL creating the model's table
+    Shell,
     // Unknown,
 }
 
+/// Suffix of model files rendered with Jinja whatever the configuration
+pub const JINJA_MODEL_SUFFIX: &str = ".sql.jinja";
+
+/// Split the path of a model file into the path without its extension (`.sql`
+/// or `.sql.jinja`) and whether it is explicitly templated (`.sql.jinja`)
+pub fn split_model_path(path: &Path) -> (PathBuf, bool) {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    match file_name.strip_suffix(JINJA_MODEL_SUFFIX) {
+        Some(stem) => (path.with_file_name(stem), true),
+        None => (path.with_extension(""), false),
+    }
+}
+
+/// Keep a single row per unique key, e.g., the latest version of each record
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DedupeInfo {
+    pub(crate) unique_key: Vec<String>,
+    /// Ordering of duplicate rows, the first one being kept, e.g., `updated_at DESC`
+    pub(crate) order_by: Option<String>,
+}
+
+/// What a failure of a model means for the run
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnError {
+    /// The run fails, and stops at once with `--fail-fast`
+    #[default]
+    Fail,
+    /// The failure is reported but tolerated
+    Continue,
+}
+
+/// What a build producing no rows means, as it often reveals a failure
+/// upstream
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnEmpty {
+    #[default]
+    Ok,
+    Warn,
+    /// The build fails, the empty relation being left in place
+    Error,
+}
+
+/// File an external model is written to, e.g., Parquet on object storage
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExternalInfo {
+    /// Path or URL of the file, e.g., `s3://bucket/orders.parquet`
+    pub(crate) location: String,
+    /// `parquet` (default), `csv` or `json`
+    pub(crate) format: Option<String>,
+}
+
+impl ExternalInfo {
+    pub fn format(&self) -> String {
+        self.format.as_deref().unwrap_or("parquet").to_lowercase()
+    }
+
+    fn quoted_location(&self) -> String {
+        format!("'{}'", self.location.replace('\'', "''"))
+    }
+
+    /// Statement writing the records of `statement` to the file
+    fn copy_statement(&self, statement: &str) -> String {
+        format!(
+            "COPY ({}) TO {} (FORMAT {})",
+            statement,
+            self.quoted_location(),
+            self.format()
+        )
+    }
+
+    /// Table function reading the file
+    fn read_expression(&self) -> String {
+        let function = match self.format().as_str() {
+            "csv" => "read_csv_auto",
+            "json" => "read_json_auto",
+            _ => "read_parquet",
+        };
+        format!("{}({})", function, self.quoted_location())
+    }
+}
+
+/// Project-wide inputs of model rendering
+#[derive(Clone, Debug, Default)]
+pub struct RenderContext {
+    /// User-defined macro files, keyed by file name
+    pub(crate) macros: HashMap<String, String>,
+    pub(crate) sources: HashMap<String, SourceInfo>,
+    /// Current target, available in templates as `target`
+    pub(crate) target: Option<String>,
+    /// Variables from the config and `--vars`, available through `var()`
+    pub(crate) vars: HashMap<String, serde_yaml::Value>,
+    /// Start of the run, available as `run_started_at`, `today` and
+    /// `yesterday` (in UTC)
+    pub(crate) run_started_at: DateTime<Utc>,
+    /// Start of the last run that built each model successfully, available
+    /// through `last_successful_run_at()`
+    pub(crate) last_successful_runs: HashMap<String, DateTime<Utc>>,
+}
+
+/// A statement of a model as executed
+pub(crate) enum CompiledStatement {
+    /// A statement executed as is, e.g., `SET`
+    Plain(String),
+    /// The model query, wrapped into its materialization
+    Create {
+        /// The model query, after deduplication and masking
+        select: String,
+        create: String,
+        /// View exposing a table created in another database
+        expose: Option<String>,
+    },
+}
+
 pub enum NodeExecutionResult {
-    Sql { n_rows: usize },
+    Sql {
+        /// Row count, unknown for views and statistics queries without an
+        /// `n_rows` column
+        n_rows: Option<usize>,
+        /// Column name and value pairs returned by the statistics query
+        stats: Vec<(String, String)>,
+        /// Whether the relation holds no rows, if known: tables are counted,
+        /// and views checked only with an `on_empty` policy
+        empty: Option<bool>,
+        /// Whether `n_rows` is DuckDB's estimate rather than a count
+        estimated: bool,
+    },
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Node {
     pub(crate) path: String,
     pub(crate) id: String,
+    /// Dotted path of the model relative to the models directory
+    pub(crate) fqn: String,
     pub(crate) raw_src: String,
     pub(crate) rendered_src: String,
     pub(crate) nexts: HashSet<String>,
     pub(crate) prevs: HashSet<String>,
     pub(crate) node_kind: NodeKind,
     pub(crate) materialize: Option<String>,
+    pub(crate) stats_sql: Option<String>,
+    /// Query that must return true for the built relation to be kept
+    pub(crate) assertion: Option<String>,
+    pub(crate) on_error: OnError,
+    pub(crate) on_empty: OnEmpty,
+    /// Groups of models that never run concurrently with each other
+    pub(crate) serialize_with: Vec<String>,
+    /// Whether to run `ANALYZE` on the table once built
+    pub(crate) analyze: bool,
+    /// Store the descriptions of the schema file as comments of the relation
+    pub(crate) persist_docs: bool,
+    /// Source tables used by the model, as (source name, table name) pairs
+    pub(crate) sources: HashSet<(String, String)>,
+    /// Attached database to create the model's table in, if not the main one
+    pub(crate) database: Option<String>,
+    /// Database schema to create the model's relation in, if not the main one
+    pub(crate) relation_schema: Option<String>,
+    /// Name of the model's relation, if not its id
+    pub(crate) alias: Option<String>,
+    /// Statements executed before and after the model's own ones
+    pub(crate) pre_hooks: Vec<String>,
+    pub(crate) post_hooks: Vec<String>,
+    /// Deduplication of the rows returned by the model query
+    pub(crate) dedupe: Option<DedupeInfo>,
+    /// Column name and masking expression pairs replacing PII columns
+    pub(crate) masked_columns: Vec<(String, String)>,
+    /// DuckDB settings applied only while the node is executed
+    pub(crate) duckdb_settings: BTreeMap<String, String>,
+    /// Environment variables set only while the node is executed: in the
+    /// process for SQL models, read by DuckDB, and in the script's own
+    /// environment for Python and shell models
+    pub(crate) env_vars: BTreeMap<String, String>,
+    pub(crate) tags: Vec<String>,
+    /// Declarations of the model's schema file, if any
+    pub(crate) schema: Option<ModelSchema>,
+    /// Pass the SQL through without Jinja rendering
+    pub(crate) raw: bool,
+    /// File the model is written to, when materialized as `external`
+    pub(crate) external: Option<ExternalInfo>,
+    /// Interpreter of Python models, if not `python3`
+    pub(crate) python: Option<String>,
+    /// Database path given to shell models in `ARNAB_DB_PATH`
+    pub(crate) db_path: Option<String>,
+    /// Drop the model's table before recreating it, as with `--full-refresh`
+    pub(crate) full_refresh: bool,
+    /// Report DuckDB's estimated row count of the table instead of counting
+    /// its rows when the estimate is above this number
+    pub(crate) estimate_rows_above: Option<usize>,
 }
 
 impl Node {
@@ -42,28 +240,274 @@ impl Node {
         Self {
             path: path.into(),
             id: id.into(),
+            fqn: id.into(),
             raw_src: raw_src.into(),
             rendered_src: Default::default(),
             nexts: Default::default(),
             prevs: Default::default(),
             materialize: None,
+            stats_sql: None,
+            assertion: None,
+            on_error: OnError::Fail,
+            on_empty: OnEmpty::Ok,
+            serialize_with: vec![],
+            analyze: false,
+            persist_docs: false,
+            sources: Default::default(),
+            database: None,
+            relation_schema: None,
+            alias: None,
+            pre_hooks: vec![],
+            post_hooks: vec![],
+            dedupe: None,
+            masked_columns: Default::default(),
+            duckdb_settings: Default::default(),
+            env_vars: Default::default(),
+            tags: Default::default(),
+            schema: None,
+            raw: false,
+            external: None,
+            python: None,
+            db_path: None,
+            full_refresh: false,
+            estimate_rows_above: None,
             node_kind: node_type,
         }
     }
 
-    /// Execute node accroding to its kind
+    /// Whether the node has settings or environment variables of its own,
+    /// which would leak into nodes executed at the same time
+    pub fn has_scoped_settings(&self) -> bool {
+        !self.duckdb_settings.is_empty() || !self.env_vars.is_empty()
+    }
+
+    /// Execute node accroding to its kind, with its own settings applied
     pub fn execute(&self, conn: &Connection) -> Result<NodeExecutionResult, ArnabError> {
-        let res = match &self.node_kind {
-            NodeKind::Sql => self.execute_sql_statements(conn)?,
+        // Scripts get their environment variables on their own process
+        let env_vars = match self.node_kind {
+            NodeKind::Sql | NodeKind::Seed => self.env_vars.clone(),
+            NodeKind::Python | NodeKind::Shell => BTreeMap::new(),
+        };
+        let mut result =
+            with_scoped_settings(conn, &self.duckdb_settings, &env_vars, || {
+                match &self.node_kind {
+                    NodeKind::Sql | NodeKind::Seed => self.execute_sql_statements(conn),
+                    NodeKind::Python => self.execute_python(conn),
+                    NodeKind::Shell => self.execute_shell(conn),
+                }
+            })?;
+        let NodeExecutionResult::Sql { n_rows, empty, .. } = &mut result;
+        *empty = self.is_empty(conn, *n_rows)?;
+        if *empty == Some(true) && self.on_empty == OnEmpty::Error {
+            return Err(ArnabError::Error(format!(
+                "Model {} built no rows (on_empty: error)",
+                self.id
+            )));
+        }
+        Ok(result)
+    }
+
+    /// Whether the built relation holds no rows, given the row count of the
+    /// statistics, if known. Otherwise views and tables are only checked with
+    /// an `on_empty` policy.
+    fn is_empty(
+        &self,
+        conn: &Connection,
+        n_rows: Option<usize>,
+    ) -> Result<Option<bool>, ArnabError> {
+        if let Some(n_rows) = n_rows {
+            return Ok(Some(n_rows == 0));
+        }
+        if self.on_empty == OnEmpty::Ok {
+            return Ok(None);
+        }
+        let sql = format!(
+            "SELECT NOT EXISTS (SELECT 1 FROM {})",
+            self.relation(self.is_table())
+        );
+        conn.query_row(&sql, [], |r| r.get(0))
+            .map(Some)
+            .map_err(|e| ArnabError::StatementExecutionError {
+                msg: e.to_string(),
+                path: self.path.clone(),
+                sql,
+            })
+    }
+
+    /// Run a Python model, then load its result into the model's table like
+    /// a SQL model reading the result file would be
+    fn execute_python(&self, conn: &Connection) -> Result<NodeExecutionResult, ArnabError> {
+        let dir = std::env::temp_dir().join(format!("arnab-{}-{}", std::process::id(), self.id));
+        let result = run_python_model(conn, self, &dir).and_then(|output_path| {
+            let mut loader = self.clone();
+            loader.node_kind = NodeKind::Sql;
+            loader.rendered_src = format!(
+                "SELECT * FROM read_parquet('{}')",
+                output_path.display().to_string().replace('\'', "''")
+            );
+            loader.execute_sql_statements(conn)
+        });
+        let _ = std::fs::remove_dir_all(&dir);
+        result
+    }
+
+    /// Run a shell model, then execute the SQL it printed
+    fn execute_shell(&self, conn: &Connection) -> Result<NodeExecutionResult, ArnabError> {
+        let sql = run_shell_model(self)?;
+        if !sql.trim().is_empty() {
+            conn.execute_batch(&sql)
+                .map_err(|e| ArnabError::StatementExecutionError {
+                    msg: e.to_string(),
+                    path: self.path.clone(),
+                    sql,
+                })?;
+        }
+        self.collect_statistics(conn)
+    }
+
+    /// Execute node with DuckDB limited to a single thread, which lowers its
+    /// peak memory usage. The configured thread count is restored afterwards.
+    pub fn execute_with_single_thread(
+        &self,
+        conn: &Connection,
+    ) -> Result<NodeExecutionResult, ArnabError> {
+        let settings = BTreeMap::from([("threads".to_string(), "1".to_string())]);
+        with_scoped_settings(conn, &settings, &BTreeMap::new(), || self.execute(conn))
+    }
+
+    /// Render `src` with Jinja, returning the rendered SQL and the source
+    /// tables it uses
+    fn render_jinja(
+        &self,
+        context: &RenderContext,
+        src: &str,
+    ) -> Result<(String, HashSet<(String, String)>), ArnabError> {
+        let mut env = minijinja::Environment::new();
+        env.add_global(
+            "target",
+            minijinja::Value::from_serializable(&context.target),
+        );
+
+        let run_started_at = context.run_started_at;
+        env.add_global("run_started_at", template_timestamp(run_started_at));
+        env.add_global("today", run_started_at.format("%Y-%m-%d").to_string());
+        env.add_global(
+            "yesterday",
+            (run_started_at - chrono::Duration::days(1))
+                .format("%Y-%m-%d")
+                .to_string(),
+        );
+        // `last_successful_run_at('model')` is none until the model is built
+        {
+            let last_successful_runs = context.last_successful_runs.clone();
+            env.add_function("last_successful_run_at", move |model: String| {
+                match last_successful_runs.get(&model) {
+                    Some(time) => minijinja::Value::from(template_timestamp(*time)),
+                    None => minijinja::Value::from(()),
+                }
+            });
+        }
+
+        // `var('name', default)` resolves to a variable, falling back to the
+        // default when it is not set
+        {
+            let vars = context.vars.clone();
+            env.add_function(
+                "var",
+                move |name: String,
+                      default: Option<minijinja::Value>|
+                      -> Result<minijinja::Value, minijinja::Error> {
+                    match (vars.get(&name), default) {
+                        (Some(value), _) => Ok(minijinja::Value::from_serializable(value)),
+                        (None, Some(default)) => Ok(default),
+                        (None, None) => Err(minijinja::Error::new(
+                            minijinja::ErrorKind::InvalidOperation,
+                            format!("variable `{}` is not set and has no default", name),
+                        )),
+                    }
+                },
+            );
+        }
+
+        // `source('source_name', 'table_name')` resolves to the relation of a
+        // declared source table. Used source tables are recorded in the node.
+        let used_sources = Arc::new(Mutex::new(HashSet::new()));
+        {
+            let sources = context.sources.clone();
+            let used_sources = used_sources.clone();
+            env.add_function(
+                "source",
+                move |source_name: String,
+                      table_name: String|
+                      -> Result<String, minijinja::Error> {
+                    let relation = sources
+                        .get(&source_name)
+                        .and_then(|s| {
+                            Some(s.resolved_relation(&source_name, s.table(&table_name)?))
+                        })
+                        .ok_or_else(|| {
+                            minijinja::Error::new(
+                                minijinja::ErrorKind::InvalidOperation,
+                                format!("source `{}.{}` is not declared", source_name, table_name),
+                            )
+                        })?;
+                    used_sources
+                        .lock()
+                        .unwrap()
+                        .insert((source_name, table_name));
+                    Ok(relation)
+                },
+            );
+        }
+
+        // Append macros to the raw source
+        let mut macro_src_concat = context
+            .macros
+            .values()
+            .map(|v| v.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        macro_src_concat.push('\n');
+        macro_src_concat.push_str(src);
+
+        let render_error = |e: minijinja::Error| {
+            ArnabError::Error(format!("Failed to render {}: {}", self.path, e))
         };
-        Ok(res)
+        // Register macro files by name so that they can be imported
+        for (name, src) in &context.macros {
+            env.add_template(name, src).map_err(render_error)?;
+        }
+        env.add_template(&self.id, &macro_src_concat)
+            .map_err(render_error)?;
+        let rendered = env
+            .get_template(&self.id)
+            .map_err(render_error)?
+            .render(minijinja::context! {})
+            .map_err(render_error)?;
+        let used_sources = used_sources.lock().unwrap().clone();
+        Ok((rendered, used_sources))
     }
 
     pub(crate) fn render_and_populate_refs(
         &mut self,
-        macros: &HashMap<String, String>,
+        context: &RenderContext,
         all_model_names: &[String],
-    ) {
+    ) -> Result<(), ArnabError> {
+        // Python models declare their dependencies with `ref()`, and shell
+        // models with `# depends_on:` comments
+        if matches!(self.node_kind, NodeKind::Python | NodeKind::Shell) {
+            self.rendered_src = self.raw_src.clone();
+            let references = match self.node_kind {
+                NodeKind::Shell => shell_references(&self.raw_src),
+                _ => python_references(&self.raw_src),
+            };
+            self.prevs = references
+                .into_iter()
+                .filter(|v| all_model_names.contains(v))
+                .collect();
+            return Ok(());
+        }
+
         // strip one-line comments
         let mut raw_no_comment = self
             .raw_src
@@ -76,53 +520,152 @@ impl Node {
         let re = Regex::new(r"/\*[\s\S]*?\*/").unwrap();
         raw_no_comment = re.replace_all(&raw_no_comment, "").to_string();
 
-        let mut env = minijinja::Environment::new();
-
-        // Append macros to the raw source
-        let mut macro_src_concat = macros
-            .values()
-            .map(|v| v.to_string())
-            .collect::<Vec<_>>()
-            .join("\n");
-        macro_src_concat.push('\n');
-        macro_src_concat.push_str(&raw_no_comment);
+        // Raw models are passed through as is, e.g., when their SQL contains
+        // literal `{{ }}` sequences
+        if self.raw {
+            self.rendered_src = raw_no_comment;
+            self.sources.clear();
+        } else {
+            let (rendered, sources) = self.render_jinja(context, &raw_no_comment)?;
+            self.rendered_src = rendered;
+            self.sources = sources;
+        }
 
-        // get all dependency candidates from a SQL statement. Then
+        // get all dependency candidates from the rendered SQL statements. Then
         // filter out those who don't belong to the found models, because
         // they could be a reference to CTE, alias, etc. So we will just ignore
         // them from graph creation.
-        let prevs = get_sql_references(&macro_src_concat)
+        let prevs = get_sql_references(&self.rendered_src)
             .into_iter()
             .filter(|v| all_model_names.contains(v))
             .collect::<HashSet<String>>();
         self.prevs = prevs;
+        Ok(())
+    }
+}
 
-        env.add_template(&self.id, &macro_src_concat).unwrap();
-        let rendered = env
-            .get_template(&self.id)
-            .unwrap()
-            .render(minijinja::context! {})
-            .unwrap();
-        self.rendered_src = rendered.to_string();
+impl Node {
+    /// Description declared in the model's schema file
+    pub fn description(&self) -> Option<&str> {
+        self.schema.as_ref()?.description.as_deref()
+    }
+
+    /// Ephemeral models are never created in the database. They are inlined
+    /// as CTEs into the models using them instead.
+    pub fn is_ephemeral(&self) -> bool {
+        self.materialize
+            .as_ref()
+            .is_some_and(|m| m.eq_ignore_ascii_case("ephemeral"))
+    }
+
+    /// The statement returning records of an ephemeral model, to be inlined
+    pub(crate) fn ephemeral_sql(&self) -> Result<String, ArnabError> {
+        let statements = self
+            .rendered_src
+            .split(';')
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>();
+        match statements[..] {
+            [statement] if self.will_produce_records(statement) => {
+                Ok(self.dedupe_statement(statement))
+            }
+            _ => Err(ArnabError::Error(format!(
+                "Ephemeral model {} must consist of a single `SELECT` statement",
+                self.id
+            ))),
+        }
+    }
+
+    /// The query returning the records of the model, as built but without
+    /// materializing them
+    pub(crate) fn select_sql(&self) -> Result<String, ArnabError> {
+        if matches!(self.node_kind, NodeKind::Python | NodeKind::Shell) {
+            let kind = match self.node_kind {
+                NodeKind::Shell => "Shell",
+                _ => "Python",
+            };
+            return Err(ArnabError::Error(format!(
+                "{} model {} can only be queried once built",
+                kind, self.id
+            )));
+        }
+        if self.is_ephemeral() {
+            return self.ephemeral_sql();
+        }
+        // Compiled statements contain exactly one query
+        let select =
+            self.compiled_statements()?
+                .into_iter()
+                .find_map(|statement| match statement {
+                    CompiledStatement::Create { select, .. } => Some(select),
+                    CompiledStatement::Plain(_) => None,
+                });
+        Ok(select.unwrap())
+    }
+
+    /// Splice ephemeral models into the statement returning records as CTEs.
+    /// `ephemerals` are (id, SQL) pairs, ordered so that each one only refers
+    /// to those before it.
+    pub(crate) fn inline_ephemerals(&mut self, ephemerals: &[(String, String)]) {
+        if ephemerals.is_empty() {
+            return;
+        }
+        let ctes = ephemerals
+            .iter()
+            .map(|(id, sql)| format!("{} AS ({})", id, sql))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        self.map_records_statements(|statement| {
+            if starts_with_keyword(statement, "WITH RECURSIVE") {
+                // `RECURSIVE` applies to the whole WITH clause, so keep
+                // the original query intact in a subquery
+                format!("WITH {} SELECT * FROM ({})", ctes, statement)
+            } else if starts_with_keyword(statement, "WITH") {
+                format!("WITH {}, {}", ctes, statement[4..].trim_start())
+            } else {
+                format!("WITH {} {}", ctes, statement)
+            }
+        });
+    }
+
+    /// Rewrite the statements returning records with `f`, keeping the others
+    pub(crate) fn map_records_statements(&mut self, f: impl Fn(&str) -> String) {
+        let statements = self
+            .rendered_src
+            .split(';')
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .map(|statement| match self.will_produce_records(statement) {
+                true => f(statement),
+                false => statement.to_string(),
+            })
+            .collect::<Vec<_>>();
+        self.rendered_src = statements.join(";\n");
+    }
+
+    /// The statements returning records, before materialization
+    pub(crate) fn records_statements(&self) -> Vec<&str> {
+        self.rendered_src
+            .split(';')
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty() && self.will_produce_records(s))
+            .collect()
     }
 }
 
 impl Node {
     /// A simple way to detect whether or not a statement will return records
     fn will_produce_records(&self, statement: &str) -> bool {
-        let starting_words = vec!["SELECT", "WITH"];
-        for sw in starting_words {
-            if statement[..50.min(statement.len())]
-                .to_uppercase()
-                .starts_with(sw)
-            {
-                return true;
-            }
-        }
-        false
+        ["SELECT", "WITH"]
+            .iter()
+            .any(|keyword| starts_with_keyword(statement, keyword))
     }
 
-    fn execute_sql_statements(&self, conn: &Connection) -> Result<NodeExecutionResult, ArnabError> {
+    /// Statements of the model, validated to have exactly one `SELECT`
+    /// statement, which is wrapped into the model's materialization
+    pub(crate) fn compiled_statements(&self) -> Result<Vec<CompiledStatement>, ArnabError> {
         let statements: Vec<String> = self
             .rendered_src
             .split(';')
@@ -131,7 +674,7 @@ impl Node {
             .collect();
 
         // Statement batch validation will check if a model has exactle one
-        // SELECT statement. First of all, we collect a list of stamtements 
+        // SELECT statement. First of all, we collect a list of stamtements
         // that returns records.
         let statements_returning_records = statements
             .iter()
@@ -143,67 +686,504 @@ impl Node {
             );
         }
 
+        // A single statement containing SELECT, WITH, etc., will be treated
+        // differently to create VIEW or TABLE.
+        let mut compiled = self
+            .render_hooks(&self.pre_hooks)?
+            .into_iter()
+            .map(CompiledStatement::Plain)
+            .collect::<Vec<_>>();
+        for statement in statements {
+            if !self.will_produce_records(&statement) {
+                compiled.push(CompiledStatement::Plain(statement));
+                continue;
+            }
+
+            let statement = &self.mask_statement(&self.dedupe_statement(&statement));
+            let kind = match &self.materialize {
+                Some(materialize) => match materialize.to_lowercase().as_str() {
+                    "table" => "TABLE",
+                    "view" => "VIEW",
+                    "external" if self.external.is_some() => "EXTERNAL",
+                    "external" => {
+                        return Err(ArnabError::Error(format!(
+                            "External model {} needs an `external` location",
+                            self.id
+                        )))
+                    }
+                    "ephemeral" => {
+                        return Err(ArnabError::Error(format!(
+                            "Ephemeral model {} cannot be executed on its own",
+                            self.id
+                        )))
+                    }
+                    _ => {
+                        return Err(ArnabError::Error(format!(
+                            "Unknown materialization type `{}`",
+                            materialize
+                        )))
+                    }
+                },
+                None => "VIEW",
+            };
+            let is_table = kind == "TABLE";
+            let relation = self.relation(is_table);
+            if let Some((schema, _)) = relation
+                .rsplit_once('.')
+                .filter(|_| self.relation_schema.is_some())
+            {
+                // In the attached database for tables stored there
+                compiled.push(CompiledStatement::Plain(format!(
+                    "CREATE SCHEMA IF NOT EXISTS {}",
+                    schema
+                )));
+            }
+            // Relations stored in another database or schema, or named
+            // otherwise, are exposed in the main schema through a view named
+            // after the model, so references keep working
+            let expose_relation = (relation != self.id).then(|| {
+                format!(
+                    "CREATE OR REPLACE VIEW {} AS SELECT * FROM {}",
+                    self.id, relation
+                )
+            });
+            let exposed = expose_relation.is_some();
+            let (create, expose) = match self.external.as_ref().filter(|_| kind == "EXTERNAL") {
+                // External models are written to a file, read through a view
+                Some(external) => {
+                    let view = format!(
+                        "CREATE OR REPLACE VIEW {} AS SELECT * FROM {}",
+                        relation,
+                        external.read_expression()
+                    );
+                    (
+                        external.copy_statement(statement),
+                        Some(match expose_relation {
+                            Some(expose) => format!("{};\n{}", view, expose),
+                            None => view,
+                        }),
+                    )
+                }
+                None => (
+                    format!("CREATE OR REPLACE {} {} AS ({})", kind, relation, statement),
+                    expose_relation,
+                ),
+            };
+            compiled.push(CompiledStatement::Create {
+                select: statement.clone(),
+                create,
+                expose,
+            });
+            if self.persist_docs {
+                compiled.extend(
+                    self.comment_statements(is_table, exposed)
+                        .into_iter()
+                        .map(CompiledStatement::Plain),
+                );
+            }
+        }
+        compiled.extend(
+            self.render_hooks(&self.post_hooks)?
+                .into_iter()
+                .map(CompiledStatement::Plain),
+        );
+        Ok(compiled)
+    }
+
+    pub(crate) fn is_table(&self) -> bool {
+        self.materialize
+            .as_ref()
+            .is_some_and(|m| m.eq_ignore_ascii_case("table"))
+    }
+
+    pub fn is_external(&self) -> bool {
+        self.materialize
+            .as_ref()
+            .is_some_and(|m| m.eq_ignore_ascii_case("external"))
+    }
+
+    /// Relation the model is created as, `[database.][schema.]name`. Only
+    /// tables are created in the attached database.
+    pub(crate) fn relation(&self, is_table: bool) -> String {
+        let mut parts = vec![];
+        if is_table {
+            parts.extend(self.database.as_deref());
+        }
+        parts.extend(self.relation_schema.as_deref());
+        parts.push(self.alias.as_deref().unwrap_or(&self.id));
+        parts.join(".")
+    }
+
+    /// Whether the model's relation exists in the database
+    pub(crate) fn relation_exists(&self, conn: &Connection) -> bool {
+        let sql = format!("SELECT 1 FROM {} LIMIT 0", self.relation(self.is_table()));
+        conn.prepare(&sql).is_ok()
+    }
+
+    /// Render hook statements, where `{{ this }}` refers to the model's relation
+    fn render_hooks(&self, hooks: &[String]) -> Result<Vec<String>, ArnabError> {
+        let env = minijinja::Environment::new();
+        let relation = self.relation(self.is_table());
+        hooks
+            .iter()
+            .map(|hook| {
+                env.render_str(hook, minijinja::context! { this => relation })
+                    .map_err(|e| {
+                        ArnabError::Error(format!("Cannot render hook of {}: {}", self.id, e))
+                    })
+            })
+            .collect()
+    }
+
+    /// `COMMENT ON` statements storing the descriptions of the schema file.
+    /// A table exposed in the main database also has its view commented.
+    fn comment_statements(&self, is_table: bool, exposed: bool) -> Vec<String> {
+        let Some(schema) = &self.schema else {
+            return vec![];
+        };
+        let quote = |s: &str| format!("'{}'", s.replace('\'', "''"));
+        let relation = self.relation(is_table);
+        let mut statements = vec![];
+        if let Some(description) = &schema.description {
+            let kind = if is_table { "TABLE" } else { "VIEW" };
+            statements.push(format!(
+                "COMMENT ON {} {} IS {}",
+                kind,
+                relation,
+                quote(description)
+            ));
+            if exposed {
+                statements.push(format!(
+                    "COMMENT ON VIEW {} IS {}",
+                    self.id,
+                    quote(description)
+                ));
+            }
+        }
+        for column in &schema.columns {
+            match column.description.as_deref() {
+                Some(description) if !description.is_empty() => statements.push(format!(
+                    "COMMENT ON COLUMN {}.\"{}\" IS {}",
+                    relation,
+                    column.name,
+                    quote(description)
+                )),
+                _ => {}
+            }
+        }
+        statements
+    }
+
+    /// Compiled SQL script of the model, as executed
+    pub(crate) fn compiled_sql(&self) -> Result<String, ArnabError> {
+        // Python and shell models are run as is
+        if matches!(self.node_kind, NodeKind::Python | NodeKind::Shell) {
+            return Ok(self.raw_src.clone());
+        }
+        let mut statements = vec![];
+        for statement in self.compiled_statements()? {
+            match statement {
+                CompiledStatement::Plain(statement) => statements.push(statement),
+                CompiledStatement::Create { create, expose, .. } => {
+                    statements.push(create);
+                    statements.extend(expose);
+                }
+            }
+        }
+        Ok(statements
+            .iter()
+            .map(|s| format!("{};\n", s))
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
+    fn execute_sql_statements(&self, conn: &Connection) -> Result<NodeExecutionResult, ArnabError> {
         // Arnab will execute all statements in a SQL file one by one.
         // We are not going to bulk-execute statements, so the source code is split
-        // by semicolon. A single statement containing SELECT, WITH, etc., will
-        // be treated differently to create VIEW or TABLE.
-        let mut n_rows: usize = 0;
-        for statement in &statements {
-            let mut adjusted_statement = statement.to_string();
-
-            // Only process non-empty statements
-            // We shall process SQL statement that returns record
-            if self.will_produce_records(&adjusted_statement) {
-                let create_view_statement =
-                    format!("CREATE OR REPLACE VIEW {} AS ({})", self.id, statement);
-                adjusted_statement = match &self.materialize {
-                    Some(materialize) => match materialize.to_lowercase().as_str() {
-                        "table" => {
-                            format!("CREATE OR REPLACE TABLE {} AS ({})", self.id, statement)
+        // by semicolon.
+        for statement in self.compiled_statements()? {
+            let (statement, adjusted_statement) = match statement {
+                CompiledStatement::Plain(statement) => (statement.clone(), statement),
+                CompiledStatement::Create {
+                    select,
+                    create,
+                    expose,
+                } => {
+                    let full_refresh = self.full_refresh && self.is_table();
+                    let create = match full_refresh {
+                        true => {
+                            format!("DROP TABLE IF EXISTS {};\n{}", self.relation(true), create)
                         }
-                        "view" => create_view_statement,
-                        _ => {
-                            return Err(ArnabError::Error(format!(
-                                "Unknown materialization type `{}`",
-                                materialize
-                            )))
-                        }
-                    },
-                    None => create_view_statement,
-                };
-            }
-
-            let res = conn.execute(&adjusted_statement, []);
-            match res {
-                Ok(_) => {
-                    if self.materialize == Some("table".into()) {
-                        // Count the number of rows
-                        let count_sql = format!("SELECT COUNT(*) FROM {}", self.id);
-                        if let Ok(stmt) = &mut conn.prepare(&count_sql) {
-                            let mut rows = stmt.query([]).unwrap();
-                            rows.next().into_iter().for_each(|r| {
-                                let n: usize = r.unwrap().get(0).unwrap();
-                                n_rows = n;
-                            });
+                        false => create,
+                    };
+                    // A dropped table is restored if its recreation fails
+                    match self.assertion.is_some() || full_refresh {
+                        true => {
+                            self.create_in_transaction(conn, &create, self.assertion.as_deref())?;
+                            match expose {
+                                Some(expose) => (select, expose),
+                                None => continue,
+                            }
                         }
+                        false => match expose {
+                            Some(expose) => (select, format!("{};\n{}", create, expose)),
+                            None => (select, create),
+                        },
                     }
                 }
-                Err(e) => {
-                    let err_msg = e.to_string();
-                    // TODO: fix this brittle way to check empty statement
-                    if err_msg.contains("No statement to prepare") {
-                        continue;
-                    }
+            };
+
+            debug!("-- {}\n{}", self.path, adjusted_statement);
+            let res = conn.execute_batch(&adjusted_statement);
+            if let Err(e) = res {
+                let err_msg = e.to_string();
+                // TODO: fix this brittle way to check empty statement
+                if err_msg.contains("No statement to prepare") {
+                    continue;
+                }
+
+                return Err(ArnabError::StatementExecutionError {
+                    msg: e.to_string(),
+                    path: self.path.clone(),
+                    sql: statement,
+                });
+            }
+        }
+
+        self.collect_statistics(conn)
+    }
+
+    /// Create the model's relation in a transaction, committed only if the
+    /// creation succeeds and the assertion query, if any, returns true. A
+    /// failing build keeps the previous version of the relation in place.
+    fn create_in_transaction(
+        &self,
+        conn: &Connection,
+        create_statement: &str,
+        assertion: Option<&str>,
+    ) -> Result<(), ArnabError> {
+        let relation = self.relation(self.is_table());
+        let assertion_sql = assertion
+            .map(|assertion| {
+                minijinja::Environment::new()
+                    .render_str(assertion, minijinja::context! { this => relation })
+                    .map_err(|e| ArnabError::Error(e.to_string()))
+            })
+            .transpose()?;
+        let to_error = |sql: &str| {
+            let sql = sql.to_string();
+            move |e: duckdb::Error| ArnabError::StatementExecutionError {
+                msg: e.to_string(),
+                path: self.path.clone(),
+                sql,
+            }
+        };
 
-                    return Err(ArnabError::StatementExecutionError {
-                        msg: e.to_string(),
+        debug!(
+            "-- {}\n{}\n{}",
+            self.path,
+            create_statement,
+            assertion_sql.as_deref().unwrap_or_default()
+        );
+        conn.execute_batch("BEGIN TRANSACTION;")
+            .map_err(to_error("BEGIN TRANSACTION"))?;
+        let result = conn
+            .execute_batch(create_statement)
+            .map_err(to_error(create_statement))
+            .and_then(|_| {
+                let Some(assertion_sql) = &assertion_sql else {
+                    return Ok(());
+                };
+                let passed = conn
+                    .query_row(assertion_sql, [], |r| r.get::<_, bool>(0))
+                    .map_err(to_error(assertion_sql))?;
+                match passed {
+                    true => Ok(()),
+                    false => Err(ArnabError::StatementExecutionError {
+                        msg: format!("Assertion of model {} failed", self.id),
                         path: self.path.clone(),
-                        sql: statement.to_string(),
+                        sql: assertion_sql.clone(),
+                    }),
+                }
+            });
+        let end_sql = match result {
+            Ok(_) => "COMMIT;",
+            Err(_) => "ROLLBACK;",
+        };
+        conn.execute_batch(end_sql).map_err(to_error(end_sql))?;
+        result
+    }
+
+    /// Wrap a statement returning records so that only the first row of each
+    /// unique key is kept
+    fn dedupe_statement(&self, statement: &str) -> String {
+        let Some(dedupe) = &self.dedupe else {
+            return statement.to_string();
+        };
+        let order_by = match &dedupe.order_by {
+            Some(order_by) => format!(" ORDER BY {}", order_by),
+            None => "".into(),
+        };
+        format!(
+            "SELECT * FROM ({}) QUALIFY row_number() OVER (PARTITION BY {}{}) = 1",
+            statement,
+            dedupe.unique_key.join(", "),
+            order_by
+        )
+    }
+
+    /// Wrap a statement returning records so that its PII columns are replaced
+    /// by their masking expressions
+    fn mask_statement(&self, statement: &str) -> String {
+        if self.masked_columns.is_empty() {
+            return statement.to_string();
+        }
+        let replacements = self
+            .masked_columns
+            .iter()
+            .map(|(column, expression)| format!("{} AS \"{}\"", expression, column))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("SELECT * REPLACE ({}) FROM ({})", replacements, statement)
+    }
+
+    /// Run the post-build statistics query of a model. Models may override the
+    /// default `SELECT COUNT(*)` with `stats_sql` in their configuration, e.g.,
+    /// to use an approximate count or to collect extra metrics. `{{ this }}`
+    /// in the query refers to the model's relation. Its `n_rows` column, if
+    /// any, is reported as the row count.
+    fn collect_statistics(&self, conn: &Connection) -> Result<NodeExecutionResult, ArnabError> {
+        let stats_sql = match &self.stats_sql {
+            Some(stats_sql) => {
+                let env = minijinja::Environment::new();
+                env.render_str(stats_sql, minijinja::context! { this => self.id })
+                    .map_err(|e| ArnabError::Error(e.to_string()))?
+            }
+            // Counting rows of a view means running the whole query again, so
+            // by default only tables are counted
+            None if self.materialize == Some("table".into()) => {
+                // Counting billions of rows takes a while, while DuckDB keeps
+                // an estimate of the row count of each table
+                let estimate = match self.estimate_rows_above {
+                    Some(threshold) => self.estimated_rows(conn)?.filter(|n| *n > threshold),
+                    None => None,
+                };
+                if let Some(n_rows) = estimate {
+                    return Ok(NodeExecutionResult::Sql {
+                        n_rows: Some(n_rows),
+                        stats: vec![("n_rows".into(), n_rows.to_string())],
+                        empty: None,
+                        estimated: true,
                     });
                 }
-            };
+                format!("SELECT COUNT(*) AS n_rows FROM {}", self.id)
+            }
+            None => {
+                return Ok(NodeExecutionResult::Sql {
+                    n_rows: None,
+                    stats: vec![],
+                    empty: None,
+                    estimated: false,
+                })
+            }
+        };
+
+        let to_error = |e: duckdb::Error| ArnabError::StatementExecutionError {
+            msg: e.to_string(),
+            path: self.path.clone(),
+            sql: stats_sql.clone(),
+        };
+        let mut stmt = conn.prepare(&stats_sql).map_err(to_error)?;
+        let mut rows = stmt.query([]).map_err(to_error)?;
+        let mut stats = vec![];
+        if let Some(row) = rows.next().map_err(to_error)? {
+            let column_names = row.as_ref().column_names();
+            for (i, name) in column_names.into_iter().enumerate() {
+                let value: Value = row.get(i).map_err(to_error)?;
+                stats.push((name, format_value(&value)));
+            }
+        }
+
+        let n_rows = stats
+            .iter()
+            .find(|(name, _)| name == "n_rows")
+            .and_then(|(_, v)| v.parse::<usize>().ok());
+        Ok(NodeExecutionResult::Sql {
+            n_rows,
+            stats,
+            empty: None,
+            estimated: false,
+        })
+    }
+
+    /// DuckDB's estimate of the number of rows of the model's table, if found
+    fn estimated_rows(&self, conn: &Connection) -> Result<Option<usize>, ArnabError> {
+        let sql = "SELECT estimated_size FROM duckdb_tables() \
+                   WHERE database_name = coalesce(?, current_database()) \
+                   AND schema_name = coalesce(?, current_schema()) AND table_name = ?";
+        let name = self.alias.as_deref().unwrap_or(&self.id);
+        let params = duckdb::params![self.database, self.relation_schema, name];
+        match conn.query_row(sql, params, |r| r.get::<_, i64>(0)) {
+            Ok(n_rows) => Ok(Some(n_rows.max(0) as usize)),
+            Err(duckdb::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(ArnabError::StatementExecutionError {
+                msg: e.to_string(),
+                path: self.path.clone(),
+                sql: sql.to_string(),
+            }),
+        }
+    }
+}
+
+/// Format a DuckDB value for display
+pub(crate) fn format_value(value: &Value) -> String {
+    let to_micros = |unit: &TimeUnit, v: i64| match unit {
+        TimeUnit::Second => v * 1_000_000,
+        TimeUnit::Millisecond => v * 1_000,
+        TimeUnit::Microsecond => v,
+        TimeUnit::Nanosecond => v / 1_000,
+    };
+    match value {
+        Value::Null => "NULL".into(),
+        Value::Boolean(v) => v.to_string(),
+        Value::TinyInt(v) => v.to_string(),
+        Value::SmallInt(v) => v.to_string(),
+        Value::Int(v) => v.to_string(),
+        Value::BigInt(v) => v.to_string(),
+        Value::HugeInt(v) => v.to_string(),
+        Value::UTinyInt(v) => v.to_string(),
+        Value::USmallInt(v) => v.to_string(),
+        Value::UInt(v) => v.to_string(),
+        Value::UBigInt(v) => v.to_string(),
+        Value::Float(v) => v.to_string(),
+        Value::Double(v) => v.to_string(),
+        Value::Decimal(v) => v.to_string(),
+        Value::Text(v) => v.clone(),
+        Value::Blob(v) => format!("<{} bytes>", v.len()),
+        Value::Timestamp(unit, v) => {
+            match chrono::NaiveDateTime::from_timestamp_micros(to_micros(unit, *v)) {
+                Some(ts) => ts.to_string(),
+                None => v.to_string(),
+            }
+        }
+        Value::Date32(v) => {
+            match chrono::NaiveDate::from_ymd_opt(1970, 1, 1)
+                .and_then(|d| d.checked_add_signed(chrono::Duration::days(*v as i64)))
+            {
+                Some(date) => date.to_string(),
+                None => v.to_string(),
+            }
+        }
+        Value::Time64(unit, v) => {
+            let micros = to_micros(unit, *v);
+            match chrono::NaiveTime::from_num_seconds_from_midnight_opt(
+                (micros / 1_000_000) as u32,
+                (micros % 1_000_000) as u32 * 1_000,
+            ) {
+                Some(time) => time.to_string(),
+                None => v.to_string(),
+            }
         }
-        Ok(NodeExecutionResult::Sql { n_rows })
     }
 }
 
@@ -271,11 +1251,26 @@ fn extract_from_subquery(query: &Query, tables: &mut HashSet<String>) {
     }
 }
 
+/// Whether a statement starts with an (ASCII) keyword, in any case. Only
+/// the keyword's length is compared, so that statements starting with
+/// non-ASCII text are neither sliced mid-character nor uppercased whole.
+fn starts_with_keyword(statement: &str, keyword: &str) -> bool {
+    statement
+        .get(..keyword.len())
+        .is_some_and(|start| start.eq_ignore_ascii_case(keyword))
+}
+
 #[cfg(test)]
 mod test {
-    use std::collections::HashSet;
+    use std::{
+        collections::{HashMap, HashSet},
+        path::Path,
+    };
 
-    use super::get_sql_references;
+    use super::{
+        get_sql_references, split_model_path, DedupeInfo, ExternalInfo, Node, NodeExecutionResult,
+        NodeKind, OnEmpty, RenderContext,
+    };
 
     #[test]
     fn get_ref() {
@@ -292,4 +1287,288 @@ mod test {
             HashSet::from(["my_sub_table".to_string(), "my_table".to_string()])
         );
     }
+
+    #[test]
+    fn inline_ephemeral_models() {
+        let ephemerals = vec![
+            ("a".to_string(), "SELECT 1 AS x".to_string()),
+            ("b".to_string(), "SELECT x FROM a".to_string()),
+        ];
+
+        let mut node = Node::new(NodeKind::Sql, "c.sql", "c", "");
+        node.rendered_src = "SET threads = 1; SELECT * FROM b;".into();
+        node.inline_ephemerals(&ephemerals);
+        assert_eq!(
+            node.rendered_src,
+            "SET threads = 1;\nWITH a AS (SELECT 1 AS x), b AS (SELECT x FROM a) SELECT * FROM b"
+        );
+
+        node.rendered_src = "with c AS (SELECT * FROM b) SELECT * FROM c".into();
+        node.inline_ephemerals(&ephemerals[1..]);
+        assert_eq!(
+            node.rendered_src,
+            "WITH b AS (SELECT x FROM a), c AS (SELECT * FROM b) SELECT * FROM c"
+        );
+    }
+
+    #[test]
+    fn dedupe_statement() {
+        let mut node = Node::new(NodeKind::Sql, "orders.sql", "orders", "");
+        node.dedupe = Some(DedupeInfo {
+            unique_key: vec!["id".into(), "region".into()],
+            order_by: Some("updated_at DESC".into()),
+        });
+        assert_eq!(
+            node.dedupe_statement("SELECT * FROM raw"),
+            "SELECT * FROM (SELECT * FROM raw) QUALIFY row_number() \
+             OVER (PARTITION BY id, region ORDER BY updated_at DESC) = 1"
+        );
+    }
+
+    #[test]
+    fn compile_table_in_other_database() {
+        let mut node = Node::new(NodeKind::Sql, "o.sql", "o", "");
+        node.rendered_src = "SET threads = 1; SELECT 1 AS a;".into();
+        node.materialize = Some("table".into());
+        node.database = Some("artifacts".into());
+        assert_eq!(
+            node.compiled_sql().unwrap(),
+            "SET threads = 1;\n\n\
+             CREATE OR REPLACE TABLE artifacts.o AS (SELECT 1 AS a);\n\n\
+             CREATE OR REPLACE VIEW o AS SELECT * FROM artifacts.o;\n"
+        );
+    }
+
+    #[test]
+    fn compile_aliased_model_with_hooks() {
+        let mut node = Node::new(NodeKind::Sql, "o.sql", "o", "");
+        node.rendered_src = "SELECT 1 AS a".into();
+        node.materialize = Some("table".into());
+        node.database = Some("artifacts".into());
+        node.relation_schema = Some("marts".into());
+        node.alias = Some("orders".into());
+        node.pre_hooks = vec!["SET threads = 1".into()];
+        node.post_hooks = vec!["INSERT INTO audit SELECT '{{ this }}'".into()];
+        assert_eq!(
+            node.compiled_sql().unwrap(),
+            "SET threads = 1;\n\n\
+             CREATE SCHEMA IF NOT EXISTS artifacts.marts;\n\n\
+             CREATE OR REPLACE TABLE artifacts.marts.orders AS (SELECT 1 AS a);\n\n\
+             CREATE OR REPLACE VIEW o AS SELECT * FROM artifacts.marts.orders;\n\n\
+             INSERT INTO audit SELECT 'artifacts.marts.orders';\n"
+        );
+    }
+
+    #[test]
+    fn compile_external_model() {
+        let mut node = Node::new(NodeKind::Sql, "o.sql", "o", "");
+        node.rendered_src = "SELECT 1 AS a".into();
+        node.materialize = Some("external".into());
+        assert!(node.compiled_sql().is_err());
+        node.external = Some(ExternalInfo {
+            location: "s3://lake/o.parquet".into(),
+            format: None,
+        });
+        assert_eq!(
+            node.compiled_sql().unwrap(),
+            "COPY (SELECT 1 AS a) TO 's3://lake/o.parquet' (FORMAT parquet);\n\n\
+             CREATE OR REPLACE VIEW o AS SELECT * FROM read_parquet('s3://lake/o.parquet');\n"
+        );
+    }
+
+    #[test]
+    fn compile_persisted_docs() {
+        let mut node = Node::new(NodeKind::Sql, "o.sql", "o", "");
+        node.rendered_src = "SELECT 1 AS id".into();
+        node.materialize = Some("table".into());
+        node.persist_docs = true;
+        node.schema = Some(
+            serde_yaml::from_str(
+                "description: Customer's orders\ncolumns:\n  - { name: id, description: Order id }\n  - { name: x, description: '' }",
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            node.compiled_sql().unwrap(),
+            "CREATE OR REPLACE TABLE o AS (SELECT 1 AS id);\n\n\
+             COMMENT ON TABLE o IS 'Customer''s orders';\n\n\
+             COMMENT ON COLUMN o.\"id\" IS 'Order id';\n"
+        );
+    }
+
+    #[test]
+    fn raw_models_skip_jinja() {
+        let src = "SELECT '{{ \"a\": 1 }}' AS payload FROM orders";
+        let mut node = Node::new(NodeKind::Sql, "p.sql", "p", src);
+        let models = vec!["orders".to_string()];
+        assert!(node
+            .render_and_populate_refs(&RenderContext::default(), &models)
+            .is_err());
+
+        node.raw = true;
+        node.render_and_populate_refs(&RenderContext::default(), &models)
+            .unwrap();
+        assert_eq!(node.rendered_src, src);
+        assert_eq!(node.prevs, HashSet::from(["orders".to_string()]));
+
+        let (base, is_jinja) = split_model_path(Path::new("models/p.prod.sql.jinja"));
+        assert_eq!(
+            (base.as_path(), is_jinja),
+            (Path::new("models/p.prod"), true)
+        );
+        let (base, is_jinja) = split_model_path(Path::new("models/p.sql"));
+        assert_eq!((base.as_path(), is_jinja), (Path::new("models/p"), false));
+    }
+
+    #[test]
+    fn render_with_target() {
+        let src = "SELECT * FROM {% if target == 'prod' %}orders{% else %}sample_orders{% endif %}";
+        let mut node = Node::new(NodeKind::Sql, "o.sql", "o", src);
+        let models = vec!["orders".to_string(), "sample_orders".to_string()];
+        let mut context = RenderContext {
+            target: Some("prod".into()),
+            ..Default::default()
+        };
+        node.render_and_populate_refs(&context, &models).unwrap();
+        assert_eq!(node.prevs, HashSet::from(["orders".to_string()]));
+
+        context.target = None;
+        node.render_and_populate_refs(&context, &models).unwrap();
+        assert_eq!(node.prevs, HashSet::from(["sample_orders".to_string()]));
+    }
+
+    #[test]
+    fn render_with_vars() {
+        let src =
+            "SELECT * FROM orders WHERE d >= '{{ var('start_date') }}' LIMIT {{ var('n', 10) }}";
+        let mut node = Node::new(NodeKind::Sql, "o.sql", "o", src);
+        let mut context = RenderContext::default();
+        assert!(node.render_and_populate_refs(&context, &[]).is_err());
+
+        context.vars = serde_yaml::from_str("start_date: 2024-01-01").unwrap();
+        node.render_and_populate_refs(&context, &[]).unwrap();
+        assert_eq!(
+            node.rendered_src.trim(),
+            "SELECT * FROM orders WHERE d >= '2024-01-01' LIMIT 10"
+        );
+    }
+
+    #[test]
+    fn render_with_time_variables() {
+        let src = "SELECT * FROM events WHERE d = '{{ yesterday }}' AND ts < '{{ run_started_at }}' \
+                   {% if last_successful_run_at('events') %}AND ts >= '{{ last_successful_run_at('events') }}'{% endif %}";
+        let mut node = Node::new(NodeKind::Sql, "e.sql", "e", src);
+        let mut context = RenderContext {
+            run_started_at: "2024-03-01T06:30:00Z".parse().unwrap(),
+            ..Default::default()
+        };
+        node.render_and_populate_refs(&context, &[]).unwrap();
+        assert_eq!(
+            node.rendered_src.trim(),
+            "SELECT * FROM events WHERE d = '2024-02-29' AND ts < '2024-03-01T06:30:00Z'"
+        );
+
+        context.last_successful_runs =
+            HashMap::from([("events".into(), "2024-02-29T06:30:00Z".parse().unwrap())]);
+        node.render_and_populate_refs(&context, &[]).unwrap();
+        assert!(node
+            .rendered_src
+            .trim()
+            .ends_with("AND ts >= '2024-02-29T06:30:00Z'"));
+    }
+
+    #[test]
+    fn empty_model_policy() {
+        let conn = duckdb::Connection::open_in_memory().unwrap();
+        let mut node = Node::new(NodeKind::Sql, "e.sql", "e", "");
+        node.rendered_src = "SELECT 1 AS a WHERE false".into();
+        let NodeExecutionResult::Sql { empty, .. } = node.execute(&conn).unwrap();
+        assert_eq!(empty, None);
+
+        node.on_empty = OnEmpty::Warn;
+        let NodeExecutionResult::Sql { empty, .. } = node.execute(&conn).unwrap();
+        assert_eq!(empty, Some(true));
+
+        node.on_empty = OnEmpty::Error;
+        assert!(node.execute(&conn).is_err());
+        node.rendered_src = "SELECT 1 AS a".into();
+        let NodeExecutionResult::Sql { empty, .. } = node.execute(&conn).unwrap();
+        assert_eq!(empty, Some(false));
+    }
+
+    #[test]
+    fn estimate_rows_of_large_tables() {
+        let conn = duckdb::Connection::open_in_memory().unwrap();
+        let mut node = Node::new(NodeKind::Sql, "big.sql", "big", "");
+        node.rendered_src = "SELECT * FROM range(1000)".into();
+        node.materialize = Some("table".into());
+        let count = |node: &Node| {
+            let NodeExecutionResult::Sql {
+                n_rows, estimated, ..
+            } = node.execute(&conn).unwrap();
+            (n_rows, estimated)
+        };
+        assert_eq!(count(&node), (Some(1000), false));
+
+        node.estimate_rows_above = Some(1000);
+        assert_eq!(count(&node), (Some(1000), false));
+        node.estimate_rows_above = Some(100);
+        assert_eq!(count(&node), (Some(1000), true));
+    }
+
+    #[test]
+    fn read_row_count_of_custom_statistics() {
+        let conn = duckdb::Connection::open_in_memory().unwrap();
+        let mut node = Node::new(NodeKind::Sql, "s.sql", "s", "");
+        node.rendered_src = "SELECT * FROM range(3)".into();
+        node.materialize = Some("table".into());
+        node.stats_sql =
+            Some("SELECT max(range) AS max_id, count(*) AS n_rows FROM {{ this }}".into());
+        let NodeExecutionResult::Sql { n_rows, stats, .. } = node.execute(&conn).unwrap();
+        assert_eq!(n_rows, Some(3));
+        assert_eq!(stats[0], ("max_id".to_string(), "2".to_string()));
+
+        node.stats_sql = Some("SELECT max(range) AS max_id FROM {{ this }}".into());
+        let NodeExecutionResult::Sql { n_rows, .. } = node.execute(&conn).unwrap();
+        assert_eq!(n_rows, None);
+    }
+
+    #[test]
+    fn failed_full_refresh_keeps_table() {
+        let conn = duckdb::Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE c AS SELECT 1 AS a")
+            .unwrap();
+        let mut node = Node::new(NodeKind::Sql, "c.sql", "c", "");
+        node.rendered_src = "SELECT * FROM missing".into();
+        node.materialize = Some("table".into());
+        node.full_refresh = true;
+        assert!(node.execute(&conn).is_err());
+        let a: i64 = conn.query_row("SELECT a FROM c", [], |r| r.get(0)).unwrap();
+        assert_eq!(a, 1);
+    }
+
+    #[test]
+    fn restore_threads_after_single_thread_retry() {
+        let conn = duckdb::Connection::open_in_memory().unwrap();
+        conn.execute_batch("SET threads = 3;").unwrap();
+        let mut node = Node::new(NodeKind::Sql, "t.sql", "t", "");
+        node.rendered_src = "SELECT current_setting('threads') AS n".into();
+        node.materialize = Some("table".into());
+        node.execute_with_single_thread(&conn).unwrap();
+        let threads = |sql: &str| -> i64 { conn.query_row(sql, [], |r| r.get(0)).unwrap() };
+        assert_eq!(threads("SELECT n FROM t"), 1);
+        assert_eq!(threads("SELECT current_setting('threads')"), 3);
+    }
+
+    #[test]
+    fn non_ascii_statements() {
+        let node = Node::new(NodeKind::Sql, "u.sql", "u", "");
+        // A multi-byte character straddles the 50th byte
+        let statement = format!("{}'売上'", " ".repeat(48));
+        assert!(!node.will_produce_records(&statement));
+        assert!(node.will_produce_records("select '売上' AS 名前"));
+        assert!(node.will_produce_records("With t AS (SELECT 1) SELECT * FROM t"));
+        assert!(!node.will_produce_records("売"));
+        assert!(!node.will_produce_records("CREATE TABLE 売上 AS SELECT 1"));
+    }
 }
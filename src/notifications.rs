@@ -0,0 +1,378 @@
+//! Notifications of finished runs, configured in the `notifications` block:
+//! webhooks receive a JSON summary of each run, and Slack channels a
+//! readable message, e.g., to alert a team when a scheduled run fails,
+//! without wrapping arnab in scripts.
+
+use std::{collections::BTreeMap, time::Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    events::{Event, EventListener, NodeStatus},
+    http::post_json,
+    time_format::iso_now,
+};
+
+/// Configuration of `notifications`
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NotificationsInfo {
+    #[serde(default)]
+    pub(crate) webhooks: Vec<WebhookInfo>,
+    #[serde(default)]
+    pub(crate) slack: Vec<SlackInfo>,
+}
+
+/// Endpoint receiving a POST request with a [`RunNotification`] per run
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookInfo {
+    pub(crate) url: String,
+    /// Extra request headers, e.g., `Authorization`
+    #[serde(default)]
+    pub(crate) headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub(crate) on: NotifyOn,
+}
+
+/// Slack incoming webhook, posted a message per run
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SlackInfo {
+    pub(crate) webhook_url: String,
+    /// Channel overriding the default one of the webhook, e.g., `#data-alerts`
+    pub(crate) channel: Option<String>,
+    #[serde(default)]
+    pub(crate) on: NotifyOn,
+}
+
+impl NotifyOn {
+    fn matches(&self, status: RunOutcome) -> bool {
+        match self {
+            NotifyOn::Always => true,
+            NotifyOn::Success => status == RunOutcome::Success,
+            NotifyOn::Failure => status == RunOutcome::Failure,
+        }
+    }
+}
+
+/// Runs a webhook is notified of
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifyOn {
+    #[default]
+    Always,
+    Success,
+    Failure,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RunOutcome {
+    Success,
+    /// Some node failed without its failure being tolerated, or the run
+    /// itself failed
+    Failure,
+}
+
+/// Summary of a finished run, as POSTed to webhooks
+#[derive(Debug, Serialize)]
+pub struct RunNotification {
+    pub(crate) status: RunOutcome,
+    /// Name of the project directory
+    pub(crate) project: String,
+    pub(crate) target: Option<String>,
+    pub(crate) finished_at: String,
+    pub(crate) duration_ms: u128,
+    pub(crate) n_success: usize,
+    pub(crate) n_errors: usize,
+    pub(crate) n_tolerated: usize,
+    pub(crate) n_skipped: usize,
+    /// Ids of the failed nodes, tolerated ones included
+    pub(crate) failed_node_ids: Vec<String>,
+    /// Errors of the failed nodes, by id
+    #[serde(skip)]
+    pub(crate) node_errors: BTreeMap<String, String>,
+    /// Error of a run that stopped before completion
+    pub(crate) error: Option<String>,
+}
+
+/// Longest error snippet of a Slack message, in characters
+const SNIPPET_LENGTH: usize = 200;
+
+impl RunNotification {
+    /// Slack message of the run, in Slack's `mrkdwn` format
+    pub fn slack_message(&self, channel: Option<&str>) -> serde_json::Value {
+        let target = match &self.target {
+            Some(target) => format!(" (target `{}`)", escape_slack(target)),
+            None => String::new(),
+        };
+        let mut lines = vec![match self.status {
+            RunOutcome::Success => format!(
+                ":white_check_mark: arnab run succeeded in `{}`{}",
+                escape_slack(&self.project),
+                target
+            ),
+            RunOutcome::Failure => format!(
+                ":x: arnab run failed in `{}`{}",
+                escape_slack(&self.project),
+                target
+            ),
+        }];
+        match &self.error {
+            Some(error) => lines.push(format!("```{}```", snippet(error))),
+            None => lines.push(format!(
+                "{} succeeded, {} failed ({} tolerated), {} skipped in {:.1}s",
+                self.n_success,
+                self.n_errors,
+                self.n_tolerated,
+                self.n_skipped,
+                self.duration_ms as f64 / 1000.0
+            )),
+        }
+        for id in &self.failed_node_ids {
+            lines.push(match self.node_errors.get(id) {
+                Some(error) => format!("• `{}`: {}", escape_slack(id), snippet(error)),
+                None => format!("• `{}`", escape_slack(id)),
+            });
+        }
+        let mut message = serde_json::json!({ "text": lines.join("\n") });
+        if let Some(channel) = channel {
+            message["channel"] = channel.into();
+        }
+        message
+    }
+}
+
+/// First line of an error, shortened and escaped for Slack
+fn snippet(error: &str) -> String {
+    let line = error.lines().next().unwrap_or_default().trim();
+    let mut snippet = line.chars().take(SNIPPET_LENGTH).collect::<String>();
+    if snippet.len() < line.len() {
+        snippet.push('…');
+    }
+    escape_slack(&snippet)
+}
+
+/// Escape the characters Slack reserves for links and mentions
+fn escape_slack(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Listens to run events to notify webhooks once runs finish
+pub struct Notifier {
+    info: NotificationsInfo,
+    project: String,
+    target: Option<String>,
+    started: Option<Instant>,
+    failed_node_ids: Vec<String>,
+    node_errors: BTreeMap<String, String>,
+}
+
+impl Notifier {
+    pub fn new(info: &NotificationsInfo, target: Option<String>) -> Self {
+        // Runs are started from the project root
+        let project = std::env::current_dir()
+            .ok()
+            .and_then(|dir| Some(dir.file_name()?.to_string_lossy().to_string()))
+            .unwrap_or_default();
+        Self {
+            info: info.clone(),
+            project,
+            target,
+            started: None,
+            failed_node_ids: vec![],
+            node_errors: BTreeMap::new(),
+        }
+    }
+
+    /// Notification of a finished run, if `event` finishes one
+    fn notification(&mut self, event: &Event) -> Option<RunNotification> {
+        let mut notification = RunNotification {
+            status: RunOutcome::Failure,
+            project: self.project.clone(),
+            target: self.target.clone(),
+            finished_at: iso_now(),
+            duration_ms: self
+                .started
+                .map_or(0, |started| started.elapsed().as_millis()),
+            n_success: 0,
+            n_errors: 0,
+            n_tolerated: 0,
+            n_skipped: 0,
+            failed_node_ids: vec![],
+            node_errors: BTreeMap::new(),
+            error: None,
+        };
+        match event {
+            Event::RunStarted { .. } => {
+                self.started = Some(Instant::now());
+                self.failed_node_ids.clear();
+                self.node_errors.clear();
+                return None;
+            }
+            Event::NodeFinished {
+                node_id,
+                status: NodeStatus::Error,
+                error,
+                ..
+            } => {
+                self.failed_node_ids.push(node_id.clone());
+                if let Some(error) = error {
+                    self.node_errors.insert(node_id.clone(), error.clone());
+                }
+                return None;
+            }
+            Event::RunFinished {
+                n_success,
+                n_errors,
+                n_tolerated,
+                n_skipped,
+                duration_ms,
+            } => {
+                if n_errors <= n_tolerated {
+                    notification.status = RunOutcome::Success;
+                }
+                notification.duration_ms = *duration_ms;
+                notification.n_success = *n_success;
+                notification.n_errors = *n_errors;
+                notification.n_tolerated = *n_tolerated;
+                notification.n_skipped = *n_skipped;
+            }
+            Event::RunFailed { error, .. } => notification.error = Some(error.clone()),
+            _ => return None,
+        }
+        notification.failed_node_ids = std::mem::take(&mut self.failed_node_ids);
+        notification.node_errors = std::mem::take(&mut self.node_errors);
+        self.started = None;
+        Some(notification)
+    }
+}
+
+impl EventListener for Notifier {
+    fn on_event(&mut self, event: &Event) {
+        let Some(notification) = self.notification(event) else {
+            return;
+        };
+        let body = serde_json::to_string(&notification).unwrap();
+        for webhook in &self.info.webhooks {
+            if !webhook.on.matches(notification.status) {
+                continue;
+            }
+            if let Err(e) = post_json(&webhook.url, &webhook.headers, &body) {
+                println!("WARNING: cannot notify webhook {}: {}", webhook.url, e);
+            }
+        }
+        for slack in &self.info.slack {
+            if !slack.on.matches(notification.status) {
+                continue;
+            }
+            let message = notification.slack_message(slack.channel.as_deref());
+            if let Err(e) = post_json(&slack.webhook_url, &BTreeMap::new(), &message.to_string()) {
+                // The URL of a Slack webhook is a secret
+                println!("WARNING: cannot notify Slack: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::{NotificationsInfo, Notifier, RunNotification, RunOutcome};
+    use crate::events::{Event, NodeStatus};
+
+    #[test]
+    fn summarize_runs() {
+        let mut notifier = Notifier::new(&NotificationsInfo::default(), Some("prod".into()));
+        let node_finished = |node_id: &str, status| Event::NodeFinished {
+            node_id: node_id.into(),
+            status,
+            duration_ms: 5,
+            n_rows: None,
+            error: None,
+            upstream_source_age_s: None,
+            stats: None,
+        };
+        let run_finished = |n_errors, n_tolerated| Event::RunFinished {
+            n_success: 2,
+            n_errors,
+            n_tolerated,
+            n_skipped: 0,
+            duration_ms: 1200,
+        };
+
+        assert!(notifier
+            .notification(&Event::RunStarted { n_nodes: 3 })
+            .is_none());
+        for event in [
+            node_finished("stg.orders", NodeStatus::Success),
+            node_finished("marts.revenue", NodeStatus::Error),
+        ] {
+            assert!(notifier.notification(&event).is_none());
+        }
+        let notification = notifier.notification(&run_finished(1, 0)).unwrap();
+        assert_eq!(notification.status, RunOutcome::Failure);
+        assert_eq!(notification.target.as_deref(), Some("prod"));
+        assert_eq!(notification.duration_ms, 1200);
+        assert_eq!(notification.failed_node_ids, vec!["marts.revenue"]);
+
+        // Failures of the last run are not carried over, and tolerated ones
+        // don't fail the run
+        notifier.notification(&Event::RunStarted { n_nodes: 3 });
+        notifier.notification(&node_finished("marts.revenue", NodeStatus::Error));
+        let notification = notifier.notification(&run_finished(1, 1)).unwrap();
+        assert_eq!(notification.status, RunOutcome::Success);
+        assert_eq!(notification.failed_node_ids, vec!["marts.revenue"]);
+
+        let notification = notifier
+            .notification(&Event::RunFailed {
+                error: "Cannot render hook".into(),
+                source_path: None,
+            })
+            .unwrap();
+        assert_eq!(notification.status, RunOutcome::Failure);
+        assert_eq!(notification.error.as_deref(), Some("Cannot render hook"));
+        assert!(notification.failed_node_ids.is_empty());
+    }
+
+    #[test]
+    fn format_slack_message() {
+        let mut notification = RunNotification {
+            status: RunOutcome::Failure,
+            project: "shop".into(),
+            target: Some("prod".into()),
+            finished_at: "2024-01-31T08:00:00Z".into(),
+            duration_ms: 1250,
+            n_success: 4,
+            n_errors: 2,
+            n_tolerated: 0,
+            n_skipped: 1,
+            failed_node_ids: vec!["marts.revenue".into(), "marts.users".into()],
+            node_errors: BTreeMap::from([(
+                "marts.revenue".into(),
+                format!("Binder Error: column <amount> not found\n{}", "LINE 1: ..."),
+            )]),
+            error: None,
+        };
+        assert_eq!(
+            notification.slack_message(Some("#data-alerts")),
+            serde_json::json!({
+                "channel": "#data-alerts",
+                "text": ":x: arnab run failed in `shop` (target `prod`)\n\
+                         4 succeeded, 2 failed (0 tolerated), 1 skipped in 1.2s\n\
+                         • `marts.revenue`: Binder Error: column &lt;amount&gt; not found\n\
+                         • `marts.users`"
+            })
+        );
+
+        notification.error = Some("x".repeat(300));
+        let message = notification.slack_message(None);
+        assert!(message.get("channel").is_none());
+        let text = message["text"].as_str().unwrap();
+        assert!(text.contains(&format!("```{}…```", "x".repeat(200))));
+    }
+}
@@ -0,0 +1,67 @@
+//! Models pinned with `arnab pin`, kept in `<target_path>/pins.json`: runs
+//! treat them as up to date and never rebuild them, so that downstream
+//! models can be iterated on against a frozen snapshot of them.
+
+use std::{collections::BTreeSet, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::ArnabError;
+
+pub const PINS_FILE: &str = "pins.json";
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Pins {
+    pub(crate) models: BTreeSet<String>,
+}
+
+impl Pins {
+    /// Load the pinned models, if any
+    pub fn load(dir: &Path) -> Result<Self, ArnabError> {
+        let file_path = dir.join(PINS_FILE);
+        if !file_path.exists() {
+            return Ok(Self::default());
+        }
+        let src = std::fs::read_to_string(&file_path).map_err(|e| {
+            ArnabError::Error(format!("Cannot read {}: {}", file_path.display(), e))
+        })?;
+        serde_json::from_str(&src)
+            .map_err(|e| ArnabError::Error(format!("Invalid pins {}: {}", file_path.display(), e)))
+    }
+
+    /// Save the pinned models, removing the file once none is left
+    pub fn save(&self, dir: &Path) -> Result<(), ArnabError> {
+        let file_path = dir.join(PINS_FILE);
+        if self.models.is_empty() {
+            if file_path.exists() {
+                std::fs::remove_file(&file_path).map_err(|e| ArnabError::Error(e.to_string()))?;
+            }
+            return Ok(());
+        }
+        std::fs::create_dir_all(dir).map_err(|e| ArnabError::Error(e.to_string()))?;
+        let json = serde_json::to_string_pretty(self).unwrap();
+        std::fs::write(file_path, json).map_err(|e| ArnabError::Error(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Pins, PINS_FILE};
+
+    #[test]
+    fn save_and_load_pins() {
+        let dir = std::env::temp_dir().join(format!("arnab_pins_{}", std::process::id()));
+        assert_eq!(Pins::load(&dir).unwrap(), Pins::default());
+
+        let mut pins = Pins::default();
+        pins.models.insert("stg.orders".into());
+        pins.save(&dir).unwrap();
+        assert_eq!(Pins::load(&dir).unwrap(), pins);
+
+        // No file is left once all models are unpinned
+        pins.models.clear();
+        pins.save(&dir).unwrap();
+        assert!(!dir.join(PINS_FILE).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
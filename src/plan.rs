@@ -0,0 +1,146 @@
+use std::fmt;
+
+use crate::{graph::Graph, maintenance::MaintenanceInfo, publish::PublisherInfo};
+
+/// A step of a pipeline run
+#[derive(Clone, Debug, PartialEq)]
+pub enum Step {
+    /// Create the missing schemas, `[database.]schema`, with the metadata
+    /// tables they hold
+    Bootstrap(Vec<String>),
+    /// Refresh expired caches of the given (source name, table name) pairs
+    RefreshSourceCaches(Vec<(String, String)>),
+    /// Build a node. Consecutive build steps may run concurrently, each node
+    /// waiting for its dependencies among them.
+    BuildNode(String),
+    /// Save the manifest of the whole graph
+    SaveManifest,
+    /// Analyze the built table models that opted in
+    AnalyzeTables,
+    /// Register the built external models in a catalog
+    PublishTables(PublisherInfo),
+    /// Append a provenance record of the run, fingerprinting the given
+    /// (source name, table name) pairs and the built tables
+    RecordProvenance(Vec<(String, String)>),
+    RunMaintenance(MaintenanceInfo),
+    /// Execute the `on_run_start` hooks, before any other step
+    RunStartHooks(Vec<String>),
+    /// Execute the `on_run_end` hooks, after all nodes are built
+    RunEndHooks(Vec<String>),
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Step::Bootstrap(schemas) => write!(f, "bootstrap schemas: {}", schemas.join(", ")),
+            Step::RefreshSourceCaches(sources) => write!(
+                f,
+                "refresh source caches: {}",
+                sources
+                    .iter()
+                    .map(|(source, table)| format!("{}.{}", source, table))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Step::BuildNode(id) => write!(f, "build {}", id),
+            Step::SaveManifest => write!(f, "save manifest"),
+            Step::AnalyzeTables => write!(f, "analyze tables"),
+            Step::PublishTables(publisher) => write!(f, "publish tables to {}", publisher.url),
+            Step::RecordProvenance(_) => write!(f, "record provenance"),
+            Step::RunMaintenance(_) => write!(f, "run maintenance"),
+            Step::RunStartHooks(hooks) => write!(f, "run {} on_run_start hooks", hooks.len()),
+            Step::RunEndHooks(hooks) => write!(f, "run {} on_run_end hooks", hooks.len()),
+        }
+    }
+}
+
+/// Ordered steps of a pipeline run over a graph, as produced by
+/// `Session::plan` and consumed by `Session::execute_plan`
+pub struct ExecutionPlan {
+    pub(crate) graph: Graph,
+    pub(crate) steps: Vec<Step>,
+    /// Rebuild table models even when their cached result is up to date
+    pub(crate) full_refresh: bool,
+    /// Stop building nodes after the first failure not tolerated
+    pub(crate) fail_fast: bool,
+    /// Show a progress bar instead of progress lines, on terminals
+    pub(crate) progress_bar: bool,
+}
+
+impl ExecutionPlan {
+    /// Ids of the nodes to build, in order
+    pub fn node_ids(&self) -> Vec<String> {
+        self.steps
+            .iter()
+            .filter_map(|step| match step {
+                Step::BuildNode(id) => Some(id.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Steps grouped into batches: consecutive build steps form one batch,
+    /// other steps are alone in theirs
+    pub fn batches(&self) -> Vec<&[Step]> {
+        let mut batches = vec![];
+        let mut start = 0;
+        for i in 1..=self.steps.len() {
+            let is_boundary = i == self.steps.len()
+                || !matches!(
+                    (&self.steps[i - 1], &self.steps[i]),
+                    (Step::BuildNode(_), Step::BuildNode(_))
+                );
+            if is_boundary {
+                batches.push(&self.steps[start..i]);
+                start = i;
+            }
+        }
+        batches
+    }
+
+    /// Print the steps, as done by `arnab run --dry-run`
+    pub fn print(&self) {
+        println!("Execution plan:");
+        for (nth, step) in (1..).zip(&self.steps) {
+            match step {
+                Step::BuildNode(id) => {
+                    let node = &self.graph.nodes[id];
+                    println!(
+                        "  {}. {} ({})",
+                        nth,
+                        step,
+                        node.materialize.as_deref().unwrap_or("view")
+                    );
+                }
+                _ => println!("  {}. {}", nth, step),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ExecutionPlan, Step};
+    use crate::graph::GraphBuilder;
+
+    #[test]
+    fn batch_build_steps() {
+        let plan = ExecutionPlan {
+            graph: GraphBuilder::new().build().unwrap(),
+            steps: vec![
+                Step::RefreshSourceCaches(vec![("raw".into(), "orders".into())]),
+                Step::BuildNode("a".into()),
+                Step::BuildNode("b".into()),
+                Step::SaveManifest,
+                Step::AnalyzeTables,
+            ],
+            full_refresh: false,
+            fail_fast: false,
+            progress_bar: false,
+        };
+        let batches = plan.batches();
+        assert_eq!(batches.len(), 4);
+        assert_eq!(batches[1].len(), 2);
+        assert_eq!(plan.node_ids(), vec!["a", "b"]);
+    }
+}
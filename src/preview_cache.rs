@@ -0,0 +1,120 @@
+//! Cache of model previews (`arnab show`), so that previewing the same model
+//! again during development doesn't rerun an expensive query. Entries are
+//! keyed by the preview query and a fingerprint of the database files, which
+//! any build changes, and expire after a TTL.
+
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{errors::ArnabError, manifest::checksum};
+
+/// Directory of the target directory holding cached previews
+pub const PREVIEW_CACHE_DIR: &str = "preview_cache";
+/// Lifetime of cached previews, in seconds, unless configured
+pub const DEFAULT_PREVIEW_CACHE_TTL: u64 = 3600;
+
+#[derive(Serialize, Deserialize)]
+struct CachedPreview {
+    /// Milliseconds since the Unix epoch
+    created_at: u64,
+    /// Header and rows, formatted
+    rows: Vec<Vec<String>>,
+}
+
+pub struct PreviewCache {
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+impl PreviewCache {
+    pub fn new(dir: PathBuf, ttl: Duration) -> Self {
+        Self { dir, ttl }
+    }
+
+    /// Key of a preview query against the given database files. Their size
+    /// and modification time, and those of their write-ahead logs, stand for
+    /// the state of the upstream relations.
+    pub fn key(sql: &str, database_paths: &[&Path]) -> String {
+        let mut fingerprint = sql.to_string();
+        for path in database_paths {
+            let wal_path = PathBuf::from(format!("{}.wal", path.display()));
+            for path in [path.to_path_buf(), wal_path] {
+                if let Ok(metadata) = std::fs::metadata(&path) {
+                    let modified = metadata
+                        .modified()
+                        .ok()
+                        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                        .unwrap_or_default();
+                    fingerprint.push_str(&format!(
+                        "\n{} {} {}",
+                        path.display(),
+                        metadata.len(),
+                        modified.as_nanos()
+                    ));
+                }
+            }
+        }
+        checksum(&fingerprint)
+    }
+
+    /// Cached rows of a preview and their age, unless missing or expired
+    pub fn get(&self, key: &str) -> Option<(Vec<Vec<String>>, Duration)> {
+        let src = std::fs::read_to_string(self.path(key)).ok()?;
+        let cached: CachedPreview = serde_json::from_str(&src).ok()?;
+        let age = Duration::from_millis(now().saturating_sub(cached.created_at));
+        (age < self.ttl).then_some((cached.rows, age))
+    }
+
+    /// Cache the rows of a preview, dropping expired entries
+    pub fn put(&self, key: &str, rows: Vec<Vec<String>>) -> Result<(), ArnabError> {
+        let to_error = |e: std::io::Error| ArnabError::Error(e.to_string());
+        std::fs::create_dir_all(&self.dir).map_err(to_error)?;
+        for entry in std::fs::read_dir(&self.dir).map_err(to_error)?.flatten() {
+            let expired = entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|t| t.elapsed().ok())
+                .is_some_and(|age| age >= self.ttl);
+            if expired {
+                let _ = std::fs::remove_file(entry.path());
+            }
+        }
+        let cached = CachedPreview {
+            created_at: now(),
+            rows,
+        };
+        std::fs::write(self.path(key), serde_json::to_string(&cached).unwrap()).map_err(to_error)
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::PreviewCache;
+
+    #[test]
+    fn key_preview_queries() {
+        let manifest = Path::new("Cargo.toml");
+        let key = PreviewCache::key("SELECT 1", &[manifest]);
+        assert_eq!(key, PreviewCache::key("SELECT 1", &[manifest]));
+        assert_ne!(key, PreviewCache::key("SELECT 2", &[manifest]));
+        assert_ne!(key, PreviewCache::key("SELECT 1", &[]));
+    }
+}
@@ -0,0 +1,92 @@
+//! Progress bar of pipeline runs on a terminal, redrawn in place below the
+//! status lines of finished nodes.
+
+use std::{
+    io::Write,
+    time::{Duration, Instant},
+};
+
+use crate::time_format::format_age;
+
+const BAR_WIDTH: usize = 30;
+
+pub struct ProgressBar {
+    total: usize,
+    n_done: usize,
+    started: Instant,
+}
+
+impl ProgressBar {
+    pub fn new(total: usize) -> Self {
+        Self {
+            total,
+            n_done: 0,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn finish_node(&mut self) {
+        self.n_done += 1;
+    }
+
+    /// Bar line with the node being built and the estimated time left, from
+    /// the average duration of the nodes done so far, e.g.,
+    /// `[==========>          ] 3/6 orders, ETA 12s`
+    pub fn render(&self, current: &str, elapsed: Duration) -> String {
+        let n_filled = BAR_WIDTH * self.n_done / self.total.max(1);
+        let mut bar = "=".repeat(n_filled);
+        if n_filled < BAR_WIDTH {
+            bar.push('>');
+        }
+        let eta = match self.n_done {
+            0 => "--".to_string(),
+            n_done => {
+                let left = elapsed.as_secs_f64() / n_done as f64 * (self.total - n_done) as f64;
+                format_age(left.round() as u64)
+            }
+        };
+        format!(
+            "[{:<width$}] {}/{} {}, ETA {}",
+            bar,
+            self.n_done,
+            self.total,
+            current,
+            eta,
+            width = BAR_WIDTH
+        )
+    }
+
+    /// Draw the bar in place of the current terminal line
+    pub fn draw(&self, current: &str) {
+        print!("\r\x1b[2K{}", self.render(current, self.started.elapsed()));
+        std::io::stdout().flush().unwrap();
+    }
+
+    /// Erase the bar, so that a status line is printed in its place
+    pub fn clear(&self) {
+        print!("\r\x1b[2K");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::ProgressBar;
+
+    #[test]
+    fn render_progress() {
+        let mut bar = ProgressBar::new(6);
+        assert_eq!(
+            bar.render("orders", Duration::ZERO),
+            format!("[>{}] 0/6 orders, ETA --", " ".repeat(29))
+        );
+        for _ in 0..3 {
+            bar.finish_node();
+        }
+        assert_eq!(
+            bar.render("users", Duration::from_secs(12)),
+            format!("[{}>{}] 3/6 users, ETA 12s", "=".repeat(15), " ".repeat(14))
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! Project checks: conventions of the project written as Jinja expressions
+//! on the `graph` object, e.g., that every mart has tests, failing
+//! compilation when they don't hold.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    data_tests::schema_tests,
+    errors::ArnabError,
+    graph::Graph,
+    listing::{model_entries, ModelEntry},
+};
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ProjectCheck {
+    pub(crate) name: String,
+    /// Expression that must be true, e.g.,
+    /// `graph.nodes | selectattr('n_upstream', 'gt', 15) | list | length == 0`
+    #[serde(rename = "assert")]
+    pub(crate) assertion: String,
+    /// Template of the failure message, rendered with `graph` too
+    pub(crate) message: Option<String>,
+}
+
+/// A node as seen by project checks: its `arnab ls` entry and more
+#[derive(Serialize)]
+struct CheckedNode {
+    #[serde(flatten)]
+    entry: ModelEntry,
+    /// Dotted path of the model relative to the models directory
+    fqn: String,
+    /// Direct dependents, sorted
+    dependents: Vec<String>,
+    /// Number of direct dependencies
+    n_upstream: usize,
+    /// Number of data tests declared in the model's schema file
+    n_tests: usize,
+    /// Source tables read, as `source.table`, sorted
+    sources: Vec<String>,
+}
+
+/// The `graph` object of check expressions, with its nodes in build order
+#[derive(Serialize)]
+struct CheckedGraph {
+    nodes: Vec<CheckedNode>,
+}
+
+impl CheckedGraph {
+    fn new(graph: &Graph) -> Self {
+        let entries = model_entries(graph, &graph.sorted_ids);
+        let nodes = entries
+            .into_iter()
+            .map(|entry| {
+                let node = &graph.nodes[&entry.id];
+                let mut dependents = node.nexts.iter().cloned().collect::<Vec<_>>();
+                dependents.sort();
+                let mut sources = node
+                    .sources
+                    .iter()
+                    .map(|(source, table)| format!("{}.{}", source, table))
+                    .collect::<Vec<_>>();
+                sources.sort();
+                CheckedNode {
+                    fqn: node.fqn.clone(),
+                    dependents,
+                    n_upstream: entry.depends_on.len(),
+                    n_tests: node
+                        .schema
+                        .as_ref()
+                        .map_or(0, |schema| schema_tests(&node.id, schema).len()),
+                    sources,
+                    entry,
+                }
+            })
+            .collect();
+        Self { nodes }
+    }
+}
+
+/// Evaluate the checks on the graph, failing with the messages of all the
+/// checks that don't hold
+pub fn run_project_checks(checks: &[ProjectCheck], graph: &Graph) -> Result<(), ArnabError> {
+    if checks.is_empty() {
+        return Ok(());
+    }
+    let env = minijinja::Environment::new();
+    let context = minijinja::context! { graph => CheckedGraph::new(graph) };
+    let mut failures = vec![];
+    for check in checks {
+        let invalid = |e: minijinja::Error| {
+            ArnabError::Error(format!("Invalid project check `{}`: {}", check.name, e))
+        };
+        let holds = env
+            .compile_expression(&check.assertion)
+            .and_then(|expression| expression.eval(&context))
+            .map_err(invalid)?
+            .is_true();
+        if holds {
+            continue;
+        }
+        let message = match &check.message {
+            Some(message) => env.render_str(message, &context).map_err(invalid)?,
+            None => format!("`{}` is false", check.assertion),
+        };
+        failures.push(format!("  - {}: {}", check.name, message));
+    }
+    match failures.is_empty() {
+        true => Ok(()),
+        false => Err(ArnabError::Error(format!(
+            "Project checks failed:\n{}",
+            failures.join("\n")
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{run_project_checks, ProjectCheck};
+    use crate::{
+        graph::GraphBuilder,
+        node::{Node, NodeKind},
+    };
+
+    #[test]
+    fn check_project_conventions() {
+        let mut builder = GraphBuilder::new();
+        for id in ["stg_orders", "stg_users", "orders"] {
+            builder
+                .add_node(Node::new(NodeKind::Sql, id, id, "SELECT 1"))
+                .unwrap();
+        }
+        builder.add_dependency("orders", "stg_orders").unwrap();
+        builder.add_dependency("orders", "stg_users").unwrap();
+        let graph = builder.build().unwrap();
+
+        let check = |assertion: &str| ProjectCheck {
+            name: "max_upstream".into(),
+            assertion: assertion.into(),
+            message: Some(
+                "{{ graph.nodes | selectattr('n_upstream', 'gt', 1) \
+                 | map(attribute='id') | join(', ') }} depend on too many models"
+                    .into(),
+            ),
+        };
+        let at_most = |n: usize| {
+            check(&format!(
+                "graph.nodes | selectattr('n_upstream', 'gt', {}) | list | length == 0",
+                n
+            ))
+        };
+        assert!(run_project_checks(&[at_most(2)], &graph).is_ok());
+        assert_eq!(
+            run_project_checks(&[at_most(1)], &graph)
+                .unwrap_err()
+                .message(),
+            "Project checks failed:\n  - max_upstream: orders depend on too many models"
+        );
+        assert!(run_project_checks(&[check("graph.nodes |")], &graph)
+            .unwrap_err()
+            .message()
+            .starts_with("Invalid project check `max_upstream`"));
+    }
+}
@@ -0,0 +1,244 @@
+//! Provenance of pipeline runs, for teams that must audit how a dataset was
+//! produced: each run appends a record of the code, configuration and data
+//! it read and wrote to `<target_path>/provenance.jsonl`. Records are
+//! hash-chained, so that editing or removing one breaks the chain. Removing
+//! the last records or the whole file doesn't, so the hash of the last record,
+//! the head, is checked against one kept elsewhere when given.
+
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use duckdb::Connection;
+use serde::{Deserialize, Serialize};
+
+use crate::{errors::ArnabError, manifest::checksum};
+
+pub const PROVENANCE_FILE: &str = "provenance.jsonl";
+
+/// Record of a pipeline run
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ProvenanceRecord {
+    pub(crate) run_started_at: String,
+    pub(crate) finished_at: String,
+    pub(crate) arnab_version: String,
+    /// Commit of the project's git repository, if any
+    pub(crate) git_commit: Option<String>,
+    /// Whether the git working tree had uncommitted changes
+    pub(crate) git_dirty: Option<bool>,
+    /// SHA-256 of the configuration file
+    pub(crate) config_hash: Option<String>,
+    pub(crate) target: Option<String>,
+    /// Fingerprints of the source tables read, by `source.table`
+    pub(crate) sources: BTreeMap<String, Fingerprint>,
+    /// Fingerprints of the tables built, by model id
+    pub(crate) outputs: BTreeMap<String, Fingerprint>,
+    pub(crate) n_errors: usize,
+    /// Hash of the previous record of the file
+    pub(crate) previous_hash: Option<String>,
+    /// SHA-256 of the record serialized without this field
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub(crate) hash: String,
+}
+
+/// Row count and order-independent checksum of the rows of a relation
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    pub(crate) relation: String,
+    pub(crate) n_rows: i64,
+    pub(crate) checksum: String,
+}
+
+/// Fingerprint the rows of a relation
+pub fn fingerprint(conn: &Connection, relation: &str) -> Result<Fingerprint, ArnabError> {
+    let sql = format!(
+        "SELECT count(*), coalesce(sum(hash(t)), 0)::VARCHAR FROM {} AS t",
+        relation
+    );
+    let (n_rows, checksum) = conn
+        .query_row(&sql, [], |r| Ok((r.get(0)?, r.get(1)?)))
+        .map_err(|e| ArnabError::StatementExecutionError {
+            msg: e.to_string(),
+            sql,
+            path: relation.to_string(),
+        })?;
+    Ok(Fingerprint {
+        relation: relation.to_string(),
+        n_rows,
+        checksum,
+    })
+}
+
+/// Commit of the git repository holding the current directory, and whether
+/// its working tree has uncommitted changes
+pub fn git_state() -> (Option<String>, Option<bool>) {
+    let run = |args: &[&str]| {
+        Command::new("git")
+            .args(args)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    let commit = run(&["rev-parse", "HEAD"]);
+    let dirty = commit
+        .as_ref()
+        .and_then(|_| run(&["status", "--porcelain"]))
+        .map(|status| !status.is_empty());
+    (commit, dirty)
+}
+
+impl ProvenanceRecord {
+    fn compute_hash(&self) -> String {
+        let mut unhashed = serde_json::to_value(self).unwrap();
+        unhashed.as_object_mut().unwrap().remove("hash");
+        checksum(&unhashed.to_string())
+    }
+
+    /// Chain the record to the last one of the file in `dir` and append it
+    pub fn append(mut self, dir: &Path) -> Result<PathBuf, ArnabError> {
+        std::fs::create_dir_all(dir).map_err(|e| ArnabError::Error(e.to_string()))?;
+        let file_path = dir.join(PROVENANCE_FILE);
+        let records = load_records(&file_path)?;
+        self.previous_hash = records.last().map(|r| r.hash.clone());
+        self.hash = self.compute_hash();
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&file_path)
+            .map_err(|e| ArnabError::Error(e.to_string()))?;
+        writeln!(file, "{}", serde_json::to_string(&self).unwrap())
+            .map_err(|e| ArnabError::Error(e.to_string()))?;
+        Ok(file_path)
+    }
+}
+
+fn load_records(file_path: &Path) -> Result<Vec<ProvenanceRecord>, ArnabError> {
+    if !file_path.exists() {
+        return Ok(vec![]);
+    }
+    let src = std::fs::read_to_string(file_path)
+        .map_err(|e| ArnabError::Error(format!("Cannot read {}: {}", file_path.display(), e)))?;
+    src.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            serde_json::from_str(line).map_err(|e| {
+                ArnabError::Error(format!("Invalid record in {}: {}", file_path.display(), e))
+            })
+        })
+        .collect()
+}
+
+/// Check that each record of a provenance file matches its hash and chains
+/// to the previous one, and that the chain holds the record hashed `head`, if
+/// given. Returns the number of records and the hash of the last one.
+pub fn verify_chain(
+    file_path: &Path,
+    head: Option<&str>,
+) -> Result<(usize, Option<String>), ArnabError> {
+    let records = load_records(file_path)?;
+    let mut previous_hash = None;
+    for (nth, record) in (1..).zip(&records) {
+        if record.previous_hash != previous_hash {
+            return Err(ArnabError::Error(format!(
+                "Record {} does not follow record {}: a record was removed or reordered",
+                nth,
+                nth - 1
+            )));
+        }
+        if record.compute_hash() != record.hash {
+            return Err(ArnabError::Error(format!(
+                "Record {} (run started at {}) was modified",
+                nth, record.run_started_at
+            )));
+        }
+        previous_hash = Some(record.hash.clone());
+    }
+    if let Some(head) = head.filter(|head| !records.iter().any(|r| r.hash == *head)) {
+        return Err(ArnabError::Error(format!(
+            "No record is hashed {}: the last records or the whole file were removed",
+            head
+        )));
+    }
+    Ok((records.len(), previous_hash))
+}
+
+#[cfg(test)]
+mod test {
+    use duckdb::Connection;
+
+    use super::{fingerprint, verify_chain, ProvenanceRecord, PROVENANCE_FILE};
+
+    #[test]
+    fn fingerprint_ignores_row_order() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE a AS SELECT * FROM (VALUES (1, 'x'), (2, 'y')) t(id, name);
+             CREATE TABLE b AS SELECT * FROM (VALUES (2, 'y'), (1, 'x')) t(id, name);
+             CREATE TABLE c AS SELECT * FROM (VALUES (1, 'x'), (2, 'z')) t(id, name);",
+        )
+        .unwrap();
+        let (a, b, c) = (
+            fingerprint(&conn, "a").unwrap(),
+            fingerprint(&conn, "b").unwrap(),
+            fingerprint(&conn, "c").unwrap(),
+        );
+        assert_eq!(a.n_rows, 2);
+        assert_eq!(a.checksum, b.checksum);
+        assert_ne!(a.checksum, c.checksum);
+    }
+
+    #[test]
+    fn detect_broken_chain() {
+        let dir = std::env::temp_dir().join(format!("arnab-provenance-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for started_at in ["2024-01-01T00:00:00Z", "2024-01-02T00:00:00Z"] {
+            let record = ProvenanceRecord {
+                run_started_at: started_at.into(),
+                ..Default::default()
+            };
+            record.append(&dir).unwrap();
+        }
+        let file_path = dir.join(PROVENANCE_FILE);
+        assert_eq!(verify_chain(&file_path, None).unwrap().0, 2);
+
+        let src = std::fs::read_to_string(&file_path).unwrap();
+        std::fs::write(&file_path, src.replacen("2024-01-01", "2023-01-01", 1)).unwrap();
+        assert!(verify_chain(&file_path, None).is_err());
+
+        let second_only = src.lines().nth(1).unwrap();
+        std::fs::write(&file_path, second_only).unwrap();
+        assert!(verify_chain(&file_path, None).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn detect_removed_head() {
+        let dir =
+            std::env::temp_dir().join(format!("arnab-provenance-head-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for started_at in ["2024-01-01T00:00:00Z", "2024-01-02T00:00:00Z"] {
+            let record = ProvenanceRecord {
+                run_started_at: started_at.into(),
+                ..Default::default()
+            };
+            record.append(&dir).unwrap();
+        }
+        let file_path = dir.join(PROVENANCE_FILE);
+        let (_, head) = verify_chain(&file_path, None).unwrap();
+        let head = head.unwrap();
+        assert_eq!(verify_chain(&file_path, Some(&head)).unwrap().0, 2);
+
+        let src = std::fs::read_to_string(&file_path).unwrap();
+        std::fs::write(&file_path, src.lines().next().unwrap()).unwrap();
+        assert!(verify_chain(&file_path, None).is_ok());
+        assert!(verify_chain(&file_path, Some(&head)).is_err());
+
+        std::fs::remove_file(&file_path).unwrap();
+        assert!(verify_chain(&file_path, Some(&head)).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,60 @@
+//! Registration of external models (files written by arnab, e.g., Parquet on
+//! object storage) in an external catalog, so that query engines outside
+//! DuckDB see new data as soon as it is built.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::http::post_json;
+
+/// Configuration of `catalog_publisher`
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct PublisherInfo {
+    /// Endpoint receiving a POST request per built external model
+    pub(crate) url: String,
+    /// Extra request headers, e.g., `Authorization`
+    #[serde(default)]
+    pub(crate) headers: BTreeMap<String, String>,
+}
+
+/// An external model as registered in a catalog
+#[derive(Debug, Serialize)]
+pub struct PublishedTable {
+    pub(crate) name: String,
+    /// Location of the written file, e.g., `s3://bucket/orders.parquet`
+    pub(crate) location: String,
+    pub(crate) format: String,
+    pub(crate) columns: Vec<PublishedColumn>,
+    pub(crate) built_at: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PublishedColumn {
+    pub(crate) name: String,
+    pub(crate) data_type: String,
+}
+
+/// Registers or refreshes tables in an external catalog
+pub trait CatalogPublisher {
+    fn publish(&self, table: &PublishedTable) -> Result<(), String>;
+}
+
+/// Publishes tables by POSTing them as JSON to an HTTP endpoint, e.g., a
+/// small service calling the Glue or Unity Catalog APIs
+pub struct HttpPublisher {
+    info: PublisherInfo,
+}
+
+impl HttpPublisher {
+    pub fn new(info: PublisherInfo) -> Self {
+        Self { info }
+    }
+}
+
+impl CatalogPublisher for HttpPublisher {
+    fn publish(&self, table: &PublishedTable) -> Result<(), String> {
+        let body = serde_json::to_string(table).unwrap();
+        post_json(&self.info.url, &self.info.headers, &body)
+    }
+}
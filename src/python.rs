@@ -0,0 +1,125 @@
+//! Python models: `.py` files defining a `model(ref)` function that returns a
+//! dataframe. They run in a subprocess, reading their upstream relations from
+//! Parquet files through `ref('name')`, and their result is written to
+//! Parquet too, then loaded into the model's table.
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use duckdb::Connection;
+use regex::Regex;
+
+use crate::{errors::ArnabError, node::Node};
+
+/// Interpreter running Python models, unless configured
+pub const DEFAULT_PYTHON: &str = "python3";
+
+/// Imports a model file and writes the dataframe returned by its `model(ref)`
+/// function. pandas and polars dataframes are supported.
+const PYTHON_RUNNER: &str = r#"import importlib.util
+import os
+import sys
+
+model_path, input_dir, output_path = sys.argv[1:4]
+
+
+def ref(name):
+    path = os.path.join(input_dir, name + ".parquet")
+    if not os.path.exists(path):
+        raise ValueError("`%s` is not an upstream model of %s" % (name, model_path))
+    import pandas
+
+    return pandas.read_parquet(path)
+
+
+spec = importlib.util.spec_from_file_location("arnab_model", model_path)
+module = importlib.util.module_from_spec(spec)
+spec.loader.exec_module(module)
+result = module.model(ref)
+if hasattr(result, "write_parquet"):
+    result.write_parquet(output_path)
+else:
+    result.to_parquet(output_path)
+"#;
+
+/// Whether a Python file is a model, i.e., defines `model()`. Other files,
+/// e.g., helpers or scripts, are ignored.
+pub fn is_python_model(src: &str) -> bool {
+    Regex::new(r"(?m)^def\s+model\s*\(").unwrap().is_match(src)
+}
+
+/// Models a Python model depends on: the arguments of its `ref()` calls
+pub fn python_references(src: &str) -> HashSet<String> {
+    let re = Regex::new(r#"\bref\(\s*["']([^"']+)["']\s*\)"#).unwrap();
+    re.captures_iter(src).map(|c| c[1].to_string()).collect()
+}
+
+/// Run a Python model in `dir`: export its upstream relations to Parquet, run
+/// the model, and return the path of the Parquet file holding its result
+pub fn run_python_model(conn: &Connection, node: &Node, dir: &Path) -> Result<PathBuf, ArnabError> {
+    let to_error = |sql: String| {
+        move |msg: String| ArnabError::StatementExecutionError {
+            msg,
+            sql,
+            path: node.path.clone(),
+        }
+    };
+    std::fs::create_dir_all(dir).map_err(|e| ArnabError::Error(e.to_string()))?;
+
+    let mut prevs = node.prevs.iter().collect::<Vec<_>>();
+    prevs.sort();
+    for prev in prevs {
+        let input_path = dir.join(format!("{}.parquet", prev));
+        let sql = format!(
+            "COPY (SELECT * FROM {}) TO '{}' (FORMAT parquet)",
+            prev,
+            input_path.display().to_string().replace('\'', "''")
+        );
+        conn.execute_batch(&sql)
+            .map_err(|e| to_error(sql.clone())(e.to_string()))?;
+    }
+
+    let runner_path = dir.join("runner.py");
+    std::fs::write(&runner_path, PYTHON_RUNNER).map_err(|e| ArnabError::Error(e.to_string()))?;
+    let output_path = dir.join("output.parquet");
+    let python = node.python.as_deref().unwrap_or(DEFAULT_PYTHON);
+    let command = format!("{} {}", python, node.path);
+    let output = Command::new(python)
+        .arg(&runner_path)
+        .arg(&node.path)
+        .arg(dir)
+        .arg(&output_path)
+        .envs(&node.env_vars)
+        .output()
+        .map_err(|e| to_error(command.clone())(e.to_string()))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(to_error(command)(stderr.trim().to_string()));
+    }
+    Ok(output_path)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::{is_python_model, python_references};
+
+    #[test]
+    fn parse_python_models() {
+        let src = "import pandas as pd\n\n\
+                   def model(ref):\n    \
+                       orders = ref('orders')\n    \
+                       users = ref(\"users\")\n    \
+                       return orders.merge(users)\n";
+        assert!(is_python_model(src));
+        assert!(!is_python_model("def helper():\n    pass\n"));
+        assert_eq!(
+            python_references(src),
+            HashSet::from(["orders".to_string(), "users".to_string()])
+        );
+    }
+}
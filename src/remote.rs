@@ -0,0 +1,246 @@
+//! Experimental remote execution: node builds are dispatched to worker
+//! processes (`arnab worker`), possibly on other machines. Messages are JSON
+//! objects, one per line, over TCP: the coordinator sends a `BuildRequest`
+//! and the worker replies with a `BuildResponse` once the node is built.
+//!
+//! Workers build nodes in their own database, so models can only refer to
+//! models built by other workers when all of them share storage, e.g., a
+//! MotherDuck database.
+//!
+//! Requests carry a token shared by the coordinator and the workers, which
+//! only build SQL models and seeds: Python and shell models would run
+//! arbitrary commands on the worker's machine.
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+};
+
+use duckdb::Connection;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    errors::ArnabError,
+    node::{Node, NodeExecutionResult, NodeKind},
+    verbosity::info,
+};
+
+pub const WORKER_TOKEN_ENV: &str = "ARNAB_WORKER_TOKEN";
+
+#[derive(Serialize, Deserialize)]
+struct BuildRequest {
+    /// Secret shared with the worker, see `worker_token`
+    token: String,
+    node: Node,
+    /// Limit DuckDB to a single thread, when retrying a node out of memory
+    single_thread: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+enum BuildResponse {
+    Built {
+        n_rows: Option<usize>,
+        stats: Vec<(String, String)>,
+        #[serde(default)]
+        empty: Option<bool>,
+        #[serde(default)]
+        estimated: bool,
+    },
+    Failed {
+        msg: String,
+        /// Failing statement, if the error comes from executing one
+        sql: Option<String>,
+    },
+}
+
+impl BuildResponse {
+    fn from_result(result: Result<NodeExecutionResult, ArnabError>) -> Self {
+        match result {
+            Ok(NodeExecutionResult::Sql {
+                n_rows,
+                stats,
+                empty,
+                estimated,
+            }) => Self::Built {
+                n_rows,
+                stats,
+                empty,
+                estimated,
+            },
+            Err(ArnabError::StatementExecutionError { msg, sql, .. }) => Self::Failed {
+                msg,
+                sql: Some(sql),
+            },
+            Err(e) => Self::Failed {
+                msg: e.message(),
+                sql: None,
+            },
+        }
+    }
+
+    fn into_result(self, node: &Node) -> Result<NodeExecutionResult, ArnabError> {
+        match self {
+            Self::Built {
+                n_rows,
+                stats,
+                empty,
+                estimated,
+            } => Ok(NodeExecutionResult::Sql {
+                n_rows,
+                stats,
+                empty,
+                estimated,
+            }),
+            Self::Failed {
+                msg,
+                sql: Some(sql),
+            } => Err(ArnabError::StatementExecutionError {
+                msg,
+                sql,
+                path: node.path.clone(),
+            }),
+            Self::Failed { msg, sql: None } => Err(ArnabError::Error(msg)),
+        }
+    }
+}
+
+/// Build a node on the worker listening at `address`
+pub fn execute_remote(
+    address: &str,
+    token: &str,
+    node: &Node,
+    single_thread: bool,
+) -> Result<NodeExecutionResult, ArnabError> {
+    let to_error =
+        |e: std::io::Error| ArnabError::Error(format!("Failed to reach worker {}: {}", address, e));
+    let mut stream = TcpStream::connect(address).map_err(to_error)?;
+    let request = BuildRequest {
+        token: token.into(),
+        node: node.clone(),
+        single_thread,
+    };
+    let mut line = serde_json::to_string(&request).unwrap();
+    line.push('\n');
+    stream.write_all(line.as_bytes()).map_err(to_error)?;
+
+    let mut line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut line)
+        .map_err(to_error)?;
+    let response: BuildResponse = serde_json::from_str(&line).map_err(|e| {
+        ArnabError::Error(format!("Invalid response from worker {}: {}", address, e))
+    })?;
+    response.into_result(node)
+}
+
+/// Serve build requests forever, each connection in its own thread with its
+/// own connection to the worker's database. Requests without `token` are
+/// rejected.
+pub fn serve(listener: TcpListener, conn: &Connection, token: &str) -> Result<(), ArnabError> {
+    let to_error = |e: std::io::Error| ArnabError::Error(e.to_string());
+    for stream in listener.incoming() {
+        let stream = stream.map_err(to_error)?;
+        let conn = conn
+            .try_clone()
+            .map_err(|e| ArnabError::Error(e.to_string()))?;
+        let token = token.to_string();
+        std::thread::spawn(move || {
+            if let Err(e) = handle_request(stream, &conn, &token) {
+                println!("WARNING: Failed to handle build request: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+fn handle_request(stream: TcpStream, conn: &Connection, token: &str) -> Result<(), std::io::Error> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let response = match serde_json::from_str::<BuildRequest>(&line) {
+        Ok(request) if !tokens_match(&request.token, token) => {
+            println!("WARNING: Rejected build request with an invalid token");
+            BuildResponse::Failed {
+                msg: "Invalid worker token".into(),
+                sql: None,
+            }
+        }
+        Ok(request) if matches!(request.node.node_kind, NodeKind::Python | NodeKind::Shell) => {
+            BuildResponse::Failed {
+                msg: format!(
+                    "Worker refused to build {}: only SQL models and seeds are built remotely",
+                    request.node.id
+                ),
+                sql: None,
+            }
+        }
+        Ok(request) => {
+            info!("Building {}", request.node.id);
+            let result = if request.single_thread {
+                request.node.execute_with_single_thread(conn)
+            } else {
+                request.node.execute(conn)
+            };
+            BuildResponse::from_result(result)
+        }
+        Err(e) => BuildResponse::Failed {
+            msg: format!("Invalid build request: {}", e),
+            sql: None,
+        },
+    };
+    let mut line = serde_json::to_string(&response).unwrap();
+    line.push('\n');
+    (&stream).write_all(line.as_bytes())
+}
+
+/// Compare tokens in a time independent of where they differ
+fn tokens_match(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::TcpListener;
+
+    use duckdb::Connection;
+
+    use super::{execute_remote, serve};
+    use crate::node::{Node, NodeExecutionResult, NodeKind};
+
+    #[test]
+    fn build_on_worker() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let conn = Connection::open_in_memory().unwrap();
+        let worker_conn = conn.try_clone().unwrap();
+        std::thread::spawn(move || serve(listener, &worker_conn, "secret"));
+
+        let mut node = Node::new(NodeKind::Sql, "t.sql", "t", "");
+        node.rendered_src = "SELECT 1 AS a UNION ALL SELECT 2".into();
+        node.materialize = Some("table".into());
+        let NodeExecutionResult::Sql { n_rows, .. } =
+            execute_remote(&address, "secret", &node, false).unwrap();
+        assert_eq!(n_rows, Some(2));
+        let count: i64 = conn
+            .query_row("SELECT count(*) FROM t", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(count, 2);
+
+        node.rendered_src = "SELECT * FROM missing".into();
+        assert!(execute_remote(&address, "secret", &node, true).is_err());
+
+        // Requests without the token and script models are rejected
+        node.rendered_src = "SELECT 1 AS a".into();
+        let Err(e) = execute_remote(&address, "guess", &node, false) else {
+            panic!("build request with an invalid token was accepted");
+        };
+        assert_eq!(e.message(), "Invalid worker token");
+        let script = Node::new(NodeKind::Shell, "s.sh", "s", "");
+        assert!(execute_remote(&address, "secret", &script, false).is_err());
+    }
+}
@@ -0,0 +1,157 @@
+//! Results of the last pipeline run, kept in `<target_path>/run_results.json`
+//! so that `arnab retry` builds again what failed, once fixed, instead of the
+//! whole selection.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::Path,
+};
+
+use chrono::{DateTime, Utc};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{errors::ArnabError, graph::Graph};
+
+pub const RUN_RESULTS_FILE: &str = "run_results.json";
+
+/// Outcome of a pipeline run
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RunSummary {
+    pub(crate) n_success: usize,
+    pub(crate) n_errors: usize,
+    /// Errors of models with `on_error: continue`, among `n_errors`
+    pub(crate) n_tolerated: usize,
+    /// Nodes left unbuilt after a failure with `--fail-fast`
+    pub(crate) n_skipped: usize,
+}
+
+impl RunSummary {
+    /// Whether a model failed without its failure being tolerated
+    pub fn failed(&self) -> bool {
+        self.n_errors > self.n_tolerated
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RunResults {
+    pub(crate) run_started_at: String,
+    /// Nodes selected by the run, in build order
+    pub(crate) selected: Vec<String>,
+    /// Nodes that failed to build
+    pub(crate) failed: Vec<String>,
+    /// Nodes left unbuilt after a failure with `--fail-fast`
+    #[serde(default)]
+    pub(crate) skipped: Vec<String>,
+    /// Counts of the run, for CI pipelines to read
+    #[serde(default)]
+    pub(crate) summary: RunSummary,
+    /// Start of the last run that built each node successfully, this one or
+    /// an earlier one
+    #[serde(default)]
+    pub(crate) last_successful_runs: BTreeMap<String, DateTime<Utc>>,
+    /// Age in seconds of the oldest cached source upstream of each selected
+    /// node at build time, for nodes reading cached sources
+    #[serde(default)]
+    pub(crate) upstream_source_ages: BTreeMap<String, u64>,
+    /// Column name and value pairs returned by the `stats_sql` of each node
+    /// built by the run
+    #[serde(default)]
+    pub(crate) node_stats: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl RunResults {
+    /// Load the results of the last run, if any
+    pub fn load(dir: &Path) -> Result<Option<Self>, ArnabError> {
+        let file_path = dir.join(RUN_RESULTS_FILE);
+        if !file_path.exists() {
+            return Ok(None);
+        }
+        let src = std::fs::read_to_string(&file_path).map_err(|e| {
+            ArnabError::Error(format!("Cannot read {}: {}", file_path.display(), e))
+        })?;
+        serde_json::from_str(&src).map(Some).map_err(|e| {
+            ArnabError::Error(format!(
+                "Invalid run results {}: {}",
+                file_path.display(),
+                e
+            ))
+        })
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<(), ArnabError> {
+        std::fs::create_dir_all(dir).map_err(|e| ArnabError::Error(e.to_string()))?;
+        let json = serde_json::to_string_pretty(self).unwrap();
+        std::fs::write(dir.join(RUN_RESULTS_FILE), json)
+            .map_err(|e| ArnabError::Error(e.to_string()))
+    }
+
+    /// Nodes to build again, in topological order: the failed and skipped
+    /// ones and the selected ones downstream of them, which may have been built on stale
+    /// or missing relations. Nodes removed from the project since are left
+    /// out.
+    pub fn retry_ids(&self, graph: &Graph) -> Vec<String> {
+        let selected = self.selected.iter().collect::<HashSet<_>>();
+        let mut to_retry = HashSet::new();
+        let mut stack = self
+            .failed
+            .iter()
+            .chain(&self.skipped)
+            .filter(|id| graph.nodes.contains_key(*id))
+            .collect::<Vec<_>>();
+        while let Some(id) = stack.pop() {
+            if to_retry.insert(id) {
+                stack.extend(
+                    graph.nodes[id]
+                        .nexts
+                        .iter()
+                        .filter(|n| selected.contains(n)),
+                );
+            }
+        }
+        graph
+            .sorted_ids
+            .iter()
+            .filter(|id| to_retry.contains(id))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RunResults;
+    use crate::{
+        graph::GraphBuilder,
+        node::{Node, NodeKind},
+    };
+
+    #[test]
+    fn retry_failed_and_downstream() {
+        let mut builder = GraphBuilder::new();
+        for id in ["a", "b", "c", "d", "e"] {
+            builder
+                .add_node(Node::new(NodeKind::Sql, id, id, "SELECT 1"))
+                .unwrap();
+        }
+        // a -> b -> c, b -> d, a -> e
+        builder.add_dependency("b", "a").unwrap();
+        builder.add_dependency("c", "b").unwrap();
+        builder.add_dependency("d", "b").unwrap();
+        builder.add_dependency("e", "a").unwrap();
+        let graph = builder.build().unwrap();
+
+        let results = RunResults {
+            selected: ["a", "b", "c", "e"].map(String::from).to_vec(),
+            failed: ["b", "removed"].map(String::from).to_vec(),
+            ..Default::default()
+        };
+        assert_eq!(results.retry_ids(&graph), vec!["b", "c"]);
+
+        let results = RunResults {
+            skipped: vec!["e".into()],
+            ..results
+        };
+        assert_eq!(results.retry_ids(&graph), vec!["b", "c", "e"]);
+    }
+}
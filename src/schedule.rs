@@ -0,0 +1,327 @@
+//! Recurring runs of `arnab schedule`: a long-lived process running the
+//! `schedules` of the config at the times of their cron expressions, in the
+//! timezone of `time_format`, and appending the outcome of each run to
+//! `<target_path>/schedule_history.jsonl`.
+
+use std::{io::Write, path::Path};
+
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDateTime, TimeZone, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    errors::ArnabError,
+    selection::Selection,
+    session::{RunOptions, Session},
+    time_format::Timezone,
+    verbosity::info,
+};
+
+pub const SCHEDULE_HISTORY_FILE: &str = "schedule_history.jsonl";
+
+/// A five-field cron expression: minute, hour, day of month, month and day
+/// of week (0 or 7 for Sunday). Fields are `*`, numbers, ranges (`1-5`) and
+/// lists of them, each optionally with a step (`*/15`).
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct CronExpr {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether the day of month, or of week, is restricted: when both are,
+    /// a day matching either of them matches, as in cron
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl TryFrom<String> for CronExpr {
+    type Error = String;
+
+    fn try_from(expr: String) -> Result<Self, Self::Error> {
+        let expanded = match expr.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            expr => expr,
+        };
+        let fields = expanded.split_whitespace().collect::<Vec<_>>();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!(
+                "Invalid cron expression `{}`: expected 5 fields",
+                expr
+            ));
+        };
+        let parse = |field: &str, min: u32, max: u32| {
+            parse_field(field, min, max)
+                .map_err(|e| format!("Invalid cron expression `{}`: {}", expr, e))
+        };
+        let mut weekdays_mask = parse(weekdays, 0, 7)?;
+        // Sunday is both 0 and 7
+        if weekdays_mask & (1 << 7) != 0 {
+            weekdays_mask |= 1;
+        }
+        Ok(Self {
+            minutes: parse(minutes, 0, 59)?,
+            hours: parse(hours, 0, 23)?,
+            days: parse(days, 1, 31)?,
+            months: parse(months, 1, 12)?,
+            weekdays: weekdays_mask,
+            days_restricted: !days.starts_with('*'),
+            weekdays_restricted: !weekdays.starts_with('*'),
+        })
+    }
+}
+
+/// Bit mask of the values of a cron field
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut mask = 0;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(step) if step > 0 => (range, step),
+                _ => return Err(format!("invalid step `{}`", step)),
+            },
+            None => (item, 1),
+        };
+        let number = |n: &str| match n.parse::<u32>() {
+            Ok(n) if (min..=max).contains(&n) => Ok(n),
+            _ => Err(format!("`{}` is not a number from {} to {}", n, min, max)),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (number(start)?, number(end)?),
+            // A single value with a step runs up to the maximum, as in cron
+            None if step > 1 => (number(range)?, max),
+            None => (number(range)?, number(range)?),
+        };
+        if start > end {
+            return Err(format!("empty range `{}`", range));
+        }
+        for n in (start..=end).step_by(step as usize) {
+            mask |= 1 << n;
+        }
+    }
+    Ok(mask)
+}
+
+impl CronExpr {
+    fn day_matches(&self, time: &NaiveDateTime) -> bool {
+        let day = self.days & (1 << time.day()) != 0;
+        let weekday = self.weekdays & (1 << time.weekday().num_days_from_sunday()) != 0;
+        match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+
+    /// First time matching the expression strictly after `after`, looking
+    /// a few years ahead at most, e.g., for `0 0 31 2 *`
+    pub fn next_after(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let start = after.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let mut time = start;
+        while time < start + Duration::days(5 * 366) {
+            let midnight = time.date().and_hms_opt(0, 0, 0)?;
+            if self.months & (1 << time.month()) == 0 {
+                let next_month = match time.month() {
+                    12 => midnight
+                        .with_day(1)?
+                        .with_month(1)?
+                        .with_year(time.year() + 1)?,
+                    month => midnight.with_day(1)?.with_month(month + 1)?,
+                };
+                time = next_month;
+            } else if !self.day_matches(&time) {
+                time = midnight + Duration::days(1);
+            } else if self.hours & (1 << time.hour()) == 0 {
+                time = time.with_minute(0)? + Duration::hours(1);
+            } else if self.minutes & (1 << time.minute()) == 0 {
+                time += Duration::minutes(1);
+            } else {
+                return Some(time);
+            }
+        }
+        None
+    }
+
+    /// Next run strictly after `after`, in the given timezone. Local times
+    /// skipped by a DST change are skipped too.
+    pub fn next_run(&self, after: DateTime<Utc>, timezone: Timezone) -> Option<DateTime<Utc>> {
+        let mut time = match timezone {
+            Timezone::Utc => after.naive_utc(),
+            Timezone::Local => after.with_timezone(&Local).naive_local(),
+        };
+        loop {
+            time = self.next_after(time)?;
+            let next = match timezone {
+                Timezone::Utc => Some(Utc.from_utc_datetime(&time)),
+                Timezone::Local => Local
+                    .from_local_datetime(&time)
+                    .earliest()
+                    .map(|t| t.with_timezone(&Utc)),
+            };
+            if let Some(next) = next.filter(|next| *next > after) {
+                return Some(next);
+            }
+        }
+    }
+}
+
+/// A recurring run of `arnab schedule`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Schedule {
+    pub(crate) name: String,
+    pub(crate) cron: CronExpr,
+    /// Selectors of the models to run, all of them if empty
+    #[serde(default)]
+    pub(crate) select: Vec<String>,
+    #[serde(default)]
+    pub(crate) exclude: Vec<String>,
+    #[serde(default)]
+    pub(crate) full_refresh: bool,
+}
+
+/// Outcome of a scheduled run, as a line of the schedule history
+#[derive(Debug, Serialize)]
+struct ScheduledRun<'a> {
+    schedule: &'a str,
+    started_at: String,
+    duration_ms: i64,
+    n_success: usize,
+    n_errors: usize,
+    error: Option<String>,
+}
+
+fn append_history(dir: &Path, run: &ScheduledRun) -> Result<(), ArnabError> {
+    std::fs::create_dir_all(dir).map_err(|e| ArnabError::Error(e.to_string()))?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(SCHEDULE_HISTORY_FILE))
+        .map_err(|e| ArnabError::Error(e.to_string()))?;
+    writeln!(file, "{}", serde_json::to_string(run).unwrap())
+        .map_err(|e| ArnabError::Error(e.to_string()))
+}
+
+fn run_schedule(session: &mut Session, schedule: &Schedule) {
+    info!("Starting scheduled run `{}`", schedule.name);
+    session.run_started_at = Utc::now();
+    let options = RunOptions {
+        selection: Selection {
+            select: schedule.select.clone(),
+            exclude: schedule.exclude.clone(),
+            ..Default::default()
+        },
+        full_refresh: schedule.full_refresh,
+        ..Default::default()
+    };
+    let result = session.run_nodes(&options);
+    let run = ScheduledRun {
+        schedule: &schedule.name,
+        started_at: session.run_started_at.to_rfc3339(),
+        duration_ms: (Utc::now() - session.run_started_at).num_milliseconds(),
+        n_success: result.as_ref().map_or(0, |summary| summary.n_success),
+        n_errors: result.as_ref().map_or(0, |summary| summary.n_errors),
+        error: result.as_ref().err().map(|e| e.message()),
+    };
+    if let Err(e) = &result {
+        println!("Error: scheduled run `{}` failed: {}", schedule.name, e);
+    }
+    if let Err(e) = append_history(&session.config.target_dir(), &run) {
+        println!(
+            "WARNING: cannot record scheduled run `{}`: {}",
+            schedule.name, e
+        );
+    }
+}
+
+/// Run the schedules at their times until interrupted. Runs are done one
+/// at a time, so a run still going at the next time of a schedule delays it.
+pub fn run_schedules(session: &mut Session, schedules: &[Schedule]) -> Result<(), ArnabError> {
+    let timezone = session
+        .config
+        .time_format
+        .as_ref()
+        .map(|f| f.timezone)
+        .unwrap_or_default();
+    let time_format = session.config.time_format.clone().unwrap_or_default();
+    let mut next_runs = vec![];
+    for schedule in schedules {
+        match schedule.cron.next_run(Utc::now(), timezone) {
+            Some(next) => next_runs.push((next, schedule)),
+            None => println!("WARNING: schedule `{}` never runs", schedule.name),
+        }
+    }
+    loop {
+        let Some(&(next, schedule)) = next_runs.iter().min_by_key(|(next, _)| *next) else {
+            return Err(ArnabError::Error("No schedule to run".into()));
+        };
+        info!(
+            "Next run: `{}` on {} {}",
+            schedule.name,
+            time_format.date(next),
+            time_format.time(next)
+        );
+        if let Ok(wait) = (next - Utc::now()).to_std() {
+            std::thread::sleep(wait);
+        }
+        run_schedule(session, schedule);
+        // Times passed during the run are skipped
+        next_runs.retain(|(_, s)| s.name != schedule.name);
+        if let Some(next) = schedule.cron.next_run(Utc::now().max(next), timezone) {
+            next_runs.push((next, schedule));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::NaiveDateTime;
+
+    use super::CronExpr;
+
+    #[test]
+    fn next_cron_runs() {
+        let time = |s: &str| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap();
+        let next = |expr: &str, after: &str| {
+            CronExpr::try_from(expr.to_string())
+                .unwrap()
+                .next_after(time(after))
+        };
+        assert_eq!(
+            next("*/15 * * * *", "2024-01-31 08:07"),
+            Some(time("2024-01-31 08:15"))
+        );
+        assert_eq!(
+            next("@daily", "2024-12-31 08:00"),
+            Some(time("2025-01-01 00:00"))
+        );
+        assert_eq!(
+            next("30 2 * * 1-5", "2024-02-02 03:00"),
+            Some(time("2024-02-05 02:30"))
+        );
+        // Either the day of month or of week matches when both are set
+        assert_eq!(
+            next("0 0 13 * 5", "2024-01-01 00:00"),
+            Some(time("2024-01-05 00:00"))
+        );
+        assert_eq!(
+            next("0 0 29 2 *", "2024-03-01 00:00"),
+            Some(time("2028-02-29 00:00"))
+        );
+        assert_eq!(next("0 0 31 2 *", "2024-01-01 00:00"), None);
+
+        for (expr, error) in [
+            ("* * * *", "expected 5 fields"),
+            ("60 * * * *", "`60` is not a number from 0 to 59"),
+            ("*/0 * * * *", "invalid step `0`"),
+        ] {
+            assert_eq!(
+                CronExpr::try_from(expr.to_string()).unwrap_err(),
+                format!("Invalid cron expression `{}`: {}", expr, error)
+            );
+        }
+    }
+}
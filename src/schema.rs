@@ -0,0 +1,343 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{errors::ArnabError, node::split_model_path};
+
+/// Schema declaration of a model, read from a YAML file sitting next to the
+/// model's SQL source, e.g., `orders.yml` for `orders.sql`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ModelSchema {
+    /// What the model holds, shown by `arnab ls`, `viz` and the docs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) description: Option<String>,
+    #[serde(default)]
+    pub(crate) columns: Vec<ColumnSchema>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ColumnSchema {
+    pub(crate) name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) description: Option<String>,
+    /// DuckDB type of the column, part of the model's contract
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) data_type: Option<String>,
+    /// PII category of the column (e.g., `email`), used to look up masking policies
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) pii: Option<String>,
+    /// Data tests run against the column by `arnab test`
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        with = "serde_yaml::with::singleton_map_recursive"
+    )]
+    pub(crate) tests: Vec<ColumnTest>,
+    /// Run the `unique` and `relationships` tests of the column against a
+    /// sample of its values, e.g., `1%`, to bound their runtime on big models
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) sample: Option<Sample>,
+}
+
+/// Share of the distinct values of a column that tests look at, written as a
+/// percentage, e.g., `1%` or `0.5%`
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Sample {
+    pub(crate) percent: f64,
+}
+
+/// Smallest sample, as values are picked among a million hash buckets
+const MIN_SAMPLE_PERCENT: f64 = 0.0001;
+
+impl Sample {
+    /// Condition keeping the rows whose `column` value is sampled. Values are
+    /// picked by hash, so the sample is the same from one run to the next and
+    /// duplicates of a sampled value are always sampled too.
+    pub fn condition(&self, column: &str) -> String {
+        format!(
+            "hash({}) % 1000000 < {}",
+            column,
+            (self.percent * 10000.0).round() as u64
+        )
+    }
+}
+
+impl TryFrom<String> for Sample {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let percent = value
+            .trim()
+            .strip_suffix('%')
+            .and_then(|p| p.trim().parse::<f64>().ok())
+            .filter(|p| *p > 0.0 && *p <= 100.0)
+            .ok_or_else(|| format!("invalid sample `{}`, expected e.g. `1%`", value))?;
+        if percent < MIN_SAMPLE_PERCENT {
+            return Err(format!(
+                "sample `{}` is too small to pick any value, the smallest is `{}%`",
+                value, MIN_SAMPLE_PERCENT
+            ));
+        }
+        Ok(Self { percent })
+    }
+}
+
+impl From<Sample> for String {
+    fn from(sample: Sample) -> Self {
+        format!("{}%", sample.percent)
+    }
+}
+
+/// Built-in column test, written either as its name (e.g., `not_null`) or as
+/// a single-key mapping from its name to its arguments
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColumnTest {
+    NotNull,
+    Unique,
+    AcceptedValues { values: Vec<serde_yaml::Value> },
+    Relationships { to: String, field: String },
+}
+
+impl ModelSchema {
+    /// Path of the schema file of a model: an existing `.yml` or `.yaml` file,
+    /// or `.yml` if there is none
+    pub fn path_for(model_path: &Path) -> PathBuf {
+        let (base, _) = split_model_path(model_path);
+        let name = base.file_name().unwrap_or_default().to_string_lossy();
+        let yaml_path = base.with_file_name(format!("{}.yaml", name));
+        if yaml_path.exists() {
+            yaml_path
+        } else {
+            base.with_file_name(format!("{}.yml", name))
+        }
+    }
+
+    /// Load the schema file of a model, if any
+    pub fn load_for(model_path: &Path) -> Result<Option<Self>, ArnabError> {
+        let schema_path = Self::path_for(model_path);
+        if !schema_path.exists() {
+            return Ok(None);
+        }
+        let src =
+            std::fs::read_to_string(&schema_path).map_err(|e| ArnabError::Error(e.to_string()))?;
+        let schema: Self = serde_yaml::from_str(&src).map_err(|e| {
+            ArnabError::Error(format!("Failed to parse {}: {}", schema_path.display(), e))
+        })?;
+        if let Some(column) = schema.duplicate_column() {
+            return Err(ArnabError::Error(format!(
+                "{} declares column `{}` more than once",
+                schema_path.display(),
+                column
+            )));
+        }
+        Ok(Some(schema))
+    }
+
+    /// Complete the declarations of a schema file with those of the model's
+    /// configuration. Columns can't be declared in both.
+    pub fn merge(self, config: ModelSchema) -> Result<Self, String> {
+        if !self.columns.is_empty() && !config.columns.is_empty() {
+            return Err("columns are declared both in the config and in a schema file".into());
+        }
+        let merged = ModelSchema {
+            description: self.description.or(config.description),
+            columns: match self.columns.is_empty() {
+                true => config.columns,
+                false => self.columns,
+            },
+        };
+        match merged.duplicate_column() {
+            Some(column) => Err(format!("column `{}` is declared more than once", column)),
+            None => Ok(merged),
+        }
+    }
+
+    /// Name of the first column declared more than once, if any
+    fn duplicate_column(&self) -> Option<&str> {
+        let mut names = HashSet::new();
+        self.columns
+            .iter()
+            .map(|c| c.name.as_str())
+            .find(|name| !names.insert(*name))
+    }
+
+    /// Write the schema file of a model
+    pub fn save_for(&self, model_path: &Path) -> Result<PathBuf, ArnabError> {
+        let schema_path = Self::path_for(model_path);
+        let yaml = serde_yaml::to_string(self).map_err(|e| ArnabError::Error(e.to_string()))?;
+        std::fs::write(&schema_path, yaml).map_err(|e| ArnabError::Error(e.to_string()))?;
+        Ok(schema_path)
+    }
+
+    /// Update the column list to `column_names`, keeping the existing
+    /// declarations (description, PII category, etc.) of known columns.
+    /// Returns the names of dropped columns.
+    pub fn sync_columns(&mut self, column_names: &[String]) -> Vec<String> {
+        let mut existing = std::mem::take(&mut self.columns)
+            .into_iter()
+            .map(|c| (c.name.clone(), c))
+            .collect::<HashMap<_, _>>();
+        self.columns = column_names
+            .iter()
+            .map(|name| {
+                existing.remove(name).unwrap_or(ColumnSchema {
+                    name: name.clone(),
+                    description: Some("".into()),
+                    data_type: None,
+                    pii: None,
+                    tests: vec![],
+                    sample: None,
+                })
+            })
+            .collect();
+        let mut dropped = existing.into_keys().collect::<Vec<_>>();
+        dropped.sort();
+        dropped
+    }
+
+    /// Resolve masking expressions of PII columns given the masking policies of
+    /// the current target, a mapping from PII category to SQL expression template.
+    /// `{{ column }}` in a template refers to the masked column. Categories
+    /// without a policy fall back to the `default` policy.
+    pub fn masking_expressions(
+        &self,
+        model_id: &str,
+        policies: &HashMap<String, String>,
+    ) -> Result<Vec<(String, String)>, ArnabError> {
+        let env = minijinja::Environment::new();
+        let mut expressions = vec![];
+        for column in &self.columns {
+            let Some(category) = &column.pii else {
+                continue;
+            };
+            let template = policies
+                .get(category)
+                .or_else(|| policies.get("default"))
+                .ok_or_else(|| {
+                    ArnabError::Error(format!(
+                        "No masking policy for PII category `{}` of column `{}.{}`",
+                        category, model_id, column.name
+                    ))
+                })?;
+            let quoted_column = format!("\"{}\"", column.name);
+            let expression = env
+                .render_str(template, minijinja::context! { column => quoted_column })
+                .map_err(|e| ArnabError::Error(e.to_string()))?;
+            expressions.push((column.name.clone(), expression));
+        }
+        Ok(expressions)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{ColumnTest, ModelSchema, Sample};
+
+    #[test]
+    fn sync_columns_preserves_descriptions() {
+        let mut schema: ModelSchema = serde_yaml::from_str(
+            "columns:\n  - { name: id, description: Primary key }\n  - { name: legacy }",
+        )
+        .unwrap();
+        let dropped = schema.sync_columns(&["id".to_string(), "amount".to_string()]);
+        assert_eq!(dropped, vec!["legacy".to_string()]);
+        assert_eq!(
+            schema.columns[0].description.as_deref(),
+            Some("Primary key")
+        );
+        assert_eq!(schema.columns[1].name, "amount");
+    }
+
+    #[test]
+    fn parse_model_description() {
+        let schema: ModelSchema = serde_yaml::from_str(
+            "description: Orders\ncolumns:\n  - { name: id, tests: [unique] }\n  - { name: id }",
+        )
+        .unwrap();
+        assert_eq!(schema.description.as_deref(), Some("Orders"));
+        assert_eq!(schema.duplicate_column(), Some("id"));
+    }
+
+    #[test]
+    fn merge_config_declarations() {
+        let file: ModelSchema = serde_yaml::from_str("columns:\n  - { name: id }").unwrap();
+        let config: ModelSchema =
+            serde_yaml::from_str("description: Orders\ncolumns:\n  - { name: amount }").unwrap();
+        assert!(file.clone().merge(config.clone()).is_err());
+
+        let file = ModelSchema {
+            description: Some("All orders".into()),
+            columns: vec![],
+        };
+        let merged = file.merge(config).unwrap();
+        assert_eq!(merged.description.as_deref(), Some("All orders"));
+        assert_eq!(merged.columns[0].name, "amount");
+    }
+
+    #[test]
+    fn masking_falls_back_to_default_policy() {
+        let schema: ModelSchema = serde_yaml::from_str(
+            "columns:\n  - { name: email, pii: email }\n  - { name: phone, pii: phone }\n  - { name: id }",
+        )
+        .unwrap();
+        let policies = HashMap::from([
+            ("email".to_string(), "md5({{ column }})".to_string()),
+            ("default".to_string(), "'***'".to_string()),
+        ]);
+        let expressions = schema.masking_expressions("users", &policies).unwrap();
+        assert_eq!(
+            expressions,
+            vec![
+                ("email".to_string(), "md5(\"email\")".to_string()),
+                ("phone".to_string(), "'***'".to_string()),
+            ]
+        );
+
+        let no_default = HashMap::from([("email".to_string(), "md5({{ column }})".to_string())]);
+        assert!(schema.masking_expressions("users", &no_default).is_err());
+    }
+
+    #[test]
+    fn parse_column_tests() {
+        let schema: ModelSchema = serde_yaml::from_str(
+            "columns:
+  - name: status
+    tests:
+      - not_null
+      - accepted_values:
+          values: [open, 1]
+      - relationships: { to: users, field: id }",
+        )
+        .unwrap();
+        let tests = &schema.columns[0].tests;
+        assert_eq!(tests[0], ColumnTest::NotNull);
+        assert_eq!(
+            tests[1],
+            ColumnTest::AcceptedValues {
+                values: vec!["open".into(), 1.into()]
+            }
+        );
+        assert_eq!(
+            tests[2],
+            ColumnTest::Relationships {
+                to: "users".into(),
+                field: "id".into()
+            }
+        );
+    }
+
+    #[test]
+    fn reject_empty_samples() {
+        let sample = Sample::try_from("0.0001%".to_string()).unwrap();
+        assert_eq!(sample.condition("id"), "hash(id) % 1000000 < 1");
+        assert!(Sample::try_from("0.00001%".to_string()).is_err());
+        assert!(Sample::try_from("0%".to_string()).is_err());
+    }
+}
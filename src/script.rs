@@ -0,0 +1,314 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use duckdb::Connection;
+
+use crate::{errors::ArnabError, time_format::format_elapsed};
+
+/// How often progress is printed while a script runs
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A statement of a SQL script, with the line it starts at
+#[derive(Debug, PartialEq)]
+pub struct ScriptStatement {
+    pub(crate) line: usize,
+    pub(crate) sql: String,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Quote {
+    /// Quoted string or identifier, closed by the same character
+    Char(char),
+    /// Dollar-quoted string, e.g., a macro body
+    Dollar,
+    BlockComment,
+}
+
+/// Splits SQL read line by line into statements, so that scripts are never
+/// loaded whole. Semicolons in quotes and comments don't end statements, and
+/// statements made only of comments are dropped.
+pub struct StatementSplitter<R> {
+    reader: R,
+    line: usize,
+    bytes_read: u64,
+    quote: Option<Quote>,
+    current: String,
+    /// Line of the first non-comment character of the current statement
+    current_line: Option<usize>,
+    pending: Vec<ScriptStatement>,
+}
+
+impl<R: BufRead> StatementSplitter<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: 0,
+            bytes_read: 0,
+            quote: None,
+            current: String::new(),
+            current_line: None,
+            pending: vec![],
+        }
+    }
+
+    fn end_statement(&mut self) {
+        let sql = std::mem::take(&mut self.current);
+        if let Some(line) = self.current_line.take() {
+            self.pending.push(ScriptStatement {
+                line,
+                sql: sql.trim().to_string(),
+            });
+        }
+    }
+
+    fn split_line(&mut self, text: &str) {
+        let mut chars = text.chars().peekable();
+        while let Some(mut c) = chars.next() {
+            let next = chars.peek().copied();
+            let two_chars = |first: char, second: char| c == first && next == Some(second);
+            match self.quote {
+                Some(Quote::Char(q)) if c == q => self.quote = None,
+                Some(Quote::Dollar) if two_chars('$', '$') => {
+                    self.current.push(c);
+                    c = chars.next().unwrap();
+                    self.quote = None;
+                }
+                Some(Quote::BlockComment) if two_chars('*', '/') => {
+                    self.current.push(c);
+                    c = chars.next().unwrap();
+                    self.quote = None;
+                }
+                Some(_) => {}
+                None => match c {
+                    ';' => {
+                        self.end_statement();
+                        continue;
+                    }
+                    '-' if two_chars('-', '-') => {
+                        // The rest of the line is a comment
+                        self.current.push(c);
+                        self.current.extend(chars.by_ref());
+                        break;
+                    }
+                    '/' if two_chars('/', '*') => {
+                        self.current.push(c);
+                        c = chars.next().unwrap();
+                        self.quote = Some(Quote::BlockComment);
+                    }
+                    _ => {
+                        if !c.is_whitespace() && self.current_line.is_none() {
+                            self.current_line = Some(self.line);
+                        }
+                        if c == '\'' || c == '"' {
+                            self.quote = Some(Quote::Char(c));
+                        } else if two_chars('$', '$') {
+                            self.current.push(c);
+                            c = chars.next().unwrap();
+                            self.quote = Some(Quote::Dollar);
+                        }
+                    }
+                },
+            }
+            self.current.push(c);
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for StatementSplitter<R> {
+    type Item = Result<ScriptStatement, std::io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if !self.pending.is_empty() {
+                return Some(Ok(self.pending.remove(0)));
+            }
+            let mut text = String::new();
+            match self.reader.read_line(&mut text) {
+                Ok(0) => {
+                    self.end_statement();
+                    return self.pending.pop().map(Ok);
+                }
+                Ok(n) => {
+                    self.line += 1;
+                    self.bytes_read += n as u64;
+                    self.split_line(&text);
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Statement to resume a script from, e.g., `migrate.sql:12`, or `12` when a
+/// single script is run
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResumePoint {
+    pub(crate) script: Option<PathBuf>,
+    pub(crate) statement: usize,
+}
+
+impl FromStr for ResumePoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (script, statement) = match s.rsplit_once(':') {
+            Some((script, statement)) => (Some(PathBuf::from(script)), statement),
+            None => (None, s),
+        };
+        match statement.parse::<usize>() {
+            Ok(statement) if statement > 0 => Ok(ResumePoint { script, statement }),
+            _ => Err(format!(
+                "expected a statement number from 1, optionally prefixed with its script, e.g., `migrate.sql:12`, got `{}`",
+                s
+            )),
+        }
+    }
+}
+
+/// First statement to execute of each script, all of them run from their
+/// start except the one to resume
+pub fn first_statements(
+    paths: &[&Path],
+    resume_from: Option<&ResumePoint>,
+) -> Result<Vec<usize>, ArnabError> {
+    let Some(resume_from) = resume_from else {
+        return Ok(vec![1; paths.len()]);
+    };
+    let resumed = match &resume_from.script {
+        Some(script) => script.as_path(),
+        None if paths.len() == 1 => paths[0],
+        None => {
+            return Err(ArnabError::Config(format!(
+                "Several scripts are run, name the one to resume, e.g., `--resume-from {}:{}`",
+                paths[0].display(),
+                resume_from.statement
+            )))
+        }
+    };
+    if !paths.contains(&resumed) {
+        return Err(ArnabError::Config(format!(
+            "Cannot resume {}, which is not one of the scripts run",
+            resumed.display()
+        )));
+    }
+    Ok(paths
+        .iter()
+        .map(|path| match *path == resumed {
+            true => resume_from.statement,
+            false => 1,
+        })
+        .collect())
+}
+
+/// Execute a SQL script one statement at a time while reading it, printing
+/// progress periodically. Statements before the `resume_from`-th one (from 1)
+/// are skipped, e.g., to resume a script after fixing a failed statement.
+/// Returns the number of executed statements.
+pub fn run_script(conn: &Connection, path: &Path, resume_from: usize) -> Result<usize, ArnabError> {
+    let file = File::open(path)
+        .map_err(|e| ArnabError::Error(format!("Cannot open {}: {}", path.display(), e)))?;
+    let mut statements = StatementSplitter::new(BufReader::new(file));
+    let started_at = Instant::now();
+    let mut last_progress = Instant::now();
+    let mut n_executed = 0;
+    let mut nth = 0;
+    let mut printed_progress = false;
+    while let Some(statement) = statements.next() {
+        let statement = statement.map_err(|e| ArnabError::Error(e.to_string()))?;
+        nth += 1;
+        if nth < resume_from {
+            continue;
+        }
+        // Statements may be huge, so only their position is reported
+        if let Err(e) = conn.execute_batch(&statement.sql) {
+            return Err(ArnabError::Error(format!(
+                "{}\nStatement {} at line {} failed, resume from it with `--resume-from {}:{}`",
+                e,
+                nth,
+                statement.line,
+                path.display(),
+                nth
+            )));
+        }
+        n_executed += 1;
+
+        if last_progress.elapsed() >= PROGRESS_INTERVAL {
+            if !printed_progress {
+                println!();
+                printed_progress = true;
+            }
+            println!(
+                "  {} statements executed, {:.1} MB read in {}",
+                n_executed,
+                statements.bytes_read as f64 / 1e6,
+                format_elapsed(started_at.elapsed())
+            );
+            last_progress = Instant::now();
+        }
+    }
+    if printed_progress {
+        print!("  ");
+    }
+    Ok(n_executed)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{first_statements, ResumePoint, ScriptStatement, StatementSplitter};
+
+    #[test]
+    fn split_statements() {
+        let script = "CREATE TABLE t (a VARCHAR); -- first; table\n\
+                      INSERT INTO t VALUES ('x;y'), ('it''s');\n\
+                      /* block; comment */\n\
+                      CREATE MACRO m() AS TABLE SELECT $$a;b$$ AS s;\n\
+                      -- only a comment;\n\
+                      SELECT \"odd;name\" FROM t";
+        let statements = StatementSplitter::new(script.as_bytes())
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statements,
+            vec![
+                ScriptStatement {
+                    line: 1,
+                    sql: "CREATE TABLE t (a VARCHAR)".into()
+                },
+                ScriptStatement {
+                    line: 2,
+                    sql: "-- first; table\nINSERT INTO t VALUES ('x;y'), ('it''s')".into()
+                },
+                ScriptStatement {
+                    line: 4,
+                    sql: "/* block; comment */\nCREATE MACRO m() AS TABLE SELECT $$a;b$$ AS s"
+                        .into()
+                },
+                ScriptStatement {
+                    line: 6,
+                    sql: "-- only a comment;\nSELECT \"odd;name\" FROM t".into()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn resume_named_script_only() {
+        let (a, b) = (Path::new("a.sql"), Path::new("b.sql"));
+        let resume_b = "b.sql:3".parse::<ResumePoint>().unwrap();
+        assert_eq!(first_statements(&[a, b], Some(&resume_b)).unwrap(), [1, 3]);
+        assert_eq!(first_statements(&[a, b], None).unwrap(), [1, 1]);
+
+        let resume = "3".parse::<ResumePoint>().unwrap();
+        assert_eq!(first_statements(&[a], Some(&resume)).unwrap(), [3]);
+        assert!(first_statements(&[a, b], Some(&resume)).is_err());
+        assert!(first_statements(&[a], Some(&resume_b)).is_err());
+        assert!("b.sql:0".parse::<ResumePoint>().is_err());
+    }
+}
@@ -0,0 +1,455 @@
+use std::{collections::HashMap, path::Path};
+
+use duckdb::{Connection, ToSql};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Deserialize;
+
+use crate::errors::ArnabError;
+
+pub const DEFAULT_SEED_PATH: &str = "seeds";
+
+const FIRST_NAMES: &[&str] = &[
+    "Aria", "Budi", "Citra", "Dewi", "Eko", "Fajar", "Gita", "Hana", "Indra", "Joko", "Kartika",
+    "Lina", "Made", "Nadia", "Oscar", "Putri", "Rina", "Sari", "Tono", "Wati", "Alice", "Bob",
+    "Carol", "David", "Emma", "Frank", "Grace", "Henry", "Irene", "James",
+];
+
+const LAST_NAMES: &[&str] = &[
+    "Ghora", "Santoso", "Wijaya", "Pratama", "Saputra", "Hidayat", "Kusuma", "Nugroho", "Lestari",
+    "Halim", "Smith", "Johnson", "Brown", "Taylor", "Miller", "Wilson", "Moore", "Clark",
+];
+
+/// Generator of a single column of a synthetic seed
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ColumnGenerator {
+    Uuid,
+    Name,
+    Int {
+        min: i64,
+        max: i64,
+    },
+    Date {
+        start: chrono::NaiveDate,
+        end: chrono::NaiveDate,
+    },
+    Choice {
+        values: Vec<String>,
+        /// Relative weight of each value. Values are equally likely if unset.
+        weights: Option<Vec<f64>>,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct GeneratedColumn {
+    pub(crate) name: String,
+    #[serde(flatten)]
+    pub(crate) generator: ColumnGenerator,
+}
+
+/// Specification of a synthetic seed table. The same spec and `seed` always
+/// produce the same rows.
+#[derive(Clone, Debug, Deserialize)]
+pub struct GeneratorSpec {
+    pub(crate) rows: usize,
+    #[serde(default)]
+    pub(crate) seed: u64,
+    pub(crate) columns: Vec<GeneratedColumn>,
+}
+
+/// Content of a seed YAML file
+#[derive(Clone, Debug, Deserialize)]
+pub struct SeedFile {
+    pub(crate) generate: Option<GeneratorSpec>,
+    /// Rows of a tiny table declared inline, e.g., a mapping of status codes,
+    /// loaded like CSV seeds
+    pub(crate) rows: Option<Vec<serde_yaml::Mapping>>,
+    /// Types of some columns of inline rows, overriding the detected ones
+    pub(crate) column_types: Option<HashMap<String, String>>,
+}
+
+impl SeedFile {
+    pub fn read(path: &Path) -> Result<Self, ArnabError> {
+        let src = std::fs::read_to_string(path).map_err(|e| ArnabError::Error(e.to_string()))?;
+        let seed_file: SeedFile = serde_yaml::from_str(&src)
+            .map_err(|e| ArnabError::Error(format!("Failed to parse {}: {}", path.display(), e)))?;
+        if seed_file.generate.is_some() && seed_file.rows.is_some() {
+            return Err(ArnabError::Error(format!(
+                "Seed {} declares both `generate` and `rows`",
+                path.display()
+            )));
+        }
+        Ok(seed_file)
+    }
+
+    /// Query returning the inline rows, if any. Columns are ordered as they
+    /// first appear, and missing values are NULL.
+    pub fn inline_sql(&self) -> Result<Option<String>, String> {
+        let Some(rows) = &self.rows else {
+            return Ok(None);
+        };
+        let mut columns: Vec<&str> = vec![];
+        for row in rows {
+            for key in row.keys() {
+                let key = key
+                    .as_str()
+                    .ok_or_else(|| format!("column name {:?} is not a string", key))?;
+                if !columns.contains(&key) {
+                    columns.push(key);
+                }
+            }
+        }
+        if columns.is_empty() {
+            return Err("`rows` has no column".into());
+        }
+        let mut values = vec![];
+        for row in rows {
+            let row_values = columns
+                .iter()
+                .map(|column| sql_literal(row.get(*column).unwrap_or(&serde_yaml::Value::Null)))
+                .collect::<Result<Vec<_>, _>>()?;
+            values.push(format!("({})", row_values.join(", ")));
+        }
+        let select = columns
+            .iter()
+            .map(
+                |column| match self.column_types.as_ref().and_then(|t| t.get(*column)) {
+                    Some(sql_type) => format!(
+                        "CAST({} AS {}) AS {}",
+                        quote_identifier(column),
+                        sql_type,
+                        quote_identifier(column)
+                    ),
+                    None => quote_identifier(column),
+                },
+            )
+            .collect::<Vec<_>>();
+        Ok(Some(format!(
+            "SELECT {} FROM (VALUES {}) AS seed({})",
+            select.join(", "),
+            values.join(", "),
+            columns
+                .iter()
+                .map(|c| quote_identifier(c))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )))
+    }
+}
+
+/// SQL literal of a scalar YAML value
+fn sql_literal(value: &serde_yaml::Value) -> Result<String, String> {
+    match value {
+        serde_yaml::Value::Null => Ok("NULL".into()),
+        serde_yaml::Value::Bool(b) => Ok(b.to_string().to_uppercase()),
+        serde_yaml::Value::Number(n) => Ok(n.to_string()),
+        serde_yaml::Value::String(s) => Ok(format!("'{}'", s.replace('\'', "''"))),
+        _ => Err(format!("value {:?} is not a scalar", value)),
+    }
+}
+
+impl ColumnGenerator {
+    fn sql_type(&self) -> &'static str {
+        match self {
+            ColumnGenerator::Uuid => "UUID",
+            ColumnGenerator::Int { .. } => "BIGINT",
+            ColumnGenerator::Date { .. } => "DATE",
+            ColumnGenerator::Name | ColumnGenerator::Choice { .. } => "VARCHAR",
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        match self {
+            ColumnGenerator::Int { min, max } if min > max => {
+                Err(format!("min ({}) is greater than max ({})", min, max))
+            }
+            ColumnGenerator::Date { start, end } if start > end => {
+                Err(format!("start ({}) is after end ({})", start, end))
+            }
+            ColumnGenerator::Choice { values, .. } if values.is_empty() => {
+                Err("choice requires at least one value".into())
+            }
+            ColumnGenerator::Choice {
+                values,
+                weights: Some(weights),
+            } if weights.len() != values.len()
+                || weights.iter().any(|w| *w < 0.0)
+                || weights.iter().sum::<f64>() <= 0.0 =>
+            {
+                Err("choice weights must be positive and have the same length as values".into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Generate a single value, formatted as text. DuckDB casts it to the
+    /// column type on append.
+    fn generate(&self, rng: &mut StdRng) -> String {
+        match self {
+            ColumnGenerator::Uuid => {
+                let mut bytes: [u8; 16] = rng.gen();
+                // Mark as version 4, variant 1
+                bytes[6] = (bytes[6] & 0x0f) | 0x40;
+                bytes[8] = (bytes[8] & 0x3f) | 0x80;
+                let hex = bytes
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<String>();
+                format!(
+                    "{}-{}-{}-{}-{}",
+                    &hex[..8],
+                    &hex[8..12],
+                    &hex[12..16],
+                    &hex[16..20],
+                    &hex[20..]
+                )
+            }
+            ColumnGenerator::Name => format!(
+                "{} {}",
+                FIRST_NAMES[rng.gen_range(0..FIRST_NAMES.len())],
+                LAST_NAMES[rng.gen_range(0..LAST_NAMES.len())]
+            ),
+            ColumnGenerator::Int { min, max } => rng.gen_range(*min..=*max).to_string(),
+            ColumnGenerator::Date { start, end } => {
+                let n_days = (*end - *start).num_days();
+                let offset = rng.gen_range(0..=n_days);
+                (*start + chrono::Duration::days(offset)).to_string()
+            }
+            ColumnGenerator::Choice { values, weights } => match weights {
+                Some(weights) => {
+                    let mut target = rng.gen_range(0.0..weights.iter().sum::<f64>());
+                    for (value, weight) in values.iter().zip(weights) {
+                        if target < *weight {
+                            return value.clone();
+                        }
+                        target -= weight;
+                    }
+                    values[values.len() - 1].clone()
+                }
+                None => values[rng.gen_range(0..values.len())].clone(),
+            },
+        }
+    }
+}
+
+impl GeneratorSpec {
+    /// Generate all rows of the seed deterministically
+    pub fn generate_rows(&self) -> Vec<Vec<String>> {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        (0..self.rows)
+            .map(|_| {
+                self.columns
+                    .iter()
+                    .map(|c| c.generator.generate(&mut rng))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Create (or replace) table `name` filled with generated rows
+    pub fn load(&self, conn: &Connection, name: &str) -> Result<usize, ArnabError> {
+        for column in &self.columns {
+            column.generator.validate().map_err(|e| {
+                ArnabError::Error(format!(
+                    "Invalid generator for `{}.{}`: {}",
+                    name, column.name, e
+                ))
+            })?;
+        }
+
+        let column_defs = self
+            .columns
+            .iter()
+            .map(|c| format!("{} {}", quote_identifier(&c.name), c.generator.sql_type()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        conn.execute_batch(&format!(
+            "CREATE OR REPLACE TABLE {} ({});",
+            quote_identifier(name),
+            column_defs
+        ))
+        .map_err(|e| ArnabError::Error(e.to_string()))?;
+
+        let rows = self.generate_rows();
+        let mut appender = conn
+            .appender(name)
+            .map_err(|e| ArnabError::Error(e.to_string()))?;
+        for row in &rows {
+            let params = row.iter().map(|v| v as &dyn ToSql).collect::<Vec<_>>();
+            appender
+                .append_row(params.as_slice())
+                .map_err(|e| ArnabError::Error(e.to_string()))?;
+        }
+        Ok(rows.len())
+    }
+}
+
+/// Identifier quoted for SQL, e.g., `"order items"` for a seed file name
+pub fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Configuration of a CSV seed
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct SeedInfo {
+    /// Types of some columns, overriding the detected ones
+    pub(crate) column_types: Option<HashMap<String, String>>,
+}
+
+/// Query reading a CSV seed, with column types detected unless overridden
+pub fn csv_seed_sql(path: &Path, info: Option<&SeedInfo>) -> String {
+    let quote = |s: &str| format!("'{}'", s.replace('\'', "''"));
+    let mut options = vec![
+        "header = true".to_string(),
+        "auto_detect = true".to_string(),
+    ];
+    if let Some(column_types) = info.and_then(|i| i.column_types.as_ref()) {
+        let mut types = column_types
+            .iter()
+            .map(|(column, sql_type)| format!("{}: {}", quote(column), quote(sql_type)))
+            .collect::<Vec<_>>();
+        types.sort();
+        options.push(format!("types = {{{}}}", types.join(", ")));
+    }
+    format!(
+        "SELECT * FROM read_csv({}, {})",
+        quote(&path.to_string_lossy()),
+        options.join(", ")
+    )
+}
+
+/// Generate all synthetic seeds declared by YAML files in `seed_path`. Seed
+/// table names follow their file names.
+pub fn generate_seeds(conn: &Connection, seed_path: &str) -> Result<(), ArnabError> {
+    let pattern = std::path::Path::new(seed_path).join("*.y*ml");
+    let paths = glob::glob(pattern.to_str().unwrap())
+        .map_err(|e| ArnabError::Error(e.to_string()))?
+        .filter_map(|p| p.ok())
+        .collect::<Vec<_>>();
+
+    if paths.is_empty() {
+        println!("No seed found in {}", seed_path);
+        return Ok(());
+    }
+
+    for path in paths {
+        let name = path.file_stem().unwrap().to_string_lossy().to_string();
+        let seed_file = SeedFile::read(&path)?;
+
+        if let Some(spec) = &seed_file.generate {
+            let n_rows = spec.load(conn, &name)?;
+            println!("Generated seed {} ({} rows)", name, n_rows);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, path::Path};
+
+    use super::{csv_seed_sql, GeneratorSpec, SeedFile, SeedInfo};
+
+    #[test]
+    fn generated_rows_are_deterministic() {
+        let src = r#"
+generate:
+  rows: 20
+  seed: 7
+  columns:
+    - { name: id, type: uuid }
+    - { name: customer, type: name }
+    - { name: age, type: int, min: 18, max: 30 }
+    - { name: joined, type: date, start: 2024-01-01, end: 2024-01-31 }
+    - { name: tier, type: choice, values: [gold, silver], weights: [1, 0] }
+"#;
+        let spec = serde_yaml::from_str::<SeedFile>(src)
+            .unwrap()
+            .generate
+            .unwrap();
+        let rows = spec.generate_rows();
+        assert_eq!(rows, spec.generate_rows());
+        assert_eq!(rows.len(), 20);
+        for row in rows {
+            let age: i64 = row[2].parse().unwrap();
+            assert!((18..=30).contains(&age));
+            assert!(row[3].starts_with("2024-01-"));
+            assert_eq!(row[4], "gold");
+        }
+    }
+
+    #[test]
+    fn reject_negative_weights() {
+        // Even with a positive total
+        let src = r#"
+rows: 1
+columns:
+  - { name: tier, type: choice, values: [gold, silver], weights: [2, -1] }
+"#;
+        let spec = serde_yaml::from_str::<GeneratorSpec>(src).unwrap();
+        let conn = duckdb::Connection::open_in_memory().unwrap();
+        assert!(spec.load(&conn, "tiers").is_err());
+    }
+
+    #[test]
+    fn load_generated_seed_with_quoted_name() {
+        let src = r#"
+rows: 3
+columns:
+  - { name: "order id", type: int, min: 1, max: 9 }
+"#;
+        let spec = serde_yaml::from_str::<GeneratorSpec>(src).unwrap();
+        let conn = duckdb::Connection::open_in_memory().unwrap();
+        assert_eq!(spec.load(&conn, "order items").unwrap(), 3);
+        let n_rows: i64 = conn
+            .query_row("SELECT count(\"order id\") FROM \"order items\"", [], |r| {
+                r.get(0)
+            })
+            .unwrap();
+        assert_eq!(n_rows, 3);
+    }
+
+    #[test]
+    fn inline_seed_rows() {
+        let src = r#"
+rows:
+  - { code: 1, label: placed, final: false }
+  - { code: 2, label: "customer's pick-up" }
+  - { code: 3, final: true }
+column_types:
+  code: SMALLINT
+"#;
+        let seed_file = serde_yaml::from_str::<SeedFile>(src).unwrap();
+        let sql = seed_file.inline_sql().unwrap().unwrap();
+        assert_eq!(
+            sql,
+            "SELECT CAST(\"code\" AS SMALLINT) AS \"code\", \"label\", \"final\" \
+             FROM (VALUES (1, 'placed', FALSE), (2, 'customer''s pick-up', NULL), (3, NULL, TRUE)) \
+             AS seed(\"code\", \"label\", \"final\")"
+        );
+        let conn = duckdb::Connection::open_in_memory().unwrap();
+        let (n_rows, code_type): (i64, String) = conn
+            .query_row(
+                &format!("SELECT count(*), any_value(typeof(code)) FROM ({})", sql),
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((n_rows, code_type.as_str()), (3, "SMALLINT"));
+
+        let nested = serde_yaml::from_str::<SeedFile>("rows: [{ code: [1] }]").unwrap();
+        assert!(nested.inline_sql().is_err());
+    }
+
+    #[test]
+    fn csv_seed_with_column_types() {
+        let info = SeedInfo {
+            column_types: Some(HashMap::from([("code".to_string(), "VARCHAR".to_string())])),
+        };
+        assert_eq!(
+            csv_seed_sql(Path::new("seeds/tiers.csv"), Some(&info)),
+            "SELECT * FROM read_csv('seeds/tiers.csv', header = true, auto_detect = true, \
+             types = {'code': 'VARCHAR'})"
+        );
+    }
+}
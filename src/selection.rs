@@ -0,0 +1,287 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
+
+use crate::{
+    errors::ArnabError,
+    manifest::{checksum, Manifest},
+    node::Node,
+};
+
+/// Model selection of a command
+#[derive(Clone, Debug, Default)]
+pub struct Selection {
+    /// Selectors of models to include. All models are included if empty.
+    pub(crate) select: Vec<String>,
+    /// Selectors of models to leave out
+    pub(crate) exclude: Vec<String>,
+    /// Also leave out descendants that only depend on excluded models
+    pub(crate) exclude_exclusive_descendants: bool,
+    /// Manifest, or directory holding it, that `state:` selectors compare
+    /// against
+    pub(crate) state: Option<PathBuf>,
+}
+
+impl Selection {
+    /// Filter `ids` by the selection, preserving order
+    pub fn apply(
+        &self,
+        ids: &[String],
+        node_map: &HashMap<String, Node>,
+    ) -> Result<Vec<String>, ArnabError> {
+        let state = self.load_state()?;
+        let selected = if self.select.is_empty() {
+            ids.to_vec()
+        } else {
+            select_nodes(&self.select, ids, node_map, state.as_ref())?
+        };
+        if self.exclude.is_empty() {
+            return Ok(selected);
+        }
+
+        let mut excluded = select_nodes(&self.exclude, ids, node_map, state.as_ref())?
+            .into_iter()
+            .collect::<HashSet<_>>();
+        if self.exclude_exclusive_descendants {
+            // `ids` is topologically sorted, so parents are decided before children
+            for id in ids {
+                let prevs = &node_map[id].prevs;
+                if !prevs.is_empty() && prevs.iter().all(|p| excluded.contains(p)) {
+                    excluded.insert(id.clone());
+                }
+            }
+        }
+        Ok(selected
+            .into_iter()
+            .filter(|id| !excluded.contains(id))
+            .collect())
+    }
+
+    /// Load the state manifest, if any selector needs it
+    fn load_state(&self) -> Result<Option<Manifest>, ArnabError> {
+        let uses_state = self
+            .select
+            .iter()
+            .chain(&self.exclude)
+            .any(|s| s.trim_start_matches('+').starts_with("state:"));
+        match (uses_state, &self.state) {
+            (false, _) => Ok(None),
+            (true, Some(path)) => Manifest::load(path).map(Some),
+            (true, None) => Err(ArnabError::Error(
+                "`state:` selectors need a manifest to compare against".into(),
+            )),
+        }
+    }
+}
+
+/// Check whether a node is selected by `pattern`. Patterns are matched against
+/// the node id and its fully-qualified name (e.g., `marts.orders` for
+/// `<models_dir>/marts/orders.sql`), or against its tags when prefixed with
+/// `tag:`, and may contain `*` wildcards. `state:modified` selects the nodes
+/// whose rendered SQL differs from the `state` manifest, new nodes included,
+/// and `state:new` only the nodes missing from it.
+pub fn matches(pattern: &str, node: &Node, state: Option<&Manifest>) -> Result<bool, ArnabError> {
+    if let Some(state_pattern) = pattern.strip_prefix("state:") {
+        let old_node = state.and_then(|s| s.nodes.get(&node.id));
+        return match state_pattern {
+            "modified" => Ok(old_node.is_none_or(|n| n.checksum != checksum(&node.rendered_src))),
+            "new" => Ok(old_node.is_none()),
+            _ => Err(ArnabError::Error(format!(
+                "Invalid selector `{}`: expected `state:modified` or `state:new`",
+                pattern
+            ))),
+        };
+    }
+    let (tag_pattern, pattern) = match pattern.strip_prefix("tag:") {
+        Some(tag_pattern) => (true, tag_pattern),
+        None => (false, pattern),
+    };
+    let pattern = glob::Pattern::new(pattern)
+        .map_err(|e| ArnabError::Error(format!("Invalid selector `{}`: {}", pattern, e)))?;
+    if tag_pattern {
+        return Ok(node.tags.iter().any(|tag| pattern.matches(tag)));
+    }
+    Ok(pattern.matches(&node.id) || pattern.matches(&node.fqn))
+}
+
+/// Filter `ids` to the nodes matching any of `selectors`, preserving order. A
+/// selector is a pattern, optionally prefixed with `+` to also select all
+/// ancestors of the matching nodes and/or suffixed with `+` to also select
+/// all their descendants, e.g., `+orders`, `stg_orders+` or `+orders+`.
+pub fn select_nodes(
+    selectors: &[String],
+    ids: &[String],
+    node_map: &HashMap<String, Node>,
+    state: Option<&Manifest>,
+) -> Result<Vec<String>, ArnabError> {
+    let mut selected = HashSet::new();
+    for selector in selectors {
+        let with_ancestors = selector.starts_with('+');
+        let with_descendants = selector.len() > 1 && selector.ends_with('+');
+        let pattern = selector.trim_start_matches('+').trim_end_matches('+');
+
+        for id in ids {
+            if !matches(pattern, &node_map[id], state)? {
+                continue;
+            }
+            selected.insert(id.clone());
+            if with_ancestors {
+                collect_related(id, node_map, |n| &n.prevs, &mut selected);
+            }
+            if with_descendants {
+                collect_related(id, node_map, |n| &n.nexts, &mut selected);
+            }
+        }
+    }
+    Ok(ids
+        .iter()
+        .filter(|id| selected.contains(*id))
+        .cloned()
+        .collect())
+}
+
+/// Collect nodes transitively reachable from `id` through `edges`
+fn collect_related(
+    id: &str,
+    node_map: &HashMap<String, Node>,
+    edges: fn(&Node) -> &HashSet<String>,
+    out: &mut HashSet<String>,
+) {
+    let Some(node) = node_map.get(id) else {
+        return;
+    };
+    for related_id in edges(node) {
+        if out.insert(related_id.clone()) {
+            collect_related(related_id, node_map, edges, out);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{select_nodes, Selection};
+    use crate::{
+        manifest::{checksum, Manifest, ManifestNode},
+        node::{Node, NodeKind},
+    };
+
+    #[test]
+    fn select_by_id_and_fqn() {
+        let mut node_map = HashMap::new();
+        for (id, fqn) in [
+            ("orders", "marts.orders"),
+            ("stg_orders", "staging.stg_orders"),
+        ] {
+            let mut node = Node::new(NodeKind::Sql, "", id, "");
+            node.fqn = fqn.into();
+            node_map.insert(id.to_string(), node);
+        }
+        let ids = vec!["stg_orders".to_string(), "orders".to_string()];
+
+        let selected = select_nodes(&["marts.*".into()], &ids, &node_map, None).unwrap();
+        assert_eq!(selected, vec!["orders".to_string()]);
+
+        let selected = select_nodes(&["*orders".into()], &ids, &node_map, None).unwrap();
+        assert_eq!(selected, ids);
+
+        node_map.get_mut("orders").unwrap().tags = vec!["finance".into()];
+        let selected = select_nodes(&["tag:fin*".into()], &ids, &node_map, None).unwrap();
+        assert_eq!(selected, vec!["orders".to_string()]);
+    }
+
+    #[test]
+    fn select_ancestors_and_descendants() {
+        // a -> b -> c, d -> c
+        let mut node_map = HashMap::new();
+        for id in ["a", "b", "c", "d"] {
+            node_map.insert(id.to_string(), Node::new(NodeKind::Sql, "", id, ""));
+        }
+        for (from, to) in [("a", "b"), ("b", "c"), ("d", "c")] {
+            node_map.get_mut(from).unwrap().nexts.insert(to.into());
+            node_map.get_mut(to).unwrap().prevs.insert(from.into());
+        }
+        let ids = ["a", "b", "d", "c"].map(String::from).to_vec();
+        let select =
+            |selector: &str| select_nodes(&[selector.into()], &ids, &node_map, None).unwrap();
+
+        assert_eq!(select("+b"), vec!["a", "b"]);
+        assert_eq!(select("b+"), vec!["b", "c"]);
+        assert_eq!(select("+b+"), vec!["a", "b", "c"]);
+        assert_eq!(select("+c"), vec!["a", "b", "d", "c"]);
+        assert_eq!(select("d+"), vec!["d", "c"]);
+    }
+
+    #[test]
+    fn exclude_exclusive_descendants() {
+        // a -> c, b -> c, a -> d
+        let mut node_map = HashMap::new();
+        for id in ["a", "b", "c", "d"] {
+            node_map.insert(id.to_string(), Node::new(NodeKind::Sql, "", id, ""));
+        }
+        for (from, to) in [("a", "c"), ("b", "c"), ("a", "d")] {
+            node_map.get_mut(from).unwrap().nexts.insert(to.into());
+            node_map.get_mut(to).unwrap().prevs.insert(from.into());
+        }
+        let ids = ["a", "b", "c", "d"].map(String::from).to_vec();
+        let mut selection = Selection {
+            exclude: vec!["a".into()],
+            ..Default::default()
+        };
+        assert_eq!(
+            selection.apply(&ids, &node_map).unwrap(),
+            vec!["b", "c", "d"]
+        );
+
+        selection.exclude_exclusive_descendants = true;
+        assert_eq!(selection.apply(&ids, &node_map).unwrap(), vec!["b", "c"]);
+    }
+
+    #[test]
+    fn select_modified_state() {
+        // a -> b -> c, where a changed and d is new
+        let mut node_map = HashMap::new();
+        for id in ["a", "b", "c", "d"] {
+            let mut node = Node::new(NodeKind::Sql, "", id, "");
+            node.rendered_src = format!("SELECT '{}'", id);
+            node_map.insert(id.to_string(), node);
+        }
+        for (from, to) in [("a", "b"), ("b", "c")] {
+            node_map.get_mut(from).unwrap().nexts.insert(to.into());
+            node_map.get_mut(to).unwrap().prevs.insert(from.into());
+        }
+        let state = Manifest {
+            generated_at: "".into(),
+            nodes: ["a", "b", "c"]
+                .into_iter()
+                .map(|id| {
+                    let sql = match id {
+                        "a" => "SELECT 'old a'".to_string(),
+                        _ => format!("SELECT '{}'", id),
+                    };
+                    let node = ManifestNode {
+                        path: "".into(),
+                        materialize: "view".into(),
+                        checksum: checksum(&sql),
+                        depends_on: vec![],
+                        sources: vec![],
+                        compiled_sql: sql,
+                        columns: vec![],
+                    };
+                    (id.to_string(), node)
+                })
+                .collect(),
+        };
+        let ids = ["a", "b", "c", "d"].map(String::from).to_vec();
+        let select = |selector: &str| {
+            select_nodes(&[selector.into()], &ids, &node_map, Some(&state)).unwrap()
+        };
+
+        assert_eq!(select("state:modified"), vec!["a", "d"]);
+        assert_eq!(select("state:modified+"), vec!["a", "b", "c", "d"]);
+        assert_eq!(select("state:new"), vec!["d"]);
+        assert!(select_nodes(&["state:gone".into()], &ids, &node_map, Some(&state)).is_err());
+    }
+}
@@ -0,0 +1,291 @@
+//! HTTP server of `arnab serve`, for orchestration tools to trigger runs and
+//! follow them without shelling out:
+//!
+//! - `POST /runs` starts a run, with an optional JSON body of `select`,
+//!   `exclude` and `full_refresh`, unless one is already running
+//! - `GET /runs/latest` returns the status of the last run started
+//! - `GET /manifest` and `GET /run_results` return the artifacts of the
+//!   last run
+//!
+//! Runs are executed one at a time by a runner thread owning the session.
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::PathBuf,
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+};
+
+use chrono::Utc;
+use duckdb::Connection;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    errors::ArnabError,
+    manifest::MANIFEST_FILE,
+    run_results::{RunSummary, RUN_RESULTS_FILE},
+    selection::Selection,
+    session::{Config, RunOptions, Session},
+    time_format::iso_now,
+};
+
+/// Longest request body accepted, far above any run request
+const MAX_BODY_LENGTH: usize = 1024 * 1024;
+
+/// Body of `POST /runs`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RunRequest {
+    select: Vec<String>,
+    exclude: Vec<String>,
+    full_refresh: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum RunState {
+    /// No run started yet
+    #[default]
+    Idle,
+    Running,
+    Succeeded,
+    /// Some model failed without its failure being tolerated
+    Failed,
+    /// The run itself failed, e.g., on an invalid model
+    Error,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+struct RunStatus {
+    /// Number of the run since the server started, 0 before the first one
+    run_id: usize,
+    state: RunState,
+    started_at: Option<String>,
+    finished_at: Option<String>,
+    summary: Option<RunSummary>,
+    error: Option<String>,
+}
+
+struct Response {
+    status: u16,
+    body: String,
+}
+
+impl Response {
+    fn json(status: u16, body: &impl Serialize) -> Self {
+        Self {
+            status,
+            body: serde_json::to_string_pretty(body).unwrap(),
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self::json(status, &serde_json::json!({ "error": message }))
+    }
+}
+
+struct Server {
+    status: Arc<Mutex<RunStatus>>,
+    runs: Sender<RunRequest>,
+    target_dir: PathBuf,
+}
+
+impl Server {
+    fn route(&self, method: &str, path: &str, body: &str) -> Response {
+        match (method, path) {
+            ("POST", "/runs") => self.start_run(body),
+            ("GET", "/runs/latest") => Response::json(200, &*self.status.lock().unwrap()),
+            ("GET", "/manifest") => self.artifact(MANIFEST_FILE),
+            ("GET", "/run_results") => self.artifact(RUN_RESULTS_FILE),
+            (_, "/runs" | "/runs/latest" | "/manifest" | "/run_results") => {
+                Response::error(405, &format!("Method {} not allowed on {}", method, path))
+            }
+            _ => Response::error(404, &format!("No endpoint {}", path)),
+        }
+    }
+
+    fn start_run(&self, body: &str) -> Response {
+        let request = match body.trim() {
+            "" => RunRequest::default(),
+            body => match serde_json::from_str(body) {
+                Ok(request) => request,
+                Err(e) => return Response::error(400, &format!("Invalid run request: {}", e)),
+            },
+        };
+        let mut status = self.status.lock().unwrap();
+        if status.state == RunState::Running {
+            return Response::error(409, &format!("Run {} is in progress", status.run_id));
+        }
+        // The runner can't pick up the run before the status is updated, as
+        // it is locked
+        if self.runs.send(request).is_err() {
+            return Response::error(503, "The runner has stopped, restart the server");
+        }
+        *status = RunStatus {
+            run_id: status.run_id + 1,
+            state: RunState::Running,
+            started_at: Some(iso_now()),
+            ..Default::default()
+        };
+        Response::json(202, &*status)
+    }
+
+    fn artifact(&self, file_name: &str) -> Response {
+        match std::fs::read_to_string(self.target_dir.join(file_name)) {
+            Ok(body) => Response { status: 200, body },
+            Err(_) => Response::error(404, &format!("No {} yet", file_name)),
+        }
+    }
+
+    fn handle(&self, stream: TcpStream) -> Result<(), std::io::Error> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap_or(0);
+                }
+            }
+        }
+        let response = match content_length {
+            length if length > MAX_BODY_LENGTH => Response::error(
+                413,
+                &format!("Request body is larger than {} bytes", MAX_BODY_LENGTH),
+            ),
+            length => {
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body)?;
+
+                let mut parts = request_line.split_whitespace();
+                let method = parts.next().unwrap_or_default();
+                let path = parts.next().unwrap_or_default();
+                // Query strings are not used by any endpoint
+                let path = path.split('?').next().unwrap_or_default();
+                self.route(method, path, &String::from_utf8_lossy(&body))
+            }
+        };
+        let reason = match response.status {
+            200 => "OK",
+            202 => "Accepted",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            409 => "Conflict",
+            413 => "Payload Too Large",
+            _ => "Service Unavailable",
+        };
+        write!(
+            &stream,
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            response.status,
+            reason,
+            response.body.len(),
+            response.body
+        )
+    }
+}
+
+/// Run the requested runs one at a time, recording their outcome
+fn run_forever(mut session: Session, runs: Receiver<RunRequest>, status: Arc<Mutex<RunStatus>>) {
+    for request in runs {
+        session.run_started_at = Utc::now();
+        let options = RunOptions {
+            selection: Selection {
+                select: request.select,
+                exclude: request.exclude,
+                ..Default::default()
+            },
+            full_refresh: request.full_refresh,
+            ..Default::default()
+        };
+        let result = session.run_nodes(&options);
+        let mut status = status.lock().unwrap();
+        status.finished_at = Some(iso_now());
+        match result {
+            Ok(summary) => {
+                status.state = match summary.failed() {
+                    true => RunState::Failed,
+                    false => RunState::Succeeded,
+                };
+                status.summary = Some(summary);
+            }
+            Err(e) => {
+                status.state = RunState::Error;
+                status.error = Some(e.message());
+            }
+        }
+    }
+}
+
+/// Serve requests forever, each connection in its own thread
+pub fn serve(listener: TcpListener, config: Config, conn: Connection) -> Result<(), ArnabError> {
+    let (sender, receiver) = channel();
+    let server = Arc::new(Server {
+        status: Default::default(),
+        runs: sender,
+        target_dir: config.target_dir(),
+    });
+    let status = server.status.clone();
+    std::thread::spawn(move || run_forever(Session::new(config, conn), receiver, status));
+
+    for stream in listener.incoming() {
+        let stream = stream.map_err(|e| ArnabError::Error(e.to_string()))?;
+        let server = server.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = server.handle(stream) {
+                println!("WARNING: Failed to handle HTTP request: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        path::PathBuf,
+        sync::{mpsc::channel, Arc, Mutex},
+    };
+
+    use super::{RunState, RunStatus, Server};
+
+    #[test]
+    fn route_requests() {
+        let (sender, receiver) = channel();
+        let server = Server {
+            status: Arc::new(Mutex::new(RunStatus::default())),
+            runs: sender,
+            target_dir: PathBuf::from("/nonexistent"),
+        };
+        let response = server.route("POST", "/runs", r#"{"select": ["marts.*"]}"#);
+        assert_eq!(response.status, 202);
+        assert_eq!(receiver.try_recv().unwrap().select, vec!["marts.*"]);
+        assert_eq!(server.status.lock().unwrap().state, RunState::Running);
+
+        // One run at a time
+        assert_eq!(server.route("POST", "/runs", "").status, 409);
+        server.status.lock().unwrap().state = RunState::Succeeded;
+        assert_eq!(server.route("POST", "/runs", "").status, 202);
+        assert_eq!(server.status.lock().unwrap().run_id, 2);
+
+        assert_eq!(server.route("POST", "/runs", "{\"selct\": []}").status, 400);
+        assert_eq!(server.route("GET", "/manifest", "").status, 404);
+        assert_eq!(server.route("DELETE", "/manifest", "").status, 405);
+        assert_eq!(server.route("GET", "/", "").status, 404);
+
+        // A stopped runner doesn't take down the server
+        server.status.lock().unwrap().state = RunState::Succeeded;
+        drop(receiver);
+        assert_eq!(server.route("POST", "/runs", "").status, 503);
+        assert_eq!(server.status.lock().unwrap().state, RunState::Succeeded);
+    }
+}
@@ -1,316 +1,2452 @@
 use std::{
-    collections::{HashMap, HashSet},
-    io::Write,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    io::{IsTerminal, Write},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::Duration,
 };
 
+use chrono::{DateTime, Utc};
 use colored::Colorize;
 use duckdb::Connection;
 use serde::Deserialize;
 
 use crate::{
+    advise::advise,
+    bootstrap::{bootstrap, required_schemas},
+    build_cache::{cache_keys, is_cacheable, kept_builds, BuildCache},
+    catalog::{analyze_table, Catalog},
+    codegen::describe_relation,
+    contracts::{expand_select_star, same_relation, star_tables},
+    data_tests::{run_tests, schema_tests, singular_tests, DataTest, TestSummary},
+    docs::{generate_docs, DOCS_DIR},
+    ephemeral::EphemeralBuild,
     errors::ArnabError,
-    graphviz::render_dot,
-    node::{Node, NodeExecutionResult, NodeKind},
+    events::{Event, EventLog, NodeStatus},
+    graph::{Graph, GraphBuilder},
+    graphml::{to_graphml, to_graphology},
+    graphviz::{dot_source, render_nodes_with, source_nodes, DotNode, VizFormat, VizRenderer},
+    hooks::{run_hooks, HookContext},
+    html_graph::to_html_graph,
+    listing::align_columns,
+    macros::{load_macros, macro_docs, MacroDoc},
+    maintenance::{run_maintenance, MaintenanceInfo},
+    manifest::{DiffStatus, Manifest, DEFAULT_TARGET_PATH, MANIFEST_FILE},
+    mermaid::to_mermaid,
+    node::{
+        format_value, split_model_path, DedupeInfo, ExternalInfo, Node, NodeExecutionResult,
+        NodeKind, OnEmpty, OnError, RenderContext,
+    },
+    notifications::{NotificationsInfo, Notifier},
+    pins::Pins,
+    plan::{ExecutionPlan, Step},
+    preview_cache::{PreviewCache, DEFAULT_PREVIEW_CACHE_TTL, PREVIEW_CACHE_DIR},
+    progress::ProgressBar,
+    project_checks::{run_project_checks, ProjectCheck},
+    provenance::{fingerprint, git_state, ProvenanceRecord},
+    publish::{CatalogPublisher, HttpPublisher, PublishedColumn, PublishedTable, PublisherInfo},
+    python::is_python_model,
+    remote::execute_remote,
+    run_results::{RunResults, RunSummary},
+    schedule::Schedule,
+    schema::{ColumnSchema, ModelSchema},
+    seed::{csv_seed_sql, SeedFile, SeedInfo, DEFAULT_SEED_PATH},
+    selection::Selection,
+    source::{refresh_cache, source_versions, upstream_source_ages, SourceInfo, CACHE_SCHEMA},
+    test_results::TestResults,
+    text_width::fill_with_dots,
+    threads::{is_out_of_memory, ThreadCount},
+    time_format::{format_age, format_elapsed, iso_now, TimeFormat},
+    verbosity::{enabled, info, verbose, Verbosity},
 };
 
-#[derive(Clone, Debug, Deserialize)]
+/// Configuration of a model. Schema declarations (`description` and
+/// `columns`) can be set here instead of in a schema file.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct ModelInfo {
     pub(crate) materialize: Option<String>,
+    /// Build the model, `true` by default. Disabled models are left out of
+    /// the graph.
+    pub(crate) enabled: Option<bool>,
+    /// Database schema to create the relation in, exposed in the main schema
+    /// through a view named after the model
+    pub(crate) schema: Option<String>,
+    /// Name of the relation, if not the model id. The model is still referred
+    /// to by its id.
+    pub(crate) alias: Option<String>,
+    /// What the model holds, as in schema files
+    pub(crate) description: Option<String>,
+    /// Column declarations and tests, as in schema files
+    pub(crate) columns: Option<Vec<ColumnSchema>>,
+    /// SQL statements executed before the model is built
+    pub(crate) pre_hook: Option<Vec<String>>,
+    /// SQL statements executed after the model is built. `{{ this }}` refers
+    /// to the model's relation.
+    pub(crate) post_hook: Option<Vec<String>>,
+    /// Custom query to collect statistics after the model is built
+    pub(crate) stats_sql: Option<String>,
+    /// Extra upstream models not referenced in the SQL, e.g., a table created
+    /// by an external process or a hook of another model
+    pub(crate) depends_on: Option<Vec<String>>,
+    /// Query that must return true once the model is built, e.g.,
+    /// `SELECT count(*) > 0 FROM {{ this }}`, or the build is rolled back
+    pub(crate) assertion: Option<String>,
+    /// `continue` to tolerate failures of the model: they neither fail the
+    /// run nor stop it with `--fail-fast`
+    pub(crate) on_error: Option<OnError>,
+    /// `warn` or `error` when the build produces no rows, `ok` by default
+    pub(crate) on_empty: Option<OnEmpty>,
+    /// Names of groups whose models never run concurrently with each other,
+    /// e.g., models whose hooks write to the same external system
+    pub(crate) serialize_with: Option<Vec<String>>,
+    /// Keep a single row per unique key
+    pub(crate) dedupe: Option<DedupeInfo>,
+    /// File written by `external` models
+    pub(crate) external: Option<ExternalInfo>,
+    /// Run `ANALYZE` on the table after it is built, overriding `analyze`
+    pub(crate) analyze: Option<bool>,
+    /// DuckDB settings applied only while the model is built, e.g., a bigger
+    /// `memory_limit`
+    pub(crate) duckdb_settings: Option<HashMap<String, String>>,
+    /// Environment variables set only while the model is built
+    pub(crate) env: Option<HashMap<String, String>>,
+    /// Labels of the model, shown by `arnab ls`
+    pub(crate) tags: Option<Vec<String>>,
+    /// Pass the SQL through without Jinja rendering, overriding `raw_sql`.
+    /// Ignored for `.sql.jinja` files.
+    pub(crate) raw: Option<bool>,
+}
+
+impl ModelInfo {
+    /// Fill the settings missing from this configuration with `defaults`.
+    /// DuckDB settings, environment variables and tags are merged instead,
+    /// and dependencies, aliases, external files and schema declarations are
+    /// never inherited.
+    pub fn with_defaults(&self, defaults: &ModelInfo) -> ModelInfo {
+        let merge_maps = |own: &Option<HashMap<String, String>>,
+                          default: &Option<HashMap<String, String>>| {
+            match (own, default) {
+                (Some(own), Some(default)) => {
+                    let mut merged = default.clone();
+                    merged.extend(own.clone());
+                    Some(merged)
+                }
+                _ => own.clone().or(default.clone()),
+            }
+        };
+        let tags = match (&self.tags, &defaults.tags) {
+            (Some(own), Some(default)) => {
+                let mut tags = default.clone();
+                tags.extend(own.iter().filter(|t| !default.contains(t)).cloned());
+                Some(tags)
+            }
+            (own, default) => own.clone().or(default.clone()),
+        };
+        ModelInfo {
+            materialize: self.materialize.clone().or(defaults.materialize.clone()),
+            enabled: self.enabled.or(defaults.enabled),
+            schema: self.schema.clone().or(defaults.schema.clone()),
+            alias: self.alias.clone(),
+            description: self.description.clone(),
+            columns: self.columns.clone(),
+            pre_hook: self.pre_hook.clone().or(defaults.pre_hook.clone()),
+            post_hook: self.post_hook.clone().or(defaults.post_hook.clone()),
+            stats_sql: self.stats_sql.clone().or(defaults.stats_sql.clone()),
+            depends_on: self.depends_on.clone(),
+            assertion: self.assertion.clone().or(defaults.assertion.clone()),
+            on_error: self.on_error.or(defaults.on_error),
+            on_empty: self.on_empty.or(defaults.on_empty),
+            serialize_with: self
+                .serialize_with
+                .clone()
+                .or(defaults.serialize_with.clone()),
+            dedupe: self.dedupe.clone().or(defaults.dedupe.clone()),
+            external: self.external.clone(),
+            analyze: self.analyze.or(defaults.analyze),
+            duckdb_settings: merge_maps(&self.duckdb_settings, &defaults.duckdb_settings),
+            env: merge_maps(&self.env, &defaults.env),
+            tags,
+            raw: self.raw.or(defaults.raw),
+        }
+    }
+}
+
+/// Directories models are discovered in. Model ids must be unique across
+/// all of them.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(from = "RawModelsDirs")]
+pub struct ModelsDirs(pub(crate) Vec<String>);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawModelsDirs {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl From<RawModelsDirs> for ModelsDirs {
+    fn from(raw: RawModelsDirs) -> Self {
+        match raw {
+            RawModelsDirs::One(dir) => ModelsDirs(vec![dir]),
+            RawModelsDirs::Many(dirs) => ModelsDirs(dirs),
+        }
+    }
+}
+
+impl ModelsDirs {
+    /// Directory new models are written to, e.g., by `arnab codegen`
+    pub fn first(&self) -> &str {
+        self.0.first().map(String::as_str).unwrap_or(".")
+    }
+}
+
+/// Environment-specific configuration, selected with `target`
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TargetInfo {
+    pub(crate) db_path: Option<String>,
+    pub(crate) artifacts_db_path: Option<String>,
+    /// Database schema of the models that don't set their own
+    pub(crate) schema: Option<String>,
+    pub(crate) duckdb_settings: Option<HashMap<String, String>>,
+    /// Variables overriding those of the config
+    pub(crate) vars: Option<HashMap<String, serde_yaml::Value>>,
+}
+
+/// A named run of `arnab job`, bundling its selection and settings
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobInfo {
+    #[serde(default)]
+    pub(crate) select: Vec<String>,
+    #[serde(default)]
+    pub(crate) exclude: Vec<String>,
+    /// Target to run against, unless `--target` is passed
+    pub(crate) target: Option<String>,
+    /// Variables overriding those of the config and the target
+    pub(crate) vars: Option<HashMap<String, serde_yaml::Value>>,
+    pub(crate) threads: Option<ThreadCount>,
+    #[serde(default)]
+    pub(crate) full_refresh: bool,
+    #[serde(default)]
+    pub(crate) fail_fast: bool,
+    /// Command notified of the job's events, instead of the global one
+    pub(crate) on_event_command: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Config {
     pub(crate) db_path: Option<String>,
+    /// Separate database file for table models, attached as `artifacts`
+    pub(crate) artifacts_db_path: Option<String>,
     pub(crate) macro_path: Option<String>,
+    pub(crate) seed_path: Option<String>,
+    /// Configuration of CSV seeds, by table name
+    pub(crate) seeds: Option<HashMap<String, SeedInfo>>,
+    /// Directory of singular data tests, `tests` by default
+    pub(crate) tests_path: Option<String>,
     pub(crate) duckdb_settings: Option<HashMap<String, String>>,
-    pub(crate) models_dir: Option<String>,
+    /// DuckDB settings applied only for a command, e.g., `run` or `viz`
+    pub(crate) command_duckdb_settings: Option<HashMap<String, HashMap<String, String>>>,
+    /// Directory of models, or a list of them, e.g., `[models, shared_models]`
+    pub(crate) models_dir: Option<ModelsDirs>,
     pub(crate) models: Option<HashMap<String, ModelInfo>>,
+    /// Default configuration of the models of a directory, by dotted path
+    /// relative to `models_dir`, e.g., `staging` or `staging.stripe`. Deeper
+    /// directories and the models' own configuration take precedence.
+    pub(crate) groups: Option<HashMap<String, ModelInfo>>,
+    pub(crate) sources: Option<HashMap<String, SourceInfo>>,
+    pub(crate) target: Option<String>,
+    pub(crate) targets: Option<HashMap<String, TargetInfo>>,
+    pub(crate) threads: Option<ThreadCount>,
+    /// Addresses of `arnab worker` processes to dispatch node builds to
+    /// (experimental)
+    pub(crate) workers: Option<Vec<String>>,
+    /// Secret shared by the coordinator and its workers, which reject build
+    /// requests without it. Overridden by the `ARNAB_WORKER_TOKEN`
+    /// environment variable.
+    pub(crate) worker_token: Option<String>,
+    /// Shell command run on failed nodes and finished runs, with the event
+    /// as JSON on stdin
+    pub(crate) on_event_command: Option<String>,
+    /// Directory of compiled SQL, docs, the manifest, the catalog and caches,
+    /// `target` by default. Overridden by the `ARNAB_TARGET_PATH` environment
+    /// variable.
+    pub(crate) target_path: Option<String>,
+    /// SQL statements executed before the first node of each pipeline run,
+    /// rendered with the run's context, e.g., `{{ run_started_at }}`
+    pub(crate) on_run_start: Option<Vec<String>>,
+    /// SQL statements executed after the last node of each pipeline run,
+    /// with `{{ n_success }}` and `{{ n_errors }}` too
+    pub(crate) on_run_end: Option<Vec<String>>,
+    /// Catalog where external models are registered once built
+    pub(crate) catalog_publisher: Option<PublisherInfo>,
+    /// Webhooks notified of finished runs
+    pub(crate) notifications: Option<NotificationsInfo>,
+    /// Maintenance to run at the end of each pipeline run
+    pub(crate) maintenance: Option<MaintenanceInfo>,
+    /// Run `ANALYZE` on table models after they are built, recording their
+    /// statistics in the catalog
+    pub(crate) analyze: Option<bool>,
+    /// Lifetime of cached `arnab show` previews, in seconds. 0 disables caching.
+    pub(crate) preview_cache_ttl: Option<u64>,
+    /// Store model and column descriptions of schema files as comments of
+    /// the built relations
+    pub(crate) persist_docs: Option<bool>,
+    /// Interpreter running Python models, `python3` by default
+    pub(crate) python: Option<String>,
+    /// Append a hash-chained provenance record of each run to
+    /// `<target_path>/provenance.jsonl`
+    pub(crate) provenance: Option<bool>,
+    /// SHA-256 of the loaded configuration file, for provenance records
+    #[serde(skip)]
+    pub(crate) config_hash: Option<String>,
+    /// Pass `.sql` models through without Jinja rendering, only rendering
+    /// `.sql.jinja` ones
+    pub(crate) raw_sql: Option<bool>,
+    /// Variables available in templates through `var()`
+    pub(crate) vars: Option<HashMap<String, serde_yaml::Value>>,
+    /// Timezone and format of printed timestamps
+    pub(crate) time_format: Option<TimeFormat>,
+    /// Acknowledge that runs without `db_path` are thrown away at exit
+    pub(crate) ephemeral: Option<bool>,
+    /// Masking policies per target: a mapping from PII category to a SQL
+    /// expression template, applied to PII columns declared in model schemas
+    pub(crate) masking_policies: Option<HashMap<String, HashMap<String, String>>>,
+    /// Report DuckDB's estimated row count of built tables instead of
+    /// counting their rows when the estimate is above this number
+    pub(crate) estimate_rows_above: Option<usize>,
+    /// Conventions of the project as Jinja expressions on the `graph`
+    /// object, checked when compiling and before runs
+    pub(crate) project_checks: Option<Vec<ProjectCheck>>,
+    /// Recurring runs of `arnab schedule`
+    pub(crate) schedules: Option<Vec<Schedule>>,
+    /// Named runs of `arnab job`
+    pub(crate) jobs: Option<HashMap<String, JobInfo>>,
+    /// Set by `--ephemeral-build` to build into an in-memory database
+    #[serde(skip)]
+    pub(crate) ephemeral_build: Option<EphemeralBuild>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
-            models_dir: Some(".".to_string()),
+            models_dir: Some(ModelsDirs(vec![".".to_string()])),
             db_path: None,
+            artifacts_db_path: None,
             macro_path: None,
+            seed_path: None,
+            tests_path: None,
+            seeds: None,
             duckdb_settings: None,
+            command_duckdb_settings: None,
             models: None,
+            groups: None,
+            sources: None,
+            target: None,
+            targets: None,
+            threads: None,
+            workers: None,
+            worker_token: None,
+            on_event_command: None,
+            target_path: None,
+            on_run_start: None,
+            on_run_end: None,
+            catalog_publisher: None,
+            notifications: None,
+            ephemeral: None,
+            raw_sql: None,
+            python: None,
+            provenance: None,
+            config_hash: None,
+            persist_docs: None,
+            preview_cache_ttl: None,
+            vars: None,
+            time_format: None,
+            analyze: None,
+            maintenance: None,
+            masking_policies: None,
+            estimate_rows_above: None,
+            project_checks: None,
+            schedules: None,
+            jobs: None,
+            ephemeral_build: None,
         }
     }
 }
 
+/// Config file names, in the order they are looked up in the project directory
+pub const CONFIG_FILE_NAMES: [&str; 3] = ["arnab.yaml", "config.yaml", "config.yml"];
+
+impl Config {
+    /// Config files present in `dir`, the first one being the one to load
+    pub fn find_files(dir: &Path) -> Vec<PathBuf> {
+        CONFIG_FILE_NAMES
+            .iter()
+            .map(|name| dir.join(name))
+            .filter(|path| path.is_file())
+            .collect()
+    }
+
+    /// Load the first config file found in `dir`, if any
+    pub fn discover(dir: &Path) -> Result<Option<(PathBuf, Config)>, ArnabError> {
+        let Some(path) = Config::find_files(dir).into_iter().next() else {
+            return Ok(None);
+        };
+        let config_str = std::fs::read_to_string(&path)
+            .map_err(|e| ArnabError::Error(format!("Cannot read {}: {}", path.display(), e)))?;
+        let config = serde_yaml::from_str(&config_str)
+            .map_err(|e| ArnabError::Error(format!("Invalid config {}: {}", path.display(), e)))?;
+        Ok(Some((path, config)))
+    }
+
+    /// Directory where arnab writes its artifacts
+    pub fn target_dir(&self) -> PathBuf {
+        PathBuf::from(self.target_path.as_deref().unwrap_or(DEFAULT_TARGET_PATH))
+    }
+
+    /// Configuration of the current target, if declared
+    pub fn target_info(&self) -> Option<&TargetInfo> {
+        self.targets.as_ref()?.get(self.target.as_ref()?)
+    }
+
+    /// Override the config with the settings of the current target. Fails
+    /// when targets are declared but not the current one.
+    pub fn apply_target(&mut self) -> Result<(), ArnabError> {
+        let (Some(target), Some(targets)) = (&self.target, &self.targets) else {
+            return Ok(());
+        };
+        let Some(info) = targets.get(target).cloned() else {
+            let mut names = targets.keys().cloned().collect::<Vec<_>>();
+            names.sort();
+            return Err(ArnabError::Error(format!(
+                "Unknown target `{}`, expected one of: {}",
+                target,
+                names.join(", ")
+            )));
+        };
+        self.db_path = info.db_path.or(self.db_path.take());
+        self.artifacts_db_path = info.artifacts_db_path.or(self.artifacts_db_path.take());
+        if let Some(vars) = info.vars {
+            self.vars.get_or_insert_with(HashMap::new).extend(vars);
+        }
+        Ok(())
+    }
+
+    /// Job of the given name, declared in `jobs`
+    pub fn job(&self, name: &str) -> Result<JobInfo, ArnabError> {
+        let jobs = self.jobs.clone().unwrap_or_default();
+        match jobs.get(name) {
+            Some(job) => Ok(job.clone()),
+            None => {
+                let mut names = jobs.keys().cloned().collect::<Vec<_>>();
+                names.sort();
+                Err(ArnabError::Error(format!(
+                    "Unknown job `{}`, expected one of: {}",
+                    name,
+                    names.join(", ")
+                )))
+            }
+        }
+    }
+
+    /// Override the configuration with a job's settings, once its target is
+    /// applied
+    pub fn apply_job(&mut self, job: &JobInfo) {
+        if let Some(vars) = &job.vars {
+            self.vars
+                .get_or_insert_with(HashMap::new)
+                .extend(vars.clone());
+        }
+        self.threads = job.threads.or(self.threads);
+        if let Some(command) = &job.on_event_command {
+            self.on_event_command = Some(command.clone());
+        }
+    }
+
+    /// Override config variables with a YAML mapping, e.g., `{start_date: 2024-01-01}`
+    pub fn override_vars(&mut self, src: &str) -> Result<(), ArnabError> {
+        let overrides: HashMap<String, serde_yaml::Value> = serde_yaml::from_str(src)
+            .map_err(|e| ArnabError::Error(format!("Invalid --vars `{}`: {}", src, e)))?;
+        self.vars.get_or_insert_with(HashMap::new).extend(overrides);
+        Ok(())
+    }
+}
+
+/// Directory of the target directory where `arnab compile` writes compiled SQL
+pub const COMPILED_DIR: &str = "compiled";
+
+/// Name under which `artifacts_db_path` is attached
+pub const ARTIFACTS_DB_NAME: &str = "artifacts";
+
+/// Options of a pipeline run
+#[derive(Clone, Debug, Default)]
+pub struct RunOptions {
+    pub(crate) selection: Selection,
+    /// Only load seeds, as `arnab seed` does
+    pub(crate) seeds_only: bool,
+    /// Print the execution plan instead of executing it
+    pub(crate) dry_run: bool,
+    /// Rebuild table models even when their cached result is up to date
+    pub(crate) full_refresh: bool,
+    /// Results of a previous run, whose failed nodes are built again instead
+    /// of the selection, as `arnab retry` does
+    pub(crate) retry: Option<RunResults>,
+    /// Stop at the first failure of a model without `on_error: continue`
+    pub(crate) fail_fast: bool,
+    /// Show a progress bar while nodes are built one at a time on a terminal
+    pub(crate) progress_bar: bool,
+    /// Only build the selected nodes whose SQL changed since the last run,
+    /// and their descendants, as `arnab run --watch` does on changes
+    pub(crate) only_modified: bool,
+}
+
+/// Options of `arnab compile`
+#[derive(Clone, Debug, Default)]
+pub struct CompileOptions {
+    pub(crate) selection: Selection,
+    /// Warn about models selecting `*` from upstream models whose columns
+    /// aren't declared in a schema file
+    pub(crate) check_select_star: bool,
+    /// Replace `*` in the final SELECT with the columns of the built
+    /// upstream relations
+    pub(crate) expand_select_star: bool,
+}
+
 /// Representation of a single process of pipeline execution
 pub struct Session {
     pub(crate) config: Config,
     pub(crate) db_conn: Connection,
+    pub(crate) events: EventLog,
+    /// Start of the session's run, as rendered in templates
+    pub(crate) run_started_at: DateTime<Utc>,
 }
 
 impl Session {
     pub fn new(config: Config, connection: Connection) -> Self {
+        let mut events = EventLog::default();
+        if let Some(notifications) = &config.notifications {
+            events.add_listener(Box::new(Notifier::new(
+                notifications,
+                config.target.clone(),
+            )));
+        }
+        if let Some(command) = &config.on_event_command {
+            events.add_command(command);
+        }
         Self {
             config,
             db_conn: connection,
+            events,
+            run_started_at: Utc::now(),
         }
     }
 
-    pub fn build_graph(&mut self) -> Result<(Vec<String>, HashMap<String, Node>), ArnabError> {
-        let glob_pattern =
-            std::path::Path::new(&self.config.models_dir.as_ref().unwrap()).join("**/*.sql");
-        let model_paths = glob::glob(glob_pattern.to_str().unwrap())
-            .unwrap()
-            .map(|v| v.unwrap())
-            .collect::<Vec<_>>();
+    /// Connection of the session, e.g., for an embedder to run pre-flight
+    /// queries between `plan` and `execute_plan` without reopening the database
+    pub fn connection(&self) -> &Connection {
+        &self.db_conn
+    }
+
+    /// Ids of the graph nodes picked by `selection`. Its `state:` selectors
+    /// compare against the manifest of the last run, unless given another one.
+    pub fn select(&self, graph: &Graph, selection: &Selection) -> Result<Vec<String>, ArnabError> {
+        let mut selection = selection.clone();
+        selection
+            .state
+            .get_or_insert_with(|| self.config.target_dir());
+        graph.select(&selection)
+    }
+
+    pub fn build_graph(&mut self) -> Result<Graph, ArnabError> {
+        // The directory of each model, to name it relative to it
+        let mut model_roots = HashMap::new();
+        for models_dir in &self.config.models_dir.as_ref().unwrap().0 {
+            for pattern in ["**/*.sql", "**/*.sql.jinja", "**/*.py", "**/*.sh"] {
+                let glob_pattern = Path::new(models_dir).join(pattern);
+                for path in glob::glob(glob_pattern.to_str().unwrap()).unwrap() {
+                    let path = path.unwrap();
+                    // Python files are only models when they define `model()`
+                    let is_python = path.extension().is_some_and(|e| e == "py");
+                    if is_python
+                        && !std::fs::read_to_string(&path).is_ok_and(|src| is_python_model(&src))
+                    {
+                        continue;
+                    }
+                    // Nested directories are searched once, with the first one
+                    model_roots
+                        .entry(path)
+                        .or_insert_with(|| models_dir.clone());
+                }
+            }
+        }
+        let mut model_paths = model_roots.keys().cloned().collect::<Vec<_>>();
+        model_paths.sort();
+        // Only suffixes naming a target make overrides, so that `orders.v2.sql`
+        // is a model of its own
+        let known_targets = self
+            .config
+            .targets
+            .iter()
+            .flat_map(|targets| targets.keys())
+            .chain(&self.config.target)
+            .map(String::as_str)
+            .collect::<HashSet<_>>();
+        let model_paths =
+            resolve_target_overrides(model_paths, self.config.target.as_deref(), &known_targets)?;
 
         // load User-defined macros
-        let mut macros = HashMap::new();
-        if let Some(macro_path) = &self.config.macro_path {
-            let macro_paths = glob::glob(
-                std::path::Path::new(macro_path)
-                    .join("*.*")
-                    .to_str()
-                    .unwrap(),
-            )
-            .unwrap();
-            for path_opt in macro_paths {
-                let path = path_opt.unwrap();
-                let path_str = path.to_string_lossy().to_string();
-                let macro_src = std::fs::read_to_string(&path_str).unwrap();
-                macros.insert(path_str, macro_src);
-            }
-        }
-
-        // Populate nodemap, a mapping from filename to Node struct
-        let mut node_map = HashMap::new();
+        let context = self.render_context()?;
+
+        let mut builder = GraphBuilder::new();
         let mut n_source = 0;
-        for p in model_paths.into_iter() {
-            let path_string = p.to_string_lossy().to_string();
-            let node_id = {
-                let path = std::path::Path::new(&path_string);
-                let path_no_ext = path.with_extension("");
-                let file_name = path_no_ext.file_name().unwrap();
-                file_name.to_string_lossy().to_string()
-            };
+        for (p, src_path) in model_paths.into_iter() {
+            let path_string = src_path.to_string_lossy().to_string();
+            let (base_path, _) = split_model_path(&p);
+            let (_, is_jinja) = split_model_path(&src_path);
+            let node_id = base_path.file_name().unwrap().to_string_lossy().to_string();
+            let fqn = model_fqn(&model_roots[&p], &base_path);
+            let model_info = self.model_info(&node_id, &fqn);
+            let model_info = model_info.as_ref();
+            if model_info.and_then(|m| m.enabled) == Some(false) {
+                info!("Skipping disabled model: {}", path_string);
+                continue;
+            }
 
-            println!("Found model source: {}", path_string);
+            if src_path == p {
+                info!("Found model source: {}", path_string);
+            } else {
+                info!(
+                    "Found model source: {} (overriding {})",
+                    path_string,
+                    p.display()
+                );
+            }
             n_source += 1;
 
             let raw_src = std::fs::read_to_string(&path_string).unwrap();
             let node_type = {
                 let extension = p.extension().unwrap().to_str().unwrap();
                 match extension {
-                    "sql" => NodeKind::Sql,
+                    "sql" | "jinja" => NodeKind::Sql,
+                    "py" => NodeKind::Python,
+                    "sh" => NodeKind::Shell,
                     _ => return Err(ArnabError::UnknownModelType(extension.into())),
                 }
             };
 
             let mut node = Node::new(node_type, &path_string, &node_id, &raw_src);
+            node.fqn = fqn;
 
             // set model's materialization mode
-            if let Some(models) = &self.config.models {
-                if let Some(model_info) = models.get(&node_id) {
-                    node.materialize.clone_from(&model_info.materialize);
+            if let Some(model_info) = model_info {
+                node.materialize.clone_from(&model_info.materialize);
+                node.stats_sql.clone_from(&model_info.stats_sql);
+                node.assertion.clone_from(&model_info.assertion);
+                node.on_error = model_info.on_error.unwrap_or_default();
+                node.on_empty = model_info.on_empty.unwrap_or_default();
+                node.serialize_with = model_info.serialize_with.clone().unwrap_or_default();
+                node.dedupe.clone_from(&model_info.dedupe);
+                node.external.clone_from(&model_info.external);
+                node.duckdb_settings = model_info
+                    .duckdb_settings
+                    .iter()
+                    .flatten()
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect();
+                node.tags = model_info.tags.clone().unwrap_or_default();
+                node.alias.clone_from(&model_info.alias);
+                node.pre_hooks = model_info.pre_hook.clone().unwrap_or_default();
+                node.post_hooks = model_info.post_hook.clone().unwrap_or_default();
+                node.env_vars = model_info
+                    .env
+                    .iter()
+                    .flatten()
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect();
+            }
+            // Python and shell models always create tables
+            if let Some(kind) = script_kind(&node.node_kind) {
+                match node.materialize.as_deref() {
+                    None => node.materialize = Some("table".into()),
+                    Some(m) if m.eq_ignore_ascii_case("table") => {}
+                    Some(m) => {
+                        return Err(ArnabError::Config(format!(
+                            "{} model `{}` can only be materialized as a table, not `{}`",
+                            kind, node_id, m
+                        )))
+                    }
                 }
             }
+            // A file can't be rolled back once written, so a failing assertion
+            // would leave rejected rows for outside engines to read
+            if node.is_external() && node.assertion.is_some() {
+                return Err(ArnabError::Config(format!(
+                    "External model `{}` cannot have an assertion, as its file is written before it could be checked",
+                    node_id
+                )));
+            }
+            match node.node_kind {
+                NodeKind::Python => node.python.clone_from(&self.config.python),
+                NodeKind::Shell => node.db_path.clone_from(&self.config.db_path),
+                _ => {}
+            }
+            // Directories are implicit tags, e.g., `staging` and `stripe` for
+            // `staging/stripe/charges.sql`
+            let fqn_parts = node.fqn.split('.').collect::<Vec<_>>();
+            for directory in &fqn_parts[..fqn_parts.len() - 1] {
+                if !node.tags.iter().any(|t| t == directory) {
+                    node.tags.push(directory.to_string());
+                }
+            }
+            node.tags.sort();
+
+            // `.sql.jinja` files are always rendered, `.sql` ones unless raw
+            node.raw = !is_jinja
+                && model_info
+                    .and_then(|m| m.raw)
+                    .or(self.config.raw_sql)
+                    .unwrap_or(false);
+            node.analyze = model_info
+                .and_then(|m| m.analyze)
+                .or(self.config.analyze)
+                .unwrap_or(false);
 
-            node_map.insert(node_id, node);
+            if self.config.artifacts_db_path.is_some() {
+                node.database = Some(ARTIFACTS_DB_NAME.into());
+            }
+            node.relation_schema = model_info
+                .and_then(|m| m.schema.clone())
+                .or_else(|| self.config.target_info()?.schema.clone());
+
+            node.schema = ModelSchema::load_for(&p)?;
+            if let Some(model_info) = model_info {
+                let declared = ModelSchema {
+                    description: model_info.description.clone(),
+                    columns: model_info.columns.clone().unwrap_or_default(),
+                };
+                if declared.description.is_some() || !declared.columns.is_empty() {
+                    let schema = node.schema.take().unwrap_or_default();
+                    node.schema =
+                        Some(schema.merge(declared).map_err(|e| {
+                            ArnabError::Config(format!("Model `{}`: {}", node_id, e))
+                        })?);
+                }
+            }
+            node.persist_docs = self.config.persist_docs.unwrap_or(false);
+
+            // mask PII columns if the current target has masking policies
+            let masking_policies = self
+                .config
+                .target
+                .as_ref()
+                .and_then(|t| self.config.masking_policies.as_ref()?.get(t));
+            if let (Some(policies), Some(schema)) = (masking_policies, &node.schema) {
+                node.masked_columns = schema
+                    .masking_expressions(&node_id, policies)
+                    .map_err(|e| ArnabError::Config(e.message()))?;
+            }
+
+            builder.add_node(node)?;
+        }
+
+        // CSV seeds are tables that models can refer to like other models
+        let seed_path = self
+            .config
+            .seed_path
+            .as_deref()
+            .unwrap_or(DEFAULT_SEED_PATH);
+        let seed_pattern = Path::new(seed_path).join("*.csv");
+        for p in glob::glob(seed_pattern.to_str().unwrap()).unwrap() {
+            let p = p.unwrap();
+            let path_string = p.to_string_lossy().to_string();
+            let node_id = p.file_stem().unwrap().to_string_lossy().to_string();
+            if builder.contains(&node_id) {
+                return Err(ArnabError::Error(format!(
+                    "Seed {} has the same name as model `{}`",
+                    path_string, node_id
+                )));
+            }
+            info!("Found seed: {}", path_string);
+
+            let seed_info = self.config.seeds.as_ref().and_then(|s| s.get(&node_id));
+            let mut node = Node::new(
+                NodeKind::Seed,
+                &path_string,
+                &node_id,
+                &csv_seed_sql(&p, seed_info),
+            );
+            node.materialize = Some("table".into());
+            if self.config.artifacts_db_path.is_some() {
+                node.database = Some(ARTIFACTS_DB_NAME.into());
+            }
+            builder.add_node(node)?;
+        }
+
+        // So are YAML seeds declaring their rows inline
+        let seed_pattern = Path::new(seed_path).join("*.y*ml");
+        for p in glob::glob(seed_pattern.to_str().unwrap()).unwrap() {
+            let p = p.unwrap();
+            let path_string = p.to_string_lossy().to_string();
+            let Some(sql) = SeedFile::read(&p)?
+                .inline_sql()
+                .map_err(|e| ArnabError::Error(format!("Invalid seed {}: {}", path_string, e)))?
+            else {
+                continue;
+            };
+            let node_id = p.file_stem().unwrap().to_string_lossy().to_string();
+            if builder.contains(&node_id) {
+                return Err(ArnabError::Error(format!(
+                    "Seed {} has the same name as `{}`",
+                    path_string, node_id
+                )));
+            }
+            info!("Found seed: {}", path_string);
+
+            let mut node = Node::new(NodeKind::Seed, &path_string, &node_id, &sql);
+            node.materialize = Some("table".into());
+            if self.config.artifacts_db_path.is_some() {
+                node.database = Some(ARTIFACTS_DB_NAME.into());
+            }
+            builder.add_node(node)?;
         }
 
         // Render SQL and populate incoming edges
-        let found_model_names = node_map
-            .keys()
-            .map(|v| v.to_string())
-            .collect::<Vec<String>>();
-        for (_, node) in node_map.iter_mut() {
-            node.render_and_populate_refs(&macros, &found_model_names);
+        let found_model_names = builder.node_ids();
+        for node in builder.nodes_mut() {
+            node.render_and_populate_refs(&context, &found_model_names)?;
         }
 
-        println!(
+        // Add explicitly declared dependencies
+        for (id, model_info) in self.config.models.iter().flatten() {
+            if !builder.contains(id) {
+                continue;
+            }
+            for dep in model_info.depends_on.iter().flatten() {
+                builder
+                    .add_dependency(id, dep)
+                    .map_err(|e| ArnabError::Config(e.message()))?;
+            }
+        }
+
+        info!(
             "Found {} model source{}, {} macro{}\n",
             n_source,
             if n_source > 1 { "s" } else { "" },
-            macros.len(),
-            if macros.len() > 1 { "s" } else { "" },
+            context.macros.len(),
+            if context.macros.len() > 1 { "s" } else { "" },
         );
 
-        // Populate outgoing nodes
-        let ids: Vec<String> = node_map.keys().map(|v| v.to_string()).collect();
-        let mut invalid_node_ids = HashSet::new();
-        for id in ids {
-            let node = &node_map.get(&id).unwrap();
-            let prevs = node.prevs.clone();
-            for prev_id in prevs {
-                match node_map.get_mut(&prev_id) {
-                    Some(prev_node) => {
-                        prev_node.nexts.insert(id.to_string());
+        let mut graph = builder.build()?;
+
+        // Inline ephemeral models into the models using them
+        let sorted_position = graph.sorted_positions();
+        for id in &graph.sorted_ids {
+            if graph.nodes[id].is_ephemeral() {
+                continue;
+            }
+            let ephemerals =
+                ephemeral_ancestors(&graph.nodes[id].prevs, &graph.nodes, &sorted_position);
+            if let Some(kind) = script_kind(&graph.nodes[id].node_kind) {
+                if let Some(ephemeral) = ephemerals.first() {
+                    return Err(ArnabError::Error(format!(
+                        "{} model `{}` cannot use ephemeral model `{}`",
+                        kind, id, ephemeral.id
+                    )));
+                }
+                continue;
+            }
+            let ctes = ephemeral_ctes(&ephemerals)?;
+            let sources = ephemerals
+                .iter()
+                .flat_map(|e| e.sources.iter().cloned())
+                .collect::<Vec<_>>();
+            let node = graph.nodes.get_mut(id).unwrap();
+            node.inline_ephemerals(&ctes);
+            node.sources.extend(sources);
+        }
+        Ok(graph)
+    }
+
+    /// Render the graph as SVG or interactive HTML, or export it as GraphML,
+    /// Graphology, DOT or Mermaid. When `diff_manifest` is given, nodes are colored by
+    /// how they changed relative to that manifest: added (green), removed
+    /// (red) or having modified compiled SQL (yellow). SVG graphs of more
+    /// than `max_nodes` nodes, sources included, are refused, while exports
+    /// are rendered by other tools and have no limit.
+    pub fn save_visualization(
+        &mut self,
+        path: &str,
+        selection: &Selection,
+        diff_manifest: Option<&Path>,
+        format: VizFormat,
+        renderer: VizRenderer,
+        max_nodes: Option<usize>,
+    ) -> Result<(), ArnabError> {
+        let graph = self.build_graph()?;
+        let sorted_valid_ids = self.select(&graph, selection)?;
+        let (mut nodes, mut edges) = source_nodes(&sorted_valid_ids, &graph);
+        let statuses = match diff_manifest {
+            None => {
+                nodes.extend(sorted_valid_ids.iter().map(|id| DotNode {
+                    name: id.clone(),
+                    fill_color: None,
+                    tooltip: graph.nodes[id].description().map(String::from),
+                }));
+                edges.extend(graph.edges(&sorted_valid_ids));
+                None
+            }
+            Some(diff_manifest) => {
+                let old = Manifest::load(diff_manifest)?;
+                // Unselected models are left out rather than reported as removed
+                let statuses = Manifest::from_graph(&graph, &graph.sorted_ids)
+                    .diff(&old)
+                    .into_iter()
+                    .filter(|(id, _)| {
+                        sorted_valid_ids.contains(id) || !graph.nodes.contains_key(id)
+                    })
+                    .collect::<BTreeMap<_, _>>();
+                nodes.extend(statuses.iter().map(|(id, status)| {
+                    DotNode {
+                        name: id.clone(),
+                        fill_color: match status {
+                            DiffStatus::Added => Some("palegreen".into()),
+                            DiffStatus::Removed => Some("lightcoral".into()),
+                            DiffStatus::Modified => Some("khaki".into()),
+                            DiffStatus::Unchanged => None,
+                        },
+                        tooltip: graph
+                            .nodes
+                            .get(id)
+                            .and_then(|n| n.description())
+                            .map(String::from),
                     }
-                    None => {
-                        invalid_node_ids.insert(prev_id.clone());
+                }));
+
+                // Removed nodes keep the incoming edges they had in the old manifest
+                edges.extend(graph.edges(&sorted_valid_ids));
+                for (id, status) in &statuses {
+                    if *status == DiffStatus::Removed {
+                        for dep in &old.nodes[id].depends_on {
+                            if statuses.contains_key(dep) {
+                                edges.push((dep.clone(), id.clone()));
+                            }
+                        }
+                    }
+                }
+                Some(statuses)
+            }
+        };
+        let output = match format {
+            VizFormat::Svg => {
+                if let Some(max_nodes) = max_nodes.filter(|max_nodes| nodes.len() > *max_nodes) {
+                    return Err(ArnabError::Error(format!(
+                        "The graph has {} nodes, more than --max-nodes {} can render legibly. \
+                         Select a subgraph instead, e.g., `--select +orders` for a model and \
+                         its ancestors, or raise --max-nodes.",
+                        nodes.len(),
+                        max_nodes
+                    )));
+                }
+                render_nodes_with(&nodes, &edges, renderer)
+            }
+            VizFormat::Graphml => to_graphml(&nodes, &edges, &graph, statuses.as_ref()),
+            VizFormat::Graphology => to_graphology(&nodes, &edges, &graph, statuses.as_ref()),
+            VizFormat::Dot => dot_source(&nodes, &edges, true),
+            VizFormat::Mermaid => to_mermaid(&nodes, &edges, &graph, statuses.as_ref()),
+            VizFormat::Html => {
+                let last_run = RunResults::load(&self.config.target_dir())?;
+                to_html_graph(&nodes, &edges, &graph, statuses.as_ref(), last_run.as_ref())
+            }
+        };
+        std::fs::write(path, output).map_err(|e| ArnabError::Error(e.to_string()))
+    }
+
+    /// Pin models, for runs to keep them as they are until unpinned. Without
+    /// models, list the pinned ones.
+    pub fn pin(&mut self, ids: &[String]) -> Result<(), ArnabError> {
+        let target_dir = self.config.target_dir();
+        let mut pins = Pins::load(&target_dir)?;
+        if ids.is_empty() {
+            match pins.models.is_empty() {
+                true => println!("No pinned models"),
+                false => pins.models.iter().for_each(|id| println!("{}", id)),
+            }
+            return Ok(());
+        }
+        let graph = self.build_graph()?;
+        for id in ids {
+            let Some(node) = graph.nodes.get(id) else {
+                return Err(ArnabError::Error(format!("Unknown model `{}`", id)));
+            };
+            if node.is_ephemeral() {
+                return Err(ArnabError::Error(format!(
+                    "Cannot pin ephemeral model `{}`, which is inlined in the models using it",
+                    id
+                )));
+            }
+            if !node.relation_exists(&self.db_conn) {
+                println!(
+                    "WARNING: `{}` is not built, so models using it fail until it is unpinned",
+                    id
+                );
+            }
+        }
+        for id in ids {
+            if pins.models.insert(id.clone()) {
+                println!("Pinned {}", id);
+            }
+        }
+        pins.save(&target_dir)
+    }
+
+    /// Unpin models, or all of them if `all`
+    pub fn unpin(&mut self, ids: &[String], all: bool) -> Result<(), ArnabError> {
+        let target_dir = self.config.target_dir();
+        let mut pins = Pins::load(&target_dir)?;
+        let ids = match all {
+            true => pins.models.iter().cloned().collect(),
+            false => ids.to_vec(),
+        };
+        for id in &ids {
+            match pins.models.remove(id) {
+                true => println!("Unpinned {}", id),
+                false => println!("WARNING: `{}` is not pinned", id),
+            }
+        }
+        pins.save(&target_dir)
+    }
+
+    /// Compare the model contracts against those of the manifest at `state`,
+    /// e.g., from production, failing on any breaking change
+    pub fn check_breaking(&mut self, state: &Path) -> Result<(), ArnabError> {
+        let old = Manifest::load(state)?;
+        let graph = self.build_graph()?;
+        let changes = Manifest::from_graph(&graph, &graph.sorted_ids).breaking_changes(&old);
+        if changes.is_empty() {
+            println!("No breaking changes to model contracts");
+            return Ok(());
+        }
+        println!("Breaking changes to model contracts:");
+        for change in &changes {
+            println!("  {}", change);
+        }
+        Err(ArnabError::Error(format!(
+            "{} breaking change{} relative to {}",
+            changes.len(),
+            if changes.len() == 1 { "" } else { "s" },
+            state.display()
+        )))
+    }
+
+    /// Print sort order recommendations for the table models filtered on the
+    /// same column by at least `min_models` models
+    pub fn advise(&mut self, min_models: usize) -> Result<(), ArnabError> {
+        let graph = self.build_graph()?;
+        let catalog = Catalog::load(&self.config.target_dir())?;
+        let advices = advise(&graph, &catalog, min_models);
+        if advices.is_empty() {
+            println!("No recommendations");
+        }
+        for advice in &advices {
+            println!("{}", advice);
+        }
+        Ok(())
+    }
+
+    /// Write the SQL executed for each selected model, with macros and
+    /// Jinja rendered and the query wrapped into its materialization, into
+    /// `<target_path>/compiled`, mirroring the models directory
+    pub fn compile(&mut self, options: &CompileOptions) -> Result<(), ArnabError> {
+        let mut graph = self.build_graph()?;
+        run_project_checks(
+            self.config.project_checks.as_deref().unwrap_or_default(),
+            &graph,
+        )?;
+        let compiled_dir = self.config.target_dir().join(COMPILED_DIR);
+        if compiled_dir.exists() {
+            std::fs::remove_dir_all(&compiled_dir).map_err(|e| ArnabError::Error(e.to_string()))?;
+        }
+        let ids = self.select(&graph, &options.selection)?;
+        if options.check_select_star {
+            self.check_select_star(&graph, &ids);
+        }
+        let mut n_compiled = 0;
+        for id in ids {
+            if options.expand_select_star && matches!(graph.nodes[&id].node_kind, NodeKind::Sql) {
+                self.expand_select_star(graph.nodes.get_mut(&id).unwrap());
+            }
+            let node = &graph.nodes[&id];
+            // Ephemeral models only exist inlined in other models
+            if node.is_ephemeral() {
+                continue;
+            }
+            let extension = match node.node_kind {
+                NodeKind::Python => "py",
+                NodeKind::Shell => "sh",
+                _ => "sql",
+            };
+            let path = compiled_dir.join(format!("{}.{}", node.fqn.replace('.', "/"), extension));
+            std::fs::create_dir_all(path.parent().unwrap())
+                .map_err(|e| ArnabError::Error(e.to_string()))?;
+            std::fs::write(&path, node.compiled_sql()?)
+                .map_err(|e| ArnabError::Error(e.to_string()))?;
+            n_compiled += 1;
+        }
+        println!(
+            "Compiled {} model{} into {}",
+            n_compiled,
+            if n_compiled > 1 { "s" } else { "" },
+            compiled_dir.display()
+        );
+        Ok(())
+    }
+
+    /// Documentation of the macros of `macro_path`
+    pub fn macro_docs(&self) -> Result<Vec<MacroDoc>, ArnabError> {
+        match &self.config.macro_path {
+            Some(macro_path) => Ok(macro_docs(&load_macros(macro_path)?)),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Write a static HTML site documenting the selected models into
+    /// `<target_path>/docs`, with statistics from the catalog of the last analysis
+    pub fn generate_docs(&mut self, selection: &Selection) -> Result<(), ArnabError> {
+        let graph = self.build_graph()?;
+        let ids = self.select(&graph, selection)?;
+        let catalog = Catalog::load(&self.config.target_dir())?;
+        let docs_dir = self.config.target_dir().join(DOCS_DIR);
+        generate_docs(&graph, &ids, &catalog, &self.macro_docs()?, &docs_dir)?;
+        println!(
+            "Documented {} model{} in {}",
+            ids.len(),
+            if ids.len() > 1 { "s" } else { "" },
+            docs_dir.join("index.html").display()
+        );
+        Ok(())
+    }
+
+    /// Warn about models selecting `*` from an upstream model without a
+    /// schema contract, as its schema changes would propagate silently
+    fn check_select_star(&self, graph: &Graph, ids: &[String]) {
+        let is_contracted = |upstream: &Node| {
+            upstream
+                .schema
+                .as_ref()
+                .is_some_and(|s| !s.columns.is_empty())
+        };
+        for id in ids {
+            let node = &graph.nodes[id];
+            if !matches!(node.node_kind, NodeKind::Sql) {
+                continue;
+            }
+            for statement in node.records_statements() {
+                for table in star_tables(statement) {
+                    // The upstream model, whether named qualified or not
+                    let upstream = node.prevs.iter().find(|prev| {
+                        let prev = &graph.nodes[prev.as_str()];
+                        same_relation(&table, &prev.relation(prev.is_table()))
+                    });
+                    if let Some(upstream) = upstream.filter(|u| !is_contracted(&graph.nodes[*u])) {
                         println!(
-                            "WARNING: Model `{}` required by `{}` not found",
-                            prev_id, id
-                        )
+                            "WARNING: {} selects `*` from {}, whose columns aren't declared in a schema file, so its schema changes will propagate silently",
+                            id, upstream
+                        );
                     }
-                };
+                }
+            }
+        }
+    }
+
+    /// Replace `*` in the final SELECT of a model with the columns of the
+    /// built upstream relations, keeping the model as is when they are unknown
+    fn expand_select_star(&self, node: &mut Node) {
+        let mut columns = HashMap::new();
+        for statement in node.records_statements() {
+            for table in star_tables(statement) {
+                if let Ok(described) = describe_relation(&self.db_conn, &table) {
+                    columns.insert(table, described.into_iter().map(|(name, _)| name).collect());
+                }
             }
         }
+        node.map_records_statements(|statement| {
+            expand_select_star(statement, &columns).unwrap_or(statement.to_string())
+        });
+        let expanded = node
+            .records_statements()
+            .iter()
+            .all(|statement| star_tables(statement).is_empty());
+        if !expanded {
+            println!(
+                "WARNING: `*` in {} left as is, as the columns it stands for are unknown",
+                node.id
+            );
+        }
+    }
+
+    /// Print the first `limit` rows returned by a model, without
+    /// materializing it. The models it refers to must already be built.
+    /// Previews are cached until the database changes, unless `refresh`.
+    pub fn show(&mut self, id: &str, limit: usize, refresh: bool) -> Result<(), ArnabError> {
+        let graph = self.build_graph()?;
+        let Some(node) = graph.nodes.get(id) else {
+            return Err(ArnabError::Error(format!("Model `{}` not found", id)));
+        };
+        let sql = format!("SELECT * FROM ({}) LIMIT {}", node.select_sql()?, limit);
+
+        // An in-memory database starts empty, so there's nothing to cache
+        let ttl = self
+            .config
+            .preview_cache_ttl
+            .unwrap_or(DEFAULT_PREVIEW_CACHE_TTL);
+        let database_paths = [&self.config.db_path, &self.config.artifacts_db_path]
+            .into_iter()
+            .flatten()
+            .map(Path::new)
+            .collect::<Vec<_>>();
+        let cache = (ttl > 0 && !database_paths.is_empty()).then(|| {
+            let dir = self.config.target_dir().join(PREVIEW_CACHE_DIR);
+            PreviewCache::new(dir, Duration::from_secs(ttl))
+        });
+        let key = PreviewCache::key(&sql, &database_paths);
+        if let Some((lines, age)) = cache
+            .as_ref()
+            .filter(|_| !refresh)
+            .and_then(|c| c.get(&key))
+        {
+            print_preview(&lines, limit, Some(age));
+            return Ok(());
+        }
+
+        let to_error = |e: duckdb::Error| ArnabError::StatementExecutionError {
+            msg: e.to_string(),
+            path: node.path.clone(),
+            sql: sql.clone(),
+        };
 
-        // Terminal ids are the id of nodes who have no outgoing edge,
-        // i.e., node.nexts.len() == 0
-        let terminal_ids: Vec<String> = node_map
-            .values()
-            .filter(|v| v.nexts.is_empty())
-            .map(|v| v.id.clone())
-            .collect();
+        let mut stmt = self.db_conn.prepare(&sql).map_err(to_error)?;
+        let mut rows = stmt.query([]).map_err(to_error)?;
+        let mut lines = vec![];
+        while let Some(row) = rows.next().map_err(to_error)? {
+            let n_columns = row.as_ref().column_count();
+            let cells = (0..n_columns)
+                .map(|i| {
+                    row.get::<_, duckdb::types::Value>(i)
+                        .map(|v| format_value(&v))
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(to_error)?;
+            lines.push(cells);
+        }
+        drop(rows);
+        lines.insert(0, stmt.column_names());
+        print_preview(&lines, limit, None);
+        if let Some(cache) = cache {
+            cache.put(&key, lines)?;
+        }
+        Ok(())
+    }
 
-        // Obtain node ids in topological order
-        let mut sorted_ids = vec![];
-        for t_id in &terminal_ids {
-            topo(t_id, &node_map, &mut sorted_ids);
+    /// Run the selected nodes, emitting `RunFailed` if the run stops before
+    /// completion
+    pub fn run_nodes(&mut self, options: &RunOptions) -> Result<RunSummary, ArnabError> {
+        let result = self.try_run_nodes(options);
+        if let Err(e) = &result {
+            let source_path = match e {
+                ArnabError::StatementExecutionError { path, .. } => Some(path.clone()),
+                _ => None,
+            };
+            self.events.emit(Event::RunFailed {
+                error: e.message(),
+                source_path,
+            });
         }
+        result
+    }
 
-        // Filter out invalid node ids
-        let sorted_valid_ids = sorted_ids
+    fn try_run_nodes(&mut self, options: &RunOptions) -> Result<RunSummary, ArnabError> {
+        let plan = self.plan(options)?;
+        if plan.node_ids().is_empty() {
+            info!("No model selected");
+            return Ok(RunSummary::default());
+        }
+        if options.dry_run {
+            plan.print();
+            return Ok(RunSummary::default());
+        }
+        if enabled(Verbosity::Verbose) {
+            plan.print();
+            println!();
+        }
+        self.execute_plan(&plan)
+    }
+
+    /// Plan a pipeline run: build the graph and list the steps to execute
+    pub fn plan(&mut self, options: &RunOptions) -> Result<ExecutionPlan, ArnabError> {
+        let mut graph = self.build_graph()?;
+        run_project_checks(
+            self.config.project_checks.as_deref().unwrap_or_default(),
+            &graph,
+        )?;
+        // Ephemeral builds start from an empty database, where nothing can be
+        // dropped from the real one
+        let ephemeral_build = self.config.ephemeral_build.is_some();
+        // Retried models are rebuilt, even if cached before their failure
+        let retry = options.retry.is_some();
+        for node in graph.nodes.values_mut() {
+            node.full_refresh = options.full_refresh && !ephemeral_build;
+            node.estimate_rows_above = self.config.estimate_rows_above;
+        }
+        let node_map = &graph.nodes;
+        // Ephemeral models only exist inlined in other models
+        let selected_ids = match &options.retry {
+            Some(results) => results.retry_ids(&graph),
+            None => self.select(&graph, &options.selection)?,
+        };
+        let modified_ids = match options.only_modified {
+            true => {
+                let modified = Selection {
+                    select: vec!["state:modified+".into()],
+                    ..Default::default()
+                };
+                Some(self.select(&graph, &modified)?)
+            }
+            false => None,
+        };
+        let mut sorted_valid_ids = selected_ids
+            .into_iter()
+            .filter(|id| modified_ids.as_ref().is_none_or(|ids| ids.contains(id)))
+            .filter(|id| !node_map[id].is_ephemeral())
+            .filter(|id| !options.seeds_only || matches!(node_map[id].node_kind, NodeKind::Seed))
+            .collect::<Vec<_>>();
+        // Pinned models are kept as they are, even when selected
+        let pins = Pins::load(&self.config.target_dir())?;
+        let pinned_ids = sorted_valid_ids
             .iter()
-            .filter(|v| !invalid_node_ids.contains(&v.to_string()))
-            .map(|v| v.to_string())
-            .collect::<Vec<String>>();
+            .filter(|id| pins.models.contains(*id))
+            .cloned()
+            .collect::<Vec<_>>();
+        if !pinned_ids.is_empty() {
+            info!("Pinned, not rebuilt: {}", pinned_ids.join(", "));
+            sorted_valid_ids.retain(|id| !pins.models.contains(id));
+        }
+        let mut steps = vec![];
+        if sorted_valid_ids.is_empty() {
+            return Ok(ExecutionPlan {
+                graph,
+                steps,
+                full_refresh: options.full_refresh || ephemeral_build || retry,
+                fail_fast: options.fail_fast,
+                progress_bar: options.progress_bar,
+            });
+        }
+
+        let mut used_sources = sorted_valid_ids
+            .iter()
+            .flat_map(|id| node_map[id].sources.iter().cloned())
+            .collect::<Vec<_>>();
+        used_sources.sort();
+        used_sources.dedup();
+        let mut schemas = required_schemas(&sorted_valid_ids, node_map);
+        if self.caches_sources(&used_sources) {
+            schemas.push(CACHE_SCHEMA.to_string());
+        }
+        if !schemas.is_empty() {
+            steps.push(Step::Bootstrap(schemas));
+        }
+        if let Some(hooks) = self.config.on_run_start.clone().filter(|h| !h.is_empty()) {
+            steps.push(Step::RunStartHooks(hooks));
+        }
+        if self.config.sources.is_some() && !used_sources.is_empty() {
+            steps.push(Step::RefreshSourceCaches(used_sources.clone()));
+        }
+        let has_external = sorted_valid_ids.iter().any(|id| node_map[id].is_external());
+        // Artifacts describe the real database, which ephemeral builds leave
+        // untouched
+        let record_provenance = self.config.provenance == Some(true) && !ephemeral_build;
+        steps.extend(sorted_valid_ids.into_iter().map(Step::BuildNode));
+        if !ephemeral_build {
+            steps.push(Step::SaveManifest);
+            steps.push(Step::AnalyzeTables);
+        }
+        if let Some(publisher) = &self.config.catalog_publisher {
+            if has_external {
+                steps.push(Step::PublishTables(publisher.clone()));
+            }
+        }
+        if record_provenance {
+            steps.push(Step::RecordProvenance(used_sources));
+        }
+        if let Some(hooks) = self.config.on_run_end.clone().filter(|h| !h.is_empty()) {
+            steps.push(Step::RunEndHooks(hooks));
+        }
+        if let Some(maintenance) = &self.config.maintenance {
+            if !maintenance.is_empty() {
+                steps.push(Step::RunMaintenance(maintenance.clone()));
+            }
+        }
+        Ok(ExecutionPlan {
+            graph,
+            steps,
+            full_refresh: options.full_refresh || ephemeral_build || retry,
+            fail_fast: options.fail_fast,
+            progress_bar: options.progress_bar,
+        })
+    }
 
-        // TODO: running purpose can also be for visualization
-        // self.run_nodes(&sorted_valid_ids, &node_map)
-        Ok((sorted_valid_ids, node_map))
+    /// Whether any of the given (source name, table name) pairs is cached
+    fn caches_sources(&self, sources: &[(String, String)]) -> bool {
+        let source_infos = self.config.sources.clone().unwrap_or_default();
+        sources.iter().any(|(source_name, table_name)| {
+            source_infos.get(source_name).is_some_and(|source| {
+                source
+                    .table(table_name)
+                    .is_some_and(|table| source.cache_ttl(table).is_some())
+            })
+        })
     }
 
-    pub fn save_visualization(&mut self, path: &str) -> Result<(), ArnabError> {
-        let (sorted_valid_ids, node_map) = self.build_graph()?;
-        let svg = render_dot(&sorted_valid_ids, &node_map);
-        std::fs::write(path, svg).map_err(|e| ArnabError::Error(e.to_string()))
+    /// Create the schemas of all models and the metadata tables of arnab,
+    /// unless they exist
+    pub fn bootstrap(&mut self) -> Result<(), ArnabError> {
+        let graph = self.build_graph()?;
+        let mut schemas = required_schemas(&graph.sorted_ids, &graph.nodes);
+        let all_sources = self
+            .config
+            .sources
+            .iter()
+            .flatten()
+            .flat_map(|(source_name, source)| {
+                source
+                    .tables
+                    .iter()
+                    .map(|table| (source_name.clone(), table.name.clone()))
+            })
+            .collect::<Vec<_>>();
+        if self.caches_sources(&all_sources) {
+            schemas.push(CACHE_SCHEMA.to_string());
+        }
+        let created = bootstrap(&self.db_conn, &schemas)?;
+        for object in &created {
+            println!("Created {}", object);
+        }
+        if created.is_empty() {
+            println!(
+                "Nothing to bootstrap: all {} required schema{} exist",
+                schemas.len(),
+                if schemas.len() == 1 { "" } else { "s" }
+            );
+        }
+        Ok(())
     }
 
-    pub fn run_nodes(
-        &mut self,
-        // sorted_valid_ids: &Vec<String>,
-        // node_map: &HashMap<String, Node>,
-    ) -> Result<(), ArnabError> {
-        let (sorted_valid_ids, node_map) = self.build_graph()?;
-        let now = chrono::Local::now();
-        println!("Start pipeline execution on {}", now.format("%Y-%m-%d"));
+    /// Execute the steps of a plan in order
+    pub fn execute_plan(&mut self, plan: &ExecutionPlan) -> Result<RunSummary, ArnabError> {
+        let graph = &plan.graph;
+        let time_format = self.config.time_format.clone().unwrap_or_default();
+        info!(
+            "Start pipeline execution on {} ({})",
+            time_format.date(chrono::Utc::now()),
+            time_format.timezone_name()
+        );
+        self.events.emit(Event::RunStarted {
+            n_nodes: plan.node_ids().len(),
+        });
 
-        // Main pipeline execution
-        let mut n_execution_success = 0;
-        let mut execution_errors = Vec::new();
-        let mut nth_processed = 1;
         let pipeline_start_time = std::time::Instant::now();
-        for s_id in &sorted_valid_ids {
-            let node = &node_map[s_id];
-
-            let start_time = std::time::Instant::now();
-            let mut status: String;
+        let mut hook_context = HookContext {
+            run_started_at: self.run_started_at.to_rfc3339(),
+            target: self.config.target.clone(),
+            ..Default::default()
+        };
+        let mut summary = RunSummary::default();
+        let mut built_ids = vec![];
+        for batch in plan.batches() {
+            match &batch[0] {
+                Step::Bootstrap(schemas) => {
+                    for created in bootstrap(&self.db_conn, schemas)? {
+                        info!("Bootstrap: created {}", created);
+                    }
+                }
+                Step::RefreshSourceCaches(sources) => self.refresh_source_caches(sources)?,
+                Step::BuildNode(_) => {
+                    let ids = batch
+                        .iter()
+                        .filter_map(|step| match step {
+                            Step::BuildNode(id) => Some(id.clone()),
+                            _ => None,
+                        })
+                        .collect::<Vec<_>>();
+                    // Source caches are refreshed by now, so their age is final
+                    let cache_hits = match plan.full_refresh {
+                        true => BTreeSet::new(),
+                        false => self.cache_hits(graph, &ids)?,
+                    };
+                    let source_ages = upstream_source_ages(
+                        graph,
+                        &source_versions(&self.db_conn),
+                        chrono::Utc::now().timestamp(),
+                    );
+                    let outcome =
+                        self.build_nodes(&ids, plan, &cache_hits, &source_ages, &time_format);
+                    summary.n_success += outcome.built_ids.len();
+                    summary.n_errors += outcome.failed_ids.len();
+                    summary.n_tolerated += outcome.n_tolerated;
+                    summary.n_skipped += outcome.skipped_ids.len();
+                    // The build cache and run results describe the real
+                    // database
+                    if self.config.ephemeral_build.is_some() {
+                        built_ids.extend(outcome.built_ids);
+                        continue;
+                    }
+                    self.update_build_cache(graph, &ids, &outcome.built_ids)?;
+                    let mut last_successful_runs = RunResults::load(&self.config.target_dir())?
+                        .map(|results| results.last_successful_runs)
+                        .unwrap_or_default();
+                    for id in &outcome.built_ids {
+                        last_successful_runs.insert(id.clone(), self.run_started_at);
+                    }
+                    RunResults {
+                        run_started_at: hook_context.run_started_at.clone(),
+                        selected: ids.clone(),
+                        failed: outcome.failed_ids,
+                        skipped: outcome.skipped_ids,
+                        summary: summary.clone(),
+                        last_successful_runs,
+                        upstream_source_ages: ids
+                            .iter()
+                            .filter_map(|id| Some((id.clone(), *source_ages.get(id)?)))
+                            .collect(),
+                        node_stats: outcome.node_stats,
+                    }
+                    .save(&self.config.target_dir())?;
+                    built_ids.extend(outcome.built_ids);
+                }
+                Step::SaveManifest => {
+                    // Ephemeral models are rebuilt as part of the models using them
+                    let mut ids = built_ids.clone();
+                    ids.extend(
+                        graph
+                            .sorted_ids
+                            .iter()
+                            .filter(|id| {
+                                let node = &graph.nodes[id.as_str()];
+                                node.is_ephemeral()
+                                    && node.nexts.iter().any(|next| built_ids.contains(next))
+                            })
+                            .cloned(),
+                    );
+                    let target_dir = self.config.target_dir();
+                    let mut manifest = match target_dir.join(MANIFEST_FILE).exists() {
+                        true => Manifest::load(&target_dir)?,
+                        false => Manifest::from_graph(graph, &[]),
+                    };
+                    manifest.update(graph, &ids);
+                    manifest.save(&target_dir)?;
+                }
+                Step::AnalyzeTables => self.analyze_tables(&built_ids, &graph.nodes)?,
+                Step::PublishTables(publisher) => {
+                    let publisher = HttpPublisher::new(publisher.clone());
+                    self.publish_tables(&built_ids, &graph.nodes, &publisher);
+                }
+                Step::RecordProvenance(sources) => {
+                    let record = ProvenanceRecord {
+                        run_started_at: hook_context.run_started_at.clone(),
+                        n_errors: summary.n_errors,
+                        ..Default::default()
+                    };
+                    self.record_provenance(record, sources, &built_ids, &graph.nodes)?;
+                }
+                Step::RunStartHooks(hooks) => {
+                    run_hooks(&self.db_conn, "on_run_start", hooks, &hook_context)?;
+                }
+                Step::RunEndHooks(hooks) => {
+                    hook_context.n_success = Some(summary.n_success);
+                    hook_context.n_errors = Some(summary.n_errors);
+                    run_hooks(&self.db_conn, "on_run_end", hooks, &hook_context)?;
+                }
+                Step::RunMaintenance(maintenance) => {
+                    self.print_summary(&summary, pipeline_start_time);
+                    info!();
+                    run_maintenance(&self.db_conn, maintenance)?;
+                    return Ok(summary);
+                }
+            }
+        }
+        self.print_summary(&summary, pipeline_start_time);
+        Ok(summary)
+    }
 
-            let now_node = chrono::Local::now();
+    fn print_summary(&mut self, summary: &RunSummary, pipeline_start_time: std::time::Instant) {
+        self.events.emit(Event::RunFinished {
+            n_success: summary.n_success,
+            n_errors: summary.n_errors,
+            n_tolerated: summary.n_tolerated,
+            n_skipped: summary.n_skipped,
+            duration_ms: pipeline_start_time.elapsed().as_millis(),
+        });
+        if !enabled(Verbosity::Quiet) {
+            return;
+        }
+        let mut details = vec![];
+        if summary.n_tolerated > 0 {
+            details.push(format!(
+                "{} tolerated by on_error: continue",
+                summary.n_tolerated
+            ));
+        }
+        if summary.n_skipped > 0 {
+            details.push(format!(
+                "{} nodes skipped by --fail-fast",
+                summary.n_skipped
+            ));
+        }
+        println!(
+            "\nPipeline execution completed in {} with {} success and {} errors{}",
+            format_elapsed(pipeline_start_time.elapsed()),
+            summary.n_success,
+            summary.n_errors,
+            match details.is_empty() {
+                true => String::new(),
+                false => format!(" ({})", details.join(", ")),
+            }
+        );
+    }
 
-            let mut process_info = format!(
-                "{}  {} of {}: creating {} {} model",
-                now_node.format("%H:%M:%S"),
-                nth_processed,
-                sorted_valid_ids.len(),
-                node.id.blue(),
-                node.materialize
-                    .as_ref()
-                    .unwrap_or(&"view".to_string())
-                    .to_lowercase(),
+    /// Build nodes, running up to the configured number of threads
+    /// concurrently, each node waiting for its dependencies among `ids`
+    fn build_nodes(
+        &mut self,
+        ids: &[String],
+        plan: &ExecutionPlan,
+        cache_hits: &BTreeSet<String>,
+        source_ages: &HashMap<String, u64>,
+        time_format: &TimeFormat,
+    ) -> BuildOutcome {
+        let graph = &plan.graph;
+        let node_map = &graph.nodes;
+        let mut max_running = self
+            .config
+            .threads
+            .unwrap_or(ThreadCount::Fixed(1))
+            .resolve(&self.db_conn)
+            .max(1);
+        // With remote workers, each worker builds one node at a time
+        // Workers build into their own database
+        let mut idle_workers = match &self.config.ephemeral_build {
+            Some(_) => vec![],
+            None => self.config.workers.clone().unwrap_or_default(),
+        };
+        if !idle_workers.is_empty() {
+            max_running = idle_workers.len();
+            info!(
+                "Dispatching nodes to {} worker{}",
+                idle_workers.len(),
+                if idle_workers.len() > 1 { "s" } else { "" }
             );
-            // pad with dots to fill terminal width nicely in `n_col` columns
-            let n_col = 80;
-            if process_info.len() < n_col {
-                process_info.extend(std::iter::repeat('.').take(n_col - process_info.len()));
+        } else if max_running > 1 {
+            info!("Running up to {} nodes concurrently", max_running);
+        }
+
+        // On a terminal with nodes running one at a time, the progress line is
+        // printed before execution and completed afterwards. Otherwise, e.g.,
+        // with concurrent nodes or in CI logs, each line is printed once the
+        // node is done, as well as when executed SQL is printed in between.
+        let live_progress = std::io::stdout().is_terminal()
+            && max_running == 1
+            && enabled(Verbosity::Normal)
+            && !enabled(Verbosity::Debug);
+        // The progress bar, if asked for, replaces the live progress line
+        let mut progress_bar =
+            (plan.progress_bar && live_progress).then(|| ProgressBar::new(ids.len()));
+        let live_progress = live_progress && progress_bar.is_none();
+
+        // Nodes are scheduled once all of their selected dependencies are done,
+        // and no other node of their `serialize_with` groups is running
+        let selected = ids.iter().map(|id| id.as_str()).collect::<HashSet<_>>();
+        let mut n_waiting_deps = ids
+            .iter()
+            .map(|id| {
+                let n_deps = node_map[id]
+                    .prevs
+                    .iter()
+                    .filter(|prev| selected.contains(prev.as_str()))
+                    .count();
+                (id.as_str(), n_deps)
+            })
+            .collect::<HashMap<_, _>>();
+        let mut pending = ids.iter().collect::<Vec<_>>();
+        let mut to_retry: Vec<&String> = vec![];
+        let mut process_infos: HashMap<&str, String> = HashMap::new();
+        let mut start_times = HashMap::new();
+        let mut busy_groups: HashSet<&str> = HashSet::new();
+        let mut n_running = 0;
+        // A node with its own settings runs alone, as DuckDB settings and
+        // environment variables are shared by all running nodes
+        let mut running_alone = false;
+        let mut n_dispatched = 0;
+        // With `--fail-fast`, no node is started after a failure
+        let mut aborted = false;
+
+        // Main pipeline execution. Each node runs in its own thread on its own
+        // connection, or on an idle worker, and reports back to this loop,
+        // which is the only one printing progress.
+        let mut execution_errors = Vec::new();
+        let mut n_tolerated = 0;
+        let mut built_ids = vec![];
+        let mut stats_by_node = BTreeMap::new();
+        let (sender, receiver) = mpsc::channel();
+        let worker_token = self.config.worker_token.clone().unwrap_or_default();
+        std::thread::scope(|scope| loop {
+            while n_running < max_running && !aborted && !running_alone {
+                let (id, retry) = if !to_retry.is_empty() {
+                    // Nodes that ran out of memory are retried alone
+                    if n_running > 0 {
+                        break;
+                    }
+                    (to_retry.remove(0), true)
+                } else if let Some(pos) = pending.iter().position(|id| {
+                    n_waiting_deps[id.as_str()] == 0
+                        && (n_running == 0 || !node_map[*id].has_scoped_settings())
+                        && !node_map[*id]
+                            .serialize_with
+                            .iter()
+                            .any(|group| busy_groups.contains(group.as_str()))
+                }) {
+                    (pending.remove(pos), false)
+                } else {
+                    break;
+                };
+                let node = &node_map[id];
+                busy_groups.extend(node.serialize_with.iter().map(|g| g.as_str()));
+                running_alone = node.has_scoped_settings();
+
+                if !retry {
+                    n_dispatched += 1;
+                    let action = match node.node_kind {
+                        NodeKind::Seed => format!("loading {} seed", node.id.blue()),
+                        _ => format!(
+                            "creating {} {} model",
+                            node.id.blue(),
+                            node.materialize.as_deref().unwrap_or("view").to_lowercase()
+                        ),
+                    };
+                    let mut process_info = format!(
+                        "{}  {} of {}: {}",
+                        time_format.time(chrono::Utc::now()),
+                        n_dispatched,
+                        ids.len(),
+                        action,
+                    );
+                    if let Some(progress_bar) = &progress_bar {
+                        progress_bar.draw(&node.id);
+                    } else if live_progress {
+                        // Pad with dots to fill terminal width nicely in `n_col` columns
+                        process_info = fill_with_dots(&process_info, 80);
+                        print!("{}", process_info);
+                        std::io::stdout().flush().unwrap();
+                    }
+                    process_infos.insert(id, process_info);
+                    start_times.insert(id.as_str(), std::time::Instant::now());
+                    self.events.emit(Event::NodeStarted {
+                        node_id: node.id.clone(),
+                        materialize: node.materialize.clone().unwrap_or("view".into()),
+                    });
+                }
+
+                let cached = cache_hits.contains(id);
+                let sender = sender.clone();
+                let conn = self
+                    .db_conn
+                    .try_clone()
+                    .map_err(|e| ArnabError::Error(e.to_string()))
+                    .and_then(|conn| {
+                        if let Some(ephemeral_build) = &self.config.ephemeral_build {
+                            ephemeral_build.apply(&conn)?;
+                        }
+                        Ok(conn)
+                    });
+                // Workers only build SQL models and seeds
+                let worker = match cached || script_kind(&node.node_kind).is_some() {
+                    true => None,
+                    false => idle_workers.pop(),
+                };
+                let worker_token = worker_token.clone();
+                scope.spawn(move || {
+                    let result = match &worker {
+                        _ if cached => Ok(NodeExecutionResult::Sql {
+                            n_rows: None,
+                            stats: vec![],
+                            empty: None,
+                            estimated: false,
+                        }),
+                        Some(worker) => execute_remote(worker, &worker_token, node, retry),
+                        None => conn.and_then(|conn| match retry {
+                            true => node.execute_with_single_thread(&conn),
+                            false => node.execute(&conn),
+                        }),
+                    };
+                    // The receiver lives until all nodes are done
+                    sender.send((id, retry, worker, cached, result)).unwrap();
+                });
+                n_running += 1;
+            }
+            if n_running == 0 {
+                break;
             }
 
-            print!("{}", process_info);
-            std::io::stdout().flush().unwrap();
+            let (id, retried, worker, cached, execution_result) = receiver.recv().unwrap();
+            n_running -= 1;
+            running_alone = false;
+            idle_workers.extend(worker);
+            let node = &node_map[id];
+            for group in &node.serialize_with {
+                busy_groups.remove(group.as_str());
+            }
+            let process_info = process_infos.get_mut(id.as_str()).unwrap();
 
-            // Execute the node
-            let execution_result = node.execute(&self.db_conn);
+            // A node running out of memory is retried once alone, with
+            // DuckDB's own parallelism reduced, which lowers its peak memory
+            // usage. Fewer nodes run concurrently from then on.
+            if let Err(ArnabError::StatementExecutionError { msg, .. }) = &execution_result {
+                if !retried && is_out_of_memory(msg) {
+                    max_running = (max_running / 2).max(1);
+                    if live_progress {
+                        print!("[out of memory, retrying alone with 1 thread] ");
+                        std::io::stdout().flush().unwrap();
+                    } else {
+                        process_info.push_str(" [out of memory, retrying alone with 1 thread]");
+                    }
+                    to_retry.push(id);
+                    continue;
+                }
+            }
 
+            let status: String;
+            let mut node_stats = None;
+            let mut finished_rows = None;
+            let mut finished_error = None;
+            let mut warn_empty = false;
             match execution_result {
+                Ok(_) if cached => {
+                    built_ids.push(id);
+                    status = "SKIPPED cache hit".yellow().to_string();
+                }
                 Ok(execution_result) => {
-                    n_execution_success += 1;
-                    status = "CREATE VIEW".green().to_string();
+                    built_ids.push(id);
                     match execution_result {
-                        NodeExecutionResult::Sql { n_rows } => {
+                        NodeExecutionResult::Sql {
+                            n_rows,
+                            stats,
+                            empty,
+                            estimated,
+                        } => {
+                            let mut status_text = "CREATE VIEW".to_string();
                             if let Some(materialize) = &node.materialize {
                                 if materialize == "table" {
-                                    status = format!("SELECT {}", n_rows).green().to_string();
+                                    status_text = match (n_rows, estimated) {
+                                        (Some(n_rows), true) => format!("SELECT ~{}", n_rows),
+                                        (Some(n_rows), false) => format!("SELECT {}", n_rows),
+                                        (None, _) => "CREATE TABLE".to_string(),
+                                    };
+                                    finished_rows = n_rows;
                                 }
                             }
+                            // Empty builds stand out, as they often reveal a
+                            // failure upstream
+                            status = match empty {
+                                Some(true) => {
+                                    warn_empty = node.on_empty == OnEmpty::Warn;
+                                    format!("{} (empty)", status_text).yellow().to_string()
+                                }
+                                _ => status_text.green().to_string(),
+                            };
+                            // Only report the collected statistics when customized
+                            if node.stats_sql.is_some() {
+                                node_stats = Some(stats);
+                            }
                         }
                     }
                 }
+                Err(e) if node.on_error == OnError::Continue => {
+                    status = "ERROR (tolerated)".yellow().to_string();
+                    finished_error = Some(e.message());
+                    execution_errors.push((id.clone(), e));
+                    n_tolerated += 1;
+                }
                 Err(e) => {
                     status = "ERROR".red().to_string();
-                    execution_errors.push(e);
+                    finished_error = Some(e.message());
+                    execution_errors.push((id.clone(), e));
+                    aborted = plan.fail_fast;
                 }
             };
-            println!("[{} in {}]", status, format_elapsed(start_time.elapsed()));
+            let elapsed = start_times[id.as_str()].elapsed();
+            self.events.emit(Event::NodeFinished {
+                node_id: node.id.clone(),
+                status: match finished_error {
+                    Some(_) => NodeStatus::Error,
+                    None => NodeStatus::Success,
+                },
+                duration_ms: elapsed.as_millis(),
+                n_rows: finished_rows,
+                error: finished_error,
+                upstream_source_age_s: source_ages.get(id).copied(),
+                stats: node_stats
+                    .as_ref()
+                    .map(|stats| stats.iter().cloned().collect()),
+            });
+            let mut status_info = match cached {
+                true => format!("[{}]", status),
+                false => format!("[{} in {}]", status, format_elapsed(elapsed)),
+            };
+            // Flags builds on stale inputs, whatever their own status
+            if let Some(age) = source_ages.get(id) {
+                let freshness = format!("[upstream sources {} old]", format_age(*age));
+                status_info.push_str(&format!(" {}", freshness.yellow()));
+            }
+            if let Some(progress_bar) = &mut progress_bar {
+                progress_bar.clear();
+                progress_bar.finish_node();
+            }
+            if live_progress {
+                println!("{}", status_info);
+            } else {
+                info!("{} {}", process_info, status_info);
+            }
+            if let Some(stats) = node_stats {
+                info!(
+                    "          stats: {}",
+                    stats
+                        .iter()
+                        .map(|(k, v)| format!("{}={}", k, v))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+                stats_by_node.insert(id.clone(), stats.into_iter().collect());
+            }
+            if warn_empty {
+                self.warn(format!("model {} built no rows", node.id));
+            }
+
+            for next in &node.nexts {
+                if let Some(n_deps) = n_waiting_deps.get_mut(next.as_str()) {
+                    *n_deps -= 1;
+                }
+            }
+        });
 
-            nth_processed += 1;
+        let skipped_ids = to_retry
+            .into_iter()
+            .chain(pending)
+            .cloned()
+            .collect::<Vec<_>>();
+        if !skipped_ids.is_empty() && enabled(Verbosity::Quiet) {
+            println!(
+                "\nStopped after the first failure (--fail-fast): {} node{} not built",
+                skipped_ids.len(),
+                if skipped_ids.len() > 1 { "s" } else { "" }
+            );
         }
 
-        if !execution_errors.is_empty() {
+        // Errors are detailed in the events of failed nodes when silent
+        if !execution_errors.is_empty() && enabled(Verbosity::Quiet) {
+            // Models failing downstream of a failed model most likely fail
+            // because of it, so only the errors of root causes are detailed
+            let failed_ids = execution_errors
+                .iter()
+                .map(|(id, _)| id.clone())
+                .collect::<Vec<_>>();
             println!("\nErrors:");
-            for err in &execution_errors {
+            for (root_id, downstream) in graph.failure_groups(&failed_ids) {
+                let (_, err) = execution_errors
+                    .iter()
+                    .find(|(id, _)| *id == root_id)
+                    .unwrap();
                 match err {
                     ArnabError::StatementExecutionError { msg, sql: _, path } => {
                         println!("Failed to execute SQL statement.");
                         println!("Source path : {}", path);
-                        println!("Error       : {}\n", msg.red());
+                        println!("Error       : {}", msg.red());
                     }
-                    _ => println!("{}\n", err),
+                    _ => println!("{}", err),
+                }
+                if !downstream.is_empty() {
+                    println!(
+                        "{} model{} failed downstream of {}: {}",
+                        downstream.len(),
+                        if downstream.len() > 1 { "s" } else { "" },
+                        root_id.blue(),
+                        downstream.join(", ")
+                    );
+                }
+                println!();
+            }
+        }
+
+        BuildOutcome {
+            built_ids: built_ids.into_iter().cloned().collect(),
+            failed_ids: execution_errors.iter().map(|(id, _)| id.clone()).collect(),
+            n_tolerated,
+            skipped_ids,
+            node_stats: stats_by_node,
+        }
+    }
+}
+
+impl Session {
+    /// Complete a provenance record with the state of the project and the
+    /// fingerprints of the read sources and built tables, then append it
+    fn record_provenance(
+        &self,
+        mut record: ProvenanceRecord,
+        sources: &[(String, String)],
+        built_ids: &[String],
+        node_map: &HashMap<String, Node>,
+    ) -> Result<(), ArnabError> {
+        (record.git_commit, record.git_dirty) = git_state();
+        record.arnab_version = env!("CARGO_PKG_VERSION").to_string();
+        record.config_hash.clone_from(&self.config.config_hash);
+        record.target.clone_from(&self.config.target);
+        let source_infos = self.config.sources.clone().unwrap_or_default();
+        for (source_name, table_name) in sources {
+            let Some(source) = source_infos.get(source_name) else {
+                continue;
+            };
+            let Some(table) = source.table(table_name) else {
+                continue;
+            };
+            let relation = source.resolved_relation(source_name, table);
+            record.sources.insert(
+                format!("{}.{}", source_name, table_name),
+                fingerprint(&self.db_conn, &relation)?,
+            );
+        }
+        for id in built_ids {
+            let node = &node_map[id];
+            if node.is_table() {
+                let relation = node.relation(true);
+                record
+                    .outputs
+                    .insert(id.clone(), fingerprint(&self.db_conn, &relation)?);
+            }
+        }
+        record.finished_at = iso_now();
+        let path = record.append(&self.config.target_dir())?;
+        info!("Recorded run provenance in {}", path.display());
+        Ok(())
+    }
+
+    /// Table models among `ids` whose cache key matches their last
+    /// successful build
+    fn cache_hits(&self, graph: &Graph, ids: &[String]) -> Result<BTreeSet<String>, ArnabError> {
+        let cache = BuildCache::load(&self.config.target_dir())?;
+        let keys = cache_keys(
+            graph,
+            &source_versions(&self.db_conn),
+            &self.cache_context(),
+        );
+        // A cache hit is kept as long as its relation still exists
+        Ok(kept_builds(graph, ids, |id| {
+            keys[id].is_some()
+                && keys[id].as_ref() == cache.keys.get(id)
+                && graph.nodes[id].relation_exists(&self.db_conn)
+        }))
+    }
+
+    /// Databases the models are built into, part of the cache keys, as
+    /// several targets may share the same `target_path`
+    fn cache_context(&self) -> String {
+        format!(
+            "target {:?}\ndb_path {:?}\nartifacts_db_path {:?}",
+            self.config.target, self.config.db_path, self.config.artifacts_db_path
+        )
+    }
+
+    /// Record the cache keys of the table models built (or kept) in a batch,
+    /// and forget those of the models that failed
+    fn update_build_cache(
+        &self,
+        graph: &Graph,
+        ids: &[String],
+        built_ids: &[String],
+    ) -> Result<(), ArnabError> {
+        let cacheable_ids = ids
+            .iter()
+            .filter(|id| is_cacheable(&graph.nodes[*id]))
+            .collect::<Vec<_>>();
+        if cacheable_ids.is_empty() {
+            return Ok(());
+        }
+        let target_dir = self.config.target_dir();
+        let mut cache = BuildCache::load(&target_dir)?;
+        let keys = cache_keys(
+            graph,
+            &source_versions(&self.db_conn),
+            &self.cache_context(),
+        );
+        for id in cacheable_ids {
+            match &keys[id] {
+                Some(key) if built_ids.contains(id) => {
+                    cache.keys.insert(id.clone(), key.clone());
+                }
+                _ => {
+                    cache.keys.remove(id);
                 }
             }
         }
+        cache.save(&target_dir)
+    }
+}
+
+/// Result of building a batch of nodes
+struct BuildOutcome {
+    built_ids: Vec<String>,
+    failed_ids: Vec<String>,
+    n_tolerated: usize,
+    skipped_ids: Vec<String>,
+    /// Statistics of the built nodes with a `stats_sql`
+    node_stats: BTreeMap<String, BTreeMap<String, String>>,
+}
 
+impl Session {
+    /// Run the data tests of the selected models against their built
+    /// relations: schema tests, and singular tests referring to them
+    pub fn run_tests(&mut self, selection: &Selection) -> Result<TestSummary, ArnabError> {
+        let graph = self.build_graph()?;
+        let selected_ids = self.select(&graph, selection)?;
+        let mut tests = vec![];
+        for id in &selected_ids {
+            let node = &graph.nodes[id];
+            if node.is_ephemeral() {
+                continue;
+            }
+            if let Some(schema) = &node.schema {
+                tests.extend(schema_tests(id, schema));
+            }
+        }
+
+        let tests_path = self.config.tests_path.as_deref().unwrap_or("tests");
+        let context = self.render_context()?;
+        let sorted_position = graph.sorted_positions();
+        for mut test_node in singular_tests(tests_path, &context, &graph.sorted_ids)? {
+            let ephemerals = ephemeral_ancestors(&test_node.prevs, &graph.nodes, &sorted_position);
+            test_node.inline_ephemerals(&ephemeral_ctes(&ephemerals)?);
+            // Without selection, tests not referring to any model still run
+            let is_selected = if selection.select.is_empty() && selection.exclude.is_empty() {
+                true
+            } else {
+                test_node.prevs.iter().any(|id| selected_ids.contains(id))
+            };
+            if is_selected {
+                tests.push(DataTest {
+                    name: test_node.fqn.clone(),
+                    sql: test_node
+                        .rendered_src
+                        .trim()
+                        .trim_end_matches(';')
+                        .to_string(),
+                    model: None,
+                    column: None,
+                });
+            }
+        }
+        if tests.is_empty() {
+            println!("No test found");
+            return Ok(TestSummary::default());
+        }
+
+        info!(
+            "Running {} test{}",
+            tests.len(),
+            if tests.len() > 1 { "s" } else { "" }
+        );
+        let summary = run_tests(&self.db_conn, &tests);
+        TestResults {
+            generated_at: iso_now(),
+            results: &summary.results,
+        }
+        .save(&self.config.target_dir())?;
         println!(
-            "\nPipeline execution completed in {} with {} success and {} errors",
-            format_elapsed(pipeline_start_time.elapsed()),
-            n_execution_success,
-            execution_errors.len()
+            "\nTests completed with {} passed and {} failed",
+            summary.n_passed, summary.n_failed
         );
-        Ok(())
+        Ok(summary)
     }
 }
 
-fn topo(root_id: &String, nodes: &HashMap<String, Node>, out: &mut Vec<String>) {
-    if out.contains(root_id) {
-        return;
+/// Pair each model path with the path of the source to use for the current
+/// target. `orders.prod.sql` overrides `orders.sql` when the target is
+/// `prod`, and is ignored otherwise, provided `prod` is one of
+/// `known_targets`. Overrides must have a default model, so that all targets
+/// share the same graph.
+fn resolve_target_overrides(
+    paths: Vec<PathBuf>,
+    target: Option<&str>,
+    known_targets: &HashSet<&str>,
+) -> Result<Vec<(PathBuf, PathBuf)>, ArnabError> {
+    // Files are matched by their path without extension, so that
+    // `orders.prod.sql` can override `orders.sql.jinja` and vice versa
+    let mut defaults = vec![];
+    let mut default_bases = HashMap::new();
+    let mut override_paths = vec![];
+    for path in paths {
+        let (base, _) = split_model_path(&path);
+        let stem = base.file_name().unwrap().to_string_lossy().to_string();
+        match stem
+            .rsplit_once('.')
+            .filter(|(_, suffix)| known_targets.contains(suffix))
+        {
+            Some((id, override_target)) => {
+                override_paths.push((base.with_file_name(id), override_target.to_string(), path))
+            }
+            None => {
+                default_bases.insert(base, path.clone());
+                defaults.push(path);
+            }
+        }
+    }
+    // Likely overrides for a target missing from the config
+    for (base, path) in &default_bases {
+        let stem = base.file_name().unwrap().to_string_lossy();
+        if let Some((id, suffix)) = stem.rsplit_once('.') {
+            if default_bases.contains_key(&base.with_file_name(id)) {
+                println!(
+                    "WARNING: {} is a model of its own, as `{}` is not a target of the config",
+                    path.display(),
+                    suffix
+                );
+            }
+        }
     }
 
-    let root_node = &nodes[root_id];
-    for prev_id in &root_node.prevs {
-        topo(prev_id, nodes, out);
+    let mut overrides = HashMap::new();
+    for (default_base, override_target, path) in override_paths {
+        let Some(default_path) = default_bases.get(&default_base) else {
+            return Err(ArnabError::Error(format!(
+                "{} overrides model `{}` for target `{}`, but there is no default {}.sql",
+                path.display(),
+                default_base.file_name().unwrap().to_string_lossy(),
+                override_target,
+                default_base.display()
+            )));
+        };
+        if Some(override_target.as_str()) == target {
+            overrides.insert(default_path.clone(), path);
+        }
     }
-    out.push(root_id.clone())
+    Ok(defaults
+        .into_iter()
+        .map(|path| {
+            let src_path = overrides.remove(&path).unwrap_or_else(|| path.clone());
+            (path, src_path)
+        })
+        .collect())
 }
 
-fn format_elapsed(elapsed: std::time::Duration) -> String {
-    let hours = elapsed.as_secs() / 3600;
-    let minutes = (elapsed.as_secs() % 3600) / 60;
-    let seconds = elapsed.as_secs() % 60;
-    let milliseconds = elapsed.subsec_millis();
+impl Session {
+    /// Configuration of a model: its own, completed with the defaults of the
+    /// groups of its directories, from the deepest one up
+    fn model_info(&self, id: &str, fqn: &str) -> Option<ModelInfo> {
+        let mut model_info = self.config.models.as_ref().and_then(|m| m.get(id)).cloned();
+        let groups = self
+            .config
+            .groups
+            .iter()
+            .flatten()
+            .collect::<HashMap<_, _>>();
+        let fqn_parts = fqn.split('.').collect::<Vec<_>>();
+        for depth in (1..fqn_parts.len()).rev() {
+            if let Some(defaults) = groups.get(&fqn_parts[..depth].join(".")) {
+                model_info = Some(model_info.unwrap_or_default().with_defaults(defaults));
+            }
+        }
+        model_info
+    }
+}
 
-    let mut components = Vec::new();
+/// Print the header and rows of a preview, with the age of cached ones
+fn print_preview(lines: &[Vec<String>], limit: usize, cached_age: Option<Duration>) {
+    let n_rows = lines.len() - 1;
+    println!("{}", align_columns(lines));
+    println!(
+        "\n{} row{} shown (limit {}){}",
+        n_rows,
+        if n_rows == 1 { "" } else { "s" },
+        limit,
+        match cached_age {
+            Some(age) => format!(
+                ", cached {} ago, pass --no-cache to refresh",
+                format_elapsed(age)
+            ),
+            None => "".into(),
+        }
+    );
+}
+
+/// Fully-qualified name of a model: its path relative to the models directory,
+/// without extension, joined by dots. `base_path` is the model path without
+/// extension.
+fn model_fqn(models_dir: &str, base_path: &std::path::Path) -> String {
+    let relative = base_path.strip_prefix(models_dir).unwrap_or(base_path);
+    relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .filter(|c| c != ".")
+        .collect::<Vec<_>>()
+        .join(".")
+}
 
-    if hours > 0 {
-        components.push(format!("{}h", hours));
+/// Name of the kind of models run as external programs, if the node is one
+fn script_kind(kind: &NodeKind) -> Option<&'static str> {
+    match kind {
+        NodeKind::Python => Some("Python"),
+        NodeKind::Shell => Some("Shell"),
+        _ => None,
     }
-    if minutes > 0 {
-        components.push(format!("{}m", minutes));
+}
+
+impl Session {
+    /// Print a warning, unless silent, and emit it as an event
+    fn warn(&mut self, message: String) {
+        if enabled(Verbosity::Quiet) {
+            println!("WARNING: {}", message);
+        }
+        self.events.emit(Event::Warning { message });
     }
-    if seconds > 0 {
-        components.push(format!("{}s", seconds));
+
+    /// Analyze the built table models that opted in, and record their
+    /// statistics in the catalog, next to those of previous runs
+    fn analyze_tables(
+        &mut self,
+        built_ids: &[String],
+        node_map: &HashMap<String, Node>,
+    ) -> Result<(), ArnabError> {
+        let nodes = built_ids
+            .iter()
+            .map(|id| &node_map[id])
+            .filter(|node| node.analyze && node.materialize.as_deref() == Some("table"))
+            .collect::<Vec<_>>();
+        if nodes.is_empty() {
+            return Ok(());
+        }
+
+        let target_dir = self.config.target_dir();
+        let mut catalog = Catalog::load(&target_dir)?;
+        for node in nodes {
+            match analyze_table(&self.db_conn, node) {
+                Ok(catalog_node) => {
+                    catalog.nodes.insert(node.id.clone(), catalog_node);
+                }
+                Err(e) => self.warn(format!("Failed to analyze {}: {}", node.id, e.message())),
+            }
+        }
+        catalog.save(&target_dir)
+    }
+
+    /// Register the built external models in a catalog. Failures are
+    /// reported without failing the run, as the models themselves are built.
+    fn publish_tables(
+        &mut self,
+        built_ids: &[String],
+        node_map: &HashMap<String, Node>,
+        publisher: &dyn CatalogPublisher,
+    ) {
+        let nodes = built_ids.iter().map(|id| &node_map[id]);
+        for node in nodes.filter(|node| node.is_external()) {
+            let external = node.external.as_ref().unwrap();
+            let table = describe_relation(&self.db_conn, &node.id).map(|columns| PublishedTable {
+                name: node.id.clone(),
+                location: external.location.clone(),
+                format: external.format(),
+                columns: columns
+                    .into_iter()
+                    .map(|(name, data_type)| PublishedColumn { name, data_type })
+                    .collect(),
+                built_at: iso_now(),
+            });
+            match table
+                .map_err(|e| e.message())
+                .and_then(|t| publisher.publish(&t))
+            {
+                Ok(_) => info!("Published {} to the catalog", node.id),
+                Err(e) => self.warn(format!("Failed to publish {}: {}", node.id, e)),
+            }
+        }
+    }
+
+    /// Inputs of model rendering: user-defined macros, sources and target
+    fn render_context(&self) -> Result<RenderContext, ArnabError> {
+        let macros = match &self.config.macro_path {
+            Some(macro_path) => load_macros(macro_path)?,
+            None => HashMap::new(),
+        };
+        Ok(RenderContext {
+            macros,
+            sources: self.config.sources.clone().unwrap_or_default(),
+            target: self.config.target.clone(),
+            vars: self.config.vars.clone().unwrap_or_default(),
+            run_started_at: self.run_started_at,
+            last_successful_runs: RunResults::load(&self.config.target_dir())?
+                .map(|results| results.last_successful_runs.into_iter().collect())
+                .unwrap_or_default(),
+        })
     }
 
-    // always show milliseconds
-    components.push(format!("{}ms", milliseconds));
+    /// Refresh expired caches of the given source tables
+    fn refresh_source_caches(&self, used_sources: &[(String, String)]) -> Result<(), ArnabError> {
+        let Some(sources) = &self.config.sources else {
+            return Ok(());
+        };
+        for (source_name, table_name) in used_sources {
+            let source = &sources[source_name];
+            let table = source.table(table_name).unwrap();
+            if refresh_cache(&self.db_conn, source_name, source, table)? {
+                info!("Refreshed cache of source {}.{}", source_name, table_name);
+            } else if source.cache_ttl(table).is_some() {
+                verbose!("Cache of source {}.{} is fresh", source_name, table_name);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Ephemeral models a node depends on, directly or through other ephemeral models
+fn collect_ephemeral_ancestors(
+    prevs: &HashSet<String>,
+    nodes: &HashMap<String, Node>,
+    out: &mut Vec<String>,
+) {
+    for prev_id in prevs {
+        match nodes.get(prev_id) {
+            Some(prev) if prev.is_ephemeral() && !out.contains(prev_id) => {
+                out.push(prev_id.clone());
+                collect_ephemeral_ancestors(&prev.prevs, nodes, out);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Ephemeral models to inline into a node with the given dependencies, in
+/// topological order
+fn ephemeral_ancestors<'a>(
+    prevs: &HashSet<String>,
+    nodes: &'a HashMap<String, Node>,
+    sorted_position: &HashMap<String, usize>,
+) -> Vec<&'a Node> {
+    let mut ephemeral_ids = vec![];
+    collect_ephemeral_ancestors(prevs, nodes, &mut ephemeral_ids);
+    ephemeral_ids.sort_by_key(|id| sorted_position.get(id));
+    ephemeral_ids.iter().map(|id| &nodes[id]).collect()
+}
 
-    components.join(" ")
+/// (id, SQL) pairs of ephemeral models, as inlined by `Node::inline_ephemerals`
+fn ephemeral_ctes(ephemerals: &[&Node]) -> Result<Vec<(String, String)>, ArnabError> {
+    ephemerals
+        .iter()
+        .map(|node| Ok((node.id.clone(), node.ephemeral_sql()?)))
+        .collect()
 }
@@ -0,0 +1,186 @@
+use std::collections::{BTreeMap, HashSet};
+
+use duckdb::Connection;
+
+use crate::{errors::ArnabError, session::Config};
+
+/// Resolve the DuckDB settings for a command. Settings of the current target
+/// override the global `duckdb_settings`, and command-specific settings
+/// override both.
+pub fn resolve_settings(config: &Config, command: &str) -> BTreeMap<String, String> {
+    let mut settings = BTreeMap::new();
+    settings.extend(config.duckdb_settings.clone().unwrap_or_default());
+    if let Some(target) = config.target_info() {
+        settings.extend(target.duckdb_settings.clone().unwrap_or_default());
+    }
+    if let Some(command_settings) = config
+        .command_duckdb_settings
+        .as_ref()
+        .and_then(|s| s.get(command))
+    {
+        settings.extend(command_settings.clone());
+    }
+    settings
+}
+
+/// Validate and apply DuckDB settings. All problems are reported at once:
+/// unknown keys (with suggestions of known ones) and rejected values.
+pub fn apply_settings(
+    conn: &Connection,
+    settings: &BTreeMap<String, String>,
+) -> Result<(), Vec<String>> {
+    let known_settings = known_settings(conn).map_err(|e| vec![e.to_string()])?;
+
+    let mut problems = vec![];
+    for (key, value) in settings {
+        if !known_settings.contains(&key.to_lowercase()) {
+            let problem = match suggest(key, &known_settings) {
+                Some(suggestion) => format!(
+                    "Unknown DuckDB setting `{}`, did you mean `{}`?",
+                    key, suggestion
+                ),
+                None => format!("Unknown DuckDB setting `{}`", key),
+            };
+            problems.push(problem);
+            continue;
+        }
+
+        let sql = format!("SET {} = '{}';", key, value.replace('\'', "''"));
+        if let Err(e) = conn.execute_batch(&sql) {
+            problems.push(format!("Cannot set `{}` to `{}`: {}", key, value, e));
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems)
+    }
+}
+
+/// Run `f` with DuckDB settings and environment variables applied, then
+/// restore their previous values, whether `f` succeeds or not. Settings and
+/// environment variables are shared by concurrently running nodes, so nodes
+/// with their own are run alone.
+pub fn with_scoped_settings<T>(
+    conn: &Connection,
+    settings: &BTreeMap<String, String>,
+    env_vars: &BTreeMap<String, String>,
+    f: impl FnOnce() -> Result<T, ArnabError>,
+) -> Result<T, ArnabError> {
+    if settings.is_empty() && env_vars.is_empty() {
+        return f();
+    }
+
+    let previous_settings = settings
+        .keys()
+        .filter_map(|key| {
+            let value = conn
+                .query_row("SELECT current_setting(?)::VARCHAR", [key], |r| {
+                    r.get::<_, String>(0)
+                })
+                .ok()?;
+            Some((key.clone(), value))
+        })
+        .collect::<BTreeMap<_, _>>();
+    let previous_env_vars = env_vars
+        .keys()
+        .map(|key| (key.clone(), std::env::var(key).ok()))
+        .collect::<Vec<_>>();
+
+    for (key, value) in env_vars {
+        std::env::set_var(key, value);
+    }
+    let result = match apply_settings(conn, settings) {
+        Ok(()) => f(),
+        Err(problems) => Err(ArnabError::Error(problems.join("\n"))),
+    };
+
+    for (key, value) in previous_env_vars {
+        match value {
+            Some(value) => std::env::set_var(&key, value),
+            None => std::env::remove_var(&key),
+        }
+    }
+    if let Err(problems) = apply_settings(conn, &previous_settings) {
+        println!(
+            "WARNING: Failed to restore DuckDB settings: {}",
+            problems.join(", ")
+        );
+    }
+    result
+}
+
+fn known_settings(conn: &Connection) -> Result<HashSet<String>, duckdb::Error> {
+    let mut stmt = conn.prepare("SELECT lower(name) FROM duckdb_settings()")?;
+    let names = stmt
+        .query_map([], |r| r.get::<_, String>(0))?
+        .collect::<Result<HashSet<_>, _>>()?;
+    Ok(names)
+}
+
+/// The closest known setting, if close enough to be a typo
+fn suggest<'a>(key: &str, known_settings: &'a HashSet<String>) -> Option<&'a str> {
+    let key = key.to_lowercase();
+    known_settings
+        .iter()
+        .map(|name| (edit_distance(&key, name), name))
+        .filter(|(distance, _)| *distance <= 3)
+        .min()
+        .map(|(_, name)| name.as_str())
+}
+
+/// Levenshtein distance between two strings
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b_chars = b.chars().collect::<Vec<_>>();
+    let mut prev_row = (0..=b_chars.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut row = vec![i + 1];
+        for (j, cb) in b_chars.iter().enumerate() {
+            let substitution = prev_row[j] + usize::from(ca != *cb);
+            row.push(substitution.min(prev_row[j + 1] + 1).min(row[j] + 1));
+        }
+        prev_row = row;
+    }
+    prev_row[b_chars.len()]
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{BTreeMap, HashSet};
+
+    use duckdb::Connection;
+
+    use super::{edit_distance, suggest, with_scoped_settings};
+
+    #[test]
+    fn suggest_close_setting() {
+        assert_eq!(edit_distance("memory_limt", "memory_limit"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+
+        let known = HashSet::from(["memory_limit".to_string(), "threads".to_string()]);
+        assert_eq!(suggest("Memory_Limt", &known), Some("memory_limit"));
+        assert_eq!(suggest("thread", &known), Some("threads"));
+        assert_eq!(suggest("something_else", &known), None);
+    }
+
+    #[test]
+    fn restore_scoped_settings() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("SET threads = 3;").unwrap();
+        let settings = BTreeMap::from([("threads".to_string(), "1".to_string())]);
+        let env_vars = BTreeMap::from([("ARNAB_TEST_SCOPED".to_string(), "on".to_string())]);
+        let threads = |conn: &Connection| -> i64 {
+            conn.query_row("SELECT current_setting('threads')", [], |r| r.get(0))
+                .unwrap()
+        };
+
+        let inside = with_scoped_settings(&conn, &settings, &env_vars, || {
+            Ok((threads(&conn), std::env::var("ARNAB_TEST_SCOPED").ok()))
+        })
+        .unwrap();
+        assert_eq!(inside, (1, Some("on".to_string())));
+        assert_eq!(threads(&conn), 3);
+        assert!(std::env::var("ARNAB_TEST_SCOPED").is_err());
+    }
+}
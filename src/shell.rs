@@ -0,0 +1,72 @@
+//! Shell-script models: `.sh` files run with `sh`, e.g., ingestion steps
+//! downloading data. As arnab holds the database while it runs, scripts don't
+//! open it themselves: whatever they print on stdout is executed as SQL,
+//! which must create or replace the model's relation.
+
+use std::{collections::HashSet, process::Command};
+
+use regex::Regex;
+
+use crate::{errors::ArnabError, node::Node};
+
+/// Models a shell model depends on, declared in comment lines such as
+/// `# depends_on: orders, users`
+pub fn shell_references(src: &str) -> HashSet<String> {
+    let re = Regex::new(r"(?m)^#\s*depends_on:(.*)$").unwrap();
+    re.captures_iter(src)
+        .flat_map(|c| {
+            c[1].split(',')
+                .map(|id| id.trim().to_string())
+                .filter(|id| !id.is_empty())
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Run a shell model, returning the SQL it printed. The script gets the
+/// model's id in `ARNAB_MODEL` and the database path in `ARNAB_DB_PATH`,
+/// next to the node's environment variables.
+pub fn run_shell_model(node: &Node) -> Result<String, ArnabError> {
+    let command = format!("sh {}", node.path);
+    let mut shell = Command::new("sh");
+    shell
+        .arg(&node.path)
+        .envs(&node.env_vars)
+        .env("ARNAB_MODEL", &node.id);
+    if let Some(db_path) = &node.db_path {
+        shell.env("ARNAB_DB_PATH", db_path);
+    }
+    let output = shell
+        .output()
+        .map_err(|e| ArnabError::StatementExecutionError {
+            msg: e.to_string(),
+            sql: command.clone(),
+            path: node.path.clone(),
+        })?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(ArnabError::StatementExecutionError {
+            msg: format!("{}\n{}", output.status, stderr.trim()),
+            sql: command,
+            path: node.path.clone(),
+        });
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::shell_references;
+
+    #[test]
+    fn parse_shell_dependencies() {
+        let src = "#!/bin/sh\n# depends_on: orders, users\n# depends_on: tiers\n\
+                   echo \"CREATE OR REPLACE TABLE $ARNAB_MODEL AS SELECT 1\"\n";
+        assert_eq!(
+            shell_references(src),
+            HashSet::from(["orders".into(), "users".into(), "tiers".into()])
+        );
+    }
+}
@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+
+use duckdb::Connection;
+use serde::Deserialize;
+
+use crate::{errors::ArnabError, graph::Graph};
+
+/// Schema holding cached copies of source tables
+pub const CACHE_SCHEMA: &str = "arnab_cache";
+
+/// A group of external tables, e.g., tables loaded by an ingestion tool or
+/// attached files, declared under `sources` in the configuration
+#[derive(Clone, Debug, Deserialize)]
+pub struct SourceInfo {
+    /// Schema (or attached database) containing the tables
+    pub(crate) schema: Option<String>,
+    /// Time-to-live of local cached copies of the tables, e.g., `30m` or `1d`.
+    /// Tables are read directly if unset.
+    pub(crate) cache: Option<String>,
+    pub(crate) tables: Vec<SourceTable>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SourceTable {
+    pub(crate) name: String,
+    /// Relation to read from instead of `schema.name`, e.g.,
+    /// `read_parquet('data/events/*.parquet')`
+    pub(crate) relation: Option<String>,
+    /// Overrides the source's cache time-to-live for this table
+    pub(crate) cache: Option<String>,
+}
+
+impl SourceInfo {
+    pub fn table(&self, name: &str) -> Option<&SourceTable> {
+        self.tables.iter().find(|t| t.name == name)
+    }
+
+    /// Fully-qualified relation of a source table
+    pub fn relation(&self, table: &SourceTable) -> String {
+        match (&table.relation, &self.schema) {
+            (Some(relation), _) => relation.clone(),
+            (None, Some(schema)) => format!("{}.{}", schema, table.name),
+            (None, None) => table.name.clone(),
+        }
+    }
+
+    pub fn cache_ttl<'a>(&'a self, table: &'a SourceTable) -> Option<&'a str> {
+        table.cache.as_deref().or(self.cache.as_deref())
+    }
+
+    /// Relation models read a source table from: its cached copy if caching
+    /// is enabled, or the source relation itself otherwise
+    pub fn resolved_relation(&self, source_name: &str, table: &SourceTable) -> String {
+        match self.cache_ttl(table) {
+            Some(_) => cache_relation(source_name, &table.name),
+            None => self.relation(table),
+        }
+    }
+}
+
+/// Creation of the metadata table recording when each cached source table
+/// was refreshed, with its schema
+pub fn cache_entries_sql() -> String {
+    format!(
+        "CREATE SCHEMA IF NOT EXISTS {schema};
+         CREATE TABLE IF NOT EXISTS {schema}.cache_entries (relation VARCHAR PRIMARY KEY, cached_at BIGINT);",
+        schema = CACHE_SCHEMA
+    )
+}
+
+pub fn cache_relation(source_name: &str, table_name: &str) -> String {
+    format!("{}.{}__{}", CACHE_SCHEMA, source_name, table_name)
+}
+
+/// Time each cached source table was last refreshed, by cache relation
+pub fn source_versions(conn: &Connection) -> HashMap<String, i64> {
+    let sql = format!(
+        "SELECT relation, cached_at FROM {}.cache_entries",
+        CACHE_SCHEMA
+    );
+    let Ok(mut stmt) = conn.prepare(&sql) else {
+        return HashMap::new();
+    };
+    stmt.query_map([], |r| Ok((r.get(0)?, r.get(1)?)))
+        .map(|rows| rows.flatten().collect())
+        .unwrap_or_default()
+}
+
+/// Age in seconds of the oldest cached source table upstream of each node,
+/// read directly or through its dependencies, given the refresh times of
+/// `source_versions`. Nodes with no cached source upstream are left out.
+pub fn upstream_source_ages(
+    graph: &Graph,
+    versions: &HashMap<String, i64>,
+    now: i64,
+) -> HashMap<String, u64> {
+    let mut ages: HashMap<String, u64> = HashMap::new();
+    for id in &graph.sorted_ids {
+        let node = &graph.nodes[id];
+        let own = node.sources.iter().filter_map(|(source, table)| {
+            versions
+                .get(&cache_relation(source, table))
+                .map(|cached_at| (now - cached_at).max(0) as u64)
+        });
+        let upstream = node.prevs.iter().filter_map(|prev| ages.get(prev).copied());
+        if let Some(age) = own.chain(upstream).max() {
+            ages.insert(id.clone(), age);
+        }
+    }
+    ages
+}
+
+/// Parse a time-to-live such as `90s`, `30m`, `12h` or `7d`
+pub fn parse_ttl(ttl: &str) -> Result<std::time::Duration, String> {
+    let ttl = ttl.trim();
+    let split_at = ttl.find(|c: char| !c.is_ascii_digit()).unwrap_or(ttl.len());
+    let (number, unit) = ttl.split_at(split_at);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("Invalid cache time-to-live `{}`", ttl))?;
+    let seconds = match unit.trim() {
+        "s" => number,
+        "m" => number * 60,
+        "h" => number * 3600,
+        "d" => number * 86400,
+        _ => return Err(format!("Invalid cache time-to-live `{}`", ttl)),
+    };
+    Ok(std::time::Duration::from_secs(seconds))
+}
+
+/// Materialize a cached source table into a local table unless the existing
+/// copy is younger than its time-to-live. Returns whether the cache was
+/// refreshed.
+pub fn refresh_cache(
+    conn: &Connection,
+    source_name: &str,
+    source: &SourceInfo,
+    table: &SourceTable,
+) -> Result<bool, ArnabError> {
+    let Some(ttl) = source.cache_ttl(table) else {
+        return Ok(false);
+    };
+    let ttl = parse_ttl(ttl).map_err(ArnabError::Error)?;
+    let cached = cache_relation(source_name, &table.name);
+    let to_error =
+        |e: duckdb::Error| ArnabError::Error(format!("Failed to cache {}: {}", cached, e));
+
+    conn.execute_batch(&cache_entries_sql()).map_err(to_error)?;
+
+    let now = chrono::Utc::now().timestamp();
+    let cached_at: Option<i64> = conn
+        .query_row(
+            &format!(
+                "SELECT cached_at FROM {}.cache_entries WHERE relation = ?",
+                CACHE_SCHEMA
+            ),
+            [&cached],
+            |r| r.get(0),
+        )
+        .ok();
+    if let Some(cached_at) = cached_at {
+        if ((now - cached_at) as u64) < ttl.as_secs() {
+            return Ok(false);
+        }
+    }
+
+    conn.execute_batch(&format!(
+        "CREATE OR REPLACE TABLE {} AS SELECT * FROM {};",
+        cached,
+        source.relation(table)
+    ))
+    .map_err(to_error)?;
+    conn.execute(
+        &format!(
+            "INSERT OR REPLACE INTO {}.cache_entries VALUES (?, ?)",
+            CACHE_SCHEMA
+        ),
+        duckdb::params![cached, now],
+    )
+    .map_err(to_error)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{cache_relation, parse_ttl, upstream_source_ages};
+    use crate::{
+        graph::GraphBuilder,
+        node::{Node, NodeKind},
+        time_format::format_age,
+    };
+
+    #[test]
+    fn parse_cache_ttl() {
+        assert_eq!(parse_ttl("90s").unwrap().as_secs(), 90);
+        assert_eq!(parse_ttl("30m").unwrap().as_secs(), 1800);
+        assert_eq!(parse_ttl("1d").unwrap().as_secs(), 86400);
+        assert!(parse_ttl("1w").is_err());
+        assert!(parse_ttl("soon").is_err());
+    }
+
+    #[test]
+    fn ages_of_upstream_sources() {
+        let mut builder = GraphBuilder::new();
+        let mut orders = Node::new(NodeKind::Sql, "orders.sql", "orders", "");
+        orders.sources.insert(("raw".into(), "orders".into()));
+        let mut payments = Node::new(NodeKind::Sql, "payments.sql", "payments", "");
+        payments.sources.insert(("raw".into(), "payments".into()));
+        payments.sources.insert(("live".into(), "rates".into()));
+        builder.add_node(orders).unwrap();
+        builder.add_node(payments).unwrap();
+        builder
+            .add_node(Node::new(NodeKind::Sql, "revenue.sql", "revenue", ""))
+            .unwrap();
+        builder
+            .add_node(Node::new(NodeKind::Sql, "static.sql", "static", ""))
+            .unwrap();
+        builder.add_dependency("revenue", "orders").unwrap();
+        builder.add_dependency("revenue", "payments").unwrap();
+        let graph = builder.build().unwrap();
+
+        // `live.rates` isn't cached, so it has no refresh time
+        let versions = HashMap::from([
+            (cache_relation("raw", "orders"), 1_000),
+            (cache_relation("raw", "payments"), 5_000),
+        ]);
+        let ages = upstream_source_ages(&graph, &versions, 10_000);
+        assert_eq!(ages["orders"], 9_000);
+        assert_eq!(ages["payments"], 5_000);
+        assert_eq!(ages["revenue"], 9_000);
+        assert!(!ages.contains_key("static"));
+        assert_eq!(format_age(ages["revenue"]), "2h 30m");
+        assert_eq!(format_age(90_061), "1d 1h");
+    }
+}
@@ -0,0 +1,148 @@
+//! Outcomes of the data tests of the last `arnab test`, kept in
+//! `<target_path>/test_results.json` and optionally exported as JUnit XML,
+//! for CI systems to show failing tests in their UI.
+
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::errors::ArnabError;
+
+pub const TEST_RESULTS_FILE: &str = "test_results.json";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TestStatus {
+    Pass,
+    /// The test query returned rows
+    Fail,
+    /// The test query itself failed
+    Error,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TestResult {
+    pub(crate) name: String,
+    /// Model and column tested, for tests declared in schema files
+    pub(crate) model: Option<String>,
+    pub(crate) column: Option<String>,
+    pub(crate) status: TestStatus,
+    /// Number of rows violating the assertion
+    pub(crate) n_failures: usize,
+    pub(crate) duration_ms: u128,
+    pub(crate) error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TestResults<'a> {
+    pub(crate) generated_at: String,
+    pub(crate) results: &'a [TestResult],
+}
+
+impl TestResults<'_> {
+    pub fn save(&self, dir: &Path) -> Result<(), ArnabError> {
+        std::fs::create_dir_all(dir).map_err(|e| ArnabError::Error(e.to_string()))?;
+        let json = serde_json::to_string_pretty(self).unwrap();
+        std::fs::write(dir.join(TEST_RESULTS_FILE), json)
+            .map_err(|e| ArnabError::Error(e.to_string()))
+    }
+
+    /// JUnit XML report, with one test case per data test, classed by model
+    pub fn to_junit(&self) -> String {
+        let count = |status| self.results.iter().filter(|r| r.status == status).count();
+        let seconds = |ms: u128| format!("{:.3}", ms as f64 / 1000.0);
+        let total_ms = self.results.iter().map(|r| r.duration_ms).sum();
+        let attributes = format!(
+            "tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{}\"",
+            self.results.len(),
+            count(TestStatus::Fail),
+            count(TestStatus::Error),
+            seconds(total_ms)
+        );
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str(&format!("<testsuites name=\"arnab\" {}>\n", attributes));
+        xml.push_str(&format!(
+            "  <testsuite name=\"arnab test\" timestamp=\"{}\" {}>\n",
+            self.generated_at, attributes
+        ));
+        for result in self.results {
+            xml.push_str(&format!(
+                "    <testcase name=\"{}\" classname=\"{}\" time=\"{}\"",
+                escape_xml(&result.name),
+                escape_xml(result.model.as_deref().unwrap_or("singular")),
+                seconds(result.duration_ms)
+            ));
+            match result.status {
+                TestStatus::Pass => xml.push_str("/>\n"),
+                TestStatus::Fail => xml.push_str(&format!(
+                    ">\n      <failure message=\"{} failing row{}\"/>\n    </testcase>\n",
+                    result.n_failures,
+                    if result.n_failures > 1 { "s" } else { "" }
+                )),
+                TestStatus::Error => xml.push_str(&format!(
+                    ">\n      <error message=\"{}\"/>\n    </testcase>\n",
+                    escape_xml(result.error.as_deref().unwrap_or_default())
+                )),
+            }
+        }
+        xml.push_str("  </testsuite>\n</testsuites>\n");
+        xml
+    }
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\n', "&#10;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{TestResult, TestResults, TestStatus};
+
+    #[test]
+    fn export_junit() {
+        let result = |name: &str, status, n_failures, error: Option<&str>| TestResult {
+            name: name.into(),
+            model: name.split_once('.').map(|(model, _)| model.into()),
+            column: None,
+            status,
+            n_failures,
+            duration_ms: 12,
+            error: error.map(Into::into),
+        };
+        let results = [
+            result("orders.id.unique", TestStatus::Pass, 0, None),
+            result("orders.id.not_null", TestStatus::Fail, 3, None),
+            result(
+                "no_refunds",
+                TestStatus::Error,
+                0,
+                Some("Table \"refunds\" <missing>"),
+            ),
+        ];
+        let xml = TestResults {
+            generated_at: "2024-01-31T08:00:00Z".into(),
+            results: &results,
+        }
+        .to_junit();
+        assert_eq!(
+            xml,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites name="arnab" tests="3" failures="1" errors="1" time="0.036">
+  <testsuite name="arnab test" timestamp="2024-01-31T08:00:00Z" tests="3" failures="1" errors="1" time="0.036">
+    <testcase name="orders.id.unique" classname="orders" time="0.012"/>
+    <testcase name="orders.id.not_null" classname="orders" time="0.012">
+      <failure message="3 failing rows"/>
+    </testcase>
+    <testcase name="no_refunds" classname="singular" time="0.012">
+      <error message="Table &quot;refunds&quot; &lt;missing&gt;"/>
+    </testcase>
+  </testsuite>
+</testsuites>
+"#
+        );
+    }
+}
@@ -0,0 +1,89 @@
+//! Width of text in terminal columns, for aligned console output: wide
+//! characters, e.g., CJK ones, take two columns, combining ones none, and
+//! ANSI color codes none.
+
+use unicode_width::UnicodeWidthChar;
+
+/// Text without its ANSI escape sequences, e.g., colors
+fn strip_ansi(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            stripped.push(c);
+            continue;
+        }
+        // A CSI sequence ends with a letter, e.g., `\x1b[34m`
+        if chars.next() == Some('[') {
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        }
+    }
+    stripped
+}
+
+/// Number of terminal columns taken by the text
+pub fn display_width(text: &str) -> usize {
+    strip_ansi(text)
+        .chars()
+        .map(|c| c.width().unwrap_or(0))
+        .sum()
+}
+
+/// Longest prefix of a text without escape sequences fitting in `width`
+/// columns
+pub fn truncate_to_width(text: &str, width: usize) -> String {
+    let mut truncated = String::new();
+    let mut used = 0;
+    for c in text.chars() {
+        used += c.width().unwrap_or(0);
+        if used > width {
+            break;
+        }
+        truncated.push(c);
+    }
+    truncated
+}
+
+/// Pad text with spaces to `width` columns
+pub fn pad_to_width(text: &str, width: usize) -> String {
+    let padding = width.saturating_sub(display_width(text));
+    format!("{}{}", text, " ".repeat(padding))
+}
+
+/// Fill a line with dots up to `n_col` columns, for the status printed after
+/// it to be aligned with others. A line too long for it is split, the
+/// status going on a continuation line.
+pub fn fill_with_dots(line: &str, n_col: usize) -> String {
+    let width = display_width(line);
+    match width < n_col {
+        true => format!("{}{}", line, ".".repeat(n_col - width)),
+        false => format!("{}\n    {}", line, ".".repeat(n_col.saturating_sub(4))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{display_width, fill_with_dots, pad_to_width, truncate_to_width};
+
+    #[test]
+    fn measure_wide_text() {
+        assert_eq!(display_width("orders"), 6);
+        assert_eq!(display_width("売上_daily"), 10);
+        assert_eq!(display_width("cafe\u{301}"), 4);
+        assert_eq!(display_width("\x1b[34morders\x1b[0m"), 6);
+
+        assert_eq!(truncate_to_width("売上_daily", 3), "売");
+        assert_eq!(truncate_to_width("売上_daily", 5), "売上_");
+        assert_eq!(pad_to_width("売上", 6), "売上  ");
+
+        assert_eq!(fill_with_dots("売上 ", 8), "売上 ...");
+        assert_eq!(
+            fill_with_dots("creating 売上_daily_by_region", 12),
+            "creating 売上_daily_by_region\n    ........"
+        );
+    }
+}
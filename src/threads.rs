@@ -0,0 +1,130 @@
+use std::str::FromStr;
+
+use duckdb::Connection;
+use serde::Deserialize;
+
+/// Minimum memory budget of a concurrently running node when resolving
+/// `threads: auto`
+const MIN_MEMORY_PER_THREAD: u64 = 512 * 1024 * 1024;
+
+/// Number of nodes allowed to run concurrently
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "RawThreadCount")]
+pub enum ThreadCount {
+    /// Derive from CPU count and DuckDB's memory limit
+    Auto,
+    Fixed(usize),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawThreadCount {
+    Number(usize),
+    Text(String),
+}
+
+impl TryFrom<RawThreadCount> for ThreadCount {
+    type Error = String;
+
+    fn try_from(raw: RawThreadCount) -> Result<Self, Self::Error> {
+        match raw {
+            RawThreadCount::Number(n) => ThreadCount::from_str(&n.to_string()),
+            RawThreadCount::Text(s) => ThreadCount::from_str(&s),
+        }
+    }
+}
+
+impl FromStr for ThreadCount {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "auto" => Ok(ThreadCount::Auto),
+            n => match n.parse::<usize>() {
+                Ok(n) if n > 0 => Ok(ThreadCount::Fixed(n)),
+                _ => Err(format!(
+                    "threads must be `auto` or a positive number, got `{}`",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl ThreadCount {
+    /// Resolve the actual number of threads. `auto` uses the CPU count, but
+    /// no more threads than DuckDB's memory limit can give each of them
+    /// `MIN_MEMORY_PER_THREAD`.
+    pub fn resolve(&self, conn: &Connection) -> usize {
+        match self {
+            ThreadCount::Fixed(n) => *n,
+            ThreadCount::Auto => {
+                let n_cpus = std::thread::available_parallelism()
+                    .map(|n| n.get())
+                    .unwrap_or(1);
+                let by_memory = memory_limit(conn)
+                    .map(|limit| (limit / MIN_MEMORY_PER_THREAD).max(1) as usize)
+                    .unwrap_or(n_cpus);
+                n_cpus.min(by_memory)
+            }
+        }
+    }
+}
+
+/// DuckDB's current memory limit in bytes
+pub fn memory_limit(conn: &Connection) -> Option<u64> {
+    let setting: String = conn
+        .query_row("SELECT current_setting('memory_limit')", [], |r| r.get(0))
+        .ok()?;
+    parse_byte_size(&setting)
+}
+
+/// Parse sizes reported by DuckDB such as `12.4 GiB` or `500.0 MB`
+fn parse_byte_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let split_at = s.find(|c: char| c.is_alphabetic()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split_at);
+    let number: f64 = number.trim().parse().ok()?;
+    let multiplier: u64 = match unit.trim().to_lowercase().as_str() {
+        "" | "b" | "bytes" => 1,
+        "kb" => 1_000,
+        "kib" => 1 << 10,
+        "mb" => 1_000_000,
+        "mib" => 1 << 20,
+        "gb" => 1_000_000_000,
+        "gib" => 1 << 30,
+        "tb" => 1_000_000_000_000,
+        "tib" => 1 << 40,
+        _ => return None,
+    };
+    Some((number * multiplier as f64) as u64)
+}
+
+/// Check whether a DuckDB error message reports memory exhaustion
+pub fn is_out_of_memory(msg: &str) -> bool {
+    let msg = msg.to_lowercase();
+    msg.contains("out of memory") || msg.contains("memory limit")
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::{parse_byte_size, ThreadCount};
+
+    #[test]
+    fn parse_thread_count() {
+        assert_eq!(ThreadCount::from_str("auto"), Ok(ThreadCount::Auto));
+        assert_eq!(ThreadCount::from_str("4"), Ok(ThreadCount::Fixed(4)));
+        assert!(ThreadCount::from_str("0").is_err());
+        let from_yaml: ThreadCount = serde_yaml::from_str("8").unwrap();
+        assert_eq!(from_yaml, ThreadCount::Fixed(8));
+    }
+
+    #[test]
+    fn parse_memory_limit() {
+        assert_eq!(parse_byte_size("1.5 GiB"), Some(3 << 29));
+        assert_eq!(parse_byte_size("500.0 MB"), Some(500_000_000));
+        assert_eq!(parse_byte_size("lots"), None);
+    }
+}
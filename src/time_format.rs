@@ -0,0 +1,155 @@
+use chrono::{format::Item, format::StrftimeItems, DateTime, Local, Utc};
+use serde::Deserialize;
+
+/// Timezone of timestamps printed to the console
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Timezone {
+    #[default]
+    Local,
+    Utc,
+}
+
+/// Formatting of timestamps printed to the console. Structured outputs
+/// (events, manifest, catalog) always use ISO-8601 timestamps in UTC.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct TimeFormat {
+    pub(crate) timezone: Timezone,
+    /// strftime-like format of times, e.g., in progress lines
+    pub(crate) time_format: String,
+    /// strftime-like format of dates
+    pub(crate) date_format: String,
+}
+
+impl Default for TimeFormat {
+    fn default() -> Self {
+        Self {
+            timezone: Timezone::Local,
+            time_format: "%H:%M:%S".into(),
+            date_format: "%Y-%m-%d".into(),
+        }
+    }
+}
+
+impl TimeFormat {
+    /// Check that both formats are valid, as invalid ones fail when printing
+    pub fn validate(&self) -> Result<(), String> {
+        for format in [&self.time_format, &self.date_format] {
+            if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+                return Err(format!("Invalid time format `{}`", format));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn time(&self, timestamp: DateTime<Utc>) -> String {
+        self.format(timestamp, &self.time_format)
+    }
+
+    pub fn date(&self, timestamp: DateTime<Utc>) -> String {
+        self.format(timestamp, &self.date_format)
+    }
+
+    /// Name of the timezone, e.g., `UTC` or `+07:00`
+    pub fn timezone_name(&self) -> String {
+        match self.timezone {
+            Timezone::Utc => "UTC".into(),
+            Timezone::Local => Local::now().format("%:z").to_string(),
+        }
+    }
+
+    fn format(&self, timestamp: DateTime<Utc>, format: &str) -> String {
+        match self.timezone {
+            Timezone::Utc => timestamp.format(format).to_string(),
+            Timezone::Local => timestamp.with_timezone(&Local).format(format).to_string(),
+        }
+    }
+}
+
+/// Current time as an ISO-8601 timestamp in UTC, for structured outputs
+pub fn iso_now() -> String {
+    Utc::now().to_rfc3339()
+}
+
+/// Timestamp as rendered in templates, e.g., `2024-01-31T08:00:00Z`, which
+/// DuckDB casts to `TIMESTAMP` and `TIMESTAMPTZ`
+pub fn template_timestamp(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+/// Human-readable duration, e.g., `1m 5s 20ms`
+pub fn format_elapsed(elapsed: std::time::Duration) -> String {
+    let hours = elapsed.as_secs() / 3600;
+    let minutes = (elapsed.as_secs() % 3600) / 60;
+    let seconds = elapsed.as_secs() % 60;
+    let milliseconds = elapsed.subsec_millis();
+
+    let mut components = Vec::new();
+
+    if hours > 0 {
+        components.push(format!("{}h", hours));
+    }
+    if minutes > 0 {
+        components.push(format!("{}m", minutes));
+    }
+    if seconds > 0 {
+        components.push(format!("{}s", seconds));
+    }
+
+    // always show milliseconds
+    components.push(format!("{}ms", milliseconds));
+
+    components.join(" ")
+}
+
+/// Format an age in seconds with its two largest units, e.g., `2d 3h`
+pub fn format_age(seconds: u64) -> String {
+    let amounts = [
+        seconds / 86400,
+        seconds % 86400 / 3600,
+        seconds % 3600 / 60,
+        seconds % 60,
+    ];
+    let components = amounts
+        .iter()
+        .zip(["d", "h", "m", "s"])
+        .skip_while(|(n, _)| **n == 0)
+        .take(2)
+        .filter(|(n, _)| **n > 0)
+        .map(|(n, unit)| format!("{}{}", n, unit))
+        .collect::<Vec<_>>();
+    match components.is_empty() {
+        true => "0s".into(),
+        false => components.join(" "),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use chrono::TimeZone;
+
+    use super::{format_elapsed, TimeFormat, Timezone};
+
+    #[test]
+    fn format_in_utc() {
+        let time_format = TimeFormat {
+            timezone: Timezone::Utc,
+            time_format: "%H:%M".into(),
+            ..Default::default()
+        };
+        let timestamp = chrono::Utc.with_ymd_and_hms(2024, 3, 1, 23, 30, 0).unwrap();
+        assert_eq!(time_format.time(timestamp), "23:30");
+        assert_eq!(time_format.date(timestamp), "2024-03-01");
+        assert_eq!(time_format.timezone_name(), "UTC");
+        assert_eq!(format_elapsed(Duration::from_millis(65_020)), "1m 5s 20ms");
+
+        let invalid = TimeFormat {
+            time_format: "%Q".into(),
+            ..Default::default()
+        };
+        assert!(invalid.validate().is_err());
+    }
+}
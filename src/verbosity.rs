@@ -0,0 +1,60 @@
+//! Levels of console output, set once from the command line and checked by
+//! every progress print, including those of build threads. Results of
+//! commands (listings, query output) and errors are printed regardless.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Console output levels, each including the previous ones
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Machine-readable output only, e.g., JSON logs
+    Silent,
+    /// Final summaries, warnings and errors (`--quiet`)
+    Quiet,
+    /// Progress of each step
+    Normal,
+    /// Plans and settings as well (`--verbose`)
+    Verbose,
+    /// SQL statements as they are executed (`--debug`)
+    Debug,
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+/// Whether output of the given level is printed
+pub fn enabled(level: Verbosity) -> bool {
+    VERBOSITY.load(Ordering::Relaxed) >= level as u8
+}
+
+/// Print a progress line, hidden by `--quiet`
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if $crate::verbosity::enabled($crate::verbosity::Verbosity::Normal) {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Print a line only shown with `--verbose` or `--debug`
+macro_rules! verbose {
+    ($($arg:tt)*) => {
+        if $crate::verbosity::enabled($crate::verbosity::Verbosity::Verbose) {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Print a line only shown with `--debug`
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if $crate::verbosity::enabled($crate::verbosity::Verbosity::Debug) {
+            println!($($arg)*);
+        }
+    };
+}
+
+pub(crate) use {debug, info, verbose};
@@ -0,0 +1,176 @@
+//! Watch mode of `arnab run --watch`: models and macros directories are
+//! polled for changes, and each change re-runs the selected models whose
+//! rendered SQL differs from the last run, along with their descendants.
+//! Comparing rendered SQL rather than files catches the models affected by
+//! a changed macro too.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use chrono::Utc;
+
+use crate::{
+    session::{RunOptions, Session},
+    verbosity::info,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Extensions of models, schema files and macros
+const WATCHED_EXTENSIONS: [&str; 6] = ["sql", "jinja", "py", "sh", "yml", "yaml"];
+
+/// Modification time and size of each file under some directories
+type Snapshot = BTreeMap<PathBuf, (Option<SystemTime>, u64)>;
+
+/// Snapshot of the watched files under `dirs`, skipping `ignored`, e.g., the
+/// target directory when models are at the project root
+fn snapshot(dirs: &[PathBuf], ignored: &Path) -> Snapshot {
+    let mut files = Snapshot::new();
+    let mut stack = dirs.to_vec();
+    while let Some(dir) = stack.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            // Hidden files and backups, e.g., of editors, are not watched
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with('.') || name.ends_with('~') {
+                continue;
+            }
+            let watched = path
+                .extension()
+                .is_some_and(|ext| WATCHED_EXTENSIONS.iter().any(|w| ext == *w));
+            match entry.metadata() {
+                Ok(metadata) if metadata.is_dir() => {
+                    if !same_path(&path, ignored) {
+                        stack.push(path)
+                    }
+                }
+                Ok(metadata) if watched => {
+                    files.insert(path, (metadata.modified().ok(), metadata.len()));
+                }
+                _ => continue,
+            }
+        }
+    }
+    files
+}
+
+/// Files added, removed or modified between two snapshots, sorted
+fn changed_paths(old: &Snapshot, new: &Snapshot) -> Vec<PathBuf> {
+    let mut changed = new
+        .iter()
+        .filter(|(path, stat)| old.get(*path) != Some(stat))
+        .map(|(path, _)| path.clone())
+        .chain(old.keys().filter(|path| !new.contains_key(*path)).cloned())
+        .collect::<Vec<_>>();
+    changed.sort();
+    changed
+}
+
+fn run(session: &mut Session, options: &RunOptions) {
+    session.run_started_at = Utc::now();
+    if let Err(e) = session.run_nodes(options) {
+        println!("Error: {}", e);
+    }
+}
+
+/// Run the pipeline, then run it again on each change until interrupted
+pub fn watch(session: &mut Session, options: &RunOptions) -> ! {
+    let mut dirs = session
+        .config
+        .models_dir
+        .as_ref()
+        .map(|dirs| dirs.0.iter().map(PathBuf::from).collect::<Vec<_>>())
+        .unwrap_or_default();
+    dirs.extend(session.config.macro_path.as_ref().map(PathBuf::from));
+    let target_dir = session.config.target_dir();
+    let listed = dirs
+        .iter()
+        .map(|dir| dir.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    run(session, options);
+    let rerun_options = RunOptions {
+        only_modified: true,
+        ..options.clone()
+    };
+    let mut files = snapshot(&dirs, &target_dir);
+    loop {
+        info!("\nWatching {} for changes, press Ctrl-C to stop", listed);
+        let changed = loop {
+            std::thread::sleep(POLL_INTERVAL);
+            let new_files = snapshot(&dirs, &target_dir);
+            let changed = changed_paths(&files, &new_files);
+            files = new_files;
+            if !changed.is_empty() {
+                break changed;
+            }
+        };
+        // Editors may write a file in several steps, so wait for them to be
+        // done before rendering
+        std::thread::sleep(POLL_INTERVAL);
+        files = snapshot(&dirs, &target_dir);
+        for path in &changed {
+            info!("Changed: {}", display_path(path));
+        }
+        run(session, &rerun_options);
+    }
+}
+
+fn same_path(a: &Path, b: &Path) -> bool {
+    a.canonicalize()
+        .ok()
+        .is_some_and(|a| b.canonicalize().is_ok_and(|b| a == b))
+}
+
+fn display_path(path: &Path) -> String {
+    path.strip_prefix(".").unwrap_or(path).display().to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::{changed_paths, snapshot};
+
+    #[test]
+    fn detect_changed_files() {
+        let dir = std::env::temp_dir().join(format!("arnab_watch_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("marts")).unwrap();
+        std::fs::create_dir_all(dir.join("target")).unwrap();
+        for name in [
+            "marts/orders.sql",
+            "marts/users.sql",
+            "marts/.orders.sql.swp",
+            "target/orders.sql",
+            "data.duckdb",
+        ] {
+            std::fs::write(dir.join(name), "SELECT 1").unwrap();
+        }
+        let dirs = vec![dir.clone()];
+        let target_dir = dir.join("target");
+        let old = snapshot(&dirs, &target_dir);
+        assert_eq!(old.len(), 2);
+
+        std::fs::write(dir.join("marts/orders.sql"), "SELECT 1, 2").unwrap();
+        std::fs::remove_file(dir.join("marts/users.sql")).unwrap();
+        std::fs::write(dir.join("stg.sql"), "SELECT 1").unwrap();
+        std::fs::write(dir.join("marts/.orders.sql.swp"), "SELECT 1, 2").unwrap();
+        std::fs::write(dir.join("target/orders.sql"), "SELECT 1, 2").unwrap();
+        std::fs::write(dir.join("data.duckdb"), "SELECT 1, 2").unwrap();
+        assert_eq!(
+            changed_paths(&old, &snapshot(&dirs, &target_dir)),
+            ["marts/orders.sql", "marts/users.sql", "stg.sql"]
+                .iter()
+                .map(|name| dir.join(name))
+                .collect::<Vec<PathBuf>>()
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}